@@ -1,67 +1,412 @@
-use crate::osm4routing::{read_edges, Edge as OsmEdge};
-use arli::graph_impl::{CompactGraph, CompactSpatialGraph};
+use crate::osm4routing::{
+  is_highway, read_edges, Edge as OsmEdge, ImportStats, Restriction, SanitizeOptions, CYCLE_NETWORK_NONE,
+};
+use crate::junctions::JunctionTableBuilder;
+use crate::osm_graph::OsmGraph;
+use crate::regions::{RegionBoundaries, RegionsBuilder};
+use crate::street_names::StreetNamesBuilder;
+use arli::graph::GraphData;
+use arli::graph_impl::{CompactGraph, CompactSpatialGraph, ForwardRange, Idx, RangeRef, ReverseRange, TurnRestrictions};
+use arli::progress::{CancellationToken, Cancelled, ProgressSink, StageProgress};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 
+/// Travel modes supported by the multi-modal graph
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TravelMode {
+  Car,
+  Bike,
+  Foot,
+}
+
+impl TravelMode {
+  fn mask(self) -> u8 {
+    match self {
+      TravelMode::Car => 1,
+      TravelMode::Bike => 2,
+      TravelMode::Foot => 4,
+    }
+  }
+}
+
+const STEPS: u16 = 1;
+const UNSIGNALIZED_CROSSING: u16 = 2;
+const CYCLE_NETWORK: u16 = 4;
+// Segments of a two-way road are stored next to each other, the flags tell where the opposite direction is
+const REVERSE_NEXT: u16 = 8;
+const REVERSE_PREVIOUS: u16 = 16;
+const TOLL: u16 = 32;
+const UNPAVED: u16 = 64;
+const LIT: u16 = 128;
+const HIGHWAY: u16 = 256;
+
+/// Names of the flags reported by [`Segment::flags`]
+pub const FLAG_NAMES: [&str; 7] = [
+  "steps",
+  "unsignalized_crossing",
+  "cycle_network",
+  "toll",
+  "unpaved",
+  "lit",
+  "highway",
+];
+
+/// Directed road segment. The graph contains segments accessible by any travel mode, use [`Segment::allows`]
+/// to check whether the segment can be traversed by a particular mode in its direction.
 #[derive(Serialize, Deserialize)]
 pub struct Segment {
-  pub length: f32,
-  pub speed_limit: u8,
+  length: f32,
+  speed_limit: u8,
+  // Bit mask of travel modes allowed to traverse the segment
+  access: u8,
+  // Bit mask of features affecting the cost
+  flags: u16,
+}
+
+impl Segment {
+  /// Length in meters
+  pub fn length(&self) -> f32 {
+    self.length
+  }
+
+  /// Speed limit in km/h, 0 if the way has none
+  pub fn speed_limit(&self) -> u8 {
+    self.speed_limit
+  }
+
+  pub fn allows(&self, mode: TravelMode) -> bool {
+    self.access & mode.mask() != 0
+  }
+
+  /// Segment is a staircase, i.e. `highway=steps`
+  pub fn has_steps(&self) -> bool {
+    self.flags & STEPS != 0
+  }
+
+  /// Segment ends at a pedestrian crossing of a major road without traffic lights
+  pub fn has_unsignalized_crossing(&self) -> bool {
+    self.flags & UNSIGNALIZED_CROSSING != 0
+  }
+
+  /// Segment is a part of a signed cycle route, e.g. a member of a local, regional or national cycle network
+  pub fn on_cycle_network(&self) -> bool {
+    self.flags & CYCLE_NETWORK != 0
+  }
+
+  /// Using the segment is charged, i.e. `toll=yes`
+  pub fn is_toll(&self) -> bool {
+    self.flags & TOLL != 0
+  }
+
+  /// Segment has an unpaved surface, e.g. gravel or dirt
+  pub fn is_unpaved(&self) -> bool {
+    self.flags & UNPAVED != 0
+  }
+
+  /// Segment is lit at night, i.e. `lit=yes`
+  pub fn is_lit(&self) -> bool {
+    self.flags & LIT != 0
+  }
+
+  /// Segment is a motorway or a trunk road
+  pub fn is_highway(&self) -> bool {
+    self.flags & HIGHWAY != 0
+  }
+
+  /// Each of the [`FLAG_NAMES`] with whether it's set, e.g. for the reports
+  pub fn flags(&self) -> [(&'static str, bool); 7] {
+    [
+      (FLAG_NAMES[0], self.has_steps()),
+      (FLAG_NAMES[1], self.has_unsignalized_crossing()),
+      (FLAG_NAMES[2], self.on_cycle_network()),
+      (FLAG_NAMES[3], self.is_toll()),
+      (FLAG_NAMES[4], self.is_unpaved()),
+      (FLAG_NAMES[5], self.is_lit()),
+      (FLAG_NAMES[6], self.is_highway()),
+    ]
+  }
+}
+
+// Speed limits of the small segments in km/h, the limit of a way is rounded to the nearest class
+const SPEED_CLASSES_KM_H: [u8; 16] = [0, 5, 10, 15, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120, 130];
+// Longest small segment in decimeters, the length is stored in 24 bits
+const MAX_LENGTH_DM: u32 = 0xff_ffff;
+
+/// [`Segment`] quantized into 6 bytes instead of 8, for the graphs embedded where every byte of the segments counts,
+/// e.g. on mobile devices. Built by [`small_segment`].
+///
+/// The length is stored in decimeters and the speed limit as one of 16 speed classes, the speed limits of the most
+/// roads fit one exactly. The access and the flags are the same as of the full segment, see [`SmallSegment::unpack`].
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SmallSegment {
+  // Length in decimeters, little endian
+  length_dm: [u8; 3],
+  // Speed class in the lower 4 bits, access mask in the upper ones
+  class_and_access: u8,
+  // Flags of the full segment, little endian
+  flags: [u8; 2],
+}
+
+impl SmallSegment {
+  pub fn new(segment: &Segment) -> Self {
+    let length_dm = ((segment.length * 10.0).round() as u32).min(MAX_LENGTH_DM).to_le_bytes();
+    let class = (0..SPEED_CLASSES_KM_H.len())
+      .min_by_key(|class| (i32::from(SPEED_CLASSES_KM_H[*class]) - i32::from(segment.speed_limit)).abs())
+      .unwrap_or(0);
+    Self {
+      length_dm: [length_dm[0], length_dm[1], length_dm[2]],
+      class_and_access: class as u8 | segment.access << 4,
+      flags: segment.flags.to_le_bytes(),
+    }
+  }
+
+  /// Length in meters, rounded to decimeters
+  pub fn length(&self) -> f32 {
+    let [low, middle, high] = self.length_dm;
+    u32::from_le_bytes([low, middle, high, 0]) as f32 / 10.0
+  }
+
+  /// Speed limit of the speed class in km/h, 0 if the way has none
+  pub fn speed_limit(&self) -> u8 {
+    SPEED_CLASSES_KM_H[(self.class_and_access & 0x0f) as usize]
+  }
+
+  pub fn allows(&self, mode: TravelMode) -> bool {
+    (self.class_and_access >> 4) & mode.mask() != 0
+  }
+
+  /// Full segment with the quantized length and speed limit, e.g. to check the flags or to use the cost functions of
+  /// the full segments
+  pub fn unpack(&self) -> Segment {
+    Segment {
+      length: self.length(),
+      speed_limit: self.speed_limit(),
+      access: self.class_and_access >> 4,
+      flags: u16::from_le_bytes(self.flags),
+    }
+  }
+}
+
+/// Segment of the same road in the opposite direction, used to detect U-turns
+pub fn reverse_segment(graph: &OsmGraph, id: u32) -> Option<u32> {
+  let segment = graph.data(id);
+  if segment.flags & REVERSE_NEXT != 0 {
+    Some(id + 1)
+  } else if segment.flags & REVERSE_PREVIOUS != 0 {
+    Some(id - 1)
+  } else {
+    None
+  }
+}
+
+fn access_mask(car: i8, bike: i8, foot: i8) -> u8 {
+  let mut mask = 0;
+  if car != 0 {
+    mask |= TravelMode::Car.mask();
+  }
+  if bike != 0 {
+    mask |= TravelMode::Bike.mask();
+  }
+  if foot != 0 {
+    mask |= TravelMode::Foot.mask();
+  }
+  mask
+}
+
+fn flags(record: &OsmEdge) -> u16 {
+  let mut flags = 0;
+  if record.properties.steps {
+    flags |= STEPS;
+  }
+  if record.unsignalized_crossing {
+    flags |= UNSIGNALIZED_CROSSING;
+  }
+  if record.properties.cycle_network != CYCLE_NETWORK_NONE {
+    flags |= CYCLE_NETWORK;
+  }
+  if record.properties.toll {
+    flags |= TOLL;
+  }
+  if record.properties.unpaved {
+    flags |= UNPAVED;
+  }
+  if record.properties.lit {
+    flags |= LIT;
+  }
+  if is_highway(record.properties.car_forward.max(record.properties.car_backward)) {
+    flags |= HIGHWAY;
+  }
+  flags
+}
+
+/// Direction of an OSM edge a segment is built for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeDirection {
+  /// From the first to the last point of the way
+  Forward,
+  Backward,
+}
+
+/// Data of the segment built for the direction of the edge, the segment data of the graphs built by
+/// [`import_osm_pbf`]
+pub fn osm_segment(record: &OsmEdge, direction: EdgeDirection) -> Segment {
+  let properties = &record.properties;
+  let forward_access = access_mask(properties.car_forward, properties.bike_forward, properties.foot);
+  let backward_access = access_mask(properties.car_backward, properties.bike_backward, properties.foot);
+  let two_way = forward_access != 0 && backward_access != 0;
+  let (access, reverse) = match direction {
+    EdgeDirection::Forward => (forward_access, REVERSE_NEXT),
+    EdgeDirection::Backward => (backward_access, REVERSE_PREVIOUS),
+  };
+  Segment {
+    length: record.length(),
+    speed_limit: properties.speed_limit_km_h,
+    access,
+    flags: flags(record) | if two_way { reverse } else { 0 },
+  }
+}
+
+/// Quantized [`osm_segment`], the segment data of the small graphs, see [`SmallSegment`]
+pub fn small_segment(record: &OsmEdge, direction: EdgeDirection) -> SmallSegment {
+  SmallSegment::new(&osm_segment(record, direction))
+}
+
+/// Optional parts of the graph
+#[derive(Default)]
+pub struct BuildOptions {
+  /// Build the table of the OSM nodes at the ends of the segments, see [`OsmGraph::junctions`]
+  pub junctions: bool,
+  /// Locate the segments in the regions, see [`OsmGraph::region`]
+  pub regions: Option<RegionBoundaries>,
+  /// Directory to keep the OSM nodes in during the import instead of the memory, see [`read_edges`]
+  pub node_store: Option<PathBuf>,
+  /// Repairs of the way geometry besides the duplicate points and the spikes removed always, see [`read_edges`]
+  pub sanitize: SanitizeOptions,
 }
 
-pub type OsmGraph = CompactSpatialGraph<Segment>;
+/// Imports the graph from the OSM file, reporting the progress of each stage to the sink. The import stops with an
+/// error once the token is cancelled. The graph comes with the number of geometry repairs made during the import and
+/// of the turn restrictions skipped.
+pub fn import_osm_pbf(
+  pbf_path: &str,
+  options: &BuildOptions,
+  progress: &mut dyn ProgressSink,
+  token: &CancellationToken,
+) -> Result<(OsmGraph, ImportStats), String> {
+  import_osm_pbf_with(pbf_path, options, osm_segment, progress, token)
+}
 
-pub fn import_osm_pbf(pbf_path: &str) -> Result<OsmGraph, String> {
-  let edges = read_edges(pbf_path)?;
+/// Same as [`import_osm_pbf`] with the data of each segment made by `segment`, see [`build_compact_graph`]
+pub fn import_osm_pbf_with<S, F>(
+  pbf_path: &str,
+  options: &BuildOptions,
+  segment: F,
+  progress: &mut dyn ProgressSink,
+  token: &CancellationToken,
+) -> Result<(OsmGraph<S>, ImportStats), String>
+where
+  F: Fn(&OsmEdge, EdgeDirection) -> S,
+{
+  let (edges, restrictions, stats) =
+    read_edges(pbf_path, options.node_store.as_deref(), &options.sanitize, progress, token)?;
 
-  Ok(build_compact_graph(&edges))
+  let stage = StageProgress::new(progress, token, "Building graph", Some(edges.len()));
+  let graph = build_compact_graph(&edges, &restrictions, options, segment, stage).map_err(|e| e.to_string())?;
+  Ok((graph, stats))
 }
 
-pub fn build_compact_graph(osm_edges: &Vec<OsmEdge>) -> OsmGraph {
-  let mut segments: Vec<Segment> = Vec::new();
+/// Builds the graph of the OSM edges, the progress is reported in the number of edges added.
+///
+/// The data of each segment is made by `segment`, e.g. [`osm_segment`], so the graph can carry other data while the
+/// topology, the geometry and the optional parts are built the same way. An edge gets a segment in each direction
+/// some travel mode may use it in. The restrictions become the forbidden transitions between the segments, see
+/// [`OsmGraph::turn_restrictions`].
+pub fn build_compact_graph<S, F>(
+  osm_edges: &[OsmEdge],
+  restrictions: &[Restriction],
+  options: &BuildOptions,
+  segment: F,
+  mut stage: StageProgress,
+) -> Result<OsmGraph<S>, Cancelled>
+where
+  F: Fn(&OsmEdge, EdgeDirection) -> S,
+{
+  let mut segments: Vec<S> = Vec::new();
+  let mut way_ids: Vec<i64> = Vec::new();
+  let mut street_names = StreetNamesBuilder::new();
+  let mut junctions = JunctionTableBuilder::new();
+  let mut regions = options.regions.as_ref().map(RegionsBuilder::new);
+  let mut source_nodes: Vec<usize> = Vec::new();
   let mut target_nodes: Vec<usize> = Vec::new();
   let mut out_segments: HashMap<usize, Vec<u32>> = HashMap::new();
 
   let mut points = Vec::new();
-  let mut geom_offsets = Vec::new();
+  let mut geometry_refs = Vec::new();
 
-  points.push(geo::Coordinate::from((0.0f32, 0.0f32))); // Sentinel for backward range
+  points.push(geo::Coordinate::from((0.0f32, 0.0f32))); // Sentinel, a reverse range can't start at the first point
   for record in osm_edges {
-    if record.properties.car_forward != 0 {
-      geom_offsets.push((points.len(), points.len() + record.geometry.num_coords()));
-      points.extend(record.geometry.0.iter());
+    stage.step()?;
+    let properties = &record.properties;
+    // Pedestrians may walk in both directions, even along oneway streets
+    let forward_access = access_mask(properties.car_forward, properties.bike_forward, properties.foot);
+    let backward_access = access_mask(properties.car_backward, properties.bike_backward, properties.foot);
 
-      let forward = Segment {
-        length: record.length(),
-        speed_limit: record.properties.speed_limit_km_h,
-      };
+    // Coordinates are always added, since the backward range refers to them as well
+    let first_point = points.len();
+    points.extend(record.geometry.0.iter());
+
+    if forward_access != 0 {
+      geometry_refs.push(RangeRef::forward(ForwardRange {
+        start: first_point as Idx,
+        end: points.len() as Idx,
+      }));
+
+      let forward = segment(record, EdgeDirection::Forward);
       out_segments
         .entry(record.source.0 as usize)
         .or_insert_with(|| Vec::new())
         .push(segments.len() as u32);
+      source_nodes.push(record.source.0 as usize);
       target_nodes.push(record.target.0 as usize);
+      way_ids.push(record.id.0);
+      street_names.push(record.name.as_deref());
+      if options.junctions {
+        junctions.push(record.source.0, record.target.0);
+      }
+      if let Some(regions) = &mut regions {
+        regions.push(&record.geometry.0);
+      }
       segments.push(forward);
     }
 
-    if record.properties.car_backward != 0 {
-      // We reuse coordinates for the edge in the opposite direction. Create a range (before, last]
-      geom_offsets.push((
-        points.len() - 1,
-        points.len() - record.geometry.num_coords() - 1,
-      ));
+    if backward_access != 0 {
+      // We reuse coordinates for the edge in the opposite direction
+      geometry_refs.push(RangeRef::reverse(ReverseRange {
+        start: first_point as Idx,
+        end: points.len() as Idx,
+      }));
 
-      let backward = Segment {
-        length: record.length(),
-        speed_limit: record.properties.speed_limit_km_h,
-      };
+      let backward = segment(record, EdgeDirection::Backward);
       out_segments
         .entry(record.target.0 as usize)
         .or_insert_with(|| Vec::new())
         .push(segments.len() as u32);
+      source_nodes.push(record.target.0 as usize);
       target_nodes.push(record.source.0 as usize);
+      way_ids.push(record.id.0);
+      street_names.push(record.name.as_deref());
+      if options.junctions {
+        junctions.push(record.target.0, record.source.0);
+      }
+      if let Some(regions) = &mut regions {
+        regions.push(&record.geometry.0);
+      }
       segments.push(backward);
     }
   }
+  stage.finish();
+  let turn_restrictions = forbidden_transitions(restrictions, &way_ids, &source_nodes, &target_nodes, &out_segments);
   let mut edge_refs: Vec<u32> = Vec::new();
   let mut edge_offsets = Vec::new();
 
@@ -72,11 +417,341 @@ pub fn build_compact_graph(osm_edges: &Vec<OsmEdge>) -> OsmGraph {
     }
   }
 
-  let mut graph = OsmGraph::from_row_data(
-    CompactGraph::from_row_data(segments, edge_offsets, edge_refs),
-    geom_offsets,
-    points,
-  );
+  let mut graph = OsmGraph::new(
+    CompactSpatialGraph::from_row_data(
+      CompactGraph::from_row_data(segments, edge_offsets, edge_refs),
+      geometry_refs,
+      points,
+    ),
+    way_ids,
+    street_names.build(),
+  )
+  .with_turn_restrictions(turn_restrictions);
+  if options.junctions {
+    graph = graph.with_junctions(junctions.build());
+  }
+  if let Some(regions) = regions {
+    graph = graph.with_regions(regions.build());
+  }
   graph.shrink();
-  graph
+  Ok(graph)
+}
+
+// Transitions between the segments forbidden by the restrictions. Segments are matched by their way and by the via
+// node they end or start at, a restriction whose ways don't meet at the via node forbids nothing.
+fn forbidden_transitions(
+  restrictions: &[Restriction],
+  way_ids: &[i64],
+  source_nodes: &[usize],
+  target_nodes: &[usize],
+  out_segments: &HashMap<usize, Vec<u32>>,
+) -> TurnRestrictions<Idx> {
+  let via_nodes: HashSet<usize> = restrictions.iter().map(|restriction| restriction.via.0 as usize).collect();
+  let mut in_segments: HashMap<usize, Vec<u32>> = HashMap::new();
+  for (id, target) in target_nodes.iter().enumerate() {
+    if via_nodes.contains(target) {
+      in_segments.entry(*target).or_default().push(id as u32);
+    }
+  }
+
+  let mut forbidden = Vec::new();
+  for restriction in restrictions {
+    let via = restriction.via.0 as usize;
+    let (from_segments, to_segments) = match (in_segments.get(&via), out_segments.get(&via)) {
+      (Some(from_segments), Some(to_segments)) => (from_segments, to_segments),
+      _ => continue,
+    };
+    for &from in from_segments.iter().filter(|id| way_ids[**id as usize] == restriction.from.0) {
+      for &to in to_segments {
+        // A U-turn restriction on a way going on past the via node only forbids turning back
+        let turns_back = target_nodes[to as usize] == source_nodes[from as usize];
+        let restricted_turn =
+          way_ids[to as usize] == restriction.to.0 && (restriction.from != restriction.to || turns_back);
+        // `only_*` forbids all turns but the restricted one, `no_*` forbids just that one
+        if restricted_turn != restriction.only {
+          forbidden.push((from, to));
+        }
+      }
+    }
+  }
+  TurnRestrictions::new(forbidden)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::osm4routing::EdgeProperties;
+  use arli::graph::neighbors_forward;
+  use arli::route::route;
+  use arli::spatial::Position;
+  use arli::waypoint::MatchedWaypoint;
+  use arli::progress::NoProgress;
+  use osmpbfreader::objects::{NodeId, WayId};
+
+  fn build_with<S, F: Fn(&OsmEdge, EdgeDirection) -> S>(osm_edges: &[OsmEdge], segment: F) -> OsmGraph<S> {
+    let token = CancellationToken::new();
+    let mut progress = NoProgress;
+    let stage = StageProgress::new(&mut progress, &token, "Building graph", Some(osm_edges.len()));
+    build_compact_graph(osm_edges, &[], &BuildOptions::default(), segment, stage).unwrap()
+  }
+
+  fn build(osm_edges: &[OsmEdge], options: &BuildOptions) -> OsmGraph {
+    let token = CancellationToken::new();
+    let mut progress = NoProgress;
+    let stage = StageProgress::new(&mut progress, &token, "Building graph", Some(osm_edges.len()));
+    build_compact_graph(osm_edges, &[], options, osm_segment, stage).unwrap()
+  }
+
+  fn edge(source: i64, target: i64, properties: EdgeProperties) -> OsmEdge {
+    way(1, source, target, properties)
+  }
+
+  fn way(id: i64, source: i64, target: i64, properties: EdgeProperties) -> OsmEdge {
+    OsmEdge {
+      id: WayId(id),
+      source: NodeId(source),
+      target: NodeId(target),
+      geometry: vec![(13.33318f32, 52.48468f32), (13.33312, 52.48757)].into(),
+      properties,
+      unsignalized_crossing: false,
+      name: None,
+    }
+  }
+
+  #[test]
+  fn test_oneway_is_walkable_in_both_directions() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.update("oneway", "yes");
+    properties.normalize();
+
+    let graph = build(&[edge(1, 2, properties)], &BuildOptions::default());
+
+    let forward = graph.data(0);
+    assert!(forward.allows(TravelMode::Car));
+    assert!(forward.allows(TravelMode::Foot));
+
+    let backward = graph.data(1);
+    assert!(!backward.allows(TravelMode::Car));
+    assert!(backward.allows(TravelMode::Foot));
+  }
+
+  #[test]
+  fn test_steps() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "steps");
+    properties.normalize();
+
+    let graph = build(&[edge(1, 2, properties)], &BuildOptions::default());
+
+    assert!(graph.data(0).has_steps());
+    assert!(!graph.data(0).allows(TravelMode::Car));
+    assert!(graph.data(1).allows(TravelMode::Foot));
+  }
+
+  #[test]
+  fn test_cycle_network() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.normalize();
+    let mut cycle_route = properties;
+    cycle_route.update("lcn", "yes");
+
+    let edges = vec![edge(1, 2, properties), edge(2, 3, cycle_route)];
+    let graph = build(&edges, &BuildOptions::default());
+
+    assert!(!graph.data(0).on_cycle_network());
+    assert!(graph.data(2).on_cycle_network());
+    assert!(graph.data(3).on_cycle_network());
+  }
+
+  #[test]
+  fn test_reverse_segment() {
+    let mut two_way = EdgeProperties::default();
+    two_way.update("highway", "residential");
+    two_way.normalize();
+    let mut oneway = EdgeProperties::default();
+    oneway.update("highway", "motorway");
+    oneway.update("oneway", "yes");
+    oneway.normalize();
+
+    let graph = build(&[edge(1, 2, oneway), edge(2, 3, two_way)], &BuildOptions::default());
+
+    assert_eq!(reverse_segment(&graph, 0), None);
+    assert_eq!(reverse_segment(&graph, 1), Some(2));
+    assert_eq!(reverse_segment(&graph, 2), Some(1));
+  }
+
+  #[test]
+  fn test_find_edges_by_way_id() {
+    let mut two_way = EdgeProperties::default();
+    two_way.update("highway", "residential");
+    two_way.normalize();
+    let mut oneway = EdgeProperties::default();
+    oneway.update("highway", "motorway");
+    oneway.update("oneway", "yes");
+    oneway.normalize();
+
+    let edges = vec![way(20, 1, 2, two_way), way(10, 2, 3, oneway), way(20, 2, 4, two_way)];
+    let graph = build(&edges, &BuildOptions::default());
+
+    assert_eq!(graph.find_edges_by_way_id(10), &[2]);
+    assert_eq!(graph.find_edges_by_way_id(20), &[0, 1, 3, 4]);
+    assert!(graph.find_edges_by_way_id(15).is_empty());
+    assert!(graph.find_edges_by_way_id(30).is_empty());
+    assert_eq!(graph.way_id(4), 20);
+  }
+
+  #[test]
+  fn test_street_names() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.normalize();
+    let mut named = way(1, 1, 2, properties);
+    named.name = Some(String::from("Tempelhofer Damm"));
+
+    let graph = build(&[named, way(2, 2, 3, properties)], &BuildOptions::default());
+
+    assert_eq!(graph.street_name(0), Some("Tempelhofer Damm"));
+    assert_eq!(graph.street_name(1), Some("Tempelhofer Damm"));
+    assert_eq!(graph.street_name(2), None);
+  }
+
+  #[test]
+  fn test_junctions() {
+    let mut two_way = EdgeProperties::default();
+    two_way.update("highway", "residential");
+    two_way.normalize();
+    let mut oneway = EdgeProperties::default();
+    oneway.update("highway", "motorway");
+    oneway.update("oneway", "yes");
+    oneway.normalize();
+    let edges = vec![edge(1, 2, oneway), edge(2, 3, two_way)];
+
+    assert!(build(&edges, &BuildOptions::default()).junctions().is_none());
+
+    let options = BuildOptions {
+      junctions: true,
+      ..Default::default()
+    };
+    let graph = build(&edges, &options);
+    let junctions = graph.junctions().unwrap();
+    assert_eq!(junctions.number_of_junctions(), 3);
+    // The oneway ends at the junction both directions of the two-way road meet at
+    assert_eq!(junctions.osm_node_id(junctions.end(0)), 2);
+    assert_eq!(junctions.start(1), junctions.end(0));
+    assert_eq!(junctions.end(2), junctions.end(0));
+    assert_eq!(junctions.osm_node_id(junctions.start(2)), 3);
+  }
+
+  #[test]
+  fn test_turn_restrictions() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.normalize();
+    // Three two-way streets meeting at the node 2, the segments 0, 2 and 5 enter it and 1, 3 and 4 leave it
+    let edges = vec![way(10, 1, 2, properties), way(20, 3, 2, properties), way(30, 2, 4, properties)];
+    let restriction = |from: i64, to: i64, only: bool| Restriction {
+      from: WayId(from),
+      via: NodeId(2),
+      to: WayId(to),
+      only,
+    };
+    let restrictions = vec![restriction(10, 20, false), restriction(30, 10, true), restriction(10, 10, false)];
+    let token = CancellationToken::new();
+    let mut progress = NoProgress;
+    let stage = StageProgress::new(&mut progress, &token, "Building graph", Some(edges.len()));
+    let graph = build_compact_graph(&edges, &restrictions, &BuildOptions::default(), osm_segment, stage).unwrap();
+
+    let forbidden = graph.turn_restrictions();
+    assert_eq!(forbidden.len(), 4);
+    assert!(forbidden.forbids(0, 3) && !forbidden.forbids(0, 4));
+    // Only left from 30 into 10, also forbidding the U-turn
+    assert!(forbidden.forbids(5, 3) && forbidden.forbids(5, 4) && !forbidden.forbids(5, 1));
+    assert!(forbidden.forbids(0, 1) && !forbidden.forbids(2, 1));
+  }
+
+  #[test]
+  fn test_shared_between_threads() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.normalize();
+    let edges = vec![way(1, 1, 2, properties), way(2, 2, 3, properties)];
+    let graph = std::sync::Arc::new(build(&edges, &BuildOptions::default()));
+    let length_cost = |from: &Segment, _: &Segment| from.length() as i32;
+
+    let workers: Vec<_> = (0..4)
+      .map(|_| {
+        let graph = std::sync::Arc::clone(&graph);
+        std::thread::spawn(move || {
+          let from = MatchedWaypoint::from_node(0, Position { x: 0.0, y: 0.0 });
+          let to = MatchedWaypoint::from_node(2, Position { x: 0.0, y: 0.0 });
+          route((graph.as_ref(), length_cost), &from, &to).map(|route| route.ids)
+        })
+      })
+      .collect();
+
+    for worker in workers {
+      assert_eq!(worker.join().unwrap().unwrap(), vec![0, 2]);
+    }
+  }
+
+  #[test]
+  fn test_custom_segments() {
+    let mut two_way = EdgeProperties::default();
+    two_way.update("highway", "residential");
+    two_way.normalize();
+    let mut oneway = EdgeProperties::default();
+    oneway.update("highway", "motorway");
+    oneway.update("oneway", "yes");
+    oneway.normalize();
+    let edges = vec![way(10, 1, 2, oneway), way(20, 2, 3, two_way)];
+
+    // Only the speed limit and whether cars may enter, e.g. for a smaller car-only graph
+    let car_segment = |record: &OsmEdge, direction: EdgeDirection| {
+      let car = match direction {
+        EdgeDirection::Forward => record.properties.car_forward,
+        EdgeDirection::Backward => record.properties.car_backward,
+      };
+      (record.properties.speed_limit_km_h, car != 0)
+    };
+    let graph = build_with(&edges, car_segment);
+
+    // Same topology as the graph with the full segments
+    let full = build(&edges, &BuildOptions::default());
+    assert_eq!(graph.number_of_nodes(), full.number_of_nodes());
+    assert_eq!(graph.find_edges_by_way_id(20), &[1, 2]);
+    assert_eq!(neighbors_forward(&graph, 0).collect::<Vec<_>>(), vec![1]);
+    assert!(graph.data(0).1);
+    assert_eq!(graph.data(0).0, oneway.speed_limit_km_h);
+    assert!(graph.data(1).1 && graph.data(2).1);
+  }
+
+  #[test]
+  fn test_small_segments() {
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "motorway");
+    properties.update("oneway", "yes");
+    properties.update("toll", "yes");
+    properties.update("maxspeed", "120");
+    properties.normalize();
+    let edges = vec![way(10, 1, 2, properties)];
+    let graph = build_with(&edges, small_segment);
+    let full = build(&edges, &BuildOptions::default());
+    assert_eq!(std::mem::size_of::<SmallSegment>(), 6);
+
+    let (small, segment) = (graph.data(0), full.data(0));
+    assert!((small.length() - segment.length()).abs() <= 0.05);
+    assert_eq!(small.speed_limit(), 120);
+    assert!(small.allows(TravelMode::Car) && !small.allows(TravelMode::Bike));
+    assert!(small.unpack().is_toll() && small.unpack().is_highway());
+
+    // Speed limits between the classes are rounded to the nearest one, the length saturates at 24 bits
+    let mut long = osm_segment(&edges[0], EdgeDirection::Forward);
+    long.speed_limit = 33;
+    long.length = 2_000_000.0;
+    let small = SmallSegment::new(&long);
+    assert_eq!(small.speed_limit(), 30);
+    assert_eq!(small.length(), MAX_LENGTH_DM as f32 / 10.0);
+  }
 }
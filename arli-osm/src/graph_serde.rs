@@ -1,16 +1,103 @@
-use crate::graph_builder::OsmGraph;
+use crate::osm_graph::OsmGraph;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fs::File;
-use std::io::{ErrorKind, Error, BufWriter, BufReader};
+use std::convert::TryInto;
+use std::io::{BufWriter, BufReader, Read};
+use std::path::Path;
 
-pub fn save_graph(graph: &OsmGraph, path: &str){
+// Multiplicative hash of 64-bit words, like FxHash. Unlike the hashers of std it stays the same between the builds
+// of the tools, and a word at a time it keeps up with reading a large graph file.
+const CHECKSUM_SEED: u64 = 0xcbf2_9ce4_8422_2325;
+const CHECKSUM_MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+
+fn add_word(checksum: u64, word: u64) -> u64 {
+  (checksum.rotate_left(5) ^ word).wrapping_mul(CHECKSUM_MULTIPLIER)
+}
+
+// Reads until the buffer is full or the file ends, returns the bytes read
+fn fill(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    match file.read(&mut buffer[filled..]) {
+      Ok(0) => break,
+      Ok(read) => filled += read,
+      Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+      Err(error) => return Err(error),
+    }
+  }
+  Ok(filled)
+}
+
+pub fn save_graph<S: Serialize>(graph: &OsmGraph<S>, path: &str){
   let file = BufWriter::new(File::create(path).unwrap());
   bincode::serialize_into(file, &graph).unwrap();
 }
 
 
-pub fn load_graph(path: &str) -> std::io::Result<OsmGraph> {
+/// Graph saved by [`save_graph`], fails with [`arli::Error::CorruptGraph`] if the file isn't a graph of this version
+/// with the same segment data, e.g. a small graph loaded as a graph of the full segments
+pub fn load_graph<S: DeserializeOwned>(path: &str) -> arli::Result<OsmGraph<S>> {
   let file = BufReader::new(File::open(path)?);
-  let mut graph: OsmGraph = bincode::deserialize_from(file).map_err(|_| Error::from(ErrorKind::InvalidData))?;
+  let mut graph: OsmGraph<S> =
+    bincode::deserialize_from(file).map_err(|error| arli::Error::CorruptGraph(error.to_string()))?;
   graph.shrink();
   Ok(graph)
-}
\ No newline at end of file
+}
+
+/// Checksum of the content of the graph file, the companion files store it to be loaded only with their graph.
+///
+/// It reads the whole file, so compute it once for all the companion files of a graph.
+pub fn graph_checksum(path: &Path) -> arli::Result<u64> {
+  let mut file = File::open(path)?;
+  let mut buffer = vec![0; 1 << 16];
+  let mut checksum = CHECKSUM_SEED;
+  let mut len = 0u64;
+  loop {
+    let read = fill(&mut file, &mut buffer)?;
+    len += read as u64;
+    let words = buffer[..read].chunks_exact(8);
+    let rest = words.remainder();
+    for word in words {
+      checksum = add_word(checksum, u64::from_le_bytes(word.try_into().unwrap()));
+    }
+    // Only the end of the file is shorter than the buffer, its last bytes are padded to a word and the length tells
+    // the padding apart from the zero bytes
+    if read < buffer.len() {
+      let mut last = [0u8; 8];
+      last[..rest.len()].copy_from_slice(rest);
+      return Ok(add_word(add_word(checksum, u64::from_le_bytes(last)), len));
+    }
+  }
+}
+
+/// Writes data built from a graph, e.g. a preprocessing artifact, with the [`graph_checksum`] of the graph
+pub fn save_companion<T: Serialize>(path: &Path, graph_checksum: u64, data: &T) -> arli::Result<()> {
+  let file = BufWriter::new(File::create(path)?);
+  bincode::serialize_into(file, &(graph_checksum, data))
+    .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()))?;
+  Ok(())
+}
+
+/// Data written by [`save_companion`], fails with [`arli::Error::GraphMismatch`] if it's built from another graph and
+/// with [`arli::Error::CorruptGraph`] if the file can't be read back
+pub fn load_companion<T: DeserializeOwned>(path: &Path, graph_checksum: u64) -> arli::Result<T> {
+  let mut file = BufReader::new(File::open(path)?);
+  let corrupt = |error: bincode::Error| arli::Error::CorruptGraph(error.to_string());
+  let checksum: u64 = bincode::deserialize_from(&mut file).map_err(corrupt)?;
+  if checksum != graph_checksum {
+    return Err(arli::Error::GraphMismatch(path.display().to_string()));
+  }
+  bincode::deserialize_from(file).map_err(corrupt)
+}
+
+/// Checksum of the graph the companion file is built from, `None` if there is no such file
+pub fn companion_checksum(path: &Path) -> arli::Result<Option<u64>> {
+  let file = match File::open(path) {
+    Ok(file) => BufReader::new(file),
+    Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(error) => return Err(error.into()),
+  };
+  let checksum = bincode::deserialize_from(file).map_err(|error| arli::Error::CorruptGraph(error.to_string()))?;
+  Ok(Some(checksum))
+}
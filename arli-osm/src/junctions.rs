@@ -0,0 +1,105 @@
+use arli::graph_impl::{Idx, MemoryUsage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// OSM node at each end of every segment.
+///
+/// Segments meeting at the same physical junction refer to the same junction id: the end junction of a segment is
+/// the start junction of each of its successors. Junction ids are dense, so data about junctions, e.g. turn costs,
+/// can be stored in a vector indexed by them.
+#[derive(Serialize, Deserialize, Default)]
+pub struct JunctionTable {
+  // OSM node id of each junction
+  osm_node_ids: Vec<i64>,
+  // Junctions at the start and at the end of each segment
+  segment_ends: Vec<(u32, u32)>,
+}
+
+impl JunctionTable {
+  /// Junctions at the start and at the end of the segment
+  pub fn ends(&self, id: Idx) -> (u32, u32) {
+    self.segment_ends[id as usize]
+  }
+
+  pub fn start(&self, id: Idx) -> u32 {
+    self.ends(id).0
+  }
+
+  pub fn end(&self, id: Idx) -> u32 {
+    self.ends(id).1
+  }
+
+  /// OSM node the junction was built from
+  pub fn osm_node_id(&self, junction: u32) -> i64 {
+    self.osm_node_ids[junction as usize]
+  }
+
+  pub fn number_of_junctions(&self) -> usize {
+    self.osm_node_ids.len()
+  }
+
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add("osm_node_ids", &self.osm_node_ids);
+    usage.add("segment_ends", &self.segment_ends);
+    usage
+  }
+
+  pub fn shrink(&mut self) {
+    self.osm_node_ids.shrink_to_fit();
+    self.segment_ends.shrink_to_fit();
+  }
+}
+
+/// Collects the junctions of the segments in the order of their ids
+pub struct JunctionTableBuilder {
+  table: JunctionTable,
+  index: HashMap<i64, u32>,
+}
+
+impl JunctionTableBuilder {
+  pub fn new() -> Self {
+    Self {
+      table: JunctionTable::default(),
+      index: HashMap::new(),
+    }
+  }
+
+  fn junction(&mut self, osm_node_id: i64) -> u32 {
+    let osm_node_ids = &mut self.table.osm_node_ids;
+    *self.index.entry(osm_node_id).or_insert_with(|| {
+      osm_node_ids.push(osm_node_id);
+      (osm_node_ids.len() - 1) as u32
+    })
+  }
+
+  /// Adds the next segment, going from the `source` to the `target` OSM node
+  pub fn push(&mut self, source: i64, target: i64) {
+    let ends = (self.junction(source), self.junction(target));
+    self.table.segment_ends.push(ends);
+  }
+
+  pub fn build(self) -> JunctionTable {
+    self.table
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_junction_table() {
+    let mut builder = JunctionTableBuilder::new();
+    builder.push(100, 200);
+    builder.push(200, 100);
+    builder.push(200, 300);
+    let junctions = builder.build();
+
+    assert_eq!(junctions.number_of_junctions(), 3);
+    assert_eq!(junctions.ends(0), (0, 1));
+    assert_eq!(junctions.ends(1), (1, 0));
+    assert_eq!(junctions.start(2), junctions.end(0));
+    assert_eq!(junctions.osm_node_id(junctions.end(2)), 300);
+  }
+}
@@ -1,7 +1,22 @@
 mod osm4routing;
 mod graph_builder;
 mod graph_serde;
+mod junctions;
+mod osm_graph;
+mod preprocess;
+mod regions;
+mod stats;
+mod street_names;
 
 
 pub use graph_builder::*;
-pub use graph_serde::{load_graph, save_graph};
+pub use junctions::JunctionTable;
+pub use osm4routing::sanitize::SanitizeStats;
+pub use osm4routing::{read_edges, Edge as OsmEdge, ImportStats, Restriction as OsmRestriction, SanitizeOptions};
+pub use osm_graph::OsmGraph;
+pub use preprocess::{
+  artifact_path, load_artifact, load_artifact_with_checksum, preprocess, PreprocessOptions, Stage, StageOutcome,
+};
+pub use regions::RegionBoundaries;
+pub use stats::{road_class, GraphStats, LengthBucket};
+pub use graph_serde::{companion_checksum, graph_checksum, load_companion, load_graph, save_companion, save_graph};
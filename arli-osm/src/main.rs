@@ -2,27 +2,196 @@ extern crate arli;
 
 mod graph_builder;
 mod graph_serde;
+mod junctions;
 mod osm4routing;
+mod osm_graph;
+mod preprocess;
+mod regions;
+mod stats;
+mod street_names;
 
-use clap::{value_t_or_exit, App, Arg};
-use graph_builder::import_osm_pbf;
-use graph_serde::save_graph;
+use arli::graph::GraphData;
+use arli::graph_impl::Idx;
+use arli::progress::{CancellationToken, ProgressSink};
+use clap::{value_t_or_exit, App, AppSettings, Arg, ArgMatches, SubCommand};
+use graph_builder::{import_osm_pbf, import_osm_pbf_with, small_segment, BuildOptions, SmallSegment};
+use osm4routing::{ImportStats, SanitizeOptions};
+use osm_graph::OsmGraph;
+use preprocess::{preprocess, PreprocessOptions, Stage};
+use regions::RegionBoundaries;
+use graph_serde::{load_graph, save_graph};
+use stats::GraphStats;
+use std::path::PathBuf;
 use std::time::Instant;
 
+// Renders the progress of the current stage on a single console line
+struct ConsoleProgress {
+    stage: Option<(String, Option<usize>, Instant)>,
+}
+
+impl ConsoleProgress {
+    fn finish_stage(&mut self) {
+        if let Some((name, _, timer)) = self.stage.take() {
+            eprintln!("\r{} done in {:.2}s", name, timer.elapsed().as_secs_f32());
+        }
+    }
+}
+
+impl ProgressSink for ConsoleProgress {
+    fn stage(&mut self, name: &str, total: Option<usize>) {
+        self.finish_stage();
+        self.stage = Some((name.to_string(), total, Instant::now()));
+    }
+
+    fn progress(&mut self, done: usize) {
+        match &self.stage {
+            Some((name, Some(total), _)) if *total > 0 => eprint!("\r{}: {}%", name, done * 100 / total),
+            Some((name, _, _)) => eprint!("\r{}: {}", name, done),
+            None => {}
+        }
+    }
+}
+
 fn main() {
     let matches = App::new("arli-osm")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("pbf").required(true))
         .arg(Arg::with_name("out").required(true))
+        .arg(
+            Arg::with_name("junctions")
+                .long("junctions")
+                .help("Stores the OSM nodes at the ends of each segment"),
+        )
+        .arg(
+            Arg::with_name("regions")
+                .long("regions")
+                .takes_value(true)
+                .help("GeoJSON file with the named polygons of the regions to locate the segments in"),
+        )
+        .arg(
+            Arg::with_name("node-store")
+                .long("node-store")
+                .takes_value(true)
+                .help("Directory to keep the OSM nodes in instead of the memory, for imports as large as the planet"),
+        )
+        .arg(
+            Arg::with_name("split-self-intersections")
+                .long("split-self-intersections")
+                .help("Splits the segments crossing themselves, so the geometry of every segment is simple"),
+        )
+        .arg(
+            Arg::with_name("small")
+                .long("small")
+                .help("Quantizes the length and the speed limit of the segments for a smaller graph, e.g. for mobile"),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Reports the distributions of the segment attributes of a built graph")
+                .arg(Arg::with_name("graph").required(true))
+                .arg(Arg::with_name("small").long("small").help("The graph is built with --small")),
+        )
+        .subcommand(
+            SubCommand::with_name("preprocess")
+                .about("Writes the artifacts of the query speed-ups next to a built graph")
+                .arg(Arg::with_name("graph").required(true))
+                .arg(
+                    Arg::with_name("stages")
+                        .long("stages")
+                        .takes_value(true)
+                        .help("Comma separated stages to run: partition, crp or cch, all of them if not set"),
+                )
+                .arg(
+                    Arg::with_name("max-cell-size")
+                        .long("max-cell-size")
+                        .takes_value(true)
+                        .help("Largest number of segments in a cell of the partition, 256 if not set"),
+                )
+                .arg(Arg::with_name("force").long("force").help("Runs the stages with up to date artifacts too"))
+                .arg(Arg::with_name("small").long("small").help("The graph is built with --small")),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("stats") {
+        print_stats(matches);
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("preprocess") {
+        run_preprocess(matches);
+        return;
+    }
+
     let pbf_path = value_t_or_exit!(matches, "pbf", String);
 
     let out_graph = value_t_or_exit!(matches, "out", String);
 
     let load_timer = Instant::now();
 
-    let graph = import_osm_pbf(&pbf_path).unwrap();
+    let options = BuildOptions {
+        junctions: matches.is_present("junctions"),
+        regions: matches
+            .value_of("regions")
+            .map(|path| RegionBoundaries::from_file(path).unwrap()),
+        node_store: matches.value_of("node-store").map(PathBuf::from),
+        sanitize: SanitizeOptions {
+            split_self_intersections: matches.is_present("split-self-intersections"),
+        },
+    };
+    let mut progress = ConsoleProgress { stage: None };
+    let token = CancellationToken::new();
+    if matches.is_present("small") {
+        let (graph, stats) = import_osm_pbf_with(&pbf_path, &options, small_segment, &mut progress, &token).unwrap();
+        progress.finish_stage();
+        report(&graph, &stats, load_timer);
+        save_graph(&graph, &out_graph);
+    } else {
+        let (graph, stats) = import_osm_pbf(&pbf_path, &options, &mut progress, &token).unwrap();
+        progress.finish_stage();
+        report(&graph, &stats, load_timer);
+        save_graph(&graph, &out_graph);
+    }
+}
+
+fn print_stats(matches: &ArgMatches) {
+    let path = value_t_or_exit!(matches, "graph", String);
+    let stats = if matches.is_present("small") {
+        let graph = load_graph::<SmallSegment>(&path).unwrap();
+        let mut stats = GraphStats::new();
+        for id in 0..graph.number_of_nodes() as Idx {
+            stats.add(&graph.data(id).unpack());
+        }
+        stats
+    } else {
+        GraphStats::of(&load_graph(&path).unwrap())
+    };
+    print!("{}", stats);
+}
 
+fn run_preprocess(matches: &ArgMatches) {
+    let path = value_t_or_exit!(matches, "graph", String);
+    let mut options = PreprocessOptions {
+        force: matches.is_present("force"),
+        ..PreprocessOptions::default()
+    };
+    if let Some(stages) = matches.value_of("stages") {
+        options.stages = stages.split(',').map(|stage| stage.trim().parse::<Stage>().unwrap()).collect();
+    }
+    if matches.is_present("max-cell-size") {
+        options.max_cell_size = value_t_or_exit!(matches, "max-cell-size", usize);
+    }
+    let timer = Instant::now();
+    let outcomes = if matches.is_present("small") {
+        preprocess(&load_graph::<SmallSegment>(&path).unwrap(), path.as_ref(), &options)
+    } else {
+        preprocess(&load_graph::<graph_builder::Segment>(&path).unwrap(), path.as_ref(), &options)
+    };
+    for (stage, outcome) in outcomes.unwrap() {
+        println!("{}: {:?}", stage.name(), outcome);
+    }
+    println!("Preprocessed in {:.2} seconds", timer.elapsed().as_secs_f32());
+}
+
+fn report<S>(graph: &OsmGraph<S>, stats: &ImportStats, load_timer: Instant) {
+    println!("{}", stats);
     println!(
         "Loaded graph with {} nodes and {} edges in {:.2} seconds",
         graph.number_of_nodes(),
@@ -30,7 +199,5 @@ fn main() {
         load_timer.elapsed().as_secs_f32()
     );
 
-    graph.print_stats();
-
-    save_graph(&graph, &out_graph);
+    println!("{}", graph.memory_usage());
 }
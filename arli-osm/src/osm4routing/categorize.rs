@@ -35,6 +35,25 @@ const BIKE_BUSWAY: i8 = 4;
 // BIKE_TRACK is a physically separated for any other traffic
 const BIKE_TRACK: i8 = 5;
 
+// CYCLE_NETWORK_NONE the edge isn't a part of a signed cycle route
+pub const CYCLE_NETWORK_NONE: i8 = 0;
+// CYCLE_NETWORK_LOCAL http://wiki.openstreetmap.org/wiki/Key:lcn
+const CYCLE_NETWORK_LOCAL: i8 = 1;
+// CYCLE_NETWORK_REGIONAL http://wiki.openstreetmap.org/wiki/Key:rcn
+const CYCLE_NETWORK_REGIONAL: i8 = 2;
+// CYCLE_NETWORK_NATIONAL http://wiki.openstreetmap.org/wiki/Key:ncn, international routes are included as well
+const CYCLE_NETWORK_NATIONAL: i8 = 3;
+
+// Level of the cycle network from the `network` tag of a route or the network key of a way, e.g. `lcn=yes`
+fn cycle_network_level(network: &str) -> i8 {
+    match network {
+        "lcn" => CYCLE_NETWORK_LOCAL,
+        "rcn" => CYCLE_NETWORK_REGIONAL,
+        "ncn" | "icn" => CYCLE_NETWORK_NATIONAL,
+        _ => CYCLE_NETWORK_NONE,
+    }
+}
+
 // Edgeself contains what mode can use the edge in each direction
 #[derive(Clone, Copy, Default)]
 pub struct EdgeProperties {
@@ -44,6 +63,15 @@ pub struct EdgeProperties {
     pub bike_forward: i8,
     pub bike_backward: i8,
     pub speed_limit_km_h: u8,
+    pub steps: bool,
+    // Highest level of the signed cycle networks the edge belongs to
+    pub cycle_network: i8,
+    // Using the edge is charged, https://wiki.openstreetmap.org/wiki/Key:toll
+    pub toll: bool,
+    // Surface of the edge isn't paved, https://wiki.openstreetmap.org/wiki/Key:surface
+    pub unpaved: bool,
+    // Edge is lit at night, https://wiki.openstreetmap.org/wiki/Key:lit
+    pub lit: bool,
 }
 
 impl EdgeProperties {
@@ -55,6 +83,11 @@ impl EdgeProperties {
             bike_forward: UNKNOWN,
             bike_backward: UNKNOWN,
             speed_limit_km_h: 50, // TODO: default value based on road-class and region settings
+            steps: false,
+            cycle_network: CYCLE_NETWORK_NONE,
+            toll: false,
+            unpaved: false,
+            lit: false,
         }
     }
 
@@ -119,6 +152,7 @@ impl EdgeProperties {
                 "cycleway" | "path" | "footway" | "steps" | "pedestrian" => {
                     self.bike_forward = BIKE_TRACK;
                     self.foot = FOOT_ALLOWED;
+                    self.steps = val == "steps";
                 }
                 "primary" | "primary_link" => {
                     self.car_forward = CAR_PRIMARY;
@@ -196,11 +230,86 @@ impl EdgeProperties {
             "maxspeed" => {
                 self.speed_limit_km_h = Self::parse_max_speed(val).unwrap_or(self.speed_limit_km_h);
             }
+            "lcn" | "rcn" | "ncn" | "icn" if val == "yes" => {
+                self.cycle_network = self.cycle_network.max(cycle_network_level(key));
+            }
+            "toll" => self.toll = val == "yes",
+            "surface" => self.unpaved = is_unpaved_surface(val),
+            "lit" => self.lit = val != "no",
             _ => {}
         }
     }
 }
 
+// Major roads are the ones pedestrians can't cross safely without traffic lights
+pub fn is_major_road(car_class: i8) -> bool {
+    car_class >= CAR_SECONDARY
+}
+
+// Highways are the roads built for fast motor traffic
+pub fn is_highway(car_class: i8) -> bool {
+    car_class >= CAR_TRUNK
+}
+
+// Surfaces listed as unpaved in https://wiki.openstreetmap.org/wiki/Key:surface
+fn is_unpaved_surface(surface: &str) -> bool {
+    matches!(
+        surface,
+        "unpaved" | "compacted" | "fine_gravel" | "gravel" | "pebblestone" | "rock" | "ground" | "dirt" | "earth"
+            | "grass" | "mud" | "sand" | "woodchips"
+    )
+}
+
+// NodeProperties contains what a pedestrian needs to know about a node
+#[derive(Clone, Copy, Default)]
+pub struct NodeProperties {
+    pub crossing: bool,
+    pub traffic_signals: bool,
+}
+
+impl NodeProperties {
+    pub fn update(&mut self, key: &str, val: &str) {
+        match (key, val) {
+            ("highway", "crossing") => self.crossing = true,
+            ("highway", "traffic_signals") | ("crossing", "traffic_signals") => {
+                self.traffic_signals = true
+            }
+            _ => {}
+        }
+    }
+
+    // Unsignalized crossing means that pedestrians have to wait for a gap in the traffic
+    pub fn unsignalized_crossing(self) -> bool {
+        self.crossing && !self.traffic_signals
+    }
+}
+
+// CycleRoute contains what is needed to know about a relation to flag its member ways
+#[derive(Clone, Copy, Default)]
+pub struct CycleRoute {
+    pub bicycle: bool,
+    pub network: i8,
+}
+
+impl CycleRoute {
+    pub fn update(&mut self, key: &str, val: &str) {
+        match key {
+            "route" => self.bicycle = val == "bicycle",
+            "network" => self.network = cycle_network_level(val),
+            _ => {}
+        }
+    }
+
+    // Level of the signed cycle network, CYCLE_NETWORK_NONE if the relation isn't a cycle route
+    pub fn network_level(self) -> i8 {
+        if self.bicycle {
+            self.network
+        } else {
+            CYCLE_NETWORK_NONE
+        }
+    }
+}
+
 #[test]
 fn test_accessible() {
     let mut p = EdgeProperties::default();
@@ -251,6 +360,11 @@ fn test_update() {
     p.update("highway", "cycleway");
     assert_eq!(BIKE_TRACK, p.bike_forward);
     assert_eq!(FOOT_ALLOWED, p.foot);
+    assert!(!p.steps);
+
+    p.update("highway", "steps");
+    assert_eq!(FOOT_ALLOWED, p.foot);
+    assert!(p.steps);
 
     p.update("foot", "designated");
     assert_eq!(FOOT_ALLOWED, p.foot);
@@ -313,3 +427,81 @@ fn test_speed_limit_re() {
     assert_eq!(EdgeProperties::parse_max_speed("50 mph"), Some(80));
     assert_eq!(EdgeProperties::parse_max_speed("none"), None);
 }
+
+#[test]
+fn test_crossing() {
+    let mut n = NodeProperties::default();
+    assert!(!n.unsignalized_crossing());
+
+    n.update("highway", "crossing");
+    assert!(n.unsignalized_crossing());
+
+    n.update("crossing", "traffic_signals");
+    assert!(!n.unsignalized_crossing());
+
+    assert!(is_major_road(CAR_PRIMARY));
+    assert!(!is_major_road(CAR_RESIDENTIAL));
+    assert!(!is_major_road(CAR_FORBIDDEN));
+}
+
+#[test]
+fn test_cycle_network() {
+    let mut p = EdgeProperties::default();
+    assert_eq!(CYCLE_NETWORK_NONE, p.cycle_network);
+
+    p.update("rcn", "yes");
+    assert_eq!(CYCLE_NETWORK_REGIONAL, p.cycle_network);
+
+    p.update("lcn", "yes");
+    assert_eq!(CYCLE_NETWORK_REGIONAL, p.cycle_network);
+
+    p.update("ncn", "no");
+    assert_eq!(CYCLE_NETWORK_REGIONAL, p.cycle_network);
+
+    let mut r = CycleRoute::default();
+    r.update("network", "lcn");
+    assert_eq!(CYCLE_NETWORK_NONE, r.network_level());
+
+    r.update("route", "bicycle");
+    assert_eq!(CYCLE_NETWORK_LOCAL, r.network_level());
+
+    r.update("network", "icn");
+    assert_eq!(CYCLE_NETWORK_NATIONAL, r.network_level());
+
+    r.update("route", "hiking");
+    assert_eq!(CYCLE_NETWORK_NONE, r.network_level());
+}
+
+#[test]
+fn test_toll() {
+    let mut p = EdgeProperties::default();
+    assert!(!p.toll);
+
+    p.update("toll", "yes");
+    assert!(p.toll);
+
+    p.update("toll", "no");
+    assert!(!p.toll);
+}
+
+#[test]
+fn test_surface_and_lit() {
+    let mut p = EdgeProperties::default();
+    assert!(!p.unpaved);
+    assert!(!p.lit);
+
+    p.update("surface", "gravel");
+    assert!(p.unpaved);
+
+    p.update("surface", "asphalt");
+    assert!(!p.unpaved);
+
+    p.update("lit", "yes");
+    assert!(p.lit);
+
+    p.update("lit", "no");
+    assert!(!p.lit);
+
+    assert!(is_highway(CAR_MOTORWAY));
+    assert!(!is_highway(CAR_PRIMARY));
+}
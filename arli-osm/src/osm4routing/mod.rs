@@ -1,7 +1,10 @@
 pub mod categorize;
 pub mod models;
+pub mod node_store;
 pub mod reader;
+pub mod sanitize;
 
 pub use categorize::*;
 pub use models::*;
-pub use reader::*;
\ No newline at end of file
+pub use reader::*;
+pub use sanitize::SanitizeOptions;
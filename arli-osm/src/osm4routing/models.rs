@@ -1,5 +1,5 @@
 use osmpbfreader::objects::{NodeId, WayId};
-use super::categorize::EdgeProperties;
+use super::categorize::{EdgeProperties, NodeProperties};
 use geo::{Coordinate, LineString, haversine_length::*};
 
 
@@ -13,6 +13,9 @@ pub struct Node {
     pub id: NodeId,
     pub coord: Coord,
     pub uses: i16,
+    // Highest car class of the ways using this node
+    pub max_car_class: i8,
+    pub properties: NodeProperties,
 }
 
 impl Default for Node {
@@ -21,6 +24,8 @@ impl Default for Node {
             id: NodeId(0),
             coord: Coord{x:0.0, y:0.0},
             uses: Default::default(),
+            max_car_class: Default::default(),
+            properties: Default::default(),
         }
     }
 }
@@ -39,9 +44,22 @@ pub struct Edge {
     pub target: NodeId,
     pub geometry: Geometry,
     pub properties: EdgeProperties,
+    // Edge ends at a node where pedestrians cross a major road without traffic lights
+    pub unsignalized_crossing: bool,
+    // Street name, or the road number if the way has no name
+    pub name: Option<String>,
 }
 
 impl Edge {
     // Length in meters of the edge
     pub fn length(&self) -> f32 { self.geometry.haversine_length()}
 }
+
+// Turn restriction from a way to another at a node, restrictions via ways aren't read
+pub struct Restriction {
+    pub from: WayId,
+    pub via: NodeId,
+    pub to: WayId,
+    // `only_*` restrictions forbid all turns but the one to the `to` way, `no_*` restrictions forbid that turn
+    pub only: bool,
+}
@@ -0,0 +1,370 @@
+use super::models::Node;
+use osmpbfreader::objects::NodeId;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Node record on disk: id, longitude, latitude, uses, highest car class and the node properties
+const RECORD_SIZE: usize = 20;
+// Node use spilled to a run: id and the car class of the way
+const USE_SIZE: usize = 9;
+// Records are read in blocks, the first id of every block is kept in memory
+const BLOCK_RECORDS: usize = 256;
+// Node uses sorted in memory before they are spilled to a run, 144 MB
+const RUN_USES: usize = 1 << 24;
+
+// NodeStore keeps the nodes used by the routable ways, keyed by the OSM node id
+pub trait NodeStore: Sync {
+    // A way of the car class goes through the node
+    fn add_use(&mut self, id: NodeId, car_class: i8) -> io::Result<()>;
+    // All ways are read, only the nodes they use are stored from now on
+    fn finish_ways(&mut self) -> io::Result<()>;
+    // Updates the node if a way uses it
+    fn update<F: FnOnce(&mut Node)>(&mut self, id: NodeId, f: F) -> io::Result<()>;
+    // All nodes are read
+    fn finish_nodes(&mut self) -> io::Result<()>;
+    // Node used by a way, safe to call from several threads
+    fn get(&self, id: NodeId) -> io::Result<Node>;
+}
+
+fn unused_node(id: NodeId) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Node {} isn't used by any way", id.0))
+}
+
+// MemoryNodes keeps the nodes in a hash map, it's the fastest store as long as the nodes fit in memory
+#[derive(Default)]
+pub struct MemoryNodes {
+    nodes: HashMap<NodeId, Node>,
+}
+
+impl NodeStore for MemoryNodes {
+    fn add_use(&mut self, id: NodeId, car_class: i8) -> io::Result<()> {
+        let node = self.nodes.entry(id).or_default();
+        node.uses += 1;
+        node.max_car_class = node.max_car_class.max(car_class);
+        Ok(())
+    }
+
+    fn finish_ways(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn update<F: FnOnce(&mut Node)>(&mut self, id: NodeId, f: F) -> io::Result<()> {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            f(node);
+        }
+        Ok(())
+    }
+
+    fn finish_nodes(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn get(&self, id: NodeId) -> io::Result<Node> {
+        self.nodes.get(&id).copied().ok_or_else(|| unused_node(id))
+    }
+}
+
+fn encode(id: i64, node: &Node, record: &mut [u8]) {
+    record[0..8].copy_from_slice(&id.to_le_bytes());
+    record[8..12].copy_from_slice(&node.coord.x.to_le_bytes());
+    record[12..16].copy_from_slice(&node.coord.y.to_le_bytes());
+    record[16..18].copy_from_slice(&node.uses.to_le_bytes());
+    record[18] = node.max_car_class as u8;
+    record[19] = node.properties.crossing as u8 | (node.properties.traffic_signals as u8) << 1;
+}
+
+fn record_id(record: &[u8]) -> i64 {
+    i64::from_le_bytes(record[0..8].try_into().unwrap())
+}
+
+fn decode(record: &[u8]) -> Node {
+    let mut node = Node {
+        id: NodeId(record_id(record)),
+        uses: i16::from_le_bytes(record[16..18].try_into().unwrap()),
+        max_car_class: record[18] as i8,
+        ..Node::default()
+    };
+    node.set_coord(
+        f32::from_le_bytes(record[8..12].try_into().unwrap()),
+        f32::from_le_bytes(record[12..16].try_into().unwrap()),
+    );
+    node.properties.crossing = record[19] & 1 != 0;
+    node.properties.traffic_signals = record[19] & 2 != 0;
+    node
+}
+
+// Position of the node record in the block, the records are searched in place
+fn find_record(block: &[u8], id: i64) -> Option<usize> {
+    let (mut low, mut high) = (0, block.len() / RECORD_SIZE);
+    while low < high {
+        let middle = (low + high) / 2;
+        let position = middle * RECORD_SIZE;
+        match record_id(&block[position..]).cmp(&id) {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => return Some(position),
+        }
+    }
+    None
+}
+
+// Positional read, it doesn't move the file cursor, so the threads reading the nodes don't need a lock
+#[cfg(unix)]
+fn read_exact_at(file: &File, data: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut data: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !data.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, data, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                data = &mut data[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Next node use of a sorted run
+fn read_use(run: &mut BufReader<File>) -> io::Result<Option<(i64, i8)>> {
+    let mut entry = [0u8; USE_SIZE];
+    match run.read_exact(&mut entry) {
+        Ok(()) => Ok(Some((i64::from_le_bytes(entry[0..8].try_into().unwrap()), entry[8] as i8))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// DiskNodes keeps the nodes in a file of records sorted by the node id, for the imports which don't fit in memory,
+// e.g. the planet.
+//
+// The node uses of the ways are sorted in memory in runs spilled to disk, the runs are merged into the records once
+// all ways are read. PBF files have the nodes sorted by id, so they are updated block after block.
+pub struct DiskNodes {
+    dir: PathBuf,
+    run_uses: usize,
+    uses: Vec<(i64, i8)>,
+    runs: Vec<PathBuf>,
+    records: File,
+    records_len: usize,
+    // First node id of every block
+    block_ids: Vec<i64>,
+    // Block being updated, it's written back once a node of another block is updated
+    updated: Option<(usize, Vec<u8>)>,
+}
+
+impl DiskNodes {
+    // Creates the store in a new subdirectory of the directory, it's removed with the store
+    pub fn create(dir: &Path) -> io::Result<DiskNodes> {
+        DiskNodes::with_run_uses(dir, RUN_USES)
+    }
+
+    fn with_run_uses(dir: &Path, run_uses: usize) -> io::Result<DiskNodes> {
+        let dir = dir.join(format!("arli-nodes-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let records = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.join("nodes"))?;
+        Ok(DiskNodes {
+            dir,
+            run_uses,
+            uses: Vec::new(),
+            runs: Vec::new(),
+            records,
+            records_len: 0,
+            block_ids: Vec::new(),
+            updated: None,
+        })
+    }
+
+    fn spill_run(&mut self) -> io::Result<()> {
+        self.uses.sort_unstable_by_key(|(id, _)| *id);
+        let path = self.dir.join(format!("run-{}", self.runs.len()));
+        let mut run = BufWriter::new(File::create(&path)?);
+        for (id, car_class) in self.uses.drain(..) {
+            run.write_all(&id.to_le_bytes())?;
+            run.write_all(&[car_class as u8])?;
+        }
+        run.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    fn block(&self, id: i64) -> Option<usize> {
+        match self.block_ids.binary_search(&id) {
+            Ok(block) => Some(block),
+            Err(0) => None,
+            Err(next) => Some(next - 1),
+        }
+    }
+
+    // Reads the records of the block into the start of the buffer, returns their length in bytes
+    fn read_block_into(&self, block: usize, data: &mut [u8]) -> io::Result<usize> {
+        let first = block * BLOCK_RECORDS;
+        let len = BLOCK_RECORDS.min(self.records_len - first) * RECORD_SIZE;
+        read_exact_at(&self.records, &mut data[..len], (first * RECORD_SIZE) as u64)?;
+        Ok(len)
+    }
+
+    fn read_block(&self, block: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; BLOCK_RECORDS * RECORD_SIZE];
+        let len = self.read_block_into(block, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    fn write_updated_block(&mut self) -> io::Result<()> {
+        if let Some((block, data)) = self.updated.take() {
+            self.records.seek(SeekFrom::Start((block * BLOCK_RECORDS * RECORD_SIZE) as u64))?;
+            self.records.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+impl NodeStore for DiskNodes {
+    fn add_use(&mut self, id: NodeId, car_class: i8) -> io::Result<()> {
+        self.uses.push((id.0, car_class));
+        if self.uses.len() >= self.run_uses {
+            self.spill_run()?;
+        }
+        Ok(())
+    }
+
+    fn finish_ways(&mut self) -> io::Result<()> {
+        self.spill_run()?;
+        let mut runs = Vec::new();
+        let mut heads = BinaryHeap::new();
+        for (index, path) in self.runs.iter().enumerate() {
+            let mut run = BufReader::new(File::open(path)?);
+            if let Some((id, car_class)) = read_use(&mut run)? {
+                heads.push(Reverse((id, car_class, index)));
+            }
+            runs.push(run);
+        }
+
+        // Uses of the same node are next to each other in the merged runs
+        let mut records = BufWriter::new(&self.records);
+        let mut current: Option<(i64, Node)> = None;
+        let mut record = [0u8; RECORD_SIZE];
+        while let Some(Reverse((id, car_class, index))) = heads.pop() {
+            if let Some((id, car_class)) = read_use(&mut runs[index])? {
+                heads.push(Reverse((id, car_class, index)));
+            }
+            match &mut current {
+                Some((current_id, node)) if *current_id == id => {
+                    node.uses += 1;
+                    node.max_car_class = node.max_car_class.max(car_class);
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some((current_id, node)) = current.take() {
+                encode(current_id, &node, &mut record);
+                records.write_all(&record)?;
+            }
+            if self.records_len == self.block_ids.len() * BLOCK_RECORDS {
+                self.block_ids.push(id);
+            }
+            self.records_len += 1;
+            let node = Node {
+                uses: 1,
+                max_car_class: car_class,
+                ..Node::default()
+            };
+            current = Some((id, node));
+        }
+        if let Some((current_id, node)) = current {
+            encode(current_id, &node, &mut record);
+            records.write_all(&record)?;
+        }
+        records.flush()?;
+        drop(records);
+
+        for path in self.runs.drain(..) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn update<F: FnOnce(&mut Node)>(&mut self, id: NodeId, f: F) -> io::Result<()> {
+        let block = match self.block(id.0) {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+        match &self.updated {
+            Some((updated, _)) if *updated == block => {}
+            _ => {
+                self.write_updated_block()?;
+                self.updated = Some((block, self.read_block(block)?));
+            }
+        }
+        if let Some((_, data)) = &mut self.updated {
+            if let Some(position) = find_record(data, id.0) {
+                let record = &mut data[position..position + RECORD_SIZE];
+                let mut node = decode(record);
+                f(&mut node);
+                encode(id.0, &node, record);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_nodes(&mut self) -> io::Result<()> {
+        self.write_updated_block()
+    }
+
+    fn get(&self, id: NodeId) -> io::Result<Node> {
+        let block = self.block(id.0).ok_or_else(|| unused_node(id))?;
+        let mut data = [0u8; BLOCK_RECORDS * RECORD_SIZE];
+        let len = self.read_block_into(block, &mut data)?;
+        let position = find_record(&data[..len], id.0).ok_or_else(|| unused_node(id))?;
+        Ok(decode(&data[position..position + RECORD_SIZE]))
+    }
+}
+
+impl Drop for DiskNodes {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_disk_nodes() {
+    let dir = std::env::temp_dir().join("arli-test-disk-nodes");
+    let mut nodes = DiskNodes::with_run_uses(&dir, 4).unwrap();
+    // More nodes than a block, with the uses spread over several runs
+    for id in (0..600).rev() {
+        nodes.add_use(NodeId(id * 2), 1).unwrap();
+    }
+    nodes.add_use(NodeId(10), 3).unwrap();
+    nodes.finish_ways().unwrap();
+
+    for id in 0..1200 {
+        nodes.update(NodeId(id), |node| node.set_coord(id as f32, 1.0)).unwrap();
+    }
+    nodes.update(NodeId(500), |node| node.properties.crossing = true).unwrap();
+    nodes.finish_nodes().unwrap();
+
+    let node = nodes.get(NodeId(10)).unwrap();
+    assert_eq!((node.uses, node.max_car_class), (2, 3));
+    assert_eq!(node.coord.x, 10.0);
+    let node = nodes.get(NodeId(500)).unwrap();
+    assert_eq!((node.uses, node.coord.x, node.properties.crossing), (1, 500.0, true));
+    assert!(!nodes.get(NodeId(502)).unwrap().properties.crossing);
+    assert_eq!(nodes.get(NodeId(1198)).unwrap().coord.x, 1198.0);
+    assert!(nodes.get(NodeId(1199)).is_err());
+
+    drop(nodes);
+    assert!(fs::read_dir(&dir).unwrap().next().is_none());
+}
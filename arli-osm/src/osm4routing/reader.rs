@@ -1,62 +1,141 @@
 use super::categorize::*;
 use super::models::*;
-use osmpbfreader::objects::{NodeId, WayId};
+use super::node_store::*;
+use super::sanitize::*;
+use arli::progress::{CancellationToken, ProgressSink, StageProgress};
+use osmpbfreader::objects::{NodeId, OsmId, WayId};
+use rayon::prelude::*;
 use std::collections::HashMap;
-use std::io::Read;
-use std::time::Instant;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+
+// What the import changed or left out of the OSM data
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ImportStats {
+    pub repairs: SanitizeStats,
+    // Turn restrictions via a way, they are skipped since the graph only forbids transitions between two segments
+    pub via_way_restrictions: usize,
+}
+
+impl fmt::Display for ImportStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, skipped {} turn restrictions via a way", self.repairs, self.via_way_restrictions)
+    }
+}
 
 // Way as represented in OpenStreetMap
 struct Way {
     id: WayId,
     nodes: Vec<NodeId>,
     properties: EdgeProperties,
+    name: Option<String>,
+}
+
+impl Way {
+    fn edge(&self, source: NodeId, target: NodeId, points: Vec<Coord>, unsignalized_crossing: bool) -> Edge {
+        Edge {
+            id: self.id,
+            source,
+            target,
+            geometry: points.into(),
+            properties: self.properties,
+            unsignalized_crossing,
+            name: self.name.clone(),
+        }
+    }
 }
 
-struct Reader {
-    nodes: HashMap<NodeId, Node>,
+struct Reader<S> {
+    nodes: S,
     ways: Vec<Way>,
+    // Highest level of the cycle route relations each way is a member of
+    cycle_networks: HashMap<WayId, i8>,
+    restrictions: Vec<Restriction>,
+    // Restrictions via a way, which are skipped
+    via_way_restrictions: usize,
 }
 
-impl Reader {
-    fn new() -> Reader {
+impl<S: NodeStore> Reader<S> {
+    fn new(nodes: S) -> Reader<S> {
         Reader {
-            nodes: HashMap::new(),
+            nodes,
             ways: Vec::new(),
+            cycle_networks: HashMap::new(),
+            restrictions: Vec::new(),
+            via_way_restrictions: 0,
         }
     }
 
-    fn split_way(&self, way: &Way) -> Vec<Edge> {
+    // Splits the way into edges at the junctions, repairing the geometry on the way
+    fn split_way(&self, way: &Way, options: &SanitizeOptions) -> io::Result<(Vec<Edge>, SanitizeStats)> {
+        let mut stats = SanitizeStats::default();
         let mut result = Vec::new();
 
+        let vertices: Vec<_> = way
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, &node_id)| {
+                // The stores don't necessarily keep the id of the node
+                let node = Node {
+                    id: node_id,
+                    ..self.nodes.get(node_id)?
+                };
+                Ok(Vertex {
+                    node,
+                    junction: node.uses > 1 || i == 0 || i == way.nodes.len() - 1,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let vertices = remove_duplicates_and_spikes(&vertices, &mut stats);
+
+        // Pedestrians walking along a major road don't cross it
+        let minor_way = !is_major_road(way.properties.car_forward);
+
         let mut source = NodeId(0);
         let mut points = Vec::new();
-        for (i, &node_id) in way.nodes.iter().enumerate() {
-            let node = self.nodes[&node_id];
+        let mut unsignalized_crossing = false;
+        for (i, vertex) in vertices.iter().enumerate() {
+            let node = &vertex.node;
             if i == 0 {
-                source = node_id;
+                source = node.id;
                 points.push(node.coord);
             } else {
+                if options.split_self_intersections && crosses_itself(&points, &node.coord) {
+                    // The previous node ends the edge, so the edges of the way still meet at an OSM node
+                    let previous = &vertices[i - 1].node;
+                    result.push(way.edge(source, previous.id, points, unsignalized_crossing));
+                    stats.splits += 1;
+
+                    source = previous.id;
+                    points = vec![previous.coord];
+                    unsignalized_crossing = false;
+                }
                 points.push(node.coord);
+                unsignalized_crossing |= minor_way
+                    && node.properties.unsignalized_crossing()
+                    && is_major_road(node.max_car_class);
 
-                if node.uses > 1 || i == way.nodes.len() - 1 {
-                    result.push(Edge {
-                        id: way.id,
-                        source,
-                        target: node_id,
-                        geometry: points.into(),
-                        properties: way.properties,
-                    });
+                if vertex.junction {
+                    result.push(way.edge(source, node.id, points, unsignalized_crossing));
 
-                    source = node_id;
+                    source = node.id;
                     points = vec![node.coord];
+                    unsignalized_crossing = false;
                 }
             }
         }
-        result
+        Ok((result, stats))
     }
 
-    fn read_ways<R: Read>(&mut self, pbf: &mut osmpbfreader::OsmPbfReader<R>) {
+    fn read_ways<R: Read>(
+        &mut self,
+        pbf: &mut osmpbfreader::OsmPbfReader<R>,
+        mut stage: StageProgress,
+    ) -> Result<(), String> {
         for obj in pbf.par_iter() {
+            stage.step().map_err(|e| e.to_string())?;
             if let Ok(osmpbfreader::OsmObj::Way(way)) = obj {
                 let mut properties = EdgeProperties::default();
                 for (key, val) in way.tags.iter() {
@@ -64,56 +143,184 @@ impl Reader {
                 }
                 properties.normalize();
                 if properties.accessible() {
+                    let car_class = properties.car_forward.max(properties.car_backward);
                     for node in &way.nodes {
-                        self.nodes.entry(*node).or_insert(Node::default()).uses += 1;
+                        self.nodes.add_use(*node, car_class).map_err(|e| e.to_string())?;
                     }
+                    // Roads without a name, like motorways, are still known by their number
+                    let name = way.tags.get("name").or_else(|| way.tags.get("ref"));
                     self.ways.push(Way {
                         id: way.id,
+                        name: name.map(|name| name.to_string()),
                         nodes: way.nodes,
                         properties,
                     });
                 }
+            } else if let Ok(osmpbfreader::OsmObj::Relation(relation)) = obj {
+                self.read_cycle_route(&relation);
+                self.read_restriction(&relation);
+            }
+        }
+        self.apply_cycle_networks();
+        self.nodes.finish_ways().map_err(|e| e.to_string())?;
+        stage.finish();
+        Ok(())
+    }
+
+    fn read_cycle_route(&mut self, relation: &osmpbfreader::Relation) {
+        let mut route = CycleRoute::default();
+        for (key, val) in relation.tags.iter() {
+            route.update(key.as_str(), val.as_str());
+        }
+        let level = route.network_level();
+        if level == CYCLE_NETWORK_NONE {
+            return;
+        }
+        for member in &relation.refs {
+            if let OsmId::Way(way_id) = member.member {
+                let way_level = self.cycle_networks.entry(way_id).or_insert(level);
+                *way_level = (*way_level).max(level);
+            }
+        }
+    }
+
+    // Only the restrictions via a node are read, a restriction via a way forbids a sequence of segments the graph
+    // can't represent, they are counted in the import stats. Restrictions with an exception for cars are skipped, they
+    // are only enforced for driving.
+    fn read_restriction(&mut self, relation: &osmpbfreader::Relation) {
+        if !relation.tags.contains("type", "restriction") {
+            return;
+        }
+        let only = match relation.tags.get("restriction").map(|kind| kind.as_str()) {
+            Some(kind) if kind.starts_with("only_") => true,
+            Some(kind) if kind.starts_with("no_") => false,
+            _ => return,
+        };
+        if matches!(relation.tags.get("except"), Some(except) if except.contains("motorcar")) {
+            return;
+        }
+        let mut from = Vec::new();
+        let mut via = None;
+        let mut to = Vec::new();
+        for member in &relation.refs {
+            match (member.role.as_str(), member.member) {
+                ("from", OsmId::Way(way_id)) => from.push(way_id),
+                ("to", OsmId::Way(way_id)) => to.push(way_id),
+                ("via", OsmId::Node(node_id)) if via.is_none() => via = Some(node_id),
+                ("via", OsmId::Way(_)) => {
+                    self.via_way_restrictions += 1;
+                    return;
+                }
+                ("via", _) => return,
+                _ => {}
+            }
+        }
+        if let Some(via) = via {
+            for from in &from {
+                for to in &to {
+                    self.restrictions.push(Restriction { from: *from, via, to: *to, only });
+                }
             }
         }
     }
 
-    fn read_nodes<R: Read>(&mut self, pbf: &mut osmpbfreader::OsmPbfReader<R>) {
+    // Relations are stored after the ways in the file, so member ways are updated once all of them are read
+    fn apply_cycle_networks(&mut self) {
+        for way in &mut self.ways {
+            if let Some(level) = self.cycle_networks.get(&way.id) {
+                way.properties.cycle_network = way.properties.cycle_network.max(*level);
+            }
+        }
+    }
+
+    fn read_nodes<R: Read>(
+        &mut self,
+        pbf: &mut osmpbfreader::OsmPbfReader<R>,
+        mut stage: StageProgress,
+    ) -> Result<(), String> {
         for obj in pbf.par_iter() {
+            stage.step().map_err(|e| e.to_string())?;
             if let Ok(osmpbfreader::OsmObj::Node(node)) = obj {
-                self.nodes.entry(node.id).and_modify(|mut_node| {
-                    mut_node.set_coord(node.lon() as f32, node.lat() as f32)
-                });
+                self.nodes
+                    .update(node.id, |mut_node| {
+                        mut_node.set_coord(node.lon() as f32, node.lat() as f32);
+                        for (key, val) in node.tags.iter() {
+                            mut_node.properties.update(key.as_str(), val.as_str());
+                        }
+                    })
+                    .map_err(|e| e.to_string())?;
             }
         }
+        self.nodes.finish_nodes().map_err(|e| e.to_string())?;
+        stage.finish();
+        Ok(())
     }
 
-    fn edges(&self) -> Vec<Edge> {
-        self.ways
-            .iter()
-            .flat_map(|way| self.split_way(way))
-            .collect()
+    // Ways are split in parallel, the edges keep the order of the ways
+    fn edges(&self, options: &SanitizeOptions) -> io::Result<(Vec<Edge>, SanitizeStats)> {
+        let edges_per_way: Vec<(Vec<Edge>, SanitizeStats)> =
+            self.ways.par_iter().map(|way| self.split_way(way, options)).collect::<io::Result<_>>()?;
+        let mut edges = Vec::with_capacity(edges_per_way.iter().map(|(way_edges, _)| way_edges.len()).sum());
+        let mut stats = SanitizeStats::default();
+        for (way_edges, way_stats) in edges_per_way {
+            edges.extend(way_edges);
+            stats.add(&way_stats);
+        }
+        Ok((edges, stats))
     }
 }
 
-pub fn read_edges(filename: &str) -> Result<Vec<Edge>, String> {
-    let mut r = Reader::new();
+/// Reads the routable edges of the file. The progress is reported in the number of OSM objects decoded, the reading
+/// stops once the token is cancelled.
+///
+/// The nodes of the ways are kept in memory, unless a directory for the node store is given. Storing them on disk
+/// makes the import slower, but it's needed for the files whose nodes don't fit in memory, like the planet.
+///
+/// The geometry of the ways is repaired while they are split into edges, the repairs made are counted in the
+/// returned stats. The edges come with the turn restrictions via a node, see [`Restriction`]. The ones via a way
+/// aren't supported, they are only counted in the stats.
+pub fn read_edges(
+    filename: &str,
+    node_store: Option<&Path>,
+    sanitize: &SanitizeOptions,
+    progress: &mut dyn ProgressSink,
+    token: &CancellationToken,
+) -> Result<(Vec<Edge>, Vec<Restriction>, ImportStats), String> {
+    match node_store {
+        Some(dir) => {
+            let nodes = DiskNodes::create(dir).map_err(|e| format!("Can't create the node store: {}", e))?;
+            read_edges_with(filename, nodes, sanitize, progress, token)
+        }
+        None => read_edges_with(filename, MemoryNodes::default(), sanitize, progress, token),
+    }
+}
+
+fn read_edges_with<S: NodeStore>(
+    filename: &str,
+    nodes: S,
+    sanitize: &SanitizeOptions,
+    progress: &mut dyn ProgressSink,
+    token: &CancellationToken,
+) -> Result<(Vec<Edge>, Vec<Restriction>, ImportStats), String> {
+    let mut r = Reader::new(nodes);
     let file = std::fs::File::open(filename).map_err(|e| e.to_string())?;
     let mut pbf = osmpbfreader::OsmPbfReader::new(file);
 
-    {
-        let t = Instant::now();
-        r.read_ways(&mut pbf);
-        println!("Decoded ways {:.2}s", t.elapsed().as_secs_f32());
-    }
-    {
-        let t = Instant::now();
-        pbf.rewind().map_err(|e| e.to_string())?;
-        r.read_nodes(&mut pbf);
-        println!("Decoded nodes {:.2}s", t.elapsed().as_secs_f32());
-    }
-    let t = Instant::now();
-    let edges = r.edges();
-    println!("Split ways {:.2}s", t.elapsed().as_secs_f32());
+    let stage = StageProgress::new(progress, token, "Decoding ways", None);
+    r.read_ways(&mut pbf, stage)?;
+
+    pbf.rewind().map_err(|e| e.to_string())?;
+    let stage = StageProgress::new(progress, token, "Decoding nodes", None);
+    r.read_nodes(&mut pbf, stage)?;
+
+    progress.stage("Splitting ways", Some(r.ways.len()));
+    let (edges, repairs) = r.edges(sanitize).map_err(|e| format!("Can't read the node store: {}", e))?;
+    let stats = ImportStats {
+        repairs,
+        via_way_restrictions: r.via_way_restrictions,
+    };
+    progress.progress(r.ways.len());
+    token.check().map_err(|e| e.to_string())?;
 
-    Ok(edges)
+    Ok((edges, r.restrictions, stats))
 }
@@ -0,0 +1,186 @@
+use super::models::{Coord, Node};
+use arli::spatial::{bearing, haversine_distance};
+use std::fmt;
+
+// Vertices closer than this to the previous one are duplicates, e.g. two OSM nodes mapped at the same place
+const DUPLICATE_DISTANCE_M: f32 = 0.1;
+
+// A way turning back by more than this at a vertex goes there and back again, the vertex is a spike
+const SPIKE_TURN_DEG: f32 = 170.0;
+
+// Repairs of the way geometry made during the import, besides the duplicates and the spikes removed always
+#[derive(Clone, Copy, Default)]
+pub struct SanitizeOptions {
+    // Split the edges crossing themselves at the vertex before the crossing, so the geometry of every edge is simple
+    pub split_self_intersections: bool,
+}
+
+// Number of the geometry repairs made during the import
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct SanitizeStats {
+    pub duplicates: usize,
+    pub spikes: usize,
+    pub splits: usize,
+}
+
+impl SanitizeStats {
+    pub fn add(&mut self, other: &SanitizeStats) {
+        self.duplicates += other.duplicates;
+        self.spikes += other.spikes;
+        self.splits += other.splits;
+    }
+}
+
+impl fmt::Display for SanitizeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Removed {} duplicate points and {} spikes, split {} self-intersecting edges",
+            self.duplicates, self.spikes, self.splits
+        )
+    }
+}
+
+// Node of a way, the junctions and the ends of the way are never removed since the graph topology depends on them
+#[derive(Clone, Copy)]
+pub struct Vertex {
+    pub node: Node,
+    pub junction: bool,
+}
+
+// Removes the consecutive duplicate points and the spikes of the way
+pub fn remove_duplicates_and_spikes(vertices: &[Vertex], stats: &mut SanitizeStats) -> Vec<Vertex> {
+    let mut kept = Vec::with_capacity(vertices.len());
+    for vertex in vertices {
+        push_vertex(&mut kept, *vertex, stats);
+    }
+    kept
+}
+
+fn push_vertex(kept: &mut Vec<Vertex>, vertex: Vertex, stats: &mut SanitizeStats) {
+    if let Some(last) = kept.last_mut() {
+        // Two junctions at the same place both stay, the edge between them has no length
+        if is_duplicate(&last.node.coord, &vertex.node.coord) && !(last.junction && vertex.junction) {
+            stats.duplicates += 1;
+            if vertex.junction {
+                *last = vertex;
+            }
+            return;
+        }
+    }
+    let spike = match kept.as_slice() {
+        [.., before, last] => !last.junction && is_spike(&before.node.coord, &last.node.coord, &vertex.node.coord),
+        _ => false,
+    };
+    if spike {
+        stats.spikes += 1;
+        kept.pop();
+        // Going back to the same place leaves a duplicate, or the vertex before may be a spike now
+        push_vertex(kept, vertex, stats);
+    } else {
+        kept.push(vertex);
+    }
+}
+
+fn is_duplicate(a: &Coord, b: &Coord) -> bool {
+    haversine_distance(a, b) < DUPLICATE_DISTANCE_M
+}
+
+fn is_spike(before: &Coord, spike: &Coord, after: &Coord) -> bool {
+    let turn = (bearing(spike, after) - bearing(before, spike)).abs();
+    turn.min(360.0 - turn) > SPIKE_TURN_DEG
+}
+
+// Whether the segment from the last point of the geometry to the next one crosses the geometry. Touching the
+// geometry, e.g. at the first point of a closed way, is not a crossing.
+pub fn crosses_itself(points: &[Coord], next: &Coord) -> bool {
+    match points.split_last() {
+        // The segment ending at the last point is skipped, it always touches the new one
+        Some((last, previous)) => points
+            .windows(2)
+            .take(previous.len().saturating_sub(1))
+            .any(|segment| segments_cross(&segment[0], &segment[1], last, next)),
+        None => false,
+    }
+}
+
+fn segments_cross(a: &Coord, b: &Coord, c: &Coord, d: &Coord) -> bool {
+    orientation(a, b, c) * orientation(a, b, d) < 0.0 && orientation(c, d, a) * orientation(c, d, b) < 0.0
+}
+
+// Positive if the point is left of the line from a to b, negative if it's right of it
+fn orientation(a: &Coord, b: &Coord, point: &Coord) -> f64 {
+    let (ax, ay) = (a.x as f64, a.y as f64);
+    (b.x as f64 - ax) * (point.y as f64 - ay) - (b.y as f64 - ay) * (point.x as f64 - ax)
+}
+
+#[cfg(test)]
+fn vertex(id: i64, x: f32, y: f32, junction: bool) -> Vertex {
+    let mut node = Node {
+        id: osmpbfreader::objects::NodeId(id),
+        ..Default::default()
+    };
+    node.set_coord(x, y);
+    Vertex { node, junction }
+}
+
+#[cfg(test)]
+fn ids(vertices: &[Vertex]) -> Vec<i64> {
+    vertices.iter().map(|vertex| vertex.node.id.0).collect()
+}
+
+#[test]
+fn test_remove_duplicates() {
+    let vertices = vec![
+        vertex(1, 13.4, 52.5, true),
+        vertex(2, 13.4, 52.5, false),
+        vertex(3, 13.401, 52.5, false),
+        vertex(4, 13.402, 52.5, false),
+        vertex(5, 13.402, 52.5, true),
+        vertex(6, 13.402, 52.5, true),
+    ];
+    let mut stats = SanitizeStats::default();
+    // The junctions replace their duplicates, but stay when they are duplicates of each other
+    assert_eq!(ids(&remove_duplicates_and_spikes(&vertices, &mut stats)), vec![1, 3, 5, 6]);
+    assert_eq!(stats.duplicates, 2);
+}
+
+#[test]
+fn test_remove_spikes() {
+    let vertices = vec![
+        vertex(1, 13.4, 52.5, true),
+        vertex(2, 13.401, 52.5, false),
+        // Goes far to the north and back
+        vertex(3, 13.401, 52.51, false),
+        vertex(4, 13.401, 52.5, false),
+        vertex(5, 13.402, 52.5, true),
+    ];
+    let mut stats = SanitizeStats::default();
+    assert_eq!(ids(&remove_duplicates_and_spikes(&vertices, &mut stats)), vec![1, 2, 5]);
+    assert_eq!((stats.spikes, stats.duplicates), (1, 1));
+
+    // A junction is kept even if the way turns back there
+    let mut stats = SanitizeStats::default();
+    let dead_end = vec![
+        vertex(1, 13.4, 52.5, true),
+        vertex(2, 13.401, 52.5, true),
+        vertex(3, 13.4005, 52.5, true),
+    ];
+    assert_eq!(remove_duplicates_and_spikes(&dead_end, &mut stats).len(), 3);
+    assert_eq!(stats, SanitizeStats::default());
+}
+
+#[test]
+fn test_crosses_itself() {
+    let loop_points = vec![
+        Coord { x: 0.0, y: 0.0 },
+        Coord { x: 2.0, y: 0.0 },
+        Coord { x: 2.0, y: 2.0 },
+        Coord { x: 1.0, y: 2.0 },
+    ];
+    assert!(crosses_itself(&loop_points, &Coord { x: 1.0, y: -1.0 }));
+    assert!(!crosses_itself(&loop_points, &Coord { x: 1.0, y: 1.0 }));
+    // Closing the ring touches the first point without crossing
+    assert!(!crosses_itself(&loop_points, &Coord { x: 0.0, y: 0.0 }));
+    assert!(!crosses_itself(&loop_points[..2], &Coord { x: 1.0, y: 0.0 }));
+}
@@ -0,0 +1,202 @@
+use crate::graph_builder::Segment;
+use crate::junctions::JunctionTable;
+use crate::regions::Regions;
+use crate::street_names::StreetNames;
+use arli::graph::*;
+use arli::graph_impl::{CompactSpatialGraph, Idx, MemoryUsage, MoreNodes, RefIterator, TurnRestrictions};
+use arli::spatial::{BoundingBox, Position};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Road graph built from OSM data, together with the OSM way id of every segment.
+///
+/// The graph traits are implemented by the underlying compact graph. Segments carry [`Segment`] data unless the graph
+/// is built with other data, see [`build_compact_graph`](crate::build_compact_graph).
+#[derive(Serialize, Deserialize)]
+pub struct OsmGraph<S = Segment> {
+  graph: CompactSpatialGraph<S>,
+  // OSM way id of each segment
+  way_ids: Vec<i64>,
+  // Segment ids sorted by their way id, for the lookup by way id
+  segments_by_way: Vec<Idx>,
+  street_names: StreetNames,
+  turn_restrictions: TurnRestrictions<Idx>,
+  // Built on request, only some of the consumers need it
+  junctions: Option<JunctionTable>,
+  regions: Option<Regions>,
+}
+
+impl<S> OsmGraph<S> {
+  pub fn new(graph: CompactSpatialGraph<S>, way_ids: Vec<i64>, street_names: StreetNames) -> Self {
+    let mut segments_by_way: Vec<Idx> = (0..way_ids.len() as Idx).collect();
+    segments_by_way.sort_by_key(|id| way_ids[*id as usize]);
+    Self {
+      graph,
+      way_ids,
+      segments_by_way,
+      street_names,
+      turn_restrictions: TurnRestrictions::default(),
+      junctions: None,
+      regions: None,
+    }
+  }
+
+  /// Adds the turns forbidden by the OSM turn restrictions
+  pub fn with_turn_restrictions(mut self, turn_restrictions: TurnRestrictions<Idx>) -> Self {
+    self.turn_restrictions = turn_restrictions;
+    self
+  }
+
+  /// Transitions between the segments forbidden by the OSM turn restrictions via a node. They apply to cars, the
+  /// other travel modes may ignore them.
+  pub fn turn_restrictions(&self) -> &TurnRestrictions<Idx> {
+    &self.turn_restrictions
+  }
+
+  /// Adds the junction table of the segments
+  pub fn with_junctions(mut self, junctions: JunctionTable) -> Self {
+    self.junctions = Some(junctions);
+    self
+  }
+
+  /// OSM nodes at the ends of the segments, `None` if the graph was built without them
+  pub fn junctions(&self) -> Option<&JunctionTable> {
+    self.junctions.as_ref()
+  }
+
+  /// Adds the regions of the segments
+  pub fn with_regions(mut self, regions: Regions) -> Self {
+    self.regions = Some(regions);
+    self
+  }
+
+  pub fn has_regions(&self) -> bool {
+    self.regions.is_some()
+  }
+
+  /// Administrative region of the segment, `None` if it's outside of all regions or the graph was built without them
+  pub fn region(&self, id: Idx) -> Option<&str> {
+    self.regions.as_ref().and_then(|regions| regions.get(id))
+  }
+
+  /// OSM way the segment was built from
+  pub fn way_id(&self, id: Idx) -> i64 {
+    self.way_ids[id as usize]
+  }
+
+  /// Street name or road number of the segment
+  pub fn street_name(&self, id: Idx) -> Option<&str> {
+    self.street_names.get(id)
+  }
+
+  /// Segments built from the OSM way, in both directions. Empty if the way isn't a part of the graph.
+  pub fn find_edges_by_way_id(&self, way_id: i64) -> &[Idx] {
+    let way_ids = &self.way_ids;
+    // Neither of the comparisons returns `Equal`, so the search always returns the position of a boundary
+    let boundary = |inclusive: bool| {
+      self
+        .segments_by_way
+        .binary_search_by(|id| {
+          let id_way = way_ids[*id as usize];
+          if id_way < way_id || (inclusive && id_way == way_id) {
+            Ordering::Less
+          } else {
+            Ordering::Greater
+          }
+        })
+        .unwrap_err()
+    };
+    &self.segments_by_way[boundary(false)..boundary(true)]
+  }
+
+  pub fn number_of_nodes(&self) -> usize {
+    self.graph.number_of_nodes()
+  }
+
+  pub fn number_of_edges(&self) -> usize {
+    self.graph.number_of_edges()
+  }
+
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add_nested("graph", self.graph.memory_usage());
+    usage.add("way_ids", &self.way_ids);
+    usage.add("segments_by_way", &self.segments_by_way);
+    usage.add_nested("street_names", self.street_names.memory_usage());
+    usage.add_nested("turn_restrictions", self.turn_restrictions.memory_usage());
+    if let Some(junctions) = &self.junctions {
+      usage.add_nested("junctions", junctions.memory_usage());
+    }
+    if let Some(regions) = &self.regions {
+      usage.add_nested("regions", regions.memory_usage());
+    }
+    usage
+  }
+
+  pub fn shrink(&mut self) {
+    self.graph.shrink();
+    self.way_ids.shrink_to_fit();
+    self.segments_by_way.shrink_to_fit();
+    self.street_names.shrink();
+    self.turn_restrictions.shrink();
+    if let Some(junctions) = &mut self.junctions {
+      junctions.shrink();
+    }
+    if let Some(regions) = &mut self.regions {
+      regions.shrink();
+    }
+  }
+}
+
+impl<S> GraphBase for OsmGraph<S> {
+  type NodeId = Idx;
+}
+
+impl<S> GraphData for OsmGraph<S> {
+  type Data = S;
+
+  fn data(&self, node_id: Idx) -> &Self::Data {
+    self.graph.data(node_id)
+  }
+}
+
+impl<'a, S> IntoNeighbors<Forward> for &'a OsmGraph<S> {
+  type Neighbors = RefIterator<'a, Idx>;
+
+  fn neighbors(self, node_id: Idx) -> Self::Neighbors {
+    neighbors_forward(&self.graph, node_id)
+  }
+}
+
+impl<'a, S> IntoNeighbors<Backward> for &'a OsmGraph<S> {
+  type Neighbors = RefIterator<'a, Idx>;
+
+  fn neighbors(self, node_id: Idx) -> Self::Neighbors {
+    neighbors_backward(&self.graph, node_id)
+  }
+}
+
+impl<'a, S> IntoGeometry for &'a OsmGraph<S> {
+  type P = Position;
+  type Geometry = RefIterator<'a, Position>;
+
+  fn geometry(self, id: Idx) -> Self::Geometry {
+    (&self.graph).geometry(id)
+  }
+}
+
+impl<S> Spatial for OsmGraph<S> {
+  type Nodes = std::vec::IntoIter<Idx>;
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Self::Nodes {
+    self.graph.find_nodes(bbox)
+  }
+}
+
+impl<S> Extensible for OsmGraph<S> {
+  type Extension = MoreNodes;
+
+  fn new_extension(&self) -> Self::Extension {
+    self.graph.new_extension()
+  }
+}
@@ -0,0 +1,348 @@
+//! Preprocessing of a built graph for the speed-up techniques of the queries.
+//!
+//! Each stage writes its artifact next to the graph file, named after the graph file with the stage name as the
+//! extension, e.g. `berlin.graph.partition`. A stage runs after the stages it depends on, the stages which don't
+//! depend on each other run in parallel. An artifact stores the checksum of the graph it's built from, see
+//! [`graph_checksum`], and is never loaded with another graph. An artifact of the same graph which is newer than the
+//! artifacts it's built from is up to date, so a run stopped half way resumes with the stages it hasn't finished.
+//!
+//! Three steps of the usual pipeline are not stages, for now:
+//! - chain contraction: the import splits the ways only at their junctions already, the chains left run across the
+//!   ends of the ways, and contracting them would renumber the segments the turn restrictions and the traffic feeds
+//!   are keyed on
+//! - landmarks: the searches have no landmark potentials to use them yet, and unlike the other artifacts they depend
+//!   on the weights, so they'd be built per profile rather than per graph
+//! - spatial index: the graph builder builds it into the graph file, see
+//!   [`CompactSpatialGraph`](arli::graph_impl::CompactSpatialGraph)
+
+use crate::graph_serde::{companion_checksum, graph_checksum, load_companion, save_companion};
+use crate::osm_graph::OsmGraph;
+use arli::cch::{nested_dissection_order, Cch};
+use arli::crp::Crp;
+use arli::partition::Partition;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+// Levels of the overlay above the partition, each one has cells this many times larger than the level below
+const OVERLAY_LEVELS: usize = 2;
+const OVERLAY_LEVEL_GROWTH: usize = 16;
+
+/// Stage of the preprocessing, see the [module](self) docs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+  /// Cells of the nodes, see [`Partition::inertial_flow`]
+  Partition,
+  /// Multilevel overlay of the partition coarsened into the upper levels, see [`Crp`]
+  Overlay,
+  /// Contraction hierarchy in the nested dissection order, see [`Cch`]
+  Hierarchy,
+}
+
+impl Stage {
+  pub const ALL: [Stage; 3] = [Stage::Partition, Stage::Overlay, Stage::Hierarchy];
+
+  /// Name on the command line and the extension of the artifact
+  pub fn name(self) -> &'static str {
+    match self {
+      Stage::Partition => "partition",
+      Stage::Overlay => "crp",
+      Stage::Hierarchy => "cch",
+    }
+  }
+
+  /// Stages whose artifacts the stage is built from
+  pub fn dependencies(self) -> &'static [Stage] {
+    match self {
+      Stage::Overlay => &[Stage::Partition],
+      Stage::Partition | Stage::Hierarchy => &[],
+    }
+  }
+}
+
+impl FromStr for Stage {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    Stage::ALL
+      .iter()
+      .find(|stage| stage.name() == name)
+      .cloned()
+      .ok_or_else(|| format!("Unknown stage {}", name))
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct PreprocessOptions {
+  /// Stages to run, the stages they depend on run as well
+  pub stages: Vec<Stage>,
+  /// Largest number of nodes in a cell of the partition
+  pub max_cell_size: usize,
+  /// Runs the stages even if their artifacts are up to date
+  pub force: bool,
+}
+
+impl Default for PreprocessOptions {
+  fn default() -> Self {
+    Self {
+      stages: Stage::ALL.to_vec(),
+      max_cell_size: 256,
+      force: false,
+    }
+  }
+}
+
+/// What a run did with a stage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StageOutcome {
+  Built,
+  UpToDate,
+}
+
+/// File of the artifact of the stage for the graph file
+pub fn artifact_path(graph_path: &Path, stage: Stage) -> PathBuf {
+  let mut path = graph_path.as_os_str().to_owned();
+  path.push(".");
+  path.push(stage.name());
+  PathBuf::from(path)
+}
+
+/// Artifact written by [`preprocess`], e.g. a [`Partition`] of [`Stage::Partition`]. Fails with
+/// [`arli::Error::GraphMismatch`] if it's built from another graph than the one at `graph_path`, e.g. the graph was
+/// rebuilt since, and with [`arli::Error::CorruptGraph`] if the file isn't an artifact of the stage.
+///
+/// It computes the [`graph_checksum`] of the graph file, use [`load_artifact_with_checksum`] to load several artifacts
+/// of the same graph.
+// The library exports it, the preprocessing itself loads the artifacts with the checksum it has already computed
+#[allow(dead_code)]
+pub fn load_artifact<T: DeserializeOwned>(graph_path: &Path, stage: Stage) -> arli::Result<T> {
+  load_artifact_with_checksum(graph_path, stage, graph_checksum(graph_path)?)
+}
+
+/// Same as [`load_artifact`] with the [`graph_checksum`] of the graph file computed by the caller
+pub fn load_artifact_with_checksum<T: DeserializeOwned>(
+  graph_path: &Path,
+  stage: Stage,
+  checksum: u64,
+) -> arli::Result<T> {
+  load_companion(&artifact_path(graph_path, stage), checksum)
+}
+
+/// Runs the stages of the options and the stages they depend on for the graph loaded from `graph_path`. Returns the
+/// outcome of every stage in the dependency order.
+pub fn preprocess<S: Sync>(
+  graph: &OsmGraph<S>,
+  graph_path: &Path,
+  options: &PreprocessOptions,
+) -> arli::Result<Vec<(Stage, StageOutcome)>> {
+  let mut required = options.stages.clone();
+  let mut i = 0;
+  while i < required.len() {
+    for dependency in required[i].dependencies() {
+      if !required.contains(dependency) {
+        required.push(*dependency);
+      }
+    }
+    i += 1;
+  }
+
+  let checksum = graph_checksum(graph_path)?;
+  let mut outcomes: BTreeMap<Stage, StageOutcome> = BTreeMap::new();
+  loop {
+    let ready: Vec<Stage> = Stage::ALL
+      .iter()
+      .cloned()
+      .filter(|stage| required.contains(stage) && !outcomes.contains_key(stage))
+      .filter(|stage| stage.dependencies().iter().all(|dependency| outcomes.contains_key(dependency)))
+      .collect();
+    if ready.is_empty() {
+      break;
+    }
+    let results: Vec<arli::Result<StageOutcome>> = ready
+      .par_iter()
+      .map(|stage| {
+        let rebuilt = stage.dependencies().iter().any(|dependency| outcomes[dependency] == StageOutcome::Built);
+        if !options.force && !rebuilt && is_up_to_date(graph_path, checksum, *stage)? {
+          return Ok(StageOutcome::UpToDate);
+        }
+        run_stage(graph, graph_path, checksum, *stage, options)?;
+        Ok(StageOutcome::Built)
+      })
+      .collect();
+    for (stage, outcome) in ready.into_iter().zip(results) {
+      outcomes.insert(stage, outcome?);
+    }
+  }
+  Ok(outcomes.into_iter().collect())
+}
+
+// The artifact exists, is built from the graph and is newer than the artifacts it's built from
+fn is_up_to_date(graph_path: &Path, checksum: u64, stage: Stage) -> arli::Result<bool> {
+  // An artifact which can't even be read back, e.g. a truncated one, is built again
+  match companion_checksum(&artifact_path(graph_path, stage)) {
+    Ok(Some(stored)) if stored == checksum => {}
+    Err(arli::Error::Io(error)) => return Err(error.into()),
+    _ => return Ok(false),
+  }
+  let modified = |path: &Path| -> arli::Result<Option<SystemTime>> {
+    match fs::metadata(path) {
+      Ok(metadata) => Ok(Some(metadata.modified()?)),
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(error) => Err(error.into()),
+    }
+  };
+  let artifact = match modified(&artifact_path(graph_path, stage))? {
+    Some(artifact) => artifact,
+    None => return Ok(false),
+  };
+  for input in stage.dependencies().iter().map(|dependency| artifact_path(graph_path, *dependency)) {
+    match modified(&input)? {
+      Some(input) if input <= artifact => {}
+      _ => return Ok(false),
+    }
+  }
+  Ok(true)
+}
+
+fn run_stage<S>(
+  graph: &OsmGraph<S>,
+  graph_path: &Path,
+  checksum: u64,
+  stage: Stage,
+  options: &PreprocessOptions,
+) -> arli::Result<()> {
+  let number_of_nodes = graph.number_of_nodes();
+  match stage {
+    Stage::Partition => {
+      let partition = Partition::inertial_flow(graph, number_of_nodes, options.max_cell_size);
+      write_artifact(graph_path, checksum, stage, &partition)
+    }
+    Stage::Overlay => {
+      let mut levels: Vec<Partition> = vec![load_artifact_with_checksum(graph_path, Stage::Partition, checksum)?];
+      let mut max_cell_size = options.max_cell_size;
+      for _ in 0..OVERLAY_LEVELS {
+        max_cell_size *= OVERLAY_LEVEL_GROWTH;
+        let coarser = levels[levels.len() - 1].coarsen(graph, max_cell_size);
+        levels.push(coarser);
+      }
+      write_artifact(graph_path, checksum, stage, &Crp::new(graph, levels))
+    }
+    Stage::Hierarchy => {
+      let order = nested_dissection_order(graph, number_of_nodes);
+      write_artifact(graph_path, checksum, stage, &Cch::new(graph, order))
+    }
+  }
+}
+
+// Writes a temporary file first, so a stage stopped half way never leaves an artifact behind
+fn write_artifact<T: Serialize>(graph_path: &Path, checksum: u64, stage: Stage, artifact: &T) -> arli::Result<()> {
+  let path = artifact_path(graph_path, stage);
+  let mut temporary = path.as_os_str().to_owned();
+  temporary.push(".tmp");
+  save_companion(Path::new(&temporary), checksum, artifact)?;
+  fs::rename(&temporary, &path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graph_builder::{build_compact_graph, osm_segment, BuildOptions};
+  use crate::graph_serde::save_graph;
+  use crate::osm4routing::{Edge as OsmEdge, EdgeProperties};
+  use arli::progress::{CancellationToken, NoProgress, StageProgress};
+  use osmpbfreader::objects::{NodeId, WayId};
+
+  #[test]
+  fn test_preprocess() {
+    // A street of 20 segments going east
+    let mut properties = EdgeProperties::default();
+    properties.update("highway", "residential");
+    properties.normalize();
+    let edges: Vec<_> = (0..20)
+      .map(|i| OsmEdge {
+        id: WayId(i),
+        source: NodeId(i),
+        target: NodeId(i + 1),
+        geometry: vec![(13.4 + i as f32 * 0.001, 52.5), (13.401 + i as f32 * 0.001, 52.5)].into(),
+        properties,
+        unsignalized_crossing: false,
+        name: None,
+      })
+      .collect();
+    let token = CancellationToken::new();
+    let mut progress = NoProgress;
+    let stage = StageProgress::new(&mut progress, &token, "Building graph", Some(edges.len()));
+    let graph = build_compact_graph(&edges, &[], &BuildOptions::default(), osm_segment, stage).unwrap();
+    let dir = std::env::temp_dir().join("arli-test-preprocess");
+    fs::create_dir_all(&dir).unwrap();
+    let graph_path = dir.join("street.graph");
+    save_graph(&graph, graph_path.to_str().unwrap());
+    for stage in Stage::ALL.iter() {
+      let _ = fs::remove_file(artifact_path(&graph_path, *stage));
+    }
+
+    // The overlay needs the partition
+    let options = PreprocessOptions {
+      stages: vec![Stage::Overlay],
+      max_cell_size: 8,
+      force: false,
+    };
+    let outcomes = preprocess(&graph, &graph_path, &options).unwrap();
+    assert_eq!(outcomes, vec![(Stage::Partition, StageOutcome::Built), (Stage::Overlay, StageOutcome::Built)]);
+    let partition: Partition = load_artifact(&graph_path, Stage::Partition).unwrap();
+    assert_eq!(partition.number_of_nodes(), graph.number_of_nodes());
+    assert!(partition.number_of_cells() > 1);
+    assert_eq!(load_artifact::<Crp>(&graph_path, Stage::Overlay).unwrap().number_of_levels(), 3);
+
+    // A second run only builds the missing stage
+    let all = PreprocessOptions {
+      stages: Stage::ALL.to_vec(),
+      ..options.clone()
+    };
+    let outcomes = preprocess(&graph, &graph_path, &all).unwrap();
+    assert_eq!(
+      outcomes,
+      vec![
+        (Stage::Partition, StageOutcome::UpToDate),
+        (Stage::Overlay, StageOutcome::UpToDate),
+        (Stage::Hierarchy, StageOutcome::Built)
+      ]
+    );
+    let forced = PreprocessOptions {
+      force: true,
+      ..all.clone()
+    };
+    assert!(preprocess(&graph, &graph_path, &forced)
+      .unwrap()
+      .iter()
+      .all(|(_, outcome)| *outcome == StageOutcome::Built));
+
+    // Saving the same graph again keeps the artifacts, a rebuilt graph doesn't load the artifacts of the old one
+    save_graph(&graph, graph_path.to_str().unwrap());
+    assert!(preprocess(&graph, &graph_path, &all)
+      .unwrap()
+      .iter()
+      .all(|(_, outcome)| *outcome == StageOutcome::UpToDate));
+    let stage = StageProgress::new(&mut progress, &token, "Building graph", Some(10));
+    let shorter = build_compact_graph(&edges[..10], &[], &BuildOptions::default(), osm_segment, stage).unwrap();
+    save_graph(&shorter, graph_path.to_str().unwrap());
+    let stale = load_artifact::<Partition>(&graph_path, Stage::Partition);
+    assert!(matches!(stale, Err(arli::Error::GraphMismatch(_))));
+    let checksum = graph_checksum(&graph_path).unwrap();
+    let stale = load_artifact_with_checksum::<Partition>(&graph_path, Stage::Partition, checksum);
+    assert!(matches!(stale, Err(arli::Error::GraphMismatch(_))));
+    preprocess(&shorter, &graph_path, &options).unwrap();
+    let partition: Partition = load_artifact_with_checksum(&graph_path, Stage::Partition, checksum).unwrap();
+    assert_eq!(partition.number_of_nodes(), shorter.number_of_nodes());
+
+    assert_eq!("cch".parse::<Stage>(), Ok(Stage::Hierarchy));
+    assert!("landmarks".parse::<Stage>().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
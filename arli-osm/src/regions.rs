@@ -0,0 +1,211 @@
+use arli::graph_impl::{Idx, MemoryUsage};
+use arli::spatial::{BoundingBox, Position};
+use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::contains::Contains;
+use geo::{LineString, MultiPolygon, Point, Polygon};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Region id of the segments outside of all regions
+const NO_REGION: u16 = u16::MAX;
+
+/// Administrative region, e.g. a country or a state, of every segment
+#[derive(Serialize, Deserialize, Default)]
+pub struct Regions {
+  names: Vec<String>,
+  region_ids: Vec<u16>,
+}
+
+impl Regions {
+  pub fn get(&self, id: Idx) -> Option<&str> {
+    match self.region_ids.get(id as usize) {
+      Some(&region_id) if region_id != NO_REGION => Some(&self.names[region_id as usize]),
+      _ => None,
+    }
+  }
+
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add("names", &self.names);
+    usage.add("region_ids", &self.region_ids);
+    usage
+  }
+
+  pub fn shrink(&mut self) {
+    self.names.shrink_to_fit();
+    self.region_ids.shrink_to_fit();
+  }
+}
+
+/// Region boundaries the segments are located in
+pub struct RegionBoundaries {
+  names: Vec<String>,
+  boundaries: Vec<(BoundingBox, MultiPolygon<f32>)>,
+}
+
+fn ring(coordinates: &Value) -> Option<LineString<f32>> {
+  let points: Option<Vec<(f32, f32)>> = coordinates
+    .as_array()?
+    .iter()
+    .map(|point| Some((point.get(0)?.as_f64()? as f32, point.get(1)?.as_f64()? as f32)))
+    .collect();
+  points.map(LineString::from)
+}
+
+fn polygon(coordinates: &Value) -> Option<Polygon<f32>> {
+  let mut rings = coordinates.as_array()?.iter().map(ring);
+  let exterior = rings.next()??;
+  let interiors: Option<Vec<_>> = rings.collect();
+  Some(Polygon::new(exterior, interiors?))
+}
+
+fn multi_polygon(geometry: &Value) -> Option<MultiPolygon<f32>> {
+  let coordinates = geometry.get("coordinates")?;
+  match geometry.get("type")?.as_str()? {
+    "Polygon" => Some(MultiPolygon(vec![polygon(coordinates)?])),
+    "MultiPolygon" => {
+      let polygons: Option<Vec<_>> = coordinates.as_array()?.iter().map(polygon).collect();
+      Some(MultiPolygon(polygons?))
+    }
+    _ => None,
+  }
+}
+
+impl RegionBoundaries {
+  /// Reads a GeoJSON feature collection of polygons and multipolygons, the region name is the `name` property
+  pub fn from_geojson(content: &str) -> Result<Self, String> {
+    let collection: Value = serde_json::from_str(content).map_err(|e| format!("Invalid GeoJSON: {}", e))?;
+    let features = collection
+      .get("features")
+      .and_then(Value::as_array)
+      .ok_or("GeoJSON must be a feature collection")?;
+    if features.len() >= NO_REGION as usize {
+      return Err(format!("At most {} regions are supported", NO_REGION));
+    }
+
+    let mut names = Vec::new();
+    let mut boundaries = Vec::new();
+    for (index, feature) in features.iter().enumerate() {
+      let name = feature
+        .get("properties")
+        .and_then(|properties| properties.get("name"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Region {} has no name", index))?;
+      let boundary = feature
+        .get("geometry")
+        .and_then(multi_polygon)
+        .ok_or_else(|| format!("Region {} must be a polygon or a multipolygon", name))?;
+      let bbox = boundary
+        .bounding_rect()
+        .ok_or_else(|| format!("Region {} is empty", name))?;
+      names.push(name.to_string());
+      boundaries.push((bbox, boundary));
+    }
+    Ok(Self { names, boundaries })
+  }
+
+  pub fn from_file(path: &str) -> Result<Self, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Can't read regions {}: {}", path, e))?;
+    RegionBoundaries::from_geojson(&content)
+  }
+
+  // Index of the first region containing the position
+  fn locate(&self, position: &Position) -> u16 {
+    let point = Point::from(*position);
+    self
+      .boundaries
+      .iter()
+      .position(|(bbox, boundary)| {
+        let (min, max) = (bbox.min(), bbox.max());
+        min.x <= position.x
+          && position.x <= max.x
+          && min.y <= position.y
+          && position.y <= max.y
+          && boundary.contains(&point)
+      })
+      .map(|index| index as u16)
+      .unwrap_or(NO_REGION)
+  }
+}
+
+/// Collects the regions of the segments in the order of their ids
+pub struct RegionsBuilder<'a> {
+  boundaries: &'a RegionBoundaries,
+  region_ids: Vec<u16>,
+}
+
+impl<'a> RegionsBuilder<'a> {
+  pub fn new(boundaries: &'a RegionBoundaries) -> Self {
+    Self {
+      boundaries,
+      region_ids: Vec::new(),
+    }
+  }
+
+  /// Adds the next segment. A segment crossing a border belongs to the region of its middle vertex.
+  pub fn push(&mut self, geometry: &[Position]) {
+    let region_id = match geometry.get(geometry.len() / 2) {
+      Some(middle) => self.boundaries.locate(middle),
+      None => NO_REGION,
+    };
+    self.region_ids.push(region_id);
+  }
+
+  pub fn build(self) -> Regions {
+    Regions {
+      names: self.boundaries.names.clone(),
+      region_ids: self.region_ids,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const REGIONS: &str = r#"{
+    "type": "FeatureCollection",
+    "features": [
+      {
+        "type": "Feature",
+        "properties": { "name": "DE" },
+        "geometry": { "type": "Polygon", "coordinates": [[[13, 52], [14, 52], [14, 53], [13, 53], [13, 52]]] }
+      },
+      {
+        "type": "Feature",
+        "properties": { "name": "PL" },
+        "geometry": {
+          "type": "MultiPolygon",
+          "coordinates": [[[[14, 52], [15, 52], [15, 53], [14, 53], [14, 52]]]]
+        }
+      }
+    ]
+  }"#;
+
+  #[test]
+  fn test_regions() {
+    let boundaries = RegionBoundaries::from_geojson(REGIONS).unwrap();
+    let mut builder = RegionsBuilder::new(&boundaries);
+    builder.push(&[Position { x: 13.4, y: 52.5 }, Position { x: 13.5, y: 52.5 }]);
+    // The middle vertex is across the border
+    builder.push(&[
+      Position { x: 13.9, y: 52.5 },
+      Position { x: 14.1, y: 52.5 },
+      Position { x: 14.2, y: 52.5 },
+    ]);
+    builder.push(&[Position { x: 10.0, y: 52.5 }, Position { x: 10.1, y: 52.5 }]);
+    let regions = builder.build();
+
+    assert_eq!(regions.get(0), Some("DE"));
+    assert_eq!(regions.get(1), Some("PL"));
+    assert_eq!(regions.get(2), None);
+    assert_eq!(regions.get(3), None);
+  }
+
+  #[test]
+  fn test_invalid_regions() {
+    assert!(RegionBoundaries::from_geojson("[]").is_err());
+    let point = r#"{"features": [{"properties": {"name": "X"}, "geometry": {"type": "Point", "coordinates": [1, 2]}}]}"#;
+    assert!(RegionBoundaries::from_geojson(point).is_err());
+  }
+}
@@ -0,0 +1,174 @@
+use crate::graph_builder::{Segment, TravelMode, FLAG_NAMES};
+use crate::osm_graph::OsmGraph;
+use arli::graph::GraphData;
+use arli::graph_impl::Idx;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Upper bounds of the segment length buckets in meters, the last bucket has no bound
+const LENGTH_BUCKETS_M: [f32; 6] = [1.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+/// Segments with a length below the bound, and at least as long as the bound of the previous bucket
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LengthBucket {
+  /// `None` for the last bucket
+  pub max_m: Option<f32>,
+  pub segments: usize,
+}
+
+/// Distributions of the segment attributes over a graph.
+///
+/// The graph doesn't keep the OSM tags, a change of the tag interpretation shows up only in these distributions, e.g.
+/// the motorways losing the speed limit or the footways becoming accessible by car. Comparing the stats of two
+/// imports is the quickest way to notice it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GraphStats {
+  pub segments: usize,
+  /// Total length of the segments in kilometers
+  pub length_km: f64,
+  /// Number of segments with each speed limit in km/h, 0 for the ways without one
+  pub speed_limits: BTreeMap<u8, usize>,
+  /// Number of segments of each road class, see [`road_class`]
+  pub road_classes: BTreeMap<&'static str, usize>,
+  pub lengths: Vec<LengthBucket>,
+  /// Number of segments with each flag set
+  pub flags: BTreeMap<&'static str, usize>,
+}
+
+/// Coarse class of the road the segment is a part of. The graph keeps only whether the road is a highway, the other
+/// classes are told apart by the travel modes allowed on the segment, e.g. a footway open to bikes is a `bike_path`.
+pub fn road_class(segment: &Segment) -> &'static str {
+  if segment.is_highway() {
+    "highway"
+  } else if segment.allows(TravelMode::Car) {
+    "road"
+  } else if segment.allows(TravelMode::Bike) {
+    "bike_path"
+  } else if segment.allows(TravelMode::Foot) {
+    "foot_path"
+  } else {
+    "no_access"
+  }
+}
+
+impl GraphStats {
+  pub fn new() -> Self {
+    let mut lengths: Vec<_> = LENGTH_BUCKETS_M.iter().map(|max_m| Some(*max_m)).collect();
+    lengths.push(None);
+    Self {
+      segments: 0,
+      length_km: 0.0,
+      speed_limits: BTreeMap::new(),
+      road_classes: BTreeMap::new(),
+      lengths: lengths.into_iter().map(|max_m| LengthBucket { max_m, segments: 0 }).collect(),
+      // Flags nothing has are still reported, a flag dropping to zero is a regression too
+      flags: FLAG_NAMES.iter().map(|flag| (*flag, 0)).collect(),
+    }
+  }
+
+  /// Stats of all segments of the graph
+  pub fn of(graph: &OsmGraph) -> Self {
+    let mut stats = GraphStats::new();
+    for id in 0..graph.number_of_nodes() as Idx {
+      stats.add(graph.data(id));
+    }
+    stats
+  }
+
+  pub fn add(&mut self, segment: &Segment) {
+    self.segments += 1;
+    self.length_km += segment.length() as f64 / 1000.0;
+    *self.speed_limits.entry(segment.speed_limit()).or_insert(0) += 1;
+    *self.road_classes.entry(road_class(segment)).or_insert(0) += 1;
+    let bucket = LENGTH_BUCKETS_M
+      .iter()
+      .position(|max_m| segment.length() < *max_m)
+      .unwrap_or(LENGTH_BUCKETS_M.len());
+    self.lengths[bucket].segments += 1;
+    for (flag, set) in segment.flags().iter() {
+      if *set {
+        *self.flags.entry(*flag).or_insert(0) += 1;
+      }
+    }
+  }
+}
+
+impl Default for GraphStats {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Counts on a single line, e.g. `30: 12, 50: 40`
+fn counts<K: fmt::Display>(f: &mut fmt::Formatter, name: &str, counts: impl Iterator<Item = (K, usize)>) -> fmt::Result {
+  let counts: Vec<_> = counts.map(|(key, count)| format!("{}: {}", key, count)).collect();
+  writeln!(f, "{}: {}", name, counts.join(", "))
+}
+
+impl fmt::Display for GraphStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{} segments, {:.1} km", self.segments, self.length_km)?;
+    counts(f, "Speed limits (km/h)", self.speed_limits.iter().map(|(limit, count)| (limit, *count)))?;
+    counts(f, "Road classes", self.road_classes.iter().map(|(class, count)| (class, *count)))?;
+    let lengths = self.lengths.iter().map(|bucket| match bucket.max_m {
+      Some(max_m) => (format!("<{}", max_m), bucket.segments),
+      None => (format!(">={}", LENGTH_BUCKETS_M[LENGTH_BUCKETS_M.len() - 1]), bucket.segments),
+    });
+    counts(f, "Lengths (m)", lengths)?;
+    counts(f, "Flags", self.flags.iter().map(|(flag, count)| (flag, *count)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graph_builder::{osm_segment, EdgeDirection};
+  use crate::osm4routing::{Edge as OsmEdge, EdgeProperties};
+  use osmpbfreader::objects::{NodeId, WayId};
+
+  fn segment(tags: &[(&str, &str)]) -> Segment {
+    let mut properties = EdgeProperties::default();
+    for (key, value) in tags {
+      properties.update(key, value);
+    }
+    properties.normalize();
+    let edge = OsmEdge {
+      id: WayId(1),
+      source: NodeId(1),
+      target: NodeId(2),
+      geometry: vec![(13.33318f32, 52.48468f32), (13.33312, 52.48757)].into(),
+      properties,
+      unsignalized_crossing: false,
+      name: None,
+    };
+    osm_segment(&edge, EdgeDirection::Forward)
+  }
+
+  #[test]
+  fn test_graph_stats() {
+    let mut stats = GraphStats::new();
+    stats.add(&segment(&[("highway", "motorway"), ("maxspeed", "120"), ("toll", "yes")]));
+    stats.add(&segment(&[("highway", "residential"), ("maxspeed", "30"), ("lit", "yes")]));
+    stats.add(&segment(&[("highway", "footway")]));
+    stats.add(&segment(&[("highway", "steps"), ("bicycle", "no")]));
+
+    assert_eq!(stats.segments, 4);
+    assert_eq!(stats.speed_limits.get(&120), Some(&1));
+    assert_eq!(stats.speed_limits.get(&30), Some(&1));
+    assert_eq!(stats.road_classes.get("highway"), Some(&1));
+    assert_eq!(stats.road_classes.get("road"), Some(&1));
+    assert_eq!(stats.road_classes.get("bike_path"), Some(&1));
+    assert_eq!(stats.road_classes.get("foot_path"), Some(&1));
+    assert_eq!(stats.flags.get("toll"), Some(&1));
+    assert_eq!(stats.flags.get("lit"), Some(&1));
+    assert_eq!(stats.flags.get("steps"), Some(&1));
+    assert_eq!(stats.flags.get("unpaved"), Some(&0));
+
+    // The segments are about 320 meters long
+    let buckets: Vec<_> = stats.lengths.iter().map(|bucket| bucket.segments).collect();
+    assert_eq!(buckets, vec![0, 0, 0, 0, 4, 0, 0]);
+    assert!((stats.length_km - 1.28).abs() < 0.01);
+    assert!(stats.to_string().contains("Road classes: bike_path: 1, foot_path: 1, highway: 1, road: 1"));
+  }
+}
@@ -0,0 +1,101 @@
+use arli::graph_impl::{Idx, MemoryUsage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Name id of the segments without a name
+const NO_NAME: u32 = 0;
+
+/// Street name of every segment. Each distinct name is stored once, segments refer to it by its index.
+#[derive(Serialize, Deserialize, Default)]
+pub struct StreetNames {
+  names: Vec<String>,
+  name_ids: Vec<u32>,
+}
+
+impl StreetNames {
+  pub fn new() -> Self {
+    Self {
+      // Placeholder for `NO_NAME`
+      names: vec![String::new()],
+      name_ids: Vec::new(),
+    }
+  }
+
+  pub fn get(&self, id: Idx) -> Option<&str> {
+    match self.name_ids.get(id as usize) {
+      Some(&name_id) if name_id != NO_NAME => Some(&self.names[name_id as usize]),
+      _ => None,
+    }
+  }
+
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add("names", &self.names);
+    usage.add("name_ids", &self.name_ids);
+    usage
+  }
+
+  pub fn shrink(&mut self) {
+    self.names.shrink_to_fit();
+    self.name_ids.shrink_to_fit();
+  }
+}
+
+/// Collects the names of the segments in the order of their ids
+pub struct StreetNamesBuilder {
+  names: StreetNames,
+  index: HashMap<String, u32>,
+}
+
+impl StreetNamesBuilder {
+  pub fn new() -> Self {
+    Self {
+      names: StreetNames::new(),
+      index: HashMap::new(),
+    }
+  }
+
+  /// Adds the name of the next segment
+  pub fn push(&mut self, name: Option<&str>) {
+    let name_id = match name {
+      Some(name) if !name.is_empty() => {
+        let names = &mut self.names.names;
+        *self.index.entry(name.to_string()).or_insert_with(|| {
+          names.push(name.to_string());
+          (names.len() - 1) as u32
+        })
+      }
+      _ => NO_NAME,
+    };
+    self.names.name_ids.push(name_id);
+  }
+
+  pub fn build(self) -> StreetNames {
+    self.names
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_street_names() {
+    let mut builder = StreetNamesBuilder::new();
+    builder.push(Some("Tempelhofer Damm"));
+    builder.push(None);
+    builder.push(Some("A100"));
+    builder.push(Some("Tempelhofer Damm"));
+    builder.push(Some(""));
+    let names = builder.build();
+
+    assert_eq!(names.get(0), Some("Tempelhofer Damm"));
+    assert_eq!(names.get(1), None);
+    assert_eq!(names.get(2), Some("A100"));
+    assert_eq!(names.get(3), Some("Tempelhofer Damm"));
+    assert_eq!(names.get(4), None);
+    assert_eq!(names.get(5), None);
+    // Repeated names are stored once
+    assert_eq!(names.names.len(), 3);
+  }
+}
@@ -0,0 +1,139 @@
+use arli::adaptive::SearchAlgorithm;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::Filter;
+
+/// Header identifying a request, taken from the client if present and echoed in the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longer ids sent by the client are replaced, like the ones with other characters than the allowed ones
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// The counter keeps ids unique within the service run, the time keeps them apart between restarts
+fn generate_request_id() -> String {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|since_epoch| since_epoch.as_millis())
+    .unwrap_or(0);
+  format!("{:x}-{:x}", now, REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// Client ids end up in the access log and the response header, so they can't contain spaces, quotes or `=`, which
+// would let them forge the other pairs of the log line
+fn is_valid_request_id(id: &str) -> bool {
+  !id.is_empty()
+    && id.len() <= MAX_REQUEST_ID_LEN
+    && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+/// Extracts the request id sent by the client or generates a new one. The id of the client is only used if it
+/// consists of letters, digits, `.`, `_` and `-` and is at most 128 characters long.
+pub fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+  warp::header::optional::<String>(REQUEST_ID_HEADER)
+    .map(|id: Option<String>| id.filter(|id| is_valid_request_id(id)).unwrap_or_else(generate_request_id))
+    .or(warp::any().map(generate_request_id))
+    .unify()
+}
+
+// Path and query of the request, logged so the request can be replayed
+fn request_target() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+  let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+  warp::path::full().and(query).map(|path: FullPath, query: String| {
+    if query.is_empty() {
+      path.as_str().to_string()
+    } else {
+      format!("{}?{}", path.as_str(), query)
+    }
+  })
+}
+
+/// Extracts the request id, see [`request_id`], and the target of the request, the path with the query
+pub fn request_id_and_target() -> impl Filter<Extract = ((String, String),), Error = Infallible> + Clone {
+  request_id().and(request_target()).map(|id, target| (id, target))
+}
+
+/// Single line summary of a routing request, printed when the request is finished.
+///
+/// The line consists of `key=value` pairs, so it's easy to grep and to parse. The request target is the last pair, so
+/// the line can be replayed by `arli-replay`.
+pub struct AccessLog {
+  pub request_id: String,
+  endpoint: &'static str,
+  profile: String,
+  started: Instant,
+  pub waypoints: usize,
+  /// Nodes settled by all searches of the request
+  pub settled: usize,
+  /// Algorithm chosen for the route search, with the number of nodes it was predicted to settle
+  pub search: Option<(SearchAlgorithm, usize)>,
+  // Path and query of the request, not set for the requests the service makes itself, e.g. the canaries
+  target: Option<String>,
+}
+
+impl AccessLog {
+  pub fn new(request_id: String, endpoint: &'static str, profile: &str, waypoints: usize) -> Self {
+    Self {
+      request_id,
+      endpoint,
+      profile: profile.to_string(),
+      started: Instant::now(),
+      waypoints,
+      settled: 0,
+      search: None,
+      target: None,
+    }
+  }
+
+  pub fn with_target(self, target: String) -> Self {
+    Self {
+      target: Some(target),
+      ..self
+    }
+  }
+
+  pub fn finish(&self, status: StatusCode) {
+    let search = match self.search {
+      Some((algorithm, predicted)) => format!(" algorithm={} predicted={}", algorithm, predicted),
+      None => String::new(),
+    };
+    let target = match &self.target {
+      Some(target) => format!(" target={}", target),
+      None => String::new(),
+    };
+    println!(
+      "access request_id={} endpoint={} profile={} status={} latency_ms={:.3} waypoints={} settled={}{}{}",
+      self.request_id,
+      self.endpoint,
+      self.profile,
+      status.as_u16(),
+      self.started.elapsed().as_secs_f64() * 1000.0,
+      self.waypoints,
+      self.settled,
+      search,
+      target
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_request_id_validation() {
+    assert!(is_valid_request_id("3f2a-17.client_b"));
+    assert!(is_valid_request_id(&generate_request_id()));
+    assert!(is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN)));
+    assert!(!is_valid_request_id(""));
+    assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    // A forged status pair or a line break would corrupt the access log
+    assert!(!is_valid_request_id("1 status=200"));
+    assert!(!is_valid_request_id("1\naccess request_id=2"));
+    assert!(!is_valid_request_id("\"quoted\""));
+  }
+}
@@ -0,0 +1,315 @@
+//! Replays the requests of an access log of the service against one or two running services, e.g. the current build
+//! and a candidate build serving the same graph, and compares their latencies and results.
+//!
+//! ```text
+//! arli-replay <access_log> <server> [<candidate_server>] [--concurrency <n>] [--limit <n>]
+//! ```
+//!
+//! The route, table and trip requests are replayed, the log lines without a target, e.g. of the canaries, are skipped.
+//! Results are compared by the response code and the distances and durations, not by the geometry or the hints.
+
+use serde_json::{json, Value};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+const REPLAYED_ENDPOINTS: [&str; 3] = ["route", "table", "trip"];
+// Differing results listed in the report, the others are only counted
+const LISTED_DIFFERENCES: usize = 10;
+
+struct Options {
+    log: String,
+    servers: Vec<String>,
+    concurrency: usize,
+    limit: Option<usize>,
+}
+
+// Response of one server to one request
+#[derive(Clone, Debug, PartialEq)]
+struct Outcome {
+    latency_ms: f64,
+    // 0 if the request failed, e.g. the server refused the connection
+    status: u16,
+    result: String,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: arli-replay <access_log> <server> [<candidate_server>] [--concurrency <n>] [--limit <n>]");
+    std::process::exit(2)
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut concurrency = 4;
+    let mut limit = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> Result<usize, String> {
+            args.next()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .ok_or_else(|| format!("{} needs a positive number", name))
+        };
+        match arg.as_str() {
+            "--concurrency" => concurrency = value("--concurrency")?,
+            "--limit" => limit = Some(value("--limit")?),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if positional.len() < 2 || positional.len() > 3 {
+        return Err("Expected an access log and one or two servers".to_string());
+    }
+    let servers = positional[1..]
+        .iter()
+        .map(|server| server.trim_start_matches("http://").trim_end_matches('/').to_string())
+        .collect();
+    Ok(Options {
+        log: positional[0].clone(),
+        servers,
+        concurrency,
+        limit,
+    })
+}
+
+// Endpoint and target of an access log line of a replayed endpoint
+fn parse_access_line(line: &str) -> Option<(&str, &str)> {
+    if !line.starts_with("access ") {
+        return None;
+    }
+    let value = |key: &str| line.split(' ').find_map(|pair| pair.strip_prefix(key));
+    let endpoint = value("endpoint=")?;
+    let target = value("target=")?;
+    if REPLAYED_ENDPOINTS.contains(&endpoint) && target.starts_with('/') {
+        Some((endpoint, target))
+    } else {
+        None
+    }
+}
+
+// Sends a GET request and reads the response until the server closes the connection
+fn get(server: &str, target: &str) -> io::Result<(u16, String)> {
+    let mut stream = TcpStream::connect(server)?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", target, server)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid HTTP response");
+    let end_of_head = response.find("\r\n\r\n").ok_or_else(invalid)?;
+    let status = response[..end_of_head]
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, response[end_of_head + 4..].to_string()))
+}
+
+// Part of the response compared between the servers. Streamed tables aren't a single JSON document, they are compared
+// as they are.
+fn result_summary(body: &str) -> String {
+    let response: Value = match serde_json::from_str(body) {
+        Ok(response) => response,
+        Err(_) => return body.trim().to_string(),
+    };
+    let routes = |key: &str| match response[key].as_array() {
+        Some(routes) => routes.iter().map(|route| json!([route["distance"], route["duration"]])).collect(),
+        None => Vec::new(),
+    };
+    let summary = json!({
+        "code": response["code"],
+        "routes": routes("routes"),
+        "trips": routes("trips"),
+        "durations": response["durations"],
+        "distances": response["distances"],
+    });
+    summary.to_string()
+}
+
+fn replay(server: &str, target: &str) -> Outcome {
+    let timer = Instant::now();
+    let response = get(server, target);
+    let latency_ms = timer.elapsed().as_secs_f64() * 1000.0;
+    match response {
+        Ok((status, body)) => Outcome {
+            latency_ms,
+            status,
+            result: result_summary(&body),
+        },
+        Err(error) => Outcome {
+            latency_ms,
+            status: 0,
+            result: error.to_string(),
+        },
+    }
+}
+
+// Value below which the share `p` of the sorted values are
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+fn report_latencies(server: &str, outcomes: &[&Outcome]) {
+    let mut latencies: Vec<f64> = outcomes.iter().map(|outcome| outcome.latency_ms).collect();
+    latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let failed = outcomes.iter().filter(|outcome| outcome.status == 0).count();
+    let not_ok = outcomes.iter().filter(|outcome| outcome.status != 0 && outcome.status != 200).count();
+    println!(
+        "{}: requests = {}, failed = {}, not OK = {}, \
+         latency p50 = {:.1} ms, p90 = {:.1} ms, p99 = {:.1} ms, max = {:.1} ms",
+        server,
+        outcomes.len(),
+        failed,
+        not_ok,
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 1.0)
+    );
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = parse_options(&args).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        usage()
+    });
+    let log = std::fs::read_to_string(&options.log).unwrap_or_else(|error| {
+        eprintln!("Can't read {}: {}", options.log, error);
+        std::process::exit(1)
+    });
+    let mut targets: Vec<String> =
+        log.lines().filter_map(parse_access_line).map(|(_, target)| target.to_string()).collect();
+    if let Some(limit) = options.limit {
+        targets.truncate(limit);
+    }
+    println!(
+        "Replaying {} requests against {} with {} workers",
+        targets.len(),
+        options.servers.join(" and "),
+        options.concurrency
+    );
+
+    // Each worker sends a request to all servers before taking the next one, so the servers see the same load
+    let targets = Arc::new(targets);
+    let servers = Arc::new(options.servers);
+    let next = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|_| {
+            let (targets, servers, next) = (Arc::clone(&targets), Arc::clone(&servers), Arc::clone(&next));
+            std::thread::spawn(move || {
+                let mut outcomes = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= targets.len() {
+                        return outcomes;
+                    }
+                    let replayed: Vec<_> = servers.iter().map(|server| replay(server, &targets[i])).collect();
+                    outcomes.push((i, replayed));
+                }
+            })
+        })
+        .collect();
+    let mut outcomes: Vec<(usize, Vec<Outcome>)> =
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect();
+    outcomes.sort_by_key(|(i, _)| *i);
+
+    for (k, server) in servers.iter().enumerate() {
+        let of_server: Vec<_> = outcomes.iter().map(|(_, replayed)| &replayed[k]).collect();
+        report_latencies(server, &of_server);
+    }
+    if servers.len() == 2 {
+        let differences: Vec<_> = outcomes
+            .iter()
+            .filter(|(_, replayed)| {
+                replayed[0].status != replayed[1].status || replayed[0].result != replayed[1].result
+            })
+            .collect();
+        println!("Different results: {} of {}", differences.len(), outcomes.len());
+        for (i, replayed) in differences.iter().take(LISTED_DIFFERENCES) {
+            println!("{}", targets[*i]);
+            for (server, outcome) in servers.iter().zip(replayed) {
+                println!("\t{}: status = {}, {}", server, outcome.status, outcome.result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_access_line() {
+        let line = "access request_id=1 endpoint=route profile=driving status=200 latency_ms=1.5 waypoints=2 \
+                    settled=10 target=/route/v1/driving/13.38,52.51;13.39,52.52?alternatives=true";
+        assert_eq!(
+            parse_access_line(line),
+            Some(("route", "/route/v1/driving/13.38,52.51;13.39,52.52?alternatives=true"))
+        );
+        // Canaries have no target
+        let canary = "access request_id=canary-a endpoint=route profile=driving status=200 latency_ms=1.5";
+        assert_eq!(parse_access_line(canary), None);
+        assert_eq!(parse_access_line("Route found: cost = 10"), None);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = ["access.log", "http://localhost:5000/", "localhost:5001", "--concurrency", "8"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!(options.servers, vec!["localhost:5000", "localhost:5001"]);
+        assert_eq!(options.concurrency, 8);
+        assert_eq!(options.limit, None);
+        assert!(parse_options(&args[..1]).is_err());
+        assert!(parse_options(&[args[0].clone(), args[1].clone(), "--limit".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_result_summary() {
+        let route = |geometry: &str, duration: f64| {
+            json!({
+                "code": "Ok",
+                "routes": [{"geometry": geometry, "distance": 1200.0, "duration": duration}],
+                "waypoints": [{"hint": geometry}],
+            })
+            .to_string()
+        };
+        assert_eq!(result_summary(&route("abc", 60.0)), result_summary(&route("xyz", 60.0)));
+        assert_ne!(result_summary(&route("abc", 60.0)), result_summary(&route("abc", 61.0)));
+        assert_eq!(result_summary(" {\"source\":0}\n{\"source\":1}\n"), "{\"source\":0}\n{\"source\":1}");
+    }
+
+    #[test]
+    fn test_get() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let responder = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).unwrap();
+            let response = "HTTP/1.0 404 Not Found\r\ncontent-type: application/json\r\n\r\n{\"code\":\"NoRoute\"}";
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+        let outcome = replay(&server, "/route/v1/driving/1,2;3,4");
+        assert!(responder.join().unwrap().starts_with("GET /route/v1/driving/1,2;3,4 HTTP/1.0\r\n"));
+        assert_eq!(outcome.status, 404);
+        assert_eq!(outcome.result, result_summary("{\"code\":\"NoRoute\"}"));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let latencies = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(percentile(&latencies, 0.5), 5.0);
+        assert_eq!(percentile(&latencies, 0.9), 9.0);
+        assert_eq!(percentile(&latencies, 1.0), 10.0);
+        assert_eq!(percentile(&latencies, 0.0), 1.0);
+        assert_eq!(percentile(&[], 0.5), 0.0);
+    }
+}
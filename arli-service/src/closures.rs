@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+/// Road closure reported by the closure feed, e.g. a construction site or a street closed for an event
+#[derive(Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Closure {
+  /// Identifies the closure in the feed, a closure sent again with the same id replaces the previous one
+  pub id: String,
+  /// OSM ways closed in both directions
+  pub way_ids: Vec<i64>,
+  /// Start of the closure in seconds since the Unix epoch, the ways are closed right away if not set
+  pub start: Option<i64>,
+  /// End of the closure in seconds since the Unix epoch, the ways stay closed until it's replaced if not set
+  pub end: Option<i64>,
+  /// Reason of the closure, e.g. `construction`, only kept for the operators
+  pub reason: Option<String>,
+}
+
+impl Closure {
+  pub fn is_valid(&self) -> bool {
+    let window = match (self.start, self.end) {
+      (Some(start), Some(end)) => start < end,
+      _ => true,
+    };
+    !self.id.is_empty() && !self.way_ids.is_empty() && window
+  }
+
+  pub fn is_active_at(&self, time: i64) -> bool {
+    let started = match self.start {
+      Some(start) => start <= time,
+      None => true,
+    };
+    started && !self.has_ended(time)
+  }
+
+  fn has_ended(&self, time: i64) -> bool {
+    match self.end {
+      Some(end) => end <= time,
+      None => false,
+    }
+  }
+}
+
+/// Body of a closure feed update
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClosureFeed {
+  pub closures: Vec<Closure>,
+}
+
+/// Ways closed at one point in time
+#[derive(Default)]
+pub struct ClosedWays {
+  ways: HashSet<i64>,
+}
+
+impl ClosedWays {
+  pub fn is_empty(&self) -> bool {
+    self.ways.is_empty()
+  }
+
+  pub fn is_closed(&self, way_id: i64) -> bool {
+    self.ways.contains(&way_id)
+  }
+}
+
+/// Closures reported by the feed, including the ones starting later.
+///
+/// A query looks up the ways closed at its time, so a closure applies once its window starts and the ways open again
+/// as soon as it ends, without another update from the feed. Ended closures are dropped with the next update.
+#[derive(Default)]
+pub struct ClosureLayer {
+  closures: RwLock<HashMap<String, Closure>>,
+}
+
+impl ClosureLayer {
+  /// Adds the closures, replacing the ones with the same id
+  pub fn update(&self, closures: Vec<Closure>, now: i64) {
+    let mut current = self.closures.write().unwrap();
+    current.retain(|_, closure| !closure.has_ended(now));
+    for closure in closures {
+      if closure.has_ended(now) {
+        // An ended closure cancels the one it replaces
+        current.remove(&closure.id);
+      } else {
+        current.insert(closure.id.clone(), closure);
+      }
+    }
+  }
+
+  pub fn closed_at(&self, time: i64) -> ClosedWays {
+    let ways = self
+      .closures
+      .read()
+      .unwrap()
+      .values()
+      .filter(|closure| closure.is_active_at(time))
+      .flat_map(|closure| closure.way_ids.iter().cloned())
+      .collect();
+    ClosedWays { ways }
+  }
+}
+
+/// Current time in seconds since the Unix epoch, the time of the closure windows
+pub fn unix_time() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|since_epoch| since_epoch.as_secs() as i64)
+    .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn closure(id: &str, way_id: i64, start: Option<i64>, end: Option<i64>) -> Closure {
+    Closure {
+      id: id.to_string(),
+      way_ids: vec![way_id],
+      start,
+      end,
+      reason: None,
+    }
+  }
+
+  #[test]
+  fn test_closure_windows() {
+    let layer = ClosureLayer::default();
+    layer.update(
+      vec![
+        closure("works", 1, Some(100), Some(200)),
+        closure("event", 2, None, Some(150)),
+        closure("bridge", 3, Some(120), None),
+      ],
+      50,
+    );
+    assert!(layer.closed_at(50).is_closed(2));
+    assert!(!layer.closed_at(50).is_closed(1));
+
+    let closed = layer.closed_at(130);
+    assert!(closed.is_closed(1) && closed.is_closed(2) && closed.is_closed(3));
+
+    // The ways open again once the window ends
+    assert!(!layer.closed_at(160).is_closed(2));
+    assert!(!layer.closed_at(200).is_closed(1));
+    assert!(layer.closed_at(1000).is_closed(3));
+
+    // A closure is moved by sending it again and cancelled by ending it
+    layer.update(vec![closure("works", 1, Some(300), Some(400))], 60);
+    assert!(!layer.closed_at(130).is_closed(1));
+    assert!(layer.closed_at(300).is_closed(1));
+    layer.update(vec![closure("bridge", 3, None, Some(70))], 80);
+    assert!(!layer.closed_at(1000).is_closed(3));
+  }
+
+  #[test]
+  fn test_invalid_closures() {
+    assert!(closure("works", 1, None, None).is_valid());
+    assert!(!closure("works", 1, Some(200), Some(100)).is_valid());
+    assert!(!closure("", 1, None, None).is_valid());
+  }
+}
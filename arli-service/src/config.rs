@@ -0,0 +1,343 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Config file read when `ARLI_CONFIG` is not set, the service runs with the defaults if it doesn't exist
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+// Seconds a U-turn at a waypoint costs the built-in driving profile, about the time it takes to turn a car around
+const DRIVING_U_TURN_PENALTY_S: i32 = 30;
+// Longest straight-line distance between the waypoints of a request to each built-in profile, a longer trip is
+// rarely made without a break, while its searches settle most of the graph
+const DRIVING_MAX_DISTANCE_KM: f32 = 3000.0;
+const WALKING_MAX_DISTANCE_KM: f32 = 50.0;
+const CYCLING_MAX_DISTANCE_KM: f32 = 500.0;
+// Longest time the searches of a request to a built-in profile take, even the longest routes are found much faster
+const SEARCH_TIMEOUT_MS: u64 = 10_000;
+
+/// Service configuration, read from a JSON file. All settings are optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Requests to the routing endpoints aren't limited if not set
+  pub rate_limit: Option<RateLimitConfig>,
+  pub snap_cache: SnapCacheConfig,
+  pub tile_cache: TileCacheConfig,
+  pub tolls: TollConfig,
+  pub preferences: PreferenceConfig,
+  pub traffic: TrafficConfig,
+  pub live_traffic: LiveTrafficConfig,
+  pub frontend: FrontendConfig,
+  pub warm_up: WarmUpConfig,
+  /// Routing profiles, the built-in driving, walking and cycling ones if none are set
+  pub profiles: Vec<ProfileConfig>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct BucketConfig {
+  /// Rate the tokens are refilled at
+  pub requests_per_second: f64,
+  /// Size of the bucket, the number of requests which can be sent at once
+  pub burst: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+  /// Limit for each client IP which doesn't send an API key
+  pub per_ip: BucketConfig,
+  /// Limits for each API key sent in the `X-Api-Key` header. Requests with other keys are rejected
+  #[serde(default)]
+  pub api_keys: HashMap<String, BucketConfig>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapCacheConfig {
+  /// Seconds a matched waypoint is reused for
+  pub ttl_s: u64,
+  /// Largest number of cached waypoints, zero disables the cache
+  pub max_entries: usize,
+}
+
+impl Default for SnapCacheConfig {
+  fn default() -> Self {
+    Self {
+      ttl_s: 60,
+      max_entries: 100_000,
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TileCacheConfig {
+  /// Total size of the cached debug tiles
+  pub max_bytes: usize,
+}
+
+impl Default for TileCacheConfig {
+  fn default() -> Self {
+    Self {
+      max_bytes: 64 * 1024 * 1024,
+    }
+  }
+}
+
+/// Factors the cost of the segments a route request prefers to avoid is multiplied by
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreferenceConfig {
+  pub avoid_unpaved: f32,
+  pub avoid_highways: f32,
+  /// Applied to the segments which aren't lit
+  pub prefer_lit: f32,
+}
+
+impl Default for PreferenceConfig {
+  fn default() -> Self {
+    Self {
+      avoid_unpaved: 3.0,
+      avoid_highways: 2.0,
+      prefer_lit: 1.5,
+    }
+  }
+}
+
+/// Typical driving speeds over the day, used by the routes with a departure or an arrival time
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrafficConfig {
+  /// Multiplier of the driving speeds in each hour of the day in the local time, 24 values
+  pub hourly_speed_factors: Vec<f32>,
+  /// Offset of the local time of the graph region from UTC
+  pub utc_offset_s: i64,
+}
+
+impl Default for TrafficConfig {
+  fn default() -> Self {
+    Self {
+      hourly_speed_factors: vec![1.0; 24],
+      utc_offset_s: 0,
+    }
+  }
+}
+
+/// Speeds reported by a live traffic feed, used by the driving routes
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LiveTrafficConfig {
+  /// Updates are collected for this long before the routes use them
+  pub fold_interval_ms: u64,
+  /// Reports older than this are dropped, the speed of the way goes back to the profile speed
+  pub max_age_s: u64,
+}
+
+impl Default for LiveTrafficConfig {
+  fn default() -> Self {
+    Self {
+      fold_interval_ms: 1000,
+      max_age_s: 600,
+    }
+  }
+}
+
+/// Static files of the web frontend
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrontendConfig {
+  /// Directory of the files, relative to the working directory unless it's absolute
+  pub dir: PathBuf,
+  /// Seconds the browsers and CDNs may cache the assets for
+  pub max_age_s: u64,
+  /// The assets never change under the same name, e.g. their names have a content hash
+  pub immutable: bool,
+}
+
+impl Default for FrontendConfig {
+  fn default() -> Self {
+    Self {
+      dir: PathBuf::from("frontend"),
+      max_age_s: 3600,
+      immutable: false,
+    }
+  }
+}
+
+/// Canary routes run on every loaded graph before it serves requests, the service isn't ready if any of them fails
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmUpConfig {
+  pub routes: Vec<CanaryConfig>,
+  /// Latency limit of the canaries which don't set their own
+  pub max_latency_ms: u64,
+}
+
+impl Default for WarmUpConfig {
+  fn default() -> Self {
+    Self {
+      routes: Vec::new(),
+      max_latency_ms: 1000,
+    }
+  }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig {
+  /// Name the canary is reported by
+  pub name: String,
+  /// Profile name as in the request path, e.g. `driving`
+  pub profile: String,
+  /// Origin and destination as in the request path, e.g. `13.388,52.517;13.397,52.529`
+  pub waypoints: String,
+  pub max_latency_ms: Option<u64>,
+}
+
+/// Routing profile made of the cost functions of the service, picked by name
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+  /// Name in the request path, e.g. `driving`
+  pub name: String,
+  /// Other names accepted in the request path
+  #[serde(default)]
+  pub aliases: Vec<String>,
+  /// Segments the profile can travel along: `car`, `foot` or `bike`
+  pub access: String,
+  /// Cost of the segments: `driving_time`, `walking_time`, `cycling_time` or `length`
+  pub cost: String,
+  /// Distance reported for the routes, one of the costs
+  #[serde(default = "ProfileConfig::default_distance")]
+  pub distance: String,
+  /// Snapping of the waypoints: `road_class` prefers the faster roads, `distance` the closest segment
+  pub snapping: String,
+  /// Largest snapping distance, 300 meters for `road_class` and 100 meters for `distance` if not set
+  pub snap_radius_m: Option<f32>,
+  /// Waypoints with no segment accepted within the snapping radius are matched to the nearest segment within this
+  /// radius instead, and the `quality` of their routes is lowered. They fail with `NoSegment` if not set.
+  pub fallback_radius_m: Option<f32>,
+  /// Routes with a departure or an arrival time and the live traffic change the speeds
+  #[serde(default)]
+  pub traffic: bool,
+  /// Routes never take the turns forbidden by the OSM turn restrictions
+  #[serde(default)]
+  pub turn_restrictions: bool,
+  /// Routes may turn around at the waypoints for this cost, e.g. instead of going around the block to a destination
+  /// on the other side of the road. They can't if not set.
+  pub u_turn_penalty: Option<i32>,
+  /// Largest straight-line distance between two waypoints of a request, the requests aren't limited if not set
+  pub max_distance_km: Option<f32>,
+  /// Largest cost of a route, in the units of the cost including the preferences and penalties. The search gives up
+  /// once every route left costs more, e.g. from a waypoint on an island. The routes aren't limited if not set.
+  pub max_cost: Option<i32>,
+  /// Format of the route and trip geometries, `polyline` with 5 digits of the coordinates or `polyline6` with 6,
+  /// `polyline` if not set. Requests can ask for the other one.
+  pub geometries: Option<String>,
+  /// Longest time the searches of a request may take, measured from its arrival. The request fails with `Timeout`
+  /// once it passes. The searches aren't limited if not set.
+  pub search_timeout_ms: Option<u64>,
+  /// CSV file with the historical travel times of the segments, an `edge_id,p50,p85` line per segment in seconds. The
+  /// routes and tables plan with the median, or with the 85th percentile for the requests with
+  /// `reliability=pessimistic`. The other segments keep the travel times of the profile.
+  pub travel_times: Option<String>,
+  /// Segment weights computed outside of the service, e.g. by a risk or an emissions model, the routes search for the
+  /// cheapest path by them
+  pub weights: Option<WeightLayerConfig>,
+}
+
+impl ProfileConfig {
+  fn new(name: &str, aliases: &[&str], access: &str, cost: &str, snapping: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+      access: access.to_string(),
+      cost: cost.to_string(),
+      distance: ProfileConfig::default_distance(),
+      snapping: snapping.to_string(),
+      snap_radius_m: None,
+      fallback_radius_m: None,
+      traffic: false,
+      turn_restrictions: false,
+      u_turn_penalty: None,
+      max_distance_km: None,
+      max_cost: None,
+      geometries: None,
+      search_timeout_ms: None,
+      travel_times: None,
+      weights: None,
+    }
+  }
+
+  fn default_distance() -> String {
+    "length".to_string()
+  }
+
+  /// Profiles the service has without a config
+  pub fn builtin() -> Vec<ProfileConfig> {
+    vec![
+      ProfileConfig {
+        traffic: true,
+        turn_restrictions: true,
+        u_turn_penalty: Some(DRIVING_U_TURN_PENALTY_S),
+        max_distance_km: Some(DRIVING_MAX_DISTANCE_KM),
+        search_timeout_ms: Some(SEARCH_TIMEOUT_MS),
+        ..ProfileConfig::new("driving", &["car"], "car", "driving_time", "road_class")
+      },
+      ProfileConfig {
+        max_distance_km: Some(WALKING_MAX_DISTANCE_KM),
+        search_timeout_ms: Some(SEARCH_TIMEOUT_MS),
+        ..ProfileConfig::new("walking", &["foot"], "foot", "walking_time", "distance")
+      },
+      ProfileConfig {
+        max_distance_km: Some(CYCLING_MAX_DISTANCE_KM),
+        search_timeout_ms: Some(SEARCH_TIMEOUT_MS),
+        ..ProfileConfig::new("cycling", &["bike"], "bike", "cycling_time", "distance")
+      },
+    ]
+  }
+}
+
+/// Weight layer file of a profile
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WeightLayerConfig {
+  /// CSV file with an `edge_id,value` line per segment, the ids are the ones of `/edge/{id}` of the loaded graph
+  pub path: String,
+  /// Whether the values multiply the segment costs or replace them
+  pub kind: WeightKind,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightKind {
+  /// Factor of the cost of the profile, e.g. `1.5` for a segment to avoid
+  Factor,
+  /// Absolute cost in the units of the profile cost, e.g. seconds
+  Cost,
+}
+
+/// Toll tariffs, tolls aren't reported if none are set
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TollConfig {
+  /// Price of a kilometer of a toll road in each region, by the region name the graph was built with
+  pub per_km: HashMap<String, f32>,
+  /// Price of a kilometer of a toll road outside of the regions in `per_km`
+  pub default_per_km: f32,
+}
+
+impl Config {
+  /// Reads the config from the file set in `ARLI_CONFIG` or from `config.json`
+  pub fn load() -> Result<Config, String> {
+    match std::env::var("ARLI_CONFIG") {
+      Ok(path) => Config::from_file(&path),
+      Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(DEFAULT_CONFIG_PATH),
+      Err(_) => Ok(Config::default()),
+    }
+  }
+
+  pub fn from_file(path: &str) -> Result<Config, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Can't read config {}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid config {}: {}", path, e))
+  }
+}
@@ -0,0 +1,94 @@
+use arli::graph::{IntoGeometry, Spatial};
+use arli::spatial::{distance_to_polyline, envelope, haversine_distance, resample, Polyline, Position};
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// Weights of the segments along the corridor are multiplied by this factor, so the route only leaves the corridor
+/// if that's more than twice as fast
+pub const CORRIDOR_FACTOR: f32 = 0.5;
+
+/// Segments further from the corridor than this are not along it, unless the request sets another radius
+pub const DEFAULT_CORRIDOR_RADIUS_M: f32 = 25.0;
+
+const MAX_CORRIDOR_RADIUS_M: f32 = 200.0;
+
+// The nodes along the corridor are searched around markers one radius apart, this many at most
+const MAX_CORRIDOR_MARKERS: f32 = 20_000.0;
+
+/// Path a route is kept close to, e.g. the route the driver chose before leaving it
+pub struct Corridor {
+  polyline: Polyline,
+  radius_m: f32,
+}
+
+impl Corridor {
+  pub fn new(points: Vec<Position>, radius_m: f32) -> Result<Self, String> {
+    if points.len() < 2 {
+      return Err("A corridor needs at least two points".to_string());
+    }
+    if !(radius_m > 0.0 && radius_m <= MAX_CORRIDOR_RADIUS_M) {
+      return Err(format!("Corridor radius must be positive and at most {} meters", MAX_CORRIDOR_RADIUS_M));
+    }
+    let length_m: f32 = points.windows(2).map(|pair| haversine_distance(&pair[0], &pair[1])).sum();
+    let max_length_m = MAX_CORRIDOR_MARKERS * radius_m;
+    if length_m > max_length_m {
+      return Err(format!(
+        "Corridor with the radius of {} meters can be at most {:.0} km long",
+        radius_m,
+        max_length_m / 1000.0
+      ));
+    }
+    Ok(Self {
+      polyline: Polyline::from(points),
+      radius_m,
+    })
+  }
+
+  /// Nodes of the graph with all their geometry within the radius of the corridor
+  pub fn nodes<G>(&self, graph: G) -> HashSet<G::NodeId>
+  where
+    G: Copy + Spatial + IntoGeometry,
+    G::NodeId: Eq + Hash,
+  {
+    let mut checked = HashSet::new();
+    let mut nodes = HashSet::new();
+    // Markers closer than the radius to each other find all the nodes within the radius of the polyline
+    for marker in resample(&self.polyline.0, self.radius_m) {
+      for id in graph.find_nodes(&envelope(&marker.position, 2.0 * self.radius_m)) {
+        if checked.insert(id) && self.contains(graph.geometry(id).map(Into::into)) {
+          nodes.insert(id);
+        }
+      }
+    }
+    nodes
+  }
+
+  fn contains<I: Iterator<Item = Position>>(&self, mut geometry: I) -> bool {
+    geometry.all(|position| match distance_to_polyline(&self.polyline, &position) {
+      Some(distance) => distance <= self.radius_m,
+      None => false,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_corridor_contains() {
+    let corridor = Corridor::new(vec![Position { x: 13.4, y: 52.5 }, Position { x: 13.41, y: 52.5 }], 25.0).unwrap();
+    let along = vec![Position { x: 13.401, y: 52.5001 }, Position { x: 13.405, y: 52.5001 }];
+    assert!(corridor.contains(along.into_iter()));
+    // Crossing the corridor at a right angle, only the crossing is close to it
+    let across = vec![Position { x: 13.405, y: 52.499 }, Position { x: 13.405, y: 52.501 }];
+    assert!(!corridor.contains(across.into_iter()));
+
+    assert!(Corridor::new(vec![Position { x: 13.4, y: 52.5 }], 25.0).is_err());
+    assert!(Corridor::new(corridor.polyline.0.clone(), 0.0).is_err());
+    // About 680 km long, too many markers one radius apart
+    let long = vec![Position { x: 13.4, y: 52.5 }, Position { x: 23.4, y: 52.5 }];
+    assert!(Corridor::new(long.clone(), 25.0).is_err());
+    assert!(Corridor::new(long, 100.0).is_ok());
+  }
+}
@@ -1,13 +1,397 @@
-use arli::waypoint::SnappedPosition;
-use arli_osm::Segment;
+use crate::config::{PreferenceConfig, ProfileConfig};
+use crate::osrm_api::Geometries;
+use crate::travel_times::{read_travel_times, TravelTimes};
+use crate::weight_layer::{read_weight_layer, SegmentWeights};
+use arli::progress::CancellationToken;
+use arli::spatial::{haversine_distance, Position};
+use arli::waypoint::{distance_score, SnapCandidate, SnapOptions, SnappedPosition};
+use arli_osm::{Segment, TravelMode};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Constant speed of the pedestrian profile
+const WALKING_SPEED_KM_H: f32 = 5.0;
+// Walking up or down the stairs takes longer than walking the same distance
+const STEPS_SLOWDOWN: f32 = 2.0;
+// Expected wait for a gap in the traffic when crossing a major road without traffic lights
+const UNSIGNALIZED_CROSSING_PENALTY_S: f32 = 30.0;
+// Average speed of the cycling profile
+const CYCLING_SPEED_KM_H: f32 = 15.0;
+// Signed cycle routes are preferred over other streets, the routes are searched as if riding along them took less
+// time, the reported durations are the actual travel times
+const CYCLE_NETWORK_PREFERENCE: f32 = 0.7;
+// Snapping distance in meters a driving waypoint trades for each km/h of the speed limit, a motorway is preferred
+// over a parallel frontage road up to 16 meters closer
+const SNAP_METERS_PER_KM_H: f32 = 0.2;
+// GPS positions of vehicles on fast roads can be far off, a waypoint is snapped to a motorway from this distance
+const MAX_SNAP_DISTANCE_FAST_ROAD_M: f32 = 300.0;
+// Range of the speed factors accepted from a request
+const MIN_SPEED_FACTOR: f32 = 0.1;
+const MAX_SPEED_FACTOR: f32 = 2.0;
+// Waypoints are snapped to the other roads from this distance
+const MAX_SNAP_DISTANCE_M: f32 = 100.0;
+// Slower roads, like residential and service roads, accept only closer waypoints
+const MAX_SNAP_DISTANCE_SLOW_ROAD_M: f32 = 50.0;
+
+/// Soft preferences of a route request. Segments the request prefers to avoid cost more, but unlike the segments
+/// a profile can't access they are still used if there is no reasonable alternative.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Preferences {
+  pub avoid_unpaved: bool,
+  pub avoid_highways: bool,
+  /// Avoid the streets which aren't lit, e.g. walking at night
+  pub prefer_lit: bool,
+}
+
+impl Preferences {
+  /// Factor the cost of leaving the segment is multiplied by, the factors of all matching preferences are combined
+  pub fn factor(&self, segment: &Segment, factors: &PreferenceConfig) -> f32 {
+    let mut factor = 1.0;
+    if self.avoid_unpaved && segment.is_unpaved() {
+      factor *= factors.avoid_unpaved;
+    }
+    if self.avoid_highways && segment.is_highway() {
+      factor *= factors.avoid_highways;
+    }
+    if self.prefer_lit && !segment.is_lit() {
+      factor *= factors.prefer_lit;
+    }
+    factor
+  }
+}
+
+impl FromStr for Preferences {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut preferences = Preferences::default();
+    for name in s.split(',') {
+      match name {
+        "avoid_unpaved" => preferences.avoid_unpaved = true,
+        "avoid_highways" => preferences.avoid_highways = true,
+        "prefer_lit" => preferences.prefer_lit = true,
+        _ => return Err(format!("Unknown preference {}", name)),
+      }
+    }
+    Ok(preferences)
+  }
+}
+
+/// Predicate selecting the segments a profile can travel along
+pub type AccessFn = fn(&Segment) -> bool;
+/// Parameters of a request the costs of a profile depend on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CostContext {
+  /// Multiplier of the travel speeds, e.g. 0.9 for a cautious driver or 0.8 for a truck
+  pub speed_factor: f32,
+}
+
+impl Default for CostContext {
+  fn default() -> Self {
+    Self { speed_factor: 1.0 }
+  }
+}
+
+impl CostContext {
+  pub fn with_speed_factor(speed_factor: f32) -> Result<Self, String> {
+    if !(MIN_SPEED_FACTOR..=MAX_SPEED_FACTOR).contains(&speed_factor) {
+      return Err(format!(
+        "Speed factor must be between {} and {}",
+        MIN_SPEED_FACTOR, MAX_SPEED_FACTOR
+      ));
+    }
+    Ok(Self { speed_factor })
+  }
+}
+
+/// Cost of leaving a segment, the snapped position is set for a segment partially traveled from a waypoint
+pub type PartialCostFn = fn(&Segment, &Segment, Option<SnappedPosition>, &CostContext) -> i32;
+/// Score of a waypoint snapping candidate, lower is better
+pub type SnapScoreFn = fn(&SnapCandidate<Segment>) -> f32;
+/// Decides whether a waypoint can be snapped to a segment
+pub type SnapFilterFn = fn(&Segment, &SnappedPosition) -> bool;
+/// Factor the routing weight of leaving a segment is multiplied by, it doesn't change the reported duration
+pub type RoutingFactorFn = fn(&Segment) -> f32;
+/// Decides whether a segment is a low class road for a profile, the routes along them are less reliable
+pub type LowClassFn = fn(&Segment) -> bool;
+pub type ProfileSnapOptions = SnapOptions<SnapScoreFn, SnapFilterFn>;
+
+/// Index of a profile in the registry, the cached waypoints of each profile are kept apart by it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProfileId(pub usize);
+
+/// Routing profile, a set of cost functions picked by name in the config
+pub struct Profile {
+  pub id: ProfileId,
+  /// Name in the request path and in the access log
+  pub name: String,
+  aliases: Vec<String>,
+  pub access: AccessFn,
+  partial_cost: PartialCostFn,
+  routing_factor: RoutingFactorFn,
+  low_class: LowClassFn,
+  distance: PartialCostFn,
+  snap_score: SnapScoreFn,
+  snap_filter: SnapFilterFn,
+  snap_radius_m: f32,
+  fallback_radius_m: Option<f32>,
+  /// The typical and the live traffic change the speeds of the routes
+  pub traffic: bool,
+  /// Routes never take the turns forbidden by the OSM turn restrictions
+  pub turn_restrictions: bool,
+  /// Cost of turning around at a waypoint, the routes can't if it's `None`
+  pub u_turn_penalty: Option<i32>,
+  /// Largest straight-line distance between two waypoints of a request
+  pub max_distance_km: Option<f32>,
+  /// Largest cost of a route, the search gives up on the routes costing more
+  pub max_cost: Option<i32>,
+  /// Format of the geometries of the responses which don't ask for one
+  pub geometries: Geometries,
+  /// Longest time the searches of a request may take
+  pub search_timeout: Option<Duration>,
+  /// Historical travel times of the segments, the routes plan with them instead of the speed limits
+  pub travel_times: Option<TravelTimes>,
+  /// Weights of the segments computed outside of the service, they replace the costs of the routes where they're set
+  pub weights: Option<SegmentWeights>,
+}
+
+impl Profile {
+  fn from_config(id: ProfileId, config: &ProfileConfig) -> Result<Self, String> {
+    let (snap_score, snap_filter, snap_radius_m) = snapping_by_name(&config.snapping)?;
+    Ok(Self {
+      id,
+      name: config.name.clone(),
+      aliases: config.aliases.clone(),
+      access: access_by_name(&config.access)?,
+      partial_cost: cost_by_name(&config.cost)?,
+      routing_factor: routing_factor_by_name(&config.cost),
+      low_class: low_class_by_name(&config.access),
+      distance: cost_by_name(&config.distance)?,
+      snap_score,
+      snap_filter,
+      snap_radius_m: config.snap_radius_m.unwrap_or(snap_radius_m),
+      fallback_radius_m: config.fallback_radius_m,
+      traffic: config.traffic,
+      turn_restrictions: config.turn_restrictions,
+      u_turn_penalty: config.u_turn_penalty,
+      max_distance_km: config.max_distance_km,
+      max_cost: config.max_cost,
+      geometries: match &config.geometries {
+        Some(geometries) => geometries.parse()?,
+        None => Geometries::default(),
+      },
+      search_timeout: config.search_timeout_ms.map(Duration::from_millis),
+      travel_times: config.travel_times.as_deref().map(read_travel_times).transpose()?,
+      weights: config.weights.as_ref().map(read_weight_layer).transpose()?,
+    })
+  }
+
+  /// Names accepted in the request path
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(String::as_str))
+  }
+
+  /// Cost function of the profile for the parameters of a request
+  pub fn cost(&self, context: CostContext) -> impl Copy + Fn(&Segment, &Segment, Option<SnappedPosition>) -> i32 {
+    let cost = self.partial_cost;
+    move |from, to, snapped| cost(from, to, snapped, &context)
+  }
+
+  /// Factor of the routing weight of a segment on top of its cost, the routes prefer the segments with lower factors
+  pub fn routing_factor(&self, segment: &Segment) -> f32 {
+    (self.routing_factor)(segment)
+  }
+
+  /// The segment is a low class road for the travel mode of the profile, it lowers the quality of the routes
+  pub fn is_low_class(&self, segment: &Segment) -> bool {
+    (self.low_class)(segment)
+  }
+
+  /// Distance reported for the routes of the profile
+  pub fn distance(&self) -> impl Copy + Fn(&Segment, &Segment, Option<SnappedPosition>) -> i32 {
+    let distance = self.distance;
+    move |from, to, snapped| distance(from, to, snapped, &CostContext::default())
+  }
+
+  /// Error message if two of the waypoints are further apart than the profile allows. It's checked before any search,
+  /// a search between waypoints that far apart would settle most of the graph.
+  pub fn check_distance(&self, waypoints: &[Position]) -> Result<(), String> {
+    let max_distance_km = match self.max_distance_km {
+      Some(max_distance_km) => max_distance_km,
+      None => return Ok(()),
+    };
+    let distance_km = waypoints
+      .iter()
+      .enumerate()
+      .flat_map(|(i, from)| waypoints[i + 1..].iter().map(move |to| haversine_distance(from, to) / 1000.0))
+      .fold(0.0, f32::max);
+    if distance_km > max_distance_km {
+      return Err(format!(
+        "Waypoints are {:.0} km apart, at most {} km are allowed for the {} profile",
+        distance_km, max_distance_km, self.name
+      ));
+    }
+    Ok(())
+  }
+
+  /// Token of a request to the profile, cancelled once the searches of the request run out of time
+  pub fn request_token(&self) -> CancellationToken {
+    match self.search_timeout {
+      Some(timeout) => CancellationToken::new().with_timeout(timeout),
+      None => CancellationToken::new(),
+    }
+  }
+
+  pub fn snap_options(&self) -> ProfileSnapOptions {
+    let mut options = SnapOptions::new(self.snap_score).with_filter(self.snap_filter);
+    options.radius_m = self.snap_radius_m;
+    options.fallback_radius_m = self.fallback_radius_m;
+    options
+  }
+}
+/// Profiles of the service by the names in the request path
+pub struct ProfileRegistry {
+  profiles: Vec<Arc<Profile>>,
+}
+
+impl ProfileRegistry {
+  /// Profiles of the config, the built-in driving, walking and cycling ones if there are none. A profile with an
+  /// unknown cost function or a name taken by another profile is an error.
+  pub fn new(configs: &[ProfileConfig]) -> Result<Self, String> {
+    let builtin = ProfileConfig::builtin();
+    let configs = if configs.is_empty() { &builtin } else { configs };
+    let mut profiles: Vec<Arc<Profile>> = Vec::new();
+    for (i, config) in configs.iter().enumerate() {
+      let profile =
+        Profile::from_config(ProfileId(i), config).map_err(|e| format!("Invalid profile {}: {}", config.name, e))?;
+      let taken = |name: &&str| profiles.iter().any(|other| other.names().any(|n| n == *name));
+      if let Some(name) = profile.names().find(taken) {
+        return Err(format!("Profile name {} is used twice", name));
+      }
+      profiles.push(Arc::new(profile));
+    }
+    Ok(Self { profiles })
+  }
+
+  pub fn find(&self, name: &str) -> Option<Arc<Profile>> {
+    self
+      .profiles
+      .iter()
+      .find(|profile| profile.names().any(|n| n == name))
+      .cloned()
+  }
+
+  /// All the names accepted in the request path
+  pub fn names(&self) -> Vec<&str> {
+    self.profiles.iter().flat_map(|profile| profile.names()).collect()
+  }
+}
+
+fn access_by_name(name: &str) -> Result<AccessFn, String> {
+  match name {
+    "car" => Ok(car_access),
+    "foot" => Ok(foot_access),
+    "bike" => Ok(bike_access),
+    _ => Err(format!("Unknown access {}", name)),
+  }
+}
+
+fn cost_by_name(name: &str) -> Result<PartialCostFn, String> {
+  match name {
+    "driving_time" => Ok(time_partial_cost),
+    "walking_time" => Ok(walking_time_partial_cost),
+    "cycling_time" => Ok(cycling_partial_cost),
+    "length" => Ok(length_partial_cost),
+    _ => Err(format!("Unknown cost {}", name)),
+  }
+}
+
+// Routes of the cycling cost prefer the signed cycle routes, the other costs have no preference of their own
+fn routing_factor_by_name(cost: &str) -> RoutingFactorFn {
+  match cost {
+    "cycling_time" => cycle_network_factor,
+    _ => no_routing_factor,
+  }
+}
+
+fn cycle_network_factor(segment: &Segment) -> f32 {
+  if segment.on_cycle_network() {
+    CYCLE_NETWORK_PREFERENCE
+  } else {
+    1.0
+  }
+}
+
+fn no_routing_factor(_segment: &Segment) -> f32 {
+  1.0
+}
+
+// Low class roads by the travel mode of the access: the slow roads for the cars, the unpaved ways, e.g. tracks and
+// trails, for the walking and the cycling profiles
+fn low_class_by_name(access: &str) -> LowClassFn {
+  match access {
+    "car" => is_slow_road,
+    _ => is_unpaved,
+  }
+}
+
+// Score, filter and default radius of the snapping
+fn snapping_by_name(name: &str) -> Result<(SnapScoreFn, SnapFilterFn, f32), String> {
+  match name {
+    "road_class" => Ok((car_snap_score, car_snap_filter, MAX_SNAP_DISTANCE_FAST_ROAD_M)),
+    "distance" => Ok((distance_score, any_segment, MAX_SNAP_DISTANCE_M)),
+    _ => Err(format!("Unknown snapping {}", name)),
+  }
+}
+
+pub fn car_access(segment: &Segment) -> bool {
+  segment.allows(TravelMode::Car)
+}
+
+pub fn foot_access(segment: &Segment) -> bool {
+  segment.allows(TravelMode::Foot)
+}
+
+pub fn bike_access(segment: &Segment) -> bool {
+  segment.allows(TravelMode::Bike)
+}
+
+pub fn car_snap_score(candidate: &SnapCandidate<Segment>) -> f32 {
+  candidate.snapped.distance - candidate.data.speed_limit() as f32 * SNAP_METERS_PER_KM_H
+}
+
+// Largest snapping distance by the speed limit, as a proxy of the road class
+pub fn car_snap_filter(segment: &Segment, snapped: &SnappedPosition) -> bool {
+  let max_distance = match segment.speed_limit() {
+    0..=30 => MAX_SNAP_DISTANCE_SLOW_ROAD_M,
+    31..=79 => MAX_SNAP_DISTANCE_M,
+    _ => MAX_SNAP_DISTANCE_FAST_ROAD_M,
+  };
+  snapped.distance <= max_distance
+}
+
+pub fn any_segment(_: &Segment, _: &SnappedPosition) -> bool {
+  true
+}
+
+// Slow roads, like service roads and tracks, are mapped less carefully than the main roads
+pub fn is_slow_road(segment: &Segment) -> bool {
+  segment.speed_limit() <= 30
+}
+
+// Unpaved ways are mapped less carefully than the streets, and may be impassable after the rain
+pub fn is_unpaved(segment: &Segment) -> bool {
+  segment.is_unpaved()
+}
 
 pub fn distance_cost(from: &Segment, _to: &Segment) -> i32 {
-  from.length as i32
+  from.length() as i32
 }
 
 pub fn time_cost(from: &Segment, _to: &Segment) -> i32 {
-  if from.speed_limit > 0 {
-    (from.length * 3.6 / from.speed_limit as f32) as i32
+  if from.speed_limit() > 0 {
+    (from.length() * 3.6 / from.speed_limit() as f32) as i32
   } else {
     3600
   }
@@ -21,18 +405,127 @@ pub fn distance_partial_cost(
   let (factor, distance) = snapped
     .map(|s| (s.factor, s.distance))
     .unwrap_or((1.0, 0.0));
-  (from.length * factor as f32 + distance * 1.4) as i32
+  (from.length() * factor as f32 + distance * 1.4) as i32
 }
 
-pub fn time_partial_cost(from: &Segment, _to: &Segment, snapped: Option<SnappedPosition>) -> i32 {
+pub fn length_partial_cost(
+  from: &Segment,
+  to: &Segment,
+  snapped: Option<SnappedPosition>,
+  _context: &CostContext,
+) -> i32 {
+  distance_partial_cost(from, to, snapped)
+}
+
+pub fn time_partial_cost(
+  from: &Segment,
+  _to: &Segment,
+  snapped: Option<SnappedPosition>,
+  context: &CostContext,
+) -> i32 {
   let (factor, distance) = snapped
     .map(|s| (s.factor, s.distance))
     .unwrap_or((1.0, 0.0));
 
-  if from.speed_limit > 0 {
+  if from.speed_limit() > 0 {
+    let speed = from.speed_limit() as f32 * context.speed_factor;
     // Assume pedestrian speed of 4 km/h for the distance to matched waypoint
-    (from.length * 3.6 * factor as f32 / from.speed_limit as f32 + distance * 3.6 / 4.0) as i32
+    (from.length() * 3.6 * factor as f32 / speed + distance * 3.6 / 4.0) as i32
   } else {
     3600
   }
 }
+
+pub fn walking_time_partial_cost(
+  from: &Segment,
+  _to: &Segment,
+  snapped: Option<SnappedPosition>,
+  context: &CostContext,
+) -> i32 {
+  let (factor, distance) = snapped
+    .map(|s| (s.factor, s.distance))
+    .unwrap_or((1.0, 0.0));
+
+  let slowdown = if from.has_steps() { STEPS_SLOWDOWN } else { 1.0 };
+  let crossing = if from.has_unsignalized_crossing() {
+    UNSIGNALIZED_CROSSING_PENALTY_S
+  } else {
+    0.0
+  };
+
+  let speed = WALKING_SPEED_KM_H * context.speed_factor;
+  ((from.length() * factor * slowdown + distance) * 3.6 / speed + crossing) as i32
+}
+
+pub fn cycling_partial_cost(
+  from: &Segment,
+  _to: &Segment,
+  snapped: Option<SnappedPosition>,
+  context: &CostContext,
+) -> i32 {
+  let (factor, distance) = snapped
+    .map(|s| (s.factor, s.distance))
+    .unwrap_or((1.0, 0.0));
+
+  let speed = CYCLING_SPEED_KM_H * context.speed_factor;
+  ((from.length() * factor + distance) * 3.6 / speed) as i32
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_profile_registry() {
+    let builtin = ProfileRegistry::new(&[]).unwrap();
+    assert_eq!(builtin.names(), vec!["driving", "car", "walking", "foot", "cycling", "bike"]);
+    let driving = builtin.find("car").unwrap();
+    assert_eq!(driving.name, "driving");
+    assert!(driving.traffic);
+    assert!(!builtin.find("foot").unwrap().traffic);
+    assert!(driving.turn_restrictions && !builtin.find("foot").unwrap().turn_restrictions);
+    assert_eq!(driving.u_turn_penalty, Some(30));
+    assert!(builtin.find("boat").is_none());
+
+    let berlin = Position { x: 13.388_86, y: 52.517_04 };
+    let potsdam = Position { x: 13.064_47, y: 52.400_93 };
+    let munich = Position { x: 11.575_49, y: 48.137_15 };
+    assert!(builtin.find("foot").unwrap().check_distance(&[berlin, potsdam]).is_ok());
+    let too_far = builtin.find("foot").unwrap().check_distance(&[berlin, potsdam, munich]);
+    assert_eq!(too_far.unwrap_err(), "Waypoints are 504 km apart, at most 50 km are allowed for the walking profile");
+    assert!(driving.check_distance(&[berlin, munich]).is_ok());
+
+    let truck = ProfileConfig {
+      name: "truck".to_string(),
+      aliases: Vec::new(),
+      access: "car".to_string(),
+      cost: "driving_time".to_string(),
+      distance: "length".to_string(),
+      snapping: "road_class".to_string(),
+      snap_radius_m: Some(500.0),
+      fallback_radius_m: Some(1000.0),
+      traffic: true,
+      turn_restrictions: true,
+      u_turn_penalty: Some(60),
+      max_distance_km: None,
+      max_cost: None,
+      geometries: Some("polyline6".to_string()),
+      search_timeout_ms: None,
+      travel_times: None,
+      weights: None,
+    };
+    let registry = ProfileRegistry::new(std::slice::from_ref(&truck)).unwrap();
+    assert_eq!(registry.names(), vec!["truck"]);
+    assert_eq!(registry.find("truck").unwrap().snap_options().radius_m, 500.0);
+    assert_eq!(registry.find("truck").unwrap().snap_options().fallback_radius_m, Some(1000.0));
+    assert_eq!(driving.snap_options().fallback_radius_m, None);
+    assert_eq!(registry.find("truck").unwrap().geometries, Geometries::Polyline6);
+
+    let unknown_cost = ProfileConfig {
+      cost: "sailing_time".to_string(),
+      ..truck.clone()
+    };
+    assert!(ProfileRegistry::new(&[unknown_cost]).is_err());
+    assert!(ProfileRegistry::new(&[truck.clone(), truck]).is_err());
+  }
+}
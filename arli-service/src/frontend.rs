@@ -0,0 +1,41 @@
+use crate::config::FrontendConfig;
+use warp::{Filter, Rejection};
+
+// Cache-Control of the frontend assets
+fn cache_control(config: &FrontendConfig) -> String {
+  let mut value = format!("public, max-age={}", config.max_age_s);
+  if config.immutable {
+    value.push_str(", immutable");
+  }
+  value
+}
+
+/// Static files of the frontend at `/frontend`. The page itself is revalidated on every load, so a new deployment
+/// shows up right away, while the assets are cached for `max_age_s`. Paths which aren't files fall back to the page,
+/// so the frontend can route them itself.
+pub fn frontend_files(config: &FrontendConfig) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+  let index = config.dir.join("index.html");
+  let no_cache = warp::reply::with::header("cache-control", "no-cache");
+  let page = warp::path::end()
+    .or(warp::path("index.html").and(warp::path::end()))
+    .and(warp::fs::file(index.clone()))
+    .map(|_, file| file)
+    .with(no_cache.clone());
+  let assets = warp::fs::dir(config.dir.clone()).with(warp::reply::with::header("cache-control", cache_control(config)));
+  let fallback = warp::fs::file(index).with(no_cache);
+  warp::path("frontend").and(page.or(assets).or(fallback))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cache_control() {
+    let mut config = FrontendConfig::default();
+    assert_eq!(cache_control(&config), "public, max-age=3600");
+    config.immutable = true;
+    config.max_age_s = 31_536_000;
+    assert_eq!(cache_control(&config), "public, max-age=31536000, immutable");
+  }
+}
@@ -0,0 +1,249 @@
+use arli::graph::IntoGeometry;
+use arli::graph_impl::Idx;
+use arli::spatial::{distance_to_polyline, haversine_distance, Polyline, Position};
+use arli::waypoint::{MatchedWaypoint, SnappedOnEdge, SnappedPosition};
+use std::convert::TryInto;
+use std::str::FromStr;
+
+// Format of the encoded hints, a hint of another version is invalid
+const HINT_VERSION: u8 = 1;
+// Bytes of the version and of the coordinate the waypoint was matched for
+const HEADER_BYTES: usize = 9;
+// Bytes of the node id, the factor, the distance and the snapped coordinate of a candidate
+const CANDIDATE_BYTES: usize = 20;
+// The hint is used for a coordinate this close to the one it was matched for, a vehicle which moved further is
+// matched again
+const MAX_HINT_MOVE_M: f32 = 5.0;
+// Snapped positions are encoded as `f32`, a candidate a bit off its segment is still on it
+const MAX_SNAPPED_OFFSET_M: f32 = 1.0;
+// URL safe base64 alphabet, the hints are sent in the query string as OSRM sends its hints
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Waypoint matched by an earlier request, returned to the client as an opaque base64 string like the OSRM hints.
+///
+/// A client sends the hints back with the next request for the same waypoints, e.g. a re-route or the next leg, so the
+/// waypoints aren't matched again and snap to the same segments as before. A hint only refers to the node ids, so it
+/// is checked against the graph it's used on, see [`WaypointHint::matched_on`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaypointHint {
+  waypoint: Position,
+  candidates: Vec<(Idx, SnappedPosition)>,
+}
+
+impl WaypointHint {
+  /// Hint of the matched waypoint, `None` if it has no candidates
+  pub fn encode(matched: &MatchedWaypoint<Idx>) -> Option<String> {
+    if matched.snapped.is_empty() {
+      return None;
+    }
+    let mut bytes = vec![HINT_VERSION];
+    bytes.extend_from_slice(&matched.waypoint.x.to_le_bytes());
+    bytes.extend_from_slice(&matched.waypoint.y.to_le_bytes());
+    for SnappedOnEdge(snapped, id) in &matched.snapped {
+      bytes.extend_from_slice(&id.to_le_bytes());
+      for value in &[snapped.factor, snapped.distance, snapped.snapped.x, snapped.snapped.y] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+      }
+    }
+    Some(encode_base64(&bytes))
+  }
+
+  /// Matched waypoint of the hint if it's still valid for the position on the graph: the position is close to the one
+  /// the hint was matched for, and each candidate is a node accepted by `accepts` with the snapped position on its
+  /// geometry. A hint made on another graph, or for another profile, is `None`, the waypoint is matched again then.
+  pub fn matched_on<G, A>(&self, graph: G, accepts: A, position: &Position) -> Option<MatchedWaypoint<Idx>>
+  where
+    G: Copy + IntoGeometry<NodeId = Idx>,
+    A: Fn(Idx) -> bool,
+  {
+    if haversine_distance(&self.waypoint, position) > MAX_HINT_MOVE_M {
+      return None;
+    }
+    let on_node = |id: Idx, snapped: &SnappedPosition| {
+      let geometry: Polyline = graph.geometry(id).map(Into::into).collect::<Vec<Position>>().into();
+      matches!(distance_to_polyline(&geometry, &snapped.snapped), Some(offset) if offset <= MAX_SNAPPED_OFFSET_M)
+    };
+    if !self.candidates.iter().all(|(id, snapped)| accepts(*id) && on_node(*id, snapped)) {
+      return None;
+    }
+    Some(MatchedWaypoint {
+      waypoint: *position,
+      snapped: self.candidates.iter().map(|(id, snapped)| SnappedOnEdge(*snapped, *id)).collect(),
+    })
+  }
+
+  // Hint of the decoded bytes, `None` if they aren't a hint of this version, e.g. a hint of an OSRM server, or if a
+  // candidate has a factor or a distance the matching never produces. The costs of the partially traveled segments
+  // are computed from them, a forged hint could make a route cost less than nothing.
+  fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    let candidates = bytes.len().checked_sub(HEADER_BYTES)?;
+    if bytes[0] != HINT_VERSION || candidates == 0 || candidates % CANDIDATE_BYTES != 0 {
+      return None;
+    }
+
+    let word = |at: usize| -> [u8; 4] { bytes[at..at + 4].try_into().unwrap() };
+    let float = |at: usize| f32::from_le_bytes(word(at));
+    let candidates = (HEADER_BYTES..bytes.len())
+      .step_by(CANDIDATE_BYTES)
+      .map(|at| {
+        let snapped = SnappedPosition {
+          factor: float(at + 4),
+          distance: float(at + 8),
+          snapped: Position {
+            x: float(at + 12),
+            y: float(at + 16),
+          },
+        };
+        (u32::from_le_bytes(word(at)), snapped)
+      })
+      .collect::<Vec<_>>();
+    let plausible = |snapped: &SnappedPosition| {
+      snapped.factor.is_finite()
+        && (0.0..=1.0).contains(&snapped.factor)
+        && snapped.distance.is_finite()
+        && snapped.distance >= 0.0
+    };
+    if !candidates.iter().all(|(_, snapped)| plausible(snapped)) {
+      return None;
+    }
+    Some(WaypointHint {
+      waypoint: Position { x: float(1), y: float(5) },
+      candidates,
+    })
+  }
+}
+
+impl FromStr for WaypointHint {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    decode_base64(s)
+      .and_then(|bytes| WaypointHint::from_bytes(&bytes))
+      .ok_or_else(|| format!("Invalid hint {}", s))
+  }
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+  let mut encoded = String::new();
+  for chunk in bytes.chunks(3) {
+    let word = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |word, (i, byte)| word | u32::from(*byte) << (16 - 8 * i));
+    // A chunk of n bytes takes n + 1 characters, there is no padding
+    for i in 0..=chunk.len() {
+      encoded.push(BASE64_ALPHABET[(word >> (18 - 6 * i) & 0x3f) as usize] as char);
+    }
+  }
+  encoded
+}
+
+// Decodes both the URL safe and the standard alphabet, with or without the padding
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+  let value = |c: u8| match c {
+    b'A'..=b'Z' => Some(c - b'A'),
+    b'a'..=b'z' => Some(c - b'a' + 26),
+    b'0'..=b'9' => Some(c - b'0' + 52),
+    b'-' | b'+' => Some(62),
+    b'_' | b'/' => Some(63),
+    _ => None,
+  };
+  let s = s.trim_end_matches('=');
+  if s.len() % 4 == 1 {
+    return None;
+  }
+  let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+  for chunk in s.as_bytes().chunks(4) {
+    let word = chunk
+      .iter()
+      .enumerate()
+      .try_fold(0u32, |word, (i, c)| Some(word | u32::from(value(*c)?) << (18 - 6 * i)))?;
+    for i in 0..chunk.len() - 1 {
+      bytes.push((word >> (16 - 8 * i)) as u8);
+    }
+  }
+  Some(bytes)
+}
+
+/// Hints sent with a request, one per waypoint separated by `;`. A waypoint without a hint has an empty one.
+///
+/// OSRM clients send back whatever hints they got, so a base64 hint which isn't a hint of this service, e.g. one of an
+/// OSRM server, is ignored and its waypoint is matched again, the way OSRM ignores the hints of another dataset. A hint
+/// which isn't base64 is invalid.
+pub fn parse_hints(hints: &str, waypoints: usize) -> Result<Vec<Option<WaypointHint>>, String> {
+  let parse = |hint: &str| {
+    if hint.is_empty() {
+      return Ok(None);
+    }
+    match decode_base64(hint) {
+      Some(bytes) => Ok(WaypointHint::from_bytes(&bytes)),
+      None => Err(format!("Invalid hint {}", hint)),
+    }
+  };
+  let hints = hints.split(';').map(parse).collect::<Result<Vec<_>, _>>()?;
+  if hints.len() != waypoints {
+    return Err(format!("Got {} hints for {} waypoints", hints.len(), waypoints));
+  }
+  Ok(hints)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_hint_round_trip() {
+    let waypoint = Position { x: 13.388_86, y: 52.517_04 };
+    let snapped = SnappedPosition {
+      snapped: Position { x: 13.388_9, y: 52.517_1 },
+      distance: 7.5,
+      factor: 0.25,
+    };
+    let matched = MatchedWaypoint {
+      waypoint,
+      snapped: vec![SnappedOnEdge(snapped, 42), SnappedOnEdge(snapped, 43)],
+    };
+    let hint = WaypointHint::encode(&matched).unwrap();
+    let decoded: WaypointHint = hint.parse().unwrap();
+    assert_eq!(decoded.waypoint, waypoint);
+    assert_eq!(decoded.candidates, vec![(42, snapped), (43, snapped)]);
+
+    assert_eq!(WaypointHint::encode(&MatchedWaypoint { waypoint, snapped: vec![] }), None);
+    assert!("".parse::<WaypointHint>().is_err());
+    assert!(hint[..hint.len() - 2].parse::<WaypointHint>().is_err());
+    assert!(format!("02{}", &hint[2..]).parse::<WaypointHint>().is_err());
+
+    let hints = parse_hints(&format!(";{}", hint), 2).unwrap();
+    assert_eq!(hints, vec![None, Some(decoded)]);
+    assert!(parse_hints(&hint, 2).is_err());
+
+    // A hint of an OSRM server is ignored, a hint which isn't base64 is invalid
+    let osrm_hint = "zv8OgP___38AAAAAAAAAAA0AAAAAAAAAAAAAAAAAAAANAAAAAAAAAAEAAAA";
+    assert_eq!(parse_hints(osrm_hint, 1).unwrap(), vec![None]);
+    assert!(parse_hints("not a hint!", 1).is_err());
+
+    // A candidate off its segment or snapped from a negative distance is ignored
+    for (factor, distance) in &[(1.5, 7.5), (-0.25, 7.5), (f32::NAN, 7.5), (0.25, -1.0), (0.25, f32::INFINITY)] {
+      let forged = SnappedPosition {
+        factor: *factor,
+        distance: *distance,
+        ..snapped
+      };
+      let forged = MatchedWaypoint {
+        waypoint,
+        snapped: vec![SnappedOnEdge(snapped, 42), SnappedOnEdge(forged, 43)],
+      };
+      assert_eq!(parse_hints(&WaypointHint::encode(&forged).unwrap(), 1).unwrap(), vec![None]);
+    }
+  }
+
+  #[test]
+  fn test_base64() {
+    assert_eq!(encode_base64(b"arli"), "YXJsaQ");
+    assert_eq!(encode_base64(&[0xfb, 0xff]), "-_8");
+    assert_eq!(decode_base64("YXJsaQ==").unwrap(), b"arli");
+    assert_eq!(decode_base64("+/8").unwrap(), vec![0xfb, 0xff]);
+    assert_eq!(decode_base64(""), Some(vec![]));
+    assert_eq!(decode_base64("YXJsa"), None);
+  }
+}
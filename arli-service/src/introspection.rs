@@ -0,0 +1,65 @@
+use arli::graph::{neighbors_backward, neighbors_forward, GraphData, IntoGeometry};
+use arli::graph_impl::Idx;
+use arli_osm::{reverse_segment, OsmGraph, TravelMode};
+use serde_json::{json, Value};
+
+/// Attributes of the segment, shared by the debug tiles and the introspection endpoints
+pub fn segment_properties(graph: &OsmGraph, id: Idx) -> Value {
+  let segment = graph.data(id);
+  let flags: Vec<_> = segment
+    .flags()
+    .iter().filter(|(_, set)| *set).map(|(flag, _)| *flag).collect();
+  let mut properties = json!({
+    "id": id,
+    "way_id": graph.way_id(id),
+    "name": graph.street_name(id),
+    "region": graph.region(id),
+    "length": segment.length(),
+    "speed_limit": segment.speed_limit(),
+    "car": segment.allows(TravelMode::Car),
+    "bike": segment.allows(TravelMode::Bike),
+    "foot": segment.allows(TravelMode::Foot),
+    "flags": flags,
+  });
+  if let Some(junctions) = graph.junctions() {
+    properties["from_node"] = junctions.osm_node_id(junctions.start(id)).into();
+    properties["to_node"] = junctions.osm_node_id(junctions.end(id)).into();
+  }
+  properties
+}
+
+// Short description of a connected segment, enough to look it up next
+fn neighbor(graph: &OsmGraph, id: Idx) -> Value {
+  json!({
+    "id": id,
+    "way_id": graph.way_id(id),
+    "name": graph.street_name(id),
+  })
+}
+
+/// Segment with its geometry and the segments a route can continue on or come from, `None` if there is no such
+/// segment
+pub fn describe_edge(graph: &OsmGraph, id: Idx) -> Option<Value> {
+  if id as usize >= graph.number_of_nodes() {
+    return None;
+  }
+  let mut edge = segment_properties(graph, id);
+  let geometry: Vec<_> = graph.geometry(id).map(|p| vec![p.x, p.y]).collect();
+  edge["geometry"] = geometry.into();
+  edge["reverse"] = reverse_segment(graph, id).into();
+  let outgoing: Vec<_> = neighbors_forward(graph, id).map(|next| neighbor(graph, next)).collect();
+  let incoming: Vec<_> = neighbors_backward(graph, id).map(|previous| neighbor(graph, previous)).collect();
+  edge["outgoing"] = outgoing.into();
+  edge["incoming"] = incoming.into();
+  Some(edge)
+}
+
+/// Segments built from the OSM way in both directions, `None` if the way isn't a part of the graph
+pub fn describe_way(graph: &OsmGraph, way_id: i64) -> Option<Value> {
+  let ids = graph.find_edges_by_way_id(way_id);
+  if ids.is_empty() {
+    return None;
+  }
+  let edges: Vec<_> = ids.iter().filter_map(|id| describe_edge(graph, *id)).collect();
+  Some(json!({ "way_id": way_id, "edges": edges }))
+}
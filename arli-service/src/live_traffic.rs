@@ -0,0 +1,153 @@
+use crate::config::LiveTrafficConfig;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Speed measured on an OSM way by the traffic feed, relative to the speed of the profile
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TrafficUpdate {
+  pub way_id: i64,
+  /// Factor the profile speed on the way is multiplied by
+  #[schema(exclusive_minimum = 0)]
+  pub speed_factor: f32,
+}
+
+impl TrafficUpdate {
+  pub fn is_valid(&self) -> bool {
+    self.speed_factor.is_finite() && self.speed_factor > 0.0
+  }
+}
+
+/// Speeds of the ways reported by the traffic feed at one point in time. A snapshot never changes, the updates are
+/// folded into a new one.
+#[derive(Default)]
+pub struct TrafficSnapshot {
+  // Latest speed factor of each way with the time it was reported at
+  factors: HashMap<i64, (f32, Instant)>,
+}
+
+impl TrafficSnapshot {
+  pub fn is_empty(&self) -> bool {
+    self.factors.is_empty()
+  }
+
+  /// Factor the speed on the way is multiplied by, 1 if the way has no recent report
+  pub fn speed_factor(&self, way_id: i64) -> f32 {
+    self.factors.get(&way_id).map(|(factor, _)| *factor).unwrap_or(1.0)
+  }
+
+  // Next snapshot with the updates reported at the time, the reports older than `max_age` are dropped
+  fn fold(&self, updates: &[TrafficUpdate], now: Instant, max_age: Duration) -> TrafficSnapshot {
+    let mut factors: HashMap<_, _> = self
+      .factors
+      .iter()
+      .filter(|(_, (_, reported))| now.duration_since(*reported) < max_age)
+      .map(|(way_id, report)| (*way_id, *report))
+      .collect();
+    for update in updates {
+      factors.insert(update.way_id, (update.speed_factor, now));
+    }
+    TrafficSnapshot { factors }
+  }
+}
+
+/// Live traffic read by the route queries and updated by the traffic feed.
+///
+/// Queries take the current snapshot and keep using it, so they never wait for the updates. A background thread
+/// collects the updates for `fold_interval_ms` and folds them into the next snapshot, dropping the expired reports.
+/// The lock is only held to clone or to swap the snapshot pointer.
+pub struct LiveTraffic {
+  current: Arc<RwLock<Arc<TrafficSnapshot>>>,
+  updates: Mutex<Sender<Vec<TrafficUpdate>>>,
+}
+
+impl LiveTraffic {
+  pub fn start(config: &LiveTrafficConfig) -> Self {
+    let max_age = Duration::from_secs(config.max_age_s);
+    let current = Arc::new(RwLock::new(Arc::new(TrafficSnapshot::default())));
+    let (sender, receiver) = channel();
+    let folded = current.clone();
+    let interval = Duration::from_millis(config.fold_interval_ms);
+    std::thread::spawn(move || fold_updates(&receiver, &folded, interval, max_age));
+    Self {
+      current,
+      updates: Mutex::new(sender),
+    }
+  }
+
+  pub fn snapshot(&self) -> Arc<TrafficSnapshot> {
+    Arc::clone(&self.current.read().unwrap())
+  }
+
+  /// Queues the updates, they are used by the queries once the next snapshot is folded
+  pub fn update(&self, updates: Vec<TrafficUpdate>) {
+    // The folding thread only stops once the sender is dropped
+    let _ = self.updates.lock().unwrap().send(updates);
+  }
+}
+
+// Folds the updates received within each interval into a new snapshot, until the traffic is dropped
+fn fold_updates(
+  receiver: &Receiver<Vec<TrafficUpdate>>,
+  current: &RwLock<Arc<TrafficSnapshot>>,
+  interval: Duration,
+  max_age: Duration,
+) {
+  loop {
+    let deadline = Instant::now() + interval;
+    let mut updates = Vec::new();
+    loop {
+      let now = Instant::now();
+      if now >= deadline {
+        break;
+      }
+      match receiver.recv_timeout(deadline - now) {
+        Ok(more) => updates.extend(more),
+        Err(RecvTimeoutError::Timeout) => break,
+        Err(RecvTimeoutError::Disconnected) => return,
+      }
+    }
+    let previous = Arc::clone(&current.read().unwrap());
+    // Without updates the snapshot only changes if some of its reports expire
+    if updates.is_empty() && previous.is_empty() {
+      continue;
+    }
+    let next = Arc::new(previous.fold(&updates, Instant::now(), max_age));
+    *current.write().unwrap() = next;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fold_updates() {
+    let start = Instant::now();
+    let max_age = Duration::from_secs(60);
+    let jam = TrafficUpdate {
+      way_id: 1,
+      speed_factor: 0.25,
+    };
+    let snapshot = TrafficSnapshot::default().fold(&[jam], start, max_age);
+    assert_eq!(snapshot.speed_factor(1), 0.25);
+    assert_eq!(snapshot.speed_factor(2), 1.0);
+
+    let slow = TrafficUpdate {
+      way_id: 2,
+      speed_factor: 0.8,
+    };
+    let next = snapshot.fold(&[slow], start + Duration::from_secs(30), max_age);
+    assert_eq!((next.speed_factor(1), next.speed_factor(2)), (0.25, 0.8));
+    // Older snapshots stay as they were
+    assert_eq!(snapshot.speed_factor(2), 1.0);
+
+    let expired = next.fold(&[], start + Duration::from_secs(61), max_age);
+    assert_eq!(expired.factors.len(), 1);
+    assert_eq!(expired.speed_factor(2), 0.8);
+  }
+}
@@ -1,98 +1,1714 @@
+mod access_log;
+mod closures;
+mod config;
+mod corridor;
 mod cost_functions;
+mod frontend;
+mod hints;
+mod introspection;
+mod live_traffic;
+mod openapi;
 mod osrm_api;
+mod rate_limit;
+mod search_metrics;
+mod snap_cache;
+mod tolls;
+mod tiles;
+mod traffic;
+mod travel_times;
+mod warm_up;
+mod weight_layer;
 
-use cost_functions::{distance_partial_cost, time_partial_cost};
-use arli::waypoint::{match_waypoint};
+use access_log::*;
+use closures::*;
+use config::{Config, PreferenceConfig, SnapCacheConfig};
+use corridor::{Corridor, CORRIDOR_FACTOR};
+use cost_functions::*;
+use frontend::frontend_files;
+use hints::WaypointHint;
+use introspection::{describe_edge, describe_way};
+use live_traffic::*;
+use arli::adaptive::{route_with_algorithm, AlgorithmChooser, SearchAlgorithm};
+use arli::graph_impl::{
+    BlockedGraph, CancellableGraph, FilteredGraph, Idx, LayerWeighted, PreferenceWeighted, RestrictedGraph, ReversedGraph,
+    TradeOffGraph, TurnRestrictions,
+};
+use arli::graph::{Backward, BaseNodeIds, Forward, GraphData, IntoNeighbors, Weighted};
+use arli::guidance::{route_intersections, route_maneuvers, route_summary};
+use arli::matrix::{AnnotatedSearch, MatrixLimits, MatrixSize};
+use arli::progress::CancellationToken;
 use arli::route::*;
+use arli::spatial::Position;
+use arli::time_dependent::{calculate_time_dependent_weight, route_arriving, route_departing};
+use arli::trip::{optimize_order, TripError};
+use arli::waypoint::{match_waypoint_or_nearest, match_waypoint_with, MatchedWaypoint, SnappedPosition, WaypointMatch};
 
-use arli_osm::{load_graph, OsmGraph};
+use arli_osm::{load_graph, reverse_segment, GraphStats, OsmGraph, Segment};
+use openapi::*;
 use osrm_api::*;
-use std::sync::Arc;
+use rate_limit::*;
+use search_metrics::*;
+use snap_cache::SnapCache;
+use tiles::*;
+use tolls::*;
+use traffic::*;
+use travel_times::Reliability;
+use warm_up::*;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
-use warp::{reject, Filter};
+use warp::http::StatusCode;
+use warp::hyper::body::{Body, Bytes};
+use warp::{Filter, Rejection};
 
-async fn osrm_route_request_handler(
-    waypoints: Waypoints,
+// Largest number of coordinates accepted by the table service, unless set with `ARLI_MAX_TABLE_SIZE`
+const DEFAULT_MAX_TABLE_SIZE: usize = 1000;
+
+// Largest number of coordinates accepted by the trip service, unless set with `ARLI_MAX_TRIP_SIZE`. The order of the
+// waypoints is optimized on the table of all of them.
+const DEFAULT_MAX_TRIP_SIZE: usize = 100;
+
+// Most summaries of OSRM have the names of the two longest roads of the leg
+const SUMMARY_NAMES: usize = 2;
+
+const GRAPH_PATH: &str = "graph.bin";
+
+// Largest body of a traffic or closure feed update, a larger one is rejected before it's read
+const MAX_FEED_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+// Graph with the number of times it was reloaded. A request is served from one generation from start to end, and
+// the artifacts cached for an older generation are stale, e.g. the matched waypoints and the tiles.
+#[derive(Clone)]
+struct LoadedGraph {
+    generation: u64,
     graph: Arc<OsmGraph>,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    println!("OSRM request: {}", waypoints);
+}
+
+// Loaded graph, replaced when the graph is reloaded
+type SharedGraph = Arc<RwLock<LoadedGraph>>;
+
+type ProfileGraph<'a> = FilteredGraph<&'a OsmGraph, AccessFn>;
+
+// Settings of the service the route costs depend on
+struct CostConfig {
+    tariffs: TollTariffs,
+    preferences: PreferenceConfig,
+    traffic: TrafficPattern,
+    live_traffic: LiveTraffic,
+    closures: ClosureLayer,
+}
+
+// Options of a route request changing what the cheapest route is and how long it's searched for
+struct CostOptions<'a> {
+    preferences: Preferences,
+    factors: &'a PreferenceConfig,
+    tolls: TollOptions<'a>,
+    time: Option<RouteTime>,
+    // Traffic slowing down the route at the requested time, only set for driving
+    traffic: Option<&'a TrafficPattern>,
+    // Speeds reported by the live traffic feed, only set for driving
+    live: Option<&'a TrafficSnapshot>,
+    // Ways closed by the closure feed at the time of the route
+    closed: &'a ClosedWays,
+    // Path the route is kept close to
+    corridor: Option<&'a Corridor>,
+    // Abandons the search once the client is gone
+    token: &'a CancellationToken,
+    // Hints of the waypoints sent by the client, the waypoints with a valid one aren't matched again
+    hints: &'a [Option<WaypointHint>],
+    // Number of alternative routes returned besides the fastest one, if they are different enough
+    alternatives: usize,
+    // Search gives up on the routes costing more, alternatives aren't limited
+    max_cost: Option<i32>,
+    // Routes are described with their geometry and steps, only by their distance, duration and segments otherwise
+    overview: bool,
+    // Format of the geometries of the routes
+    geometries: Geometries,
+    // Percentile of the historical travel times the route plans with, if the profile has them
+    reliability: Reliability,
+}
+
+// Options of the searches of a table row
+struct RowOptions<'a> {
+    // Search gives up on the destinations costing more
+    max_cost: Option<i32>,
+    // Percentile of the historical travel times the durations are computed with, if the profile has them
+    reliability: Reliability,
+    // Ways closed by the closure feed at the time of the request
+    closed: &'a ClosedWays,
+    // Abandons the row once the request runs out of time
+    token: &'a CancellationToken,
+}
+
+// Cancels the token when dropped, warp drops the handler of a request when the client disconnects
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Matches waypoints to the graph of a profile, reusing the recently matched ones
+struct WaypointMatcher<'a> {
+    graph: ProfileGraph<'a>,
+    number_of_nodes: usize,
+    generation: u64,
+    profile: &'a Profile,
+    options: ProfileSnapOptions,
+    cache: &'a SnapCache,
+    // Turns the profile must not take, `None` if it ignores the turn restrictions
+    restrictions: Option<&'a TurnRestrictions<Idx>>,
+}
 
-    let mut matched_origin = match_waypoint(graph.as_ref(), &waypoints.0[0]);
+impl<'a> WaypointMatcher<'a> {
+    fn new(loaded: &'a LoadedGraph, profile: &'a Profile, cache: &'a SnapCache) -> Self {
+        Self {
+            graph: FilteredGraph::new(loaded.graph.as_ref(), profile.access),
+            number_of_nodes: loaded.graph.number_of_nodes(),
+            generation: loaded.generation,
+            profile,
+            options: profile.snap_options(),
+            cache,
+            restrictions: if profile.turn_restrictions { Some(loaded.graph.turn_restrictions()) } else { None },
+        }
+    }
+
+    // Transitions of the graph augmented by the waypoints forbidden by the turn restrictions
+    fn forbidden_turns<'b, G: BaseNodeIds<NodeId = Idx>>(&self, graph: &'b G) -> impl Copy + Fn(Idx, Idx) -> bool + 'b
+    where
+        'a: 'b,
+    {
+        let restrictions = self.restrictions;
+        move |from: Idx, to: Idx| match restrictions {
+            Some(restrictions) => restrictions.forbids(graph.base_id(from), graph.base_id(to)),
+            None => false,
+        }
+    }
+
+    // Slowdown of the segments of the graph augmented by the waypoints to their historical travel time at the
+    // percentile of the reliability, 1 if the profile has no travel times
+    fn historical_slowdown<'b, G: BaseNodeIds<NodeId = Idx>>(
+        &'b self,
+        graph: &'b G,
+        reliability: Reliability,
+    ) -> impl Copy + Fn(Idx) -> f32 + 'b {
+        let travel_times = self.profile.travel_times.as_ref();
+        let base_graph = &self.graph;
+        move |id: Idx| match travel_times {
+            Some(travel_times) => {
+                let base_id = graph.base_id(id);
+                travel_times.slowdown(base_id, base_graph.data(base_id), reliability)
+            }
+            None => 1.0,
+        }
+    }
+
+    fn match_waypoint(&self, position: &Position) -> MatchedWaypoint<Idx> {
+        self.cache.get_or_insert_with(self.generation, self.profile.id, position, || {
+            match_waypoint_with(&self.graph, position, &self.options)
+        })
+    }
+
+    // Reuses the waypoint matched by an earlier request if its hint is still valid on this graph and profile
+    fn match_waypoint_with_hint(&self, position: &Position, hint: Option<&WaypointHint>) -> MatchedWaypoint<Idx> {
+        let graph = &self.graph;
+        let accepts = |id: Idx| (id as usize) < self.number_of_nodes && graph.accepts(id);
+        match hint.and_then(|hint| hint.matched_on(graph, accepts, position)) {
+            Some(matched) => matched,
+            None => self.match_waypoint(position),
+        }
+    }
+
+    // Nearest segments to a waypoint no segment is accepted for, if the profile has a fallback radius. They aren't
+    // cached, the cache would lose which waypoints are matched with the fallback.
+    fn match_nearest(&self, position: &Position) -> Option<MatchedWaypoint<Idx>> {
+        match match_waypoint_or_nearest(&self.graph, position, &self.options) {
+            WaypointMatch::Nearest(matched) => Some(matched),
+            WaypointMatch::Matched(_) | WaypointMatch::Unmatched => None,
+        }
+    }
+}
+
+// Cached waypoints are matched regardless of the closures, the candidates on closed ways are dropped by each request
+fn drop_closed(osm_graph: &OsmGraph, closed: &ClosedWays, matched: &mut MatchedWaypoint<Idx>) {
+    if !closed.is_empty() {
+        matched.snapped.retain(|candidate| !closed.is_closed(osm_graph.way_id(candidate.1)));
+    }
+}
+
+// Nodes of the graph augmented by the waypoints which are on closed ways
+fn is_closed<'a, G: BaseNodeIds<NodeId = Idx>>(
+    osm_graph: &'a OsmGraph,
+    closed: &'a ClosedWays,
+    graph: &'a G,
+) -> impl Copy + Fn(Idx) -> bool + 'a {
+    move |id: Idx| !closed.is_empty() && closed.is_closed(osm_graph.way_id(graph.base_id(id)))
+}
+
+// Fastest route on the weighted graph, a time dependent one if the request has a departure or an arrival time
+fn search_route<G>(
+    graph: G,
+    options: &CostOptions,
+    algorithm: SearchAlgorithm,
+    from: &MatchedWaypoint<Idx>,
+    to: &MatchedWaypoint<Idx>,
+) -> arli::Result<Route<i32, Idx>>
+where
+    G: Copy + Weighted<Weight = i32, NodeId = Idx> + IntoNeighbors<Forward> + IntoNeighbors<Backward>,
+{
+    match options.time {
+        Some(time @ RouteTime::DepartAt(_)) => {
+            route_departing(TimeDependentGraph::new(graph, options.traffic, time), from, to).ok_or(arli::Error::NoRoute)
+        }
+        Some(time @ RouteTime::ArriveBy(_)) => {
+            route_arriving(TimeDependentGraph::new(graph, options.traffic, time), from, to).ok_or(arli::Error::NoRoute)
+        }
+        None => match (options.max_cost, algorithm) {
+            (Some(max_cost), SearchAlgorithm::Dijkstra) => route_with_limit(graph, from, to, max_cost),
+            (Some(max_cost), SearchAlgorithm::Bidirectional) => route_bidir_with_limit(graph, from, to, max_cost),
+            (None, _) => route_with_algorithm(graph, from, to, algorithm),
+        },
+    }
+}
+
+// Fastest route followed by the alternatives asked for, time dependent routes have no alternatives
+fn search_routes<G>(
+    graph: G,
+    options: &CostOptions,
+    algorithm: SearchAlgorithm,
+    from: &MatchedWaypoint<Idx>,
+    to: &MatchedWaypoint<Idx>,
+) -> Vec<Route<i32, Idx>>
+where
+    G: Copy + Weighted<Weight = i32, NodeId = Idx> + IntoNeighbors<Forward> + IntoNeighbors<Backward>,
+{
+    match options.time {
+        None if options.alternatives > 0 => route_alternatives(graph, from, to, options.alternatives + 1),
+        _ => search_route(graph, options, algorithm, from, to).into_iter().collect(),
+    }
+}
+
+fn find_route<C>(
+    osm_graph: &OsmGraph,
+    matcher: &WaypointMatcher,
+    cost: C,
+    options: &CostOptions,
+    waypoints: &Waypoints,
+    timing: &mut QueryTiming,
+    log: &mut AccessLog,
+) -> Result<OsrmRouteResponse, OsrmError>
+where
+    C: Copy + Fn(&Segment, &Segment, Option<SnappedPosition>) -> i32,
+{
+    let graph = &matcher.graph;
+    let open = |matched: &mut MatchedWaypoint<Idx>| drop_closed(osm_graph, options.closed, matched);
+    let hint = |i: usize| options.hints.get(i).and_then(Option::as_ref);
+    // Waypoints no open segment is accepted for are matched to the nearest ones, if the profile has a fallback radius
+    let mut fallback = false;
+    let mut match_waypoint = |i: usize| {
+        let mut matched = matcher.match_waypoint_with_hint(&waypoints.0[i], hint(i));
+        open(&mut matched);
+        if matched.snapped.is_empty() {
+            if let Some(mut nearest) = matcher.match_nearest(&waypoints.0[i]) {
+                open(&mut nearest);
+                fallback |= !nearest.snapped.is_empty();
+                matched = nearest;
+            }
+        }
+        matched
+    };
+    let mut matched_origin = timed(&mut timing.snapping, || match_waypoint(0));
     if matched_origin.snapped.is_empty() {
         println!("Origin is not matched: {:?}", waypoints.0[0]);
-        return Err(reject::not_found());
+        return Err(OsrmError::new("NoSegment", "Could not find a matching segment for the origin"));
     }
 
-    let mut matched_destination = match_waypoint(graph.as_ref(), &waypoints.0[1]);
+    let mut matched_destination = timed(&mut timing.snapping, || match_waypoint(1));
     if matched_destination.snapped.is_empty() {
         println!("Destination is not matched: {:?}", waypoints.0[1]);
-        return Err(reject::not_found());
+        return Err(OsrmError::new("NoSegment", "Could not find a matching segment for the destination"));
+    }
+
+    let connected = timed(&mut timing.overlay_build, || match options.time {
+        // The search runs backward from the destination, so the origin is matched to the whole segment
+        Some(RouteTime::ArriveBy(_)) => connect_destination_to_graph(graph, &mut matched_destination),
+        _ => match matcher.profile.u_turn_penalty {
+            Some(_) => {
+                let reverse = |id: Idx| reverse_segment(osm_graph, id).filter(|reverse| graph.accepts(*reverse));
+                connect_waypoints_with_u_turns(graph, &mut matched_origin, &mut matched_destination, reverse)
+            }
+            None => connect_waypoints_to_graph(graph, &mut matched_origin, &mut matched_destination),
+        },
+    });
+    let augmented_graph = match connected {
+        Ok(augmented_graph) => augmented_graph,
+        Err(_) => return Err(OsrmError::new("NoSegment", "Could not find a matching segment for the waypoints")),
+    };
+
+    // Time dependent routes only search forward from the departure or backward from the arrival
+    let prediction = timed(&mut timing.search, || {
+        AlgorithmChooser::default().predict(osm_graph, &waypoints.0[0], &waypoints.0[1])
+    });
+    let algorithm = match options.time {
+        Some(_) => SearchAlgorithm::Dijkstra,
+        None => prediction.algorithm,
+    };
+    log.search = Some((algorithm, prediction.settled));
+
+    // Slower roads take longer to travel, the durations of the route include the live traffic
+    let live_factor = |id: Idx| match options.live {
+        Some(live) if !live.is_empty() => 1.0 / live.speed_factor(osm_graph.way_id(augmented_graph.base_id(id))),
+        _ => 1.0,
+    };
+    // Durations are the historical travel times of the requested reliability, if the profile has them
+    let historical = matcher.historical_slowdown(&augmented_graph, options.reliability);
+    let weighted = |live: bool| {
+        let factor = move |id: Idx| historical(id) * if live { live_factor(id) } else { 1.0 };
+        PreferenceWeighted::new((&augmented_graph, cost), factor)
+    };
+    let live_weighted = weighted(true);
+    let has_live_traffic = matches!(options.live, Some(live) if !live.is_empty());
+    let route_duration = |weighted, ids: &[Idx]| match options.time {
+        Some(time @ RouteTime::DepartAt(_)) => {
+            let graph = TimeDependentGraph::new(weighted, options.traffic, time);
+            calculate_time_dependent_weight(graph, ids.iter().cloned())
+        }
+        Some(time @ RouteTime::ArriveBy(_)) => {
+            let graph = TimeDependentGraph::new(weighted, options.traffic, time);
+            calculate_time_dependent_weight(ReversedGraph::new(graph), ids.iter().rev().cloned())
+        }
+        None => calculate_weight(weighted, ids.iter().cloned()),
+    };
+
+    // Segments along the corridor are cheaper, so the route only leaves it to save a lot of time
+    let corridor = timed(&mut timing.search, || options.corridor.map(|corridor| corridor.nodes(graph)));
+    let along_corridor = |id: Idx| match &corridor {
+        Some(nodes) if nodes.contains(&augmented_graph.base_id(id)) => CORRIDOR_FACTOR,
+        _ => 1.0,
+    };
+
+    let tolls = options.tolls;
+    let factor = |id: Idx| {
+        let segment = osm_graph.data(augmented_graph.base_id(id));
+        let preference = options.preferences.factor(segment, options.factors) * matcher.profile.routing_factor(segment);
+        preference * historical(id) * live_factor(id) * along_corridor(id)
+    };
+    let closed = is_closed(osm_graph, options.closed, &augmented_graph);
+    // Turning around at a waypoint costs the penalty of the profile, it isn't a part of the reported duration
+    let u_turn_weighted = augmented_graph.with_u_turn_penalty(cost, matcher.profile.u_turn_penalty.unwrap_or(0));
+    // The weight layer of the profile replaces the costs the route is searched by, not the reported duration
+    let layer = |id: Idx| match &matcher.profile.weights {
+        Some(weights) => weights.get(&augmented_graph.base_id(id)),
+        None => None,
+    };
+    let layered = LayerWeighted::new(u_turn_weighted, layer);
+    let preferred = BlockedGraph::new(PreferenceWeighted::new(layered, factor), closed);
+    let preferred = RestrictedGraph::new(preferred, matcher.forbidden_turns(&augmented_graph));
+    let preferred = CancellableGraph::new(preferred, options.token);
+    let routes = timed(&mut timing.search, || match tolls.seconds_per_cent {
+        Some(seconds_per_cent) if !tolls.tariffs.is_empty() => {
+            let toll = TollWeight::new(&augmented_graph, osm_graph, tolls.tariffs);
+            let price = move |cents: i32| (cents as f32 * seconds_per_cent).round() as i32;
+            let graph = TradeOffGraph::new(preferred, toll, price);
+            search_routes(graph, options, algorithm, &matched_origin, &matched_destination)
+        }
+        _ => search_routes(preferred, options, algorithm, &matched_origin, &matched_destination),
+    });
+    if options.token.is_past_deadline() {
+        println!("Route search is out of time");
+        return Err(timeout_error());
+    }
+    if options.token.is_cancelled() {
+        println!("Route search is cancelled");
+        return Err(OsrmError::new("Cancelled", "The request is cancelled"));
     }
 
-    let augmented_graph = connect_waypoints_to_graph(
-        graph.as_ref(),
-        &mut matched_origin,
-        &mut matched_destination,
+    let mut routes = routes.into_iter();
+    let route = match routes.next() {
+        Some(route) => route,
+        None => {
+            println!("No route found");
+            return Err(OsrmError::new("NoRoute", "No route found between the waypoints"));
+        }
+    };
+    log.settled = route.num_resolved;
+    record_search(algorithm, prediction.settled, route.num_resolved);
+
+    // Alternatives are described the same way as the fastest route, each one is a route of the response
+    // The intersections only offer the turns the route could take
+    let turns = RestrictedGraph::new(&augmented_graph, matcher.forbidden_turns(&augmented_graph));
+    let describe = |route: &Route<i32, Idx>, timing: &mut QueryTiming| {
+        // The summary skips the geometry, the steps and the sections, which take longer than the search of short routes
+        if !options.overview {
+            let (distance, duration) = timed(&mut timing.geometry, || {
+                let distance =
+                    calculate_weight((&augmented_graph, matcher.profile.distance()), route.ids.iter().cloned());
+                (distance, route_duration(live_weighted, &route.ids))
+            });
+            println!("Route found: cost = {:?}, distance = {:?}, duration = {:?}", route.cost, distance, duration);
+            let segments = route.ids.iter().map(|id| augmented_graph.base_id(*id)).collect();
+            return timed(&mut timing.serialization, || {
+                let waypoints = [&matched_origin, &matched_destination];
+                OsrmRouteResponse::summary(distance, duration, route.cost, segments, waypoints)
+            });
+        }
+        let (geometry, maneuvers, intersections, summary, distance, duration) = timed(&mut timing.geometry, || {
+            let geometry = collect_route_geometry(&augmented_graph, route.ids.iter().cloned(), JoinVertices::Drop);
+            let maneuvers = route_maneuvers(&augmented_graph, &route.ids);
+            let intersections = route_intersections(turns, &route.ids);
+            let distance = calculate_weight(
+                (&augmented_graph, matcher.profile.distance()),
+                route.ids.iter().cloned(),
+            );
+            let duration = route_duration(live_weighted, &route.ids);
+            let summary = route_summary(
+                &route.ids,
+                |id| {
+                    let base_id = augmented_graph.base_id(id);
+                    let length = osm_graph.data(base_id).length();
+                    osm_graph.street_name(base_id).map(|name| (name, length))
+                },
+                SUMMARY_NAMES,
+            );
+            (geometry, maneuvers, intersections, summary, distance, duration)
+        });
+        let mut quality = route.quality;
+        quality.low_class_share = low_class_share(&route.ids, |id| {
+            let segment = osm_graph.data(augmented_graph.base_id(id));
+            (segment.length(), matcher.profile.is_low_class(segment))
+        });
+        quality.fallback = fallback;
+
+        // Distance and duration of the route in each region, if the graph was built with the regions
+        let regions = timed(&mut timing.geometry, || {
+            if !osm_graph.has_regions() {
+                return None;
+            }
+            let sections = split_by_region(&route.ids, |id| osm_graph.region(augmented_graph.base_id(id)));
+            let sections = sections.into_iter().map(|section| {
+                let ids = route.ids[section.nodes].iter().cloned();
+                let distance = calculate_weight((&augmented_graph, matcher.profile.distance()), ids.clone());
+                let duration = calculate_weight(live_weighted, ids);
+                OsrmRegionSection::new(section.region, distance, duration)
+            });
+            Some(sections.collect::<Vec<_>>())
+        });
+        let toll = if tolls.tariffs.is_empty() {
+            None
+        } else {
+            let toll = TollWeight::new(&augmented_graph, osm_graph, tolls.tariffs);
+            Some(calculate_weight(toll, route.ids.iter().cloned()))
+        };
+
+        // Duration of the same route without the live traffic, the client tells how much the traffic slows it down
+        let usual_duration = if has_live_traffic {
+            Some(timed(&mut timing.geometry, || route_duration(weighted(false), &route.ids)))
+        } else {
+            None
+        };
+
+        println!("Route found: cost = {:?}, distance = {:?}, duration = {:?}", route.cost, distance, duration);
+
+        timed(&mut timing.serialization, || {
+            let mut response = OsrmRouteResponse::new(
+                EncodedGeometry::new(geometry, options.geometries),
+                distance,
+                duration,
+                route.cost,
+                &maneuvers,
+                &summary,
+                [&matched_origin, &matched_destination],
+            )
+            .with_quality(&quality)
+            .with_intersections(&intersections);
+            if let Some(regions) = regions {
+                response = response.with_regions(regions);
+            }
+            if let Some(toll) = toll {
+                response = response.with_toll(toll);
+            }
+            if let Some(usual_duration) = usual_duration {
+                response = response.with_usual_duration(usual_duration);
+            }
+            if let Some(time) = options.time {
+                let (departure, arrival) = time.departure_and_arrival(duration);
+                response = response.with_times(departure, arrival);
+            }
+            response
+        })
+    };
+    let response = describe(&route, timing);
+    Ok(routes.fold(response, |response, alternative| {
+        response.with_alternative(describe(&alternative, timing))
+    }))
+}
+
+// Runs the canary routes on the graph with the default options, no closures and no live traffic, so a failure means
+// the graph is broken. Waypoints aren't cached, a graph failing the canaries leaves nothing behind.
+fn run_canaries(loaded: &LoadedGraph, costs: &CostConfig, canaries: &[Canary]) -> Vec<CanaryFailure> {
+    let cache = SnapCache::new(SnapCacheConfig { ttl_s: 0, max_entries: 0 });
+    let closed = ClosedWays::default();
+    let token = CancellationToken::new();
+    let options = CostOptions {
+        preferences: Preferences::default(),
+        factors: &costs.preferences,
+        tolls: TollOptions {
+            tariffs: &costs.tariffs,
+            seconds_per_cent: None,
+        },
+        time: None,
+        traffic: None,
+        live: None,
+        closed: &closed,
+        corridor: None,
+        token: &token,
+        hints: &[],
+        alternatives: 0,
+        max_cost: None,
+        overview: true,
+        geometries: Geometries::default(),
+        reliability: Reliability::default(),
+    };
+    canaries
+        .iter()
+        .filter_map(|canary| {
+            let mut log = AccessLog::new(format!("canary-{}", canary.name), "route", &canary.profile.name, 2);
+            let timer = Instant::now();
+            let response = find_route(
+                loaded.graph.as_ref(),
+                &WaypointMatcher::new(loaded, &canary.profile, &cache),
+                canary.profile.cost(CostContext::default()),
+                &options,
+                &canary.waypoints,
+                &mut QueryTiming::default(),
+                &mut log,
+            );
+            let latency = timer.elapsed();
+            let failure = canary.check(latency, response.err().as_ref().map(OsrmError::code));
+            match &failure {
+                Some(failure) => println!("Canary {} failed: {}", failure.name, failure.reason),
+                None => println!("Canary {} passed in {} ms", canary.name, latency.as_millis()),
+            }
+            failure
+        })
+        .collect()
+}
+
+fn server_timing(timing: &QueryTiming) -> String {
+    let phases = [
+        ("snapping", timing.snapping),
+        ("overlay", timing.overlay_build),
+        ("search", timing.search),
+        ("geometry", timing.geometry),
+        ("serialization", timing.serialization),
+    ];
+    phases
+        .iter()
+        .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Error of a search stopped at the deadline of the request
+fn timeout_error() -> OsrmError {
+    OsrmError::new("Timeout", "The search took longer than the timeout of the profile")
+}
+
+// Status of a request whose searches failed
+fn search_error_status(error: &OsrmError) -> StatusCode {
+    match error.code() {
+        "Timeout" => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+// Error response with the request id in the body and the header, the request is finished with it
+fn error_reply(error: OsrmError, status: StatusCode, log: &AccessLog) -> Box<dyn warp::Reply> {
+    log.finish(status);
+    let error = error.with_request_id(&log.request_id);
+    Box::new(warp::reply::with_header(
+        warp::reply::with_status(warp::reply::json(&error), status),
+        REQUEST_ID_HEADER,
+        log.request_id.clone(),
+    ))
+}
+
+/// Fastest route between two waypoints
+#[utoipa::path(
+    get,
+    path = "/route/v1/{profile}/{coordinates}",
+    tag = "routing",
+    params(
+        ("profile" = String, Path, description = "Routing profile"),
+        ("coordinates" = String, Path, description = "Exactly two `longitude,latitude` pairs separated by `;`"),
+        OsrmRouteOptions,
+        ("X-Request-Id" = Option<String>, Header,
+            description = "Identifies the request in the service log, generated if not set or if it isn't made of \
+            letters, digits, `.`, `_` and `-` of at most 128 characters. Returned in the response header"),
+    ),
+    responses(
+        (status = 200, body = OsrmRouteResponse,
+            description = "Route is found. Time spent in each query phase is reported in the `Server-Timing` header"),
+        (status = 400, body = OsrmError, description = "`InvalidValue` if a coordinate is out of range, e.g. latitude \
+            first, `DistanceExceeded` if the waypoints are too far apart for the profile, `InvalidOptions` for unknown \
+            preferences or geometries, a speed factor out of range, a negative toll value, both times set, an invalid \
+            corridor, viewport, hint, number of alternatives, overview or reliability"),
+        (status = 401, body = OsrmError, description = INVALID_API_KEY_DESCRIPTION),
+        (status = 404, body = OsrmError,
+            description = "`NoSegment` if a waypoint can't be matched, `NoRoute` if there is no route between them"),
+        (status = 429, body = OsrmError, description = TOO_MANY_REQUESTS_DESCRIPTION,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying"))),
+        (status = 504, body = OsrmError, description = TIMEOUT_DESCRIPTION),
+    )
+)]
+async fn osrm_route_request_handler(
+    profile: Arc<Profile>,
+    waypoints: Waypoints,
+    options: OsrmRouteOptions,
+    (request_id, target): (String, String),
+    loaded: LoadedGraph,
+    cache: Arc<SnapCache>,
+    costs: Arc<CostConfig>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    println!("OSRM request {}: {} {}", request_id, profile.name, waypoints);
+    let mut log = AccessLog::new(request_id, "route", &profile.name, waypoints.0.len()).with_target(target);
+    if let Err(message) = check_coordinates(&waypoints.0) {
+        let error = OsrmError::new("InvalidValue", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    if let Err(message) = profile.check_distance(&waypoints.0) {
+        let error = OsrmError::new("DistanceExceeded", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+
+    let parsed = (
+        options.preferences(),
+        options.cost_context(),
+        options.route_time(),
+        options.corridor(),
+        options.viewport(),
     );
+    let (preferences, context, time, corridor, viewport) = match parsed {
+        (Ok(preferences), Ok(context), Ok(time), Ok(corridor), Ok(viewport)) => {
+            (preferences, context, time, corridor, viewport)
+        }
+        (Err(message), _, _, _, _)
+        | (_, Err(message), _, _, _)
+        | (_, _, Err(message), _, _)
+        | (_, _, _, Err(message), _)
+        | (_, _, _, _, Err(message)) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+
+    let hints = match options.hints(waypoints.0.len()) {
+        Ok(hints) => hints,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let alternatives = match options.alternatives() {
+        Ok(alternatives) => alternatives,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let overview = match options.overview() {
+        Ok(overview) => overview,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let geometries = match options.geometries(profile.geometries) {
+        Ok(geometries) => geometries,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let reliability = match options.reliability() {
+        Ok(reliability) => reliability,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let seconds_per_cent = match options.seconds_per_cent() {
+        Ok(seconds_per_cent) => seconds_per_cent,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+
+    // The search blocks a thread, so it runs apart from the handler which is dropped if the client disconnects
+    // The timeout includes the time the request waits for a thread
+    let token = profile.request_token();
+    let _cancel = CancelOnDrop(token.clone());
+    let search = tokio::task::spawn_blocking(move || {
+        // The route uses the traffic of the moment it starts at, updates folded meanwhile don't change it
+        let live = costs.live_traffic.snapshot();
+        // Closures are looked up at the requested time, a route planned for later avoids the closures of then
+        let closed = costs.closures.closed_at(match time {
+            Some(RouteTime::DepartAt(at)) | Some(RouteTime::ArriveBy(at)) => at,
+            None => unix_time(),
+        });
+        let cost_options = CostOptions {
+            preferences,
+            factors: &costs.preferences,
+            tolls: TollOptions {
+                tariffs: &costs.tariffs,
+                seconds_per_cent,
+            },
+            time,
+            traffic: if profile.traffic { Some(&costs.traffic) } else { None },
+            live: if profile.traffic { Some(&live) } else { None },
+            closed: &closed,
+            corridor: corridor.as_ref(),
+            token: &token,
+            hints: &hints,
+            alternatives,
+            max_cost: profile.max_cost,
+            overview,
+            geometries,
+            reliability,
+        };
 
-    let route = route(
-        (&augmented_graph, time_partial_cost),
+        let mut timing = QueryTiming::default();
+        let response = find_route(
+            loaded.graph.as_ref(),
+            &WaypointMatcher::new(&loaded, &profile, cache.as_ref()),
+            profile.cost(context),
+            &cost_options,
+            &waypoints,
+            &mut timing,
+            &mut log,
+        );
+        let response = response.map(|response| {
+            timed(&mut timing.serialization, || match &viewport {
+                Some(viewport) => warp::reply::json(&response.with_viewport(viewport)),
+                None => warp::reply::json(&response),
+            })
+        });
+        (response, timing, log)
+    });
+    let (response, timing, log) = search.await.unwrap();
+
+    println!("Query timing: {} (total {:.3} ms)", server_timing(&timing), timing.total().as_secs_f64() * 1000.0);
+
+    match response {
+        Ok(response) => {
+            log.finish(StatusCode::OK);
+            let response = warp::reply::with_header(response, "server-timing", server_timing(&timing));
+            Ok(Box::new(warp::reply::with_header(response, REQUEST_ID_HEADER, log.request_id)))
+        }
+        Err(error) => {
+            let status = search_error_status(&error);
+            Ok(error_reply(error, status, &log))
+        }
+    }
+}
+
+// Matches the waypoints of a table or a trip, without the candidates on closed ways
+fn match_open_waypoints(
+    osm_graph: &OsmGraph,
+    matcher: &WaypointMatcher,
+    closed: &ClosedWays,
+    positions: &[Position],
+) -> Vec<MatchedWaypoint<Idx>> {
+    let open = |position: &Position| {
+        let mut matched = matcher.match_waypoint(position);
+        drop_closed(osm_graph, closed, &mut matched);
+        matched
+    };
+    positions.iter().map(open).collect()
+}
+
+// Returns the durations and distances of the row together with the number of nodes settled to compute it. The row
+// is incomplete if the token is cancelled meanwhile.
+fn table_row<C>(
+    osm_graph: &OsmGraph,
+    matcher: &WaypointMatcher,
+    cost: C,
+    origin: &Position,
+    destinations: &[MatchedWaypoint<Idx>],
+    options: &RowOptions,
+) -> (Vec<TableEntry>, usize)
+where
+    C: Copy + Fn(&Segment, &Segment, Option<SnappedPosition>) -> i32,
+{
+    let mut matched_origin = matcher.match_waypoint(origin);
+    drop_closed(osm_graph, options.closed, &mut matched_origin);
+    let augmented_graph = match connect_origin_to_graph(&matcher.graph, &mut matched_origin) {
+        Ok(augmented_graph) => augmented_graph,
+        // An origin off the graph reaches no destination
+        Err(_) => return (vec![None; destinations.len()], 0),
+    };
+    let historical = matcher.historical_slowdown(&augmented_graph, options.reliability);
+    let weighted = PreferenceWeighted::new((&augmented_graph, cost), historical);
+    let open = BlockedGraph::new(weighted, is_closed(osm_graph, options.closed, &augmented_graph));
+    let restricted = RestrictedGraph::new(open, matcher.forbidden_turns(&augmented_graph));
+    let mut search = AnnotatedSearch::new(
+        CancellableGraph::new(restricted, options.token),
+        (&augmented_graph, matcher.profile.distance()),
         &matched_origin,
-        &matched_destination,
     );
+    if let Some(max_cost) = options.max_cost {
+        search = search.with_max_cost(max_cost);
+    }
+    let row = destinations.iter().map(|destination| search.cost_to(destination)).collect();
+    (row, search.num_settled())
+}
 
-    if let Some(route) = route {
+/// Durations of the fastest routes between all pairs of coordinates
+#[utoipa::path(
+    get,
+    path = "/table/v1/{profile}/{coordinates}",
+    tag = "routing",
+    params(
+        ("profile" = String, Path, description = "Routing profile"),
+        ("coordinates" = String, Path,
+            description = "`longitude,latitude` pairs separated by `;`, each one is both a source and a destination"),
+        TableOptions,
+        ("X-Request-Id" = Option<String>, Header,
+            description = "Identifies the request in the service log, generated if not set or if it isn't made of \
+            letters, digits, `.`, `_` and `-` of at most 128 characters. Returned in the response header"),
+    ),
+    responses(
+        (status = 200, description = "Durations and distances of the fastest routes", content(
+            ("application/json" = OsrmTableResponse),
+            ("application/x-ndjson" = OsrmTableRow),
+        )),
+        (status = 400, body = OsrmError, description = "`TooBig` if there are too many coordinates, `InvalidValue` if \
+            one is out of range, e.g. latitude first, `DistanceExceeded` if they are too far apart for the profile, \
+            `InvalidOptions` for unknown annotations or reliability or a speed factor out of range"),
+        (status = 401, body = OsrmError, description = INVALID_API_KEY_DESCRIPTION),
+        (status = 429, body = OsrmError, description = TOO_MANY_REQUESTS_DESCRIPTION,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying"))),
+        (status = 504, body = OsrmError, description = TIMEOUT_DESCRIPTION),
+    )
+)]
+async fn osrm_table_request_handler(
+    profile: Arc<Profile>,
+    coordinates: Coordinates,
+    options: TableOptions,
+    limits: MatrixLimits,
+    (request_id, target): (String, String),
+    (loaded, cache): (LoadedGraph, Arc<SnapCache>),
+    costs: Arc<CostConfig>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    println!("OSRM table request {}: {} with {} coordinates", request_id, profile.name, coordinates.0.len());
+    let mut log = AccessLog::new(request_id, "table", &profile.name, coordinates.0.len()).with_target(target);
 
-        let geometry = collect_route_geometry(&augmented_graph, route.ids.iter().cloned());
-        let distance = calculate_weight(
-            (&augmented_graph, distance_partial_cost),
-            route.ids.iter().cloned(),
-        );
-        let duration = calculate_weight(
-            (&augmented_graph, time_partial_cost),
-            route.ids.iter().cloned(),
-        );
+    // Every coordinate is both a source and a destination
+    if let Err(error) = MatrixSize::new(coordinates.0.len(), coordinates.0.len(), &limits) {
+        let error = OsrmError::new("TooBig", &error.to_string());
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    if let Err(message) = check_coordinates(&coordinates.0) {
+        let error = OsrmError::new("InvalidValue", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    if let Err(message) = profile.check_distance(&coordinates.0) {
+        let error = OsrmError::new("DistanceExceeded", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
 
-        println!("Route found: cost = {:?}, distance = {:?}, duration = {:?}", route.cost, distance, duration);
+    // Costs of all profiles are durations in seconds, the rows are searched without the routing preferences of the
+    // profile so that they and the cutoff are the actual travel times
+    let max_cost = options.max_duration.map(|duration| duration.min(i32::MAX as u32) as i32);
+    let parsed = (options.annotations(), options.cost_context(), options.reliability());
+    let (annotations, context, reliability) = match parsed {
+        (Ok(annotations), Ok(context), Ok(reliability)) => (annotations, context, reliability),
+        (Err(message), _, _) | (_, Err(message), _) | (_, _, Err(message)) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+
+    if options.stream.unwrap_or(false) {
+        // Rows are computed one by one and sent as soon as they are ready
+        let (mut sender, body) = Body::channel();
+        let response = warp::http::Response::builder()
+            .header("content-type", "application/x-ndjson")
+            .header(REQUEST_ID_HEADER, log.request_id.as_str())
+            .body(body)
+            .unwrap();
+        // The searches block a thread, the rows are passed to the task sending them to the client
+        let (rows, mut ready) = tokio::sync::mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(line) = ready.recv().await {
+                if sender.send_data(Bytes::from(line)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let token = profile.request_token();
+        tokio::task::spawn_blocking(move || {
+            let matcher = WaypointMatcher::new(&loaded, &profile, cache.as_ref());
+            let closed = costs.closures.closed_at(unix_time());
+            let destinations = match_open_waypoints(&loaded.graph, &matcher, &closed, &coordinates.0);
+            let row_options = RowOptions {
+                max_cost,
+                reliability,
+                closed: &closed,
+                token: &token,
+            };
+            for (source, origin) in coordinates.0.iter().enumerate() {
+                let cost = profile.cost(context);
+                let (row, settled) = table_row(&loaded.graph, &matcher, cost, origin, &destinations, &row_options);
+                log.settled += settled;
+                // The rows sent so far are complete, the client tells a timeout by the missing rows
+                if token.is_cancelled() {
+                    println!("Table stream is out of time after {} rows", source);
+                    log.finish(StatusCode::GATEWAY_TIMEOUT);
+                    return;
+                }
+                let mut line = serde_json::to_string(&OsrmTableRow::new(source, &row, annotations)).unwrap();
+                line.push('\n');
+                // The sending task is gone once the client closes the stream
+                if rows.send(line).is_err() {
+                    println!("Table stream is closed by the client after {} rows", source);
+                    break;
+                }
+            }
+            log.finish(StatusCode::OK);
+        });
+        return Ok(Box::new(response));
+    }
+
+    // The searches block a thread, so they run apart from the handler like the ones of a route
+    let token = profile.request_token();
+    let _cancel = CancelOnDrop(token.clone());
+    let search = tokio::task::spawn_blocking(move || -> Box<dyn warp::Reply> {
+        let matcher = WaypointMatcher::new(&loaded, &profile, cache.as_ref());
+        let closed = costs.closures.closed_at(unix_time());
+        let destinations = match_open_waypoints(&loaded.graph, &matcher, &closed, &coordinates.0);
+        let row_options = RowOptions {
+            max_cost,
+            reliability,
+            closed: &closed,
+            token: &token,
+        };
+        let mut rows = Vec::new();
+        for origin in &coordinates.0 {
+            let cost = profile.cost(context);
+            let (row, settled) = table_row(&loaded.graph, &matcher, cost, origin, &destinations, &row_options);
+            log.settled += settled;
+            if token.is_cancelled() {
+                return error_reply(timeout_error(), StatusCode::GATEWAY_TIMEOUT, &log);
+            }
+            rows.push(row);
+        }
+        log.finish(StatusCode::OK);
+        let response = warp::reply::json(&OsrmTableResponse::new(rows, annotations, &destinations));
+        Box::new(warp::reply::with_header(response, REQUEST_ID_HEADER, log.request_id))
+    });
+    Ok(search.await.unwrap())
+}
+
+// Route between two consecutive waypoints of a trip
+fn find_trip_leg<'a, C>(
+    osm_graph: &'a OsmGraph,
+    matcher: &WaypointMatcher,
+    cost: C,
+    from: &Position,
+    to: &Position,
+    closed: &ClosedWays,
+    token: &CancellationToken,
+) -> Option<TripLeg<'a>>
+where
+    C: Copy + Fn(&Segment, &Segment, Option<SnappedPosition>) -> i32,
+{
+    let mut matched_origin = matcher.match_waypoint(from);
+    drop_closed(osm_graph, closed, &mut matched_origin);
+    let mut matched_destination = matcher.match_waypoint(to);
+    drop_closed(osm_graph, closed, &mut matched_destination);
+    let augmented_graph =
+        connect_waypoints_to_graph(&matcher.graph, &mut matched_origin, &mut matched_destination).ok()?;
+    // Trips have no reliability option, they plan with the typical travel times
+    let historical = matcher.historical_slowdown(&augmented_graph, Reliability::Typical);
+    let weighted = PreferenceWeighted::new((&augmented_graph, cost), historical);
+    // The legs are routed with the preferences of the profile, their durations are the plain travel times
+    let factor = |id: Idx| historical(id) * matcher.profile.routing_factor(osm_graph.data(augmented_graph.base_id(id)));
+    let preferred = PreferenceWeighted::new((&augmented_graph, cost), factor);
+    let open = BlockedGraph::new(preferred, is_closed(osm_graph, closed, &augmented_graph));
+    let restricted = RestrictedGraph::new(open, matcher.forbidden_turns(&augmented_graph));
+    let route = route(CancellableGraph::new(restricted, token), &matched_origin, &matched_destination).ok()?;
+    let turns = RestrictedGraph::new(&augmented_graph, matcher.forbidden_turns(&augmented_graph));
+    let ids = route.ids.iter().cloned();
+    let summary = route_summary(
+        &route.ids,
+        |id| {
+            let base_id = augmented_graph.base_id(id);
+            let length = osm_graph.data(base_id).length();
+            osm_graph.street_name(base_id).map(|name| (name, length))
+        },
+        SUMMARY_NAMES,
+    );
+    Some(TripLeg {
+        geometry: collect_route_geometry(&augmented_graph, ids.clone(), JoinVertices::Drop),
+        distance: calculate_weight((&augmented_graph, matcher.profile.distance()), ids.clone()),
+        duration: calculate_weight(weighted, ids),
+        cost: route.cost,
+        maneuvers: route_maneuvers(&augmented_graph, &route.ids),
+        intersections: route_intersections(turns, &route.ids),
+        summary,
+    })
+}
+
+/// Fastest order of visiting all the coordinates, with the route connecting them
+#[utoipa::path(
+    get,
+    path = "/trip/v1/{profile}/{coordinates}",
+    tag = "routing",
+    params(
+        ("profile" = String, Path, description = "Routing profile"),
+        ("coordinates" = String, Path, description = "`longitude,latitude` pairs separated by `;`"),
+        OsrmTripOptions,
+        ("X-Request-Id" = Option<String>, Header,
+            description = "Identifies the request in the service log, generated if not set or if it isn't made of \
+            letters, digits, `.`, `_` and `-` of at most 128 characters. Returned in the response header"),
+    ),
+    responses(
+        (status = 200, body = OsrmTripResponse, description = "Trip is found"),
+        (status = 400, body = OsrmError, description = "`TooBig` if there are too many coordinates, `InvalidValue` if \
+            one is out of range, e.g. latitude first, `DistanceExceeded` if they are too far apart for the profile, \
+            `InvalidOptions` for unknown options, `NotImplemented` for a trip which isn't a round trip and doesn't go \
+            from the first to the last coordinate"),
+        (status = 401, body = OsrmError, description = INVALID_API_KEY_DESCRIPTION),
+        (status = 404, body = OsrmError, description = "`NoSegment` if a coordinate can't be matched, `NoTrips` if \
+            not all of them can be reached from each other"),
+        (status = 429, body = OsrmError, description = TOO_MANY_REQUESTS_DESCRIPTION,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying"))),
+        (status = 504, body = OsrmError, description = TIMEOUT_DESCRIPTION),
+    )
+)]
+async fn osrm_trip_request_handler(
+    profile: Arc<Profile>,
+    coordinates: Coordinates,
+    options: OsrmTripOptions,
+    limits: MatrixLimits,
+    (request_id, target): (String, String),
+    (loaded, cache): (LoadedGraph, Arc<SnapCache>),
+    costs: Arc<CostConfig>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    println!("OSRM trip request {}: {} with {} coordinates", request_id, profile.name, coordinates.0.len());
+    let mut log = AccessLog::new(request_id, "trip", &profile.name, coordinates.0.len()).with_target(target);
+
+    if let Err(error) = MatrixSize::new(coordinates.0.len(), coordinates.0.len(), &limits) {
+        let error = OsrmError::new("TooBig", &error.to_string());
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    if let Err(message) = check_coordinates(&coordinates.0) {
+        let error = OsrmError::new("InvalidValue", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    if let Err(message) = profile.check_distance(&coordinates.0) {
+        let error = OsrmError::new("DistanceExceeded", &message);
+        return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+    }
+    let trip_options = match options.trip_options() {
+        Ok(trip_options) if coordinates.0.len() >= 2 => trip_options,
+        Ok(_) => {
+            let error = OsrmError::new("InvalidOptions", "A trip needs at least two coordinates");
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+    let geometries = match options.geometries(profile.geometries) {
+        Ok(geometries) => geometries,
+        Err(message) => {
+            let error = OsrmError::new("InvalidOptions", &message);
+            return Ok(error_reply(error, StatusCode::BAD_REQUEST, &log));
+        }
+    };
+
+    // The matrix, the order and the legs block a thread, so they run apart from the handler like a route
+    let token = profile.request_token();
+    let _cancel = CancelOnDrop(token.clone());
+    let search = tokio::task::spawn_blocking(move || -> Box<dyn warp::Reply> {
+        let matcher = WaypointMatcher::new(&loaded, &profile, cache.as_ref());
+        let cost = profile.cost(CostContext::default());
+        let closed = costs.closures.closed_at(unix_time());
+        let destinations = match_open_waypoints(&loaded.graph, &matcher, &closed, &coordinates.0);
+        if destinations.iter().any(|destination| destination.snapped.is_empty()) {
+            let error = OsrmError::new("NoSegment", "Could not find a matching segment for a coordinate");
+            return error_reply(error, StatusCode::NOT_FOUND, &log);
+        }
+
+        // The order is optimized on the durations between all the coordinates
+        let row_options = RowOptions {
+            max_cost: None,
+            reliability: Reliability::Typical,
+            closed: &closed,
+            token: &token,
+        };
+        let mut durations = Vec::new();
+        for origin in &coordinates.0 {
+            let (row, settled) = table_row(&loaded.graph, &matcher, cost, origin, &destinations, &row_options);
+            log.settled += settled;
+            if token.is_cancelled() {
+                return error_reply(timeout_error(), StatusCode::GATEWAY_TIMEOUT, &log);
+            }
+            durations.push(row.iter().map(|entry| entry.map(|(duration, _)| duration)).collect::<Vec<_>>());
+        }
+        let order = match optimize_order(&durations, &trip_options) {
+            Ok(order) => order,
+            Err(error @ TripError::Unsupported) => {
+                let error = OsrmError::new("NotImplemented", &error.to_string());
+                return error_reply(error, StatusCode::BAD_REQUEST, &log);
+            }
+            Err(error @ TripError::Unreachable) => {
+                let error = OsrmError::new("NoTrips", &error.to_string());
+                return error_reply(error, StatusCode::NOT_FOUND, &log);
+            }
+        };
+
+        // A round trip returns to the waypoint it started at
+        let mut stops = order.clone();
+        if trip_options.roundtrip {
+            stops.push(order[0]);
+        }
+        let legs: Option<Vec<_>> = stops
+            .windows(2)
+            .map(|leg| {
+                let (from, to) = (&coordinates.0[leg[0]], &coordinates.0[leg[1]]);
+                find_trip_leg(&loaded.graph, &matcher, cost, from, to, &closed, &token)
+            })
+            .collect();
+        if token.is_cancelled() {
+            return error_reply(timeout_error(), StatusCode::GATEWAY_TIMEOUT, &log);
+        }
+        match legs {
+            Some(legs) => {
+                log.finish(StatusCode::OK);
+                let response = warp::reply::json(&OsrmTripResponse::new(&legs, &order, &destinations, geometries));
+                Box::new(warp::reply::with_header(response, REQUEST_ID_HEADER, log.request_id))
+            }
+            None => {
+                let error = OsrmError::new("NoTrips", "No route found between the waypoints of the trip");
+                error_reply(error, StatusCode::NOT_FOUND, &log)
+            }
+        }
+    });
+    Ok(search.await.unwrap())
+}
+
+/// Graph segments within the web mercator tile, for the debug map. Rendered tiles are cached
+#[utoipa::path(
+    get,
+    path = "/debug/tiles/{z}/{x}/{y}",
+    tag = "debug",
+    params(
+        ("z" = u8, Path, minimum = 12, maximum = 22, description = "Zoom level"),
+        ("x" = u32, Path, description = "Column of the tile"),
+        ("y" = u32, Path, description = "Row of the tile"),
+    ),
+    responses(
+        (status = 200, body = Object, content_type = "application/geo+json",
+            description = "GeoJSON feature collection"),
+        (status = 400, body = OsrmError, description = "`InvalidUrl` for a zoom level or tile out of range"),
+    )
+)]
+async fn tile_handler(
+    z: u8,
+    x: u32,
+    y: u32,
+    loaded: LoadedGraph,
+    tile_cache: Arc<TileCache>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if !is_valid_tile(z, x, y) {
+        let message = format!("Tiles are available for zoom levels {} to {}", MIN_TILE_ZOOM, MAX_TILE_ZOOM);
+        let error = OsrmError::new("InvalidUrl", &message);
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST)));
+    }
+    let tile = tile_cache.get_or_render((z, x, y, loaded.generation), || render_tile(&loaded.graph, z, x, y));
+    let response = warp::http::Response::builder()
+        .header("content-type", "application/geo+json")
+        .body(Body::from(tile))
+        .unwrap();
+    Ok(Box::new(response))
+}
+
+/// Attributes, geometry and connected segments of a graph segment, to investigate why a route uses it
+#[utoipa::path(
+    get,
+    path = "/edge/{id}",
+    tag = "debug",
+    params(("id" = u32, Path, description = "Id of the segment")),
+    responses(
+        (status = 200, body = Object, description = "Segment with its outgoing and incoming segments"),
+        (status = 404, body = OsrmError, description = "`NoSegment` if there is no segment with the id"),
+    )
+)]
+async fn edge_handler(id: Idx, graph: Arc<OsmGraph>) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match describe_edge(graph.as_ref(), id) {
+        Some(edge) => Ok(Box::new(warp::reply::json(&edge))),
+        None => {
+            let error = OsrmError::new("NoSegment", &format!("There is no segment {}", id));
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::NOT_FOUND)))
+        }
+    }
+}
+
+/// Segments built from the OSM way in both directions, each described like by `/edge/{id}`
+#[utoipa::path(
+    get,
+    path = "/way/{way_id}",
+    tag = "debug",
+    params(("way_id" = i64, Path, description = "Id of the OSM way")),
+    responses(
+        (status = 200, body = Object, description = "Segments of the way"),
+        (status = 404, body = OsrmError, description = "`NoSegment` if the way isn't a part of the graph"),
+    )
+)]
+async fn way_handler(way_id: i64, graph: Arc<OsmGraph>) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match describe_way(graph.as_ref(), way_id) {
+        Some(way) => Ok(Box::new(warp::reply::json(&way))),
+        None => {
+            let error = OsrmError::new("NoSegment", &format!("The way {} isn't a part of the graph", way_id));
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::NOT_FOUND)))
+        }
+    }
+}
+
+/// Generation and size of the loaded graph, memory allocated for each of its arrays and cache statistics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, body = Object, description = "Graph and cache metrics"))
+)]
+async fn metrics_handler(
+    loaded: LoadedGraph,
+    cache: Arc<SnapCache>,
+    tile_cache: Arc<TileCache>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let graph = &loaded.graph;
+    let memory = graph.memory_usage();
+    Ok(warp::reply::json(&serde_json::json!({
+        "graph": {
+            "generation": loaded.generation,
+            "nodes": graph.number_of_nodes(),
+            "edges": graph.number_of_edges(),
+            "memory_bytes": memory.total_bytes(),
+            "memory": memory,
+        },
+        "snap_cache": cache.metrics(),
+        "tile_cache": tile_cache.metrics(),
+        "search_algorithms": search_metrics(),
+    })))
+}
+
+/// Distributions of the speed limits, road classes, lengths and flags of the segments of the loaded graph, to compare
+/// imports
+///
+/// The histograms are counted over all segments, so they are computed apart from the handler.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "operations",
+    responses((status = 200, body = Object, description = "Segment attribute histograms"))
+)]
+async fn stats_handler(loaded: LoadedGraph) -> Result<impl warp::Reply, warp::Rejection> {
+    let generation = loaded.generation;
+    let stats = tokio::task::spawn_blocking(move || GraphStats::of(&loaded.graph)).await.unwrap();
+    Ok(warp::reply::json(&serde_json::json!({ "generation": generation, "stats": stats })))
+}
+
+/// Whether the canary routes of the warm-up config passed on the served graph
+///
+/// A load balancer doesn't send traffic to a bad build this way.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "operations",
+    responses(
+        (status = 200, body = Object, description = "The service is ready"),
+        (status = 503, body = Object, description = "Canary routes failed, listed in `failures`"),
+    )
+)]
+async fn ready_handler(readiness: Arc<Readiness>) -> Result<impl warp::Reply, warp::Rejection> {
+    let failures = readiness.failures();
+    let status = if failures.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "ready": failures.is_empty(), "failures": failures })),
+        status,
+    ))
+}
+
+/// Loads graph.bin again as a new generation, a graph failing the canary routes is not used
+///
+/// The graph is loaded in the background and swapped in when it's ready, requests are served from the old graph
+/// meanwhile. The waypoints cached for the old one are dropped once it's loaded.
+#[utoipa::path(
+    post,
+    path = "/reload",
+    tag = "operations",
+    responses((status = 202, description = "The graph is being loaded in the background"))
+)]
+async fn reload_handler(
+    graph: SharedGraph,
+    cache: Arc<SnapCache>,
+    costs: Arc<CostConfig>,
+    canaries: Arc<Vec<Canary>>,
+    readiness: Arc<Readiness>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    std::thread::spawn(move || {
+        let reload_timer = Instant::now();
+        match load_graph(GRAPH_PATH) {
+            Ok(reloaded) => {
+                let reloaded = Arc::new(reloaded);
+                let candidate = LoadedGraph {
+                    generation: graph.read().unwrap().generation + 1,
+                    graph: Arc::clone(&reloaded),
+                };
+                let failures = run_canaries(&candidate, &costs, &canaries);
+                if !failures.is_empty() {
+                    println!("Reloaded graph failed {} canary routes, keeping the old one", failures.len());
+                    return;
+                }
+                readiness.set(failures);
+                let mut loaded = graph.write().unwrap();
+                loaded.generation += 1;
+                loaded.graph = reloaded;
+                let generation = loaded.generation;
+                drop(loaded);
+                // Cached waypoints refer to the nodes of the old graph
+                cache.set_generation(generation);
+                println!(
+                    "Reloaded graph in {:.1} seconds",
+                    reload_timer.elapsed().as_secs_f32()
+                );
+            }
+            Err(e) => println!("Can't reload graph: {}", e),
+        }
+    });
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "status": "reloading" })),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+/// Reports the live speeds of OSM ways, driving routes use them once the next update is folded in
+#[utoipa::path(
+    post,
+    path = "/traffic",
+    tag = "feeds",
+    request_body = Vec<TrafficUpdate>,
+    responses(
+        (status = 202, description = "The speeds are queued"),
+        (status = 400, body = OsrmError, description = "`InvalidValue` if a speed factor isn't positive"),
+        (status = 411, description = "The request has no `Content-Length`"),
+        (status = 413, description = "The body is larger than 16 MiB"),
+    )
+)]
+async fn traffic_update_handler(
+    updates: Vec<TrafficUpdate>,
+    costs: Arc<CostConfig>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if !updates.iter().all(TrafficUpdate::is_valid) {
+        let error = OsrmError::new("InvalidValue", "Speed factors must be positive");
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST)));
+    }
+    let accepted = updates.len();
+    costs.live_traffic.update(updates);
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "accepted": accepted })),
+        StatusCode::ACCEPTED,
+    )))
+}
+
+/// Closes OSM ways for a time window, e.g. for construction or an event. Routes neither snap to nor pass closed ways
+///
+/// The closures apply to the routes from the start of their windows.
+#[utoipa::path(
+    post,
+    path = "/closures",
+    tag = "feeds",
+    request_body = ClosureFeed,
+    responses(
+        (status = 202, description = "The closures are stored"),
+        (status = 400, body = OsrmError,
+            description = "`InvalidValue` if a closure has no id or ways, or its window ends before it starts"),
+        (status = 411, description = "The request has no `Content-Length`"),
+        (status = 413, description = "The body is larger than 16 MiB"),
+    )
+)]
+async fn closure_update_handler(
+    feed: ClosureFeed,
+    costs: Arc<CostConfig>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if !feed.closures.iter().all(Closure::is_valid) {
+        let error = OsrmError::new("InvalidValue", "Closures must have an id, ways and a window ending after it starts");
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST)));
+    }
+    for closure in &feed.closures {
+        println!(
+            "Closure {} of {} ways from {:?} to {:?}: {}",
+            closure.id,
+            closure.way_ids.len(),
+            closure.start,
+            closure.end,
+            closure.reason.as_deref().unwrap_or("no reason")
+        );
+    }
+    let accepted = feed.closures.len();
+    costs.closures.update(feed.closures, unix_time());
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "accepted": accepted })),
+        StatusCode::ACCEPTED,
+    )))
+}
 
-        let response = OsrmRouteResponse::new(geometry, distance, duration, route.cost, &waypoints);
-        return Ok(warp::reply::json(&response));
+// Turns a rate limit rejection into an OSRM error, other rejections are passed to warp
+async fn handle_limited(rejection: Rejection) -> Result<Box<dyn warp::Reply>, Rejection> {
+    match rejection.find::<Limited>() {
+        Some(Limited::UnknownApiKey) => {
+            let error = OsrmError::new("InvalidApiKey", "The API key is not known");
+            Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&error),
+                StatusCode::UNAUTHORIZED,
+            )))
+        }
+        Some(Limited::TooManyRequests { retry_after }) => {
+            let error = OsrmError::new("TooManyRequests", "Too many requests, retry later");
+            let reply = warp::reply::with_status(warp::reply::json(&error), StatusCode::TOO_MANY_REQUESTS);
+            // Whole seconds, rounded up so the client doesn't retry too early
+            let retry_after_s = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            Ok(Box::new(warp::reply::with_header(reply, "retry-after", retry_after_s.to_string())))
+        }
+        None => Err(rejection),
     }
+}
+
+// Profile named in the request path, unknown names aren't found like any other unknown path
+async fn find_profile(name: String, profiles: Arc<ProfileRegistry>) -> Result<Arc<Profile>, Rejection> {
+    profiles.find(&name).ok_or_else(warp::reject::not_found)
+}
 
-    println!("No route found");
-    return Err(reject::not_found());
+// Size limit set with the environment variable, or the default one
+fn max_size(variable: &str, default: usize) -> usize {
+    std::env::var(variable)
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(default)
 }
 
 #[tokio::main]
 async fn main() {
     let startup_timer = Instant::now();
 
-    let graph = Arc::new(load_graph("graph.bin").unwrap());
+    let config = Config::load().unwrap();
+    let limiter = config.rate_limit.map(|config| Arc::new(RateLimiter::new(config)));
+    let limit = rate_limit(limiter);
+
+    let graph = Arc::new(load_graph(GRAPH_PATH).unwrap());
     println!(
         "Loaded graph with {} nodes and {} edges in {:.1} seconds",
         graph.number_of_nodes(),
         graph.number_of_edges(),
         startup_timer.elapsed().as_secs_f32()
     );
-    graph.print_stats();
+    println!("{}", graph.memory_usage());
+
+    let shared_graph: SharedGraph = Arc::new(RwLock::new(LoadedGraph { generation: 0, graph }));
+    let reload_graph = shared_graph.clone();
+    // The graph and its generation are read together, so a request never mixes them up with the reloaded ones
+    let loaded = warp::any().map(move || shared_graph.read().unwrap().clone());
+    let graph = loaded.clone().map(|loaded: LoadedGraph| loaded.graph);
+
+    let snap_cache = Arc::new(SnapCache::new(config.snap_cache));
+    let cache = warp::any().map(move || Arc::clone(&snap_cache));
 
-    let graph = warp::any().map(move || Arc::clone(&graph));
+    let costs = Arc::new(CostConfig {
+        tariffs: TollTariffs::new(&config.tolls),
+        preferences: config.preferences,
+        traffic: TrafficPattern::new(&config.traffic).unwrap(),
+        live_traffic: LiveTraffic::start(&config.live_traffic),
+        closures: ClosureLayer::default(),
+    });
+
+    let profiles = Arc::new(ProfileRegistry::new(&config.profiles).unwrap());
+
+    // Canaries run before the service takes any request, it reports whether it's ready from the start
+    let canaries = Arc::new(Canary::from_config(&config.warm_up, &profiles).unwrap());
+    let readiness = Arc::new(Readiness::default());
+    if !canaries.is_empty() {
+        let warm_up_timer = Instant::now();
+        readiness.set(run_canaries(&reload_graph.read().unwrap(), &costs, &canaries));
+        println!(
+            "Ran {} canary routes in {:.1} seconds",
+            canaries.len(),
+            warm_up_timer.elapsed().as_secs_f32()
+        );
+    }
+    let readiness = warp::any().map(move || Arc::clone(&readiness));
+
+    let costs = warp::any().map(move || Arc::clone(&costs));
+
+    let tile_cache = Arc::new(TileCache::new(config.tile_cache));
+    let tile_cache = warp::any().map(move || Arc::clone(&tile_cache));
 
     let cors = warp::cors().allow_any_origin();
 
+    let registry = Arc::clone(&profiles);
+    let profile = warp::path::param::<String>()
+        .and(warp::any().map(move || Arc::clone(&registry)))
+        .and_then(find_profile);
+
     let route_api = warp::path("route")
         .and(warp::path("v1"))
-        .and(warp::path("driving"))
+        .and(profile.clone())
         .and(warp::path::param::<Waypoints>())
         .and(warp::path::end())
-        .and(graph.clone())
+        .and(limit.clone())
+        .and(warp::query::<OsrmRouteOptions>())
+        .and(request_id_and_target())
+        .and(loaded.clone())
+        .and(cache.clone())
+        .and(costs.clone())
         .and_then(osrm_route_request_handler)
+        .with(cors.clone());
+
+    let max_table_size = max_size("ARLI_MAX_TABLE_SIZE", DEFAULT_MAX_TABLE_SIZE);
+    let table_api = warp::path("table")
+        .and(warp::path("v1"))
+        .and(profile.clone())
+        .and(warp::path::param::<Coordinates>())
+        .and(warp::path::end())
+        .and(limit.clone())
+        .and(warp::query::<TableOptions>())
+        .and(warp::any().map(move || MatrixLimits::square(max_table_size)))
+        .and(request_id_and_target())
+        .and(loaded.clone().and(cache.clone()).map(|loaded, cache| (loaded, cache)))
+        .and(costs.clone())
+        .and_then(osrm_table_request_handler)
+        .with(cors.clone());
+
+    let max_trip_size = max_size("ARLI_MAX_TRIP_SIZE", DEFAULT_MAX_TRIP_SIZE);
+    let trip_api = warp::path("trip")
+        .and(warp::path("v1"))
+        .and(profile)
+        .and(warp::path::param::<Coordinates>())
+        .and(warp::path::end())
+        .and(limit)
+        .and(warp::query::<OsrmTripOptions>())
+        .and(warp::any().map(move || MatrixLimits::square(max_trip_size)))
+        .and(request_id_and_target())
+        .and(loaded.clone().and(cache.clone()).map(|loaded, cache| (loaded, cache)))
+        .and(costs.clone())
+        .and_then(osrm_trip_request_handler)
         .with(cors);
 
-    let frontend = warp::path("frontend").and(warp::fs::dir("frontend"));
+    let spec = openapi_spec(&profiles, max_table_size, max_trip_size);
+    let openapi = warp::path("openapi.json")
+        .and(warp::path::end())
+        .map(move || warp::reply::json(&spec));
+    let docs = warp::path("docs")
+        .and(warp::path::end())
+        .map(|| warp::reply::html(SWAGGER_UI));
+
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(loaded.clone())
+        .and(cache.clone())
+        .and(tile_cache.clone())
+        .and_then(metrics_handler);
+
+    let stats = warp::path("stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(loaded.clone())
+        .and_then(stats_handler);
+
+    let debug_tiles = warp::path("debug")
+        .and(warp::path("tiles"))
+        .and(warp::path::param::<u8>())
+        .and(warp::path::param::<u32>())
+        .and(warp::path::param::<u32>())
+        .and(warp::path::end())
+        .and(loaded)
+        .and(tile_cache)
+        .and_then(tile_handler);
+
+    let edge = warp::path("edge")
+        .and(warp::path::param::<Idx>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(graph.clone())
+        .and_then(edge_handler);
+
+    let way = warp::path("way")
+        .and(warp::path::param::<i64>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(graph)
+        .and_then(way_handler);
+
+    let reload = warp::path("reload")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::any().map(move || reload_graph.clone()))
+        .and(cache)
+        .and(costs.clone())
+        .and(warp::any().map(move || Arc::clone(&canaries)))
+        .and(readiness.clone())
+        .and_then(reload_handler);
+
+    let ready = warp::path("ready")
+        .and(warp::path::end())
+        .and(readiness)
+        .and_then(ready_handler);
+
+    let traffic = warp::path("traffic")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_FEED_BODY_BYTES))
+        .and(warp::body::json())
+        .and(costs.clone())
+        .and_then(traffic_update_handler);
+
+    let closures = warp::path("closures")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_FEED_BODY_BYTES))
+        .and(warp::body::json())
+        .and(costs)
+        .and_then(closure_update_handler);
+
+    let frontend = frontend_files(&config.frontend);
 
     println!("Started service with the bind address 127.0.0.1:5000");
-    warp::serve(route_api.or(frontend))
+    let routes = route_api
+        .or(table_api)
+        .or(trip_api)
+        .or(openapi)
+        .or(docs)
+        .or(metrics)
+        .or(stats)
+        .or(ready)
+        .or(reload)
+        .or(traffic)
+        .or(closures)
+        .or(debug_tiles)
+        .or(edge)
+        .or(way)
+        .or(frontend)
+        .recover(handle_limited);
+    warp::serve(routes)
         .run(([127, 0, 0, 1], 5000))
         .await;
 }
@@ -0,0 +1,197 @@
+use crate::closures::{Closure, ClosureFeed};
+use crate::cost_functions::ProfileRegistry;
+use crate::live_traffic::TrafficUpdate;
+use crate::osrm_api::{OsrmError, OsrmRouteResponse, OsrmTableResponse, OsrmTableRow, OsrmTripResponse};
+use serde_json::{json, Value};
+use utoipa::OpenApi;
+
+/// Page rendering the specification with Swagger UI
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>arli service API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@3/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@3/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// Description of the responses to a request with an unknown API key
+pub const INVALID_API_KEY_DESCRIPTION: &str = "`InvalidApiKey` if the `X-Api-Key` header has an unknown key";
+/// Description of the responses to a client over its rate limit
+pub const TOO_MANY_REQUESTS_DESCRIPTION: &str = "`TooManyRequests` if the client is over its rate limit";
+/// Description of the responses to a request running out of time
+pub const TIMEOUT_DESCRIPTION: &str =
+  "`Timeout` if the searches take longer than the `search_timeout_ms` of the profile";
+
+// Endpoints are described by the `utoipa::path` attributes of their handlers, the query parameters and the bodies by
+// the types the handlers deserialize and serialize
+#[derive(OpenApi)]
+#[openapi(
+  info(title = "arli service", description = "Routing API partially compatible with OSRM"),
+  paths(
+    crate::osrm_route_request_handler,
+    crate::osrm_table_request_handler,
+    crate::osrm_trip_request_handler,
+    crate::metrics_handler,
+    crate::stats_handler,
+    crate::ready_handler,
+    crate::tile_handler,
+    crate::edge_handler,
+    crate::way_handler,
+    crate::reload_handler,
+    crate::traffic_update_handler,
+    crate::closure_update_handler,
+  ),
+  components(schemas(
+    OsrmRouteResponse,
+    OsrmTableResponse,
+    OsrmTableRow,
+    OsrmTripResponse,
+    OsrmError,
+    TrafficUpdate,
+    ClosureFeed,
+    Closure
+  ))
+)]
+struct ApiDoc;
+
+// Parameter of the endpoint with the name
+fn parameter<'a>(spec: &'a mut Value, path: &str, name: &str) -> Option<&'a mut Value> {
+  let parameters = spec["paths"][path]["get"]["parameters"].as_array_mut()?;
+  parameters.iter_mut().find(|parameter| parameter["name"] == name)
+}
+
+/// OpenAPI specification of the endpoints served by the service.
+///
+/// Parameters which depend on the service setup, like the profile names and the size limits, are filled in
+/// from the same values the handlers use.
+pub fn openapi_spec(profiles: &ProfileRegistry, max_table_size: usize, max_trip_size: usize) -> Value {
+  let mut api = ApiDoc::openapi();
+  // The license is taken from the manifest, which has none
+  api.info.license = None;
+  let mut spec = serde_json::to_value(api).unwrap();
+  let names = profiles.names();
+  let osrm_paths = [
+    ("/route/v1/{profile}/{coordinates}", None),
+    ("/table/v1/{profile}/{coordinates}", Some(format!("Up to {}", max_table_size))),
+    ("/trip/v1/{profile}/{coordinates}", Some(format!("From 2 to {}", max_trip_size))),
+  ];
+  for (path, limit) in osrm_paths.iter() {
+    if let Some(profile) = parameter(&mut spec, path, "profile") {
+      profile["schema"]["enum"] = json!(names);
+    }
+    if let Some(coordinates) = parameter(&mut spec, path, "coordinates") {
+      coordinates["schema"]["pattern"] = json!(r"^-?[\d.]+,-?[\d.]+(;-?[\d.]+,-?[\d.]+)*$");
+      coordinates["example"] = json!("13.388860,52.517037;13.397634,52.529407");
+      if let Some(limit) = limit {
+        let description = coordinates["description"].as_str().unwrap_or_default();
+        coordinates["description"] = json!(format!("{} {}", limit, description));
+      }
+    }
+  }
+  spec
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::osrm_api::{OsrmRouteOptions, OsrmTripOptions, TableOptions};
+  use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+  // Deserializer which only records the names of the fields of the struct deserialized from it
+  struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+  impl<'de, 'a> Deserializer<'de> for FieldNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+      Err(de::Error::custom("Not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+      self,
+      _name: &'static str,
+      fields: &'static [&'static str],
+      _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+      *self.0 = fields;
+      Err(de::Error::custom("Only the fields are read"))
+    }
+
+    serde::forward_to_deserialize_any! {
+      bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit unit_struct
+      newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+  }
+
+  fn field_names<'de, T: Deserialize<'de>>() -> Vec<&'static str> {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    let mut fields = fields.to_vec();
+    fields.sort_unstable();
+    fields
+  }
+
+  fn query_parameters<'a>(spec: &'a Value, path: &str) -> Vec<&'a str> {
+    let parameters = spec["paths"][path]["get"]["parameters"].as_array().unwrap();
+    let mut names: Vec<_> = parameters
+      .iter()
+      .filter(|parameter| parameter["in"] == "query")
+      .map(|parameter| parameter["name"].as_str().unwrap())
+      .collect();
+    names.sort_unstable();
+    names
+  }
+
+  // Targets of the `$ref`s of the value
+  fn references<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+      Value::Object(object) => {
+        if let Some(Value::String(target)) = object.get("$ref") {
+          found.push(target);
+        }
+        object.values().for_each(|value| references(value, found));
+      }
+      Value::Array(array) => array.iter().for_each(|value| references(value, found)),
+      _ => {}
+    }
+  }
+
+  #[test]
+  fn test_every_schema_is_defined() {
+    let spec = openapi_spec(&ProfileRegistry::new(&[]).unwrap(), 100, 10);
+    let mut found = Vec::new();
+    references(&spec, &mut found);
+    assert!(found.contains(&"#/components/schemas/OsrmRouteResponse"));
+    for target in found {
+      let name = target.trim_start_matches("#/components/schemas/");
+      assert!(spec["components"]["schemas"].get(name).is_some(), "{}", target);
+    }
+
+    let profile = &spec["paths"]["/route/v1/{profile}/{coordinates}"]["get"]["parameters"][0];
+    assert_eq!(profile["schema"]["enum"][1], "car");
+    let coordinates = &spec["paths"]["/table/v1/{profile}/{coordinates}"]["get"]["parameters"][1];
+    assert!(coordinates["description"].as_str().unwrap().starts_with("Up to 100 "));
+  }
+
+  #[test]
+  fn test_every_option_is_documented() {
+    let spec = openapi_spec(&ProfileRegistry::new(&[]).unwrap(), 100, 10);
+    let options = [
+      ("/route/v1/{profile}/{coordinates}", field_names::<OsrmRouteOptions>()),
+      ("/table/v1/{profile}/{coordinates}", field_names::<TableOptions>()),
+      ("/trip/v1/{profile}/{coordinates}", field_names::<OsrmTripOptions>()),
+    ];
+    for (path, fields) in options.iter() {
+      assert!(!fields.is_empty());
+      assert_eq!(&query_parameters(&spec, path), fields, "{}", path);
+    }
+  }
+}
@@ -1,46 +1,292 @@
-use arli::spatial::{Position, Coordinate};
+use crate::corridor::{Corridor, DEFAULT_CORRIDOR_RADIUS_M};
+use crate::cost_functions::{CostContext, Preferences};
+use crate::hints::{parse_hints, WaypointHint};
+use crate::traffic::RouteTime;
+use crate::travel_times::Reliability;
+use arli::spatial::{bounding_box, clip_polyline, BoundingBox, Coordinate, Position};
 use arli::graph::Weight;
-use polyline::encode_coordinates;
+use arli::guidance::{Intersection, Maneuver, ManeuverKind};
+use arli::route::RouteQuality;
+use arli::trip::TripOptions;
+use arli::waypoint::MatchedWaypoint;
+use arli::graph_impl::Idx;
+use polyline::{decode_polyline, encode_coordinates};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Deserialize, Serialize)]
+/// Most alternative routes a request can ask for, each one is another search
+pub const MAX_ALTERNATIVES: usize = 3;
+
+#[derive(Deserialize, Serialize, ToSchema)]
 struct OsrmWaypoint {
+  /// Distance from the coordinate to the road it is snapped to, in meters
   distance: f32,
+  /// Location on the road the coordinate is snapped to
+  #[schema(min_items = 2, max_items = 2)]
   location: Vec<f32>,
+  /// Opaque base64 matched waypoint to send back in `hints`, e.g. when re-routing, so the waypoint snaps to the same
+  /// road
+  #[serde(skip_serializing_if = "Option::is_none")]
+  hint: Option<String>,
 }
 
 impl OsrmWaypoint {
-  pub fn from(p: &Position) -> Self {
-    OsrmWaypoint {
-      distance: 0.0,
-      location: vec![p.x, p.y],
+  /// Location on the road the waypoint is snapped to and its distance from the requested coordinate. A waypoint
+  /// without a matching road is reported at the requested coordinate.
+  pub fn from(matched: &MatchedWaypoint<Idx>) -> Self {
+    let nearest = matched
+      .snapped
+      .iter()
+      .map(|candidate| candidate.0)
+      .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    match nearest {
+      Some(snapped) => OsrmWaypoint {
+        distance: snapped.distance,
+        location: vec![snapped.snapped.x, snapped.snapped.y],
+        hint: WaypointHint::encode(matched),
+      },
+      None => OsrmWaypoint {
+        distance: 0.0,
+        location: vec![matched.waypoint.x, matched.waypoint.y],
+        hint: None,
+      },
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct OsrmManeuver {
+  location: Vec<f32>,
+  bearing_before: u16,
+  bearing_after: u16,
+  #[serde(rename = "type")]
+  kind: String,
+}
+
+fn osrm_bearing(bearing: f32) -> u16 {
+  bearing.round() as u16 % 360
+}
+
+impl OsrmManeuver {
+  pub fn from(maneuver: &Maneuver) -> Self {
+    let kind = match maneuver.kind {
+      ManeuverKind::Depart => "depart",
+      ManeuverKind::Turn => "turn",
+      ManeuverKind::Arrive => "arrive",
+    };
+    OsrmManeuver {
+      location: vec![maneuver.location.x, maneuver.location.y],
+      bearing_before: osrm_bearing(maneuver.bearing_before),
+      bearing_after: osrm_bearing(maneuver.bearing_after),
+      kind: String::from(kind),
     }
   }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
+struct OsrmIntersection {
+  location: Vec<f32>,
+  bearings: Vec<u16>,
+  entry: Vec<bool>,
+  #[serde(rename = "in", skip_serializing_if = "Option::is_none")]
+  arrival: Option<usize>,
+  #[serde(rename = "out", skip_serializing_if = "Option::is_none")]
+  departure: Option<usize>,
+}
+
+impl OsrmIntersection {
+  pub fn from(intersection: &Intersection) -> Self {
+    OsrmIntersection {
+      location: vec![intersection.location.x, intersection.location.y],
+      bearings: intersection.bearings.iter().map(|bearing| osrm_bearing(*bearing)).collect(),
+      entry: intersection.entry.clone(),
+      arrival: intersection.arrival,
+      departure: intersection.departure,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct OsrmStep {
+  #[schema(inline)]
+  maneuver: OsrmManeuver,
+  #[serde(skip_serializing_if = "Vec::is_empty", default)]
+  #[schema(inline)]
+  intersections: Vec<OsrmIntersection>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 struct OsrmLeg {
   weight: f64,
   distance: f64,
   summary: String,
   duration: f64,
-  steps: Vec<String>,
+  #[schema(inline)]
+  steps: Vec<OsrmStep>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl OsrmLeg {
+  fn new<W: Weight + Into<f64>>(cost: W, distance: W, duration: W, maneuvers: &[Maneuver], summary: &[&str]) -> Self {
+    OsrmLeg {
+      weight: cost.into(),
+      distance: distance.into(),
+      summary: summary.join(", "),
+      duration: duration.into(),
+      steps: maneuvers
+        .iter()
+        .map(|maneuver| OsrmStep {
+          maneuver: OsrmManeuver::from(maneuver),
+          intersections: Vec::new(),
+        })
+        .collect(),
+    }
+  }
+
+  // Intersection of each step, see `route_intersections`
+  fn with_intersections(mut self, intersections: &[Intersection]) -> Self {
+    for (step, intersection) in self.steps.iter_mut().zip(intersections) {
+      step.intersections = vec![OsrmIntersection::from(intersection)];
+    }
+    self
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 struct OsrmRoute {
   distance: f64,
   duration: f64,
-  geometry: String,
+  /// Encoded polyline with precision 5, or 6 with `polyline6` geometries, missing if `overview` is `false`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  geometry: Option<String>,
+  /// Format `geometry` is encoded in, the viewport parts are encoded in the same one
+  #[serde(skip)]
+  geometries: Geometries,
+  #[schema(inline)]
   legs: Vec<OsrmLeg>,
+  /// Not a part of the OSRM API: ids of the segments of the route, only if `overview` is `false`, see `/edge/{id}`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[schema(value_type = Option<Vec<u32>>)]
+  segments: Option<Vec<Idx>>,
+  /// Not a part of the OSRM API: reliability of the route from 0 to 1, lowered by long snapping distances, by the
+  /// roads poorly mapped for the profile and by the waypoints matched to the nearest segment beyond the snapping radius
+  #[serde(skip_serializing_if = "Option::is_none")]
+  quality: Option<f32>,
+  /// Not a part of the OSRM API: distance and duration in each region the route passes through, only if the graph
+  /// was built with regions
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[schema(inline)]
+  regions: Option<Vec<OsrmRegionSection>>,
+  /// Not a part of the OSRM API: estimated toll of the route, in the currency of the configured tariffs
+  #[serde(skip_serializing_if = "Option::is_none")]
+  toll: Option<f64>,
+  /// Not a part of the OSRM API: duration of the route without the live traffic, only if the traffic is live
+  #[serde(skip_serializing_if = "Option::is_none")]
+  usual_duration: Option<f64>,
+  /// Not a part of the OSRM API: departure from the origin, in seconds since the Unix epoch
+  #[serde(skip_serializing_if = "Option::is_none")]
+  departure: Option<i64>,
+  /// Not a part of the OSRM API: arrival at the destination, in seconds since the Unix epoch
+  #[serde(skip_serializing_if = "Option::is_none")]
+  arrival: Option<i64>,
+  /// Not a part of the OSRM API: bounding box of the geometry, `[min_lon, min_lat, max_lon, max_lat]`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  bbox: Option<[f32; 4]>,
+  /// Not a part of the OSRM API: parts of the geometry within the requested viewport, in the format of `geometry`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  viewport_geometry: Option<Vec<String>>,
+}
+
+/// Format of the geometries of a response, see the `geometries` option of OSRM
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Geometries {
+  /// Encoded polyline with 5 digits of the coordinates, about a meter
+  #[default]
+  Polyline,
+  /// Encoded polyline with 6 digits of the coordinates, about 10 centimeters
+  Polyline6,
+}
+
+impl Geometries {
+  fn precision(self) -> u32 {
+    match self {
+      Geometries::Polyline => 5,
+      Geometries::Polyline6 => 6,
+    }
+  }
+}
+
+impl FromStr for Geometries {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "polyline" => Ok(Geometries::Polyline),
+      "polyline6" => Ok(Geometries::Polyline6),
+      _ => Err(format!("Unknown geometries {}, expected polyline or polyline6", name)),
+    }
+  }
+}
+
+// Format asked for by a request, or the one of the profile
+fn geometries(value: &Option<String>, default: Geometries) -> Result<Geometries, String> {
+  match value {
+    Some(value) => value.parse(),
+    None => Ok(default),
+  }
+}
+
+/// Geometry of a route encoded in the format of the response
+pub struct EncodedGeometry {
+  polyline: String,
+  bbox: Option<[f32; 4]>,
+  geometries: Geometries,
+}
+
+impl EncodedGeometry {
+  pub fn new<P: Into<Position>, Geometry: IntoIterator<Item = P>>(geometry: Geometry, geometries: Geometries) -> Self {
+    let points: Vec<Position> = geometry.into_iter().map(Into::into).collect();
+    Self {
+      polyline: encode_geometry(&points, geometries),
+      bbox: geometry_bbox(&points),
+      geometries,
+    }
+  }
 }
 
-#[derive(Deserialize, Serialize)]
+fn encode_geometry(points: &[Position], geometries: Geometries) -> String {
+  encode_coordinates(points.iter().cloned().map(map_coordinates), geometries.precision()).unwrap()
+}
+
+fn geometry_bbox(points: &[Position]) -> Option<[f32; 4]> {
+  bounding_box(points.iter().cloned()).map(|bbox| [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y])
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct OsrmRegionSection {
+  region: Option<String>,
+  distance: f64,
+  duration: f64,
+}
+
+impl OsrmRegionSection {
+  pub fn new<W: Weight + Into<f64>>(region: Option<&str>, distance: W, duration: W) -> OsrmRegionSection {
+    OsrmRegionSection {
+      region: region.map(String::from),
+      distance: distance.into(),
+      duration: duration.into(),
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct OsrmRouteResponse {
   code: String,
+  /// Fastest route followed by the alternatives, if they are asked for
+  #[schema(inline)]
   routes: Vec<OsrmRoute>,
+  #[schema(inline)]
   waypoints: Vec<OsrmWaypoint>,
 }
 
@@ -55,27 +301,53 @@ impl RequestError {
 
 pub struct Waypoints(pub Vec<Position>);
 
+fn parse_coordinates(s: &str) -> Result<Vec<Position>, RequestError> {
+  let mut result = Vec::new();
+  for coord_str in s.split(';') {
+    let coords: Vec<_> = coord_str.split(',').map(|s| s.parse::<f32>()).collect();
+    if coords.len() != 2 {
+      return Err(RequestError::with(
+        "Each waypoint must have two coordinates",
+      ));
+    };
+    let coords: Vec<_> = coords.iter().filter_map(|r| r.as_ref().ok()).collect();
+    if coords.len() != 2 {
+      return Err(RequestError(format!(
+        "Error one the coordinates for {}",
+        coord_str
+      )));
+    };
+    result.push(Position::from((*coords[0], *coords[1])));
+  }
+  Ok(result)
+}
+
+/// Error message if a coordinate is out of the range of the longitude and the latitude. Coordinates are longitude
+/// first, a coordinate which would be in range the other way around is reported as swapped. Swapped coordinates within
+/// both ranges, e.g. in most of Europe, can't be told apart from valid ones and aren't matched to the graph instead.
+pub fn check_coordinates(positions: &[Position]) -> Result<(), String> {
+  for (index, position) in positions.iter().enumerate() {
+    let in_range = |longitude: f32, latitude: f32| longitude.abs() <= 180.0 && latitude.abs() <= 90.0;
+    if in_range(position.x, position.y) {
+      continue;
+    }
+    let message = format!(
+      "Coordinate {} is {},{}, out of the range of longitude,latitude",
+      index, position.x, position.y
+    );
+    if in_range(position.y, position.x) {
+      return Err(format!("{}. It looks like latitude,longitude, swap the values", message));
+    }
+    return Err(message);
+  }
+  Ok(())
+}
+
 impl FromStr for Waypoints {
   type Err = RequestError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let mut result = Vec::new();
-    for coord_str in s.split(';') {
-      let coords: Vec<_> = coord_str.split(',').map(|s| s.parse::<f32>()).collect();
-      if coords.len() != 2 {
-        return Err(RequestError::with(
-          "Each waypoint must have two coordinates",
-        ));
-      };
-      let coords: Vec<_> = coords.iter().filter_map(|r| r.as_ref().ok()).collect();
-      if coords.len() != 2 {
-        return Err(RequestError(format!(
-          "Error one the coordinates for {}",
-          coord_str
-        )));
-      };
-      result.push(Position::from((*coords[0], *coords[1])));
-    }
+    let result = parse_coordinates(s)?;
     if result.len() != 2 {
       return Err(RequestError(format!(
         "Expect exactly 2 waypoints, {} found",
@@ -86,6 +358,17 @@ impl FromStr for Waypoints {
   }
 }
 
+/// Coordinates of a table request, each one is both a source and a destination
+pub struct Coordinates(pub Vec<Position>);
+
+impl FromStr for Coordinates {
+  type Err = RequestError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(Coordinates(parse_coordinates(s)?))
+  }
+}
+
 impl fmt::Display for Waypoints {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     for p in &self.0 {
@@ -101,33 +384,640 @@ fn map_coordinates<P: Into<Position>>(p: P) -> Coordinate<f64> {
 }
 
 impl OsrmRouteResponse {
-  pub fn new<P:Into<Position>, Geometry: IntoIterator<Item = P>, W: Weight + Into<f64>>(
-    geometry: Geometry,
+  pub fn new<W: Weight + Into<f64>>(
+    geometry: EncodedGeometry,
+    route_distance: W,
+    route_duration: W,
+    cost: W,
+    maneuvers: &[Maneuver],
+    summary: &[&str],
+    waypoints: [&MatchedWaypoint<Idx>; 2],
+  ) -> OsrmRouteResponse {
+    let route = OsrmRoute {
+      duration: route_duration.into(),
+      distance: route_distance.into(),
+      geometry: Some(geometry.polyline),
+      geometries: geometry.geometries,
+      legs: vec![OsrmLeg::new(cost, route_distance, route_duration, maneuvers, summary)],
+      segments: None,
+      quality: None,
+      regions: None,
+      toll: None,
+      usual_duration: None,
+      departure: None,
+      arrival: None,
+      bbox: geometry.bbox,
+      viewport_geometry: None,
+    };
+
+    OsrmRouteResponse {
+      code: String::from("Ok"),
+      routes: vec![route],
+      waypoints: waypoints.iter().map(|waypoint| OsrmWaypoint::from(waypoint)).collect(),
+    }
+  }
+
+  /// Route without the geometry and the steps, see [`OsrmRouteOptions::overview`]
+  pub fn summary<W: Weight + Into<f64>>(
     route_distance: W,
     route_duration: W,
     cost: W,
-    waypoints: &Waypoints,
+    segments: Vec<Idx>,
+    waypoints: [&MatchedWaypoint<Idx>; 2],
   ) -> OsrmRouteResponse {
     let route = OsrmRoute {
       duration: route_duration.into(),
       distance: route_distance.into(),
-      geometry: encode_coordinates(geometry.into_iter().map(map_coordinates), 5).unwrap(),
-      legs: vec![OsrmLeg {
-        weight: cost.into(),
-        distance: route_distance.into(),
-        summary: String::from("test"),
-        duration: route_duration.into(),
-        steps: vec![],
-      }],
+      geometry: None,
+      geometries: Geometries::default(),
+      legs: vec![OsrmLeg::new(cost, route_distance, route_duration, &[], &[])],
+      segments: Some(segments),
+      quality: None,
+      regions: None,
+      toll: None,
+      usual_duration: None,
+      departure: None,
+      arrival: None,
+      bbox: None,
+      viewport_geometry: None,
     };
 
     OsrmRouteResponse {
       code: String::from("Ok"),
       routes: vec![route],
-      waypoints: vec![
-        OsrmWaypoint::from(&waypoints.0[0]),
-        OsrmWaypoint::from(&waypoints.0[1]),
-      ],
+      waypoints: waypoints.iter().map(|waypoint| OsrmWaypoint::from(waypoint)).collect(),
+    }
+  }
+
+  /// Appends the routes of the other response, e.g. an alternative route between the same waypoints
+  pub fn with_alternative(mut self, alternative: OsrmRouteResponse) -> Self {
+    self.routes.extend(alternative.routes);
+    self
+  }
+
+  /// Roads at each step of the route, see [`Intersection`]
+  pub fn with_intersections(mut self, intersections: &[Intersection]) -> Self {
+    for route in &mut self.routes {
+      route.legs = route.legs.drain(..).map(|leg| leg.with_intersections(intersections)).collect();
+    }
+    self
+  }
+
+  pub fn with_regions(mut self, regions: Vec<OsrmRegionSection>) -> Self {
+    if let Some(route) = self.routes.first_mut() {
+      route.regions = Some(regions);
+    }
+    self
+  }
+
+  /// Departure from the origin and arrival at the destination, in seconds since the Unix epoch
+  pub fn with_times(mut self, departure: i64, arrival: i64) -> Self {
+    for route in &mut self.routes {
+      route.departure = Some(departure);
+      route.arrival = Some(arrival);
+    }
+    self
+  }
+
+  /// Toll of the route, in cents
+  pub fn with_toll(mut self, toll_cents: i32) -> Self {
+    for route in &mut self.routes {
+      route.toll = Some(f64::from(toll_cents) / 100.0);
+    }
+    self
+  }
+
+  /// Duration of the route without the live traffic, in seconds
+  pub fn with_usual_duration(mut self, duration: i32) -> Self {
+    for route in &mut self.routes {
+      route.usual_duration = Some(f64::from(duration));
+    }
+    self
+  }
+
+  /// Parts of the geometry within the viewport, a route leaving the viewport and coming back has several parts. Routes
+  /// without the geometry have no parts either.
+  pub fn with_viewport(mut self, viewport: &BoundingBox) -> Self {
+    for route in &mut self.routes {
+      let geometry = match &route.geometry {
+        Some(geometry) => geometry,
+        None => continue,
+      };
+      let line = decode_polyline(geometry, route.geometries.precision()).unwrap();
+      let points: Vec<_> = line.0.iter().map(|c| Position { x: c.x as f32, y: c.y as f32 }).collect();
+      let parts = clip_polyline(&points, viewport);
+      route.viewport_geometry = Some(parts.iter().map(|part| encode_geometry(part, route.geometries)).collect());
+    }
+    self
+  }
+
+  pub fn with_quality(mut self, quality: &RouteQuality) -> Self {
+    for route in &mut self.routes {
+      route.quality = Some(quality.score());
+    }
+    self
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct OsrmTripWaypoint {
+  #[serde(flatten)]
+  #[schema(inline)]
+  waypoint: OsrmWaypoint,
+  /// Position of the waypoint in the trip
+  waypoint_index: usize,
+  trips_index: usize,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct OsrmTripResponse {
+  code: String,
+  #[schema(inline)]
+  trips: Vec<OsrmRoute>,
+  /// Waypoints in the order of the coordinates
+  #[schema(inline)]
+  waypoints: Vec<OsrmTripWaypoint>,
+}
+
+/// Route between two consecutive waypoints of a trip
+pub struct TripLeg<'a> {
+  pub geometry: Vec<Position>,
+  pub distance: i32,
+  pub duration: i32,
+  pub cost: i32,
+  pub maneuvers: Vec<Maneuver>,
+  pub intersections: Vec<Intersection>,
+  pub summary: Vec<&'a str>,
+}
+
+impl OsrmTripResponse {
+  /// Trip visiting the waypoints in the order, the legs connect the consecutive waypoints of the trip
+  pub fn new(
+    legs: &[TripLeg],
+    order: &[usize],
+    waypoints: &[MatchedWaypoint<Idx>],
+    geometries: Geometries,
+  ) -> OsrmTripResponse {
+    // Each leg starts at the waypoint the previous one ends at
+    let geometry = legs
+      .iter()
+      .enumerate()
+      .flat_map(|(index, leg)| leg.geometry.iter().skip(if index == 0 { 0 } else { 1 }).cloned());
+    let geometry = EncodedGeometry::new(geometry, geometries);
+    let trip = OsrmRoute {
+      distance: legs.iter().map(|leg| f64::from(leg.distance)).sum(),
+      duration: legs.iter().map(|leg| f64::from(leg.duration)).sum(),
+      geometry: Some(geometry.polyline),
+      geometries,
+      legs: legs
+        .iter()
+        .map(|leg| {
+          OsrmLeg::new(leg.cost, leg.distance, leg.duration, &leg.maneuvers, &leg.summary)
+            .with_intersections(&leg.intersections)
+        })
+        .collect(),
+      segments: None,
+      quality: None,
+      regions: None,
+      toll: None,
+      usual_duration: None,
+      departure: None,
+      arrival: None,
+      bbox: geometry.bbox,
+      viewport_geometry: None,
+    };
+    let waypoints = waypoints
+      .iter()
+      .enumerate()
+      .map(|(index, waypoint)| OsrmTripWaypoint {
+        waypoint: OsrmWaypoint::from(waypoint),
+        waypoint_index: order.iter().position(|id| *id == index).unwrap(),
+        trips_index: 0,
+      })
+      .collect();
+
+    OsrmTripResponse {
+      code: String::from("Ok"),
+      trips: vec![trip],
+      waypoints,
+    }
+  }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct OsrmTableResponse {
+  code: String,
+  /// Durations in seconds of the fastest routes, `null` for the pairs without a route
+  #[serde(skip_serializing_if = "Option::is_none")]
+  durations: Option<Vec<Vec<Option<f64>>>>,
+  /// Distances in meters of the fastest routes, `null` for the pairs without a route
+  #[serde(skip_serializing_if = "Option::is_none")]
+  distances: Option<Vec<Vec<Option<f64>>>>,
+  #[schema(inline)]
+  sources: Vec<OsrmWaypoint>,
+  #[schema(inline)]
+  destinations: Vec<OsrmWaypoint>,
+}
+
+/// Duration and distance of the cheapest route between a pair of coordinates
+pub type TableEntry = Option<(i32, i32)>;
+
+// Values of an annotation are only sent if it's requested
+fn if_requested<T, F: FnOnce() -> T>(requested: bool, values: F) -> Option<T> {
+  if requested {
+    Some(values())
+  } else {
+    None
+  }
+}
+
+fn durations(row: &[TableEntry]) -> Vec<Option<f64>> {
+  row.iter().map(|entry| entry.map(|(duration, _)| f64::from(duration))).collect()
+}
+
+fn distances(row: &[TableEntry]) -> Vec<Option<f64>> {
+  row.iter().map(|entry| entry.map(|(_, distance)| f64::from(distance))).collect()
+}
+
+impl OsrmTableResponse {
+  /// Every waypoint is both a source and a destination
+  pub fn new(
+    rows: Vec<Vec<TableEntry>>,
+    annotations: Annotations,
+    waypoints: &[MatchedWaypoint<Idx>],
+  ) -> OsrmTableResponse {
+    OsrmTableResponse {
+      code: String::from("Ok"),
+      durations: if_requested(annotations.duration, || rows.iter().map(|row| durations(row)).collect()),
+      distances: if_requested(annotations.distance, || rows.iter().map(|row| distances(row)).collect()),
+      sources: waypoints.iter().map(OsrmWaypoint::from).collect(),
+      destinations: waypoints.iter().map(OsrmWaypoint::from).collect(),
+    }
+  }
+}
+
+/// Single row of a streamed table response, rows are sent as newline delimited JSON
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct OsrmTableRow {
+  source: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  durations: Option<Vec<Option<f64>>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  distances: Option<Vec<Option<f64>>>,
+}
+
+impl OsrmTableRow {
+  pub fn new(source: usize, row: &[TableEntry], annotations: Annotations) -> OsrmTableRow {
+    OsrmTableRow {
+      source,
+      durations: if_requested(annotations.duration, || durations(row)),
+      distances: if_requested(annotations.distance, || distances(row)),
+    }
+  }
+}
+
+/// Values reported in a table response
+#[derive(Clone, Copy, Debug)]
+pub struct Annotations {
+  pub duration: bool,
+  pub distance: bool,
+}
+
+impl FromStr for Annotations {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut annotations = Annotations {
+      duration: false,
+      distance: false,
+    };
+    for name in s.split(',') {
+      match name {
+        "duration" => annotations.duration = true,
+        "distance" => annotations.distance = true,
+        _ => return Err(format!("Unknown annotation {}", name)),
+      }
+    }
+    Ok(annotations)
+  }
+}
+
+/// Query parameters of a route request, their doc comments are the descriptions of the OpenAPI specification
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OsrmRouteOptions {
+  /// Seconds of travel time worth one unit of toll money. Routes are slower by up to that time per unit of toll
+  /// saved, the fastest route is returned regardless of the tolls if not set.
+  #[param(minimum = 0)]
+  pub toll_value: Option<f32>,
+  /// Comma separated list of `avoid_unpaved`, `avoid_highways` and `prefer_lit`. Matching roads cost more, but are
+  /// still used if there is no reasonable alternative.
+  pub preferences: Option<String>,
+  /// Multiplier of the travel speeds of the profile, e.g. 0.9 for a cautious driver
+  #[param(minimum = 0.1, maximum = 2.0, default = 1.0)]
+  pub speed_factor: Option<f32>,
+  /// Departure time in seconds since the Unix epoch, driving routes are slowed down by the typical traffic at that
+  /// time
+  pub depart_at: Option<i64>,
+  /// Time to arrive by in seconds since the Unix epoch, can't be combined with `depart_at`. The route is searched
+  /// backward from the destination.
+  pub arrive_by: Option<i64>,
+  /// Encoded polyline with the precision of 5 digits, e.g. the previous route of the driver. Segments along it cost
+  /// half as much, so the route stays close to it.
+  pub corridor: Option<String>,
+  /// Segments within this distance in meters from the corridor are along it
+  #[param(exclusive_minimum = 0, maximum = 200, default = 25)]
+  pub corridor_radius: Option<f32>,
+  /// Bounding box `min_lon,min_lat,max_lon,max_lat` of the map view, the parts of the route geometry within it are
+  /// reported in `viewport_geometry`
+  pub viewport: Option<String>,
+  /// `hint` of each waypoint returned by an earlier request, separated by `;`, empty for a waypoint without one. The
+  /// waypoints with a valid hint aren't matched again, base64 hints of other services are ignored.
+  pub hints: Option<String>,
+  /// `true` for one alternative route, or the number of alternatives up to 3. The alternatives follow the fastest
+  /// route in `routes`, fewer are returned if the other routes are too similar or too slow. Routes with `depart_at`
+  /// or `arrive_by` have no alternatives.
+  #[param(default = "false")]
+  pub alternatives: Option<String>,
+  /// `false` for only the distance, duration and weight of the routes and the ids of their segments, which is faster
+  /// to build, e.g. for a client comparing many routes. `full`, the default, adds the geometry and the steps.
+  #[param(default = "full")]
+  pub overview: Option<String>,
+  /// `polyline` or `polyline6` for the precision of the geometries, the `geometries` of the profile by default
+  pub geometries: Option<String>,
+  /// `typical` or `pessimistic` to plan with the 85th percentile of the historical travel times instead of the
+  /// median, a buffer against the variability of the traffic. Only changes the profiles with `travel_times`.
+  #[param(default = "typical")]
+  pub reliability: Option<String>,
+}
+
+fn cost_context(speed_factor: Option<f32>) -> Result<CostContext, String> {
+  match speed_factor {
+    Some(speed_factor) => CostContext::with_speed_factor(speed_factor),
+    None => Ok(CostContext::default()),
+  }
+}
+
+fn reliability(value: &Option<String>) -> Result<Reliability, String> {
+  match value {
+    Some(value) => value.parse(),
+    None => Ok(Reliability::default()),
+  }
+}
+
+// Seconds of travel time worth a cent of toll money, the toll value is set per unit of the money
+fn seconds_per_cent(toll_value: Option<f32>) -> Result<Option<f32>, String> {
+  match toll_value {
+    Some(value) if !value.is_finite() || value < 0.0 => {
+      Err(format!("Invalid toll_value {}, expected a non-negative number", value))
+    }
+    Some(value) => Ok(Some(value / 100.0)),
+    None => Ok(None),
+  }
+}
+
+impl OsrmRouteOptions {
+  pub fn cost_context(&self) -> Result<CostContext, String> {
+    cost_context(self.speed_factor)
+  }
+
+  pub fn reliability(&self) -> Result<Reliability, String> {
+    reliability(&self.reliability)
+  }
+
+  pub fn seconds_per_cent(&self) -> Result<Option<f32>, String> {
+    seconds_per_cent(self.toll_value)
+  }
+
+  pub fn route_time(&self) -> Result<Option<RouteTime>, String> {
+    match (self.depart_at, self.arrive_by) {
+      (Some(_), Some(_)) => Err("Only one of depart_at and arrive_by can be set".to_string()),
+      (Some(departure), None) => Ok(Some(RouteTime::DepartAt(departure))),
+      (None, Some(arrival)) => Ok(Some(RouteTime::ArriveBy(arrival))),
+      (None, None) => Ok(None),
+    }
+  }
+
+  pub fn preferences(&self) -> Result<Preferences, String> {
+    match &self.preferences {
+      Some(preferences) => preferences.parse(),
+      None => Ok(Preferences::default()),
+    }
+  }
+
+  pub fn viewport(&self) -> Result<Option<BoundingBox>, String> {
+    let viewport = match &self.viewport {
+      Some(viewport) => viewport,
+      None => return Ok(None),
+    };
+    let values: Vec<f32> = viewport
+      .split(',')
+      .map(|value| value.parse::<f32>())
+      .collect::<Result<_, _>>()
+      .map_err(|_| format!("Invalid viewport {}", viewport))?;
+    match values.as_slice() {
+      [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => Ok(Some(BoundingBox::new(
+        Position { x: *min_x, y: *min_y },
+        Position { x: *max_x, y: *max_y },
+      ))),
+      _ => Err(format!("Viewport must be min_lon,min_lat,max_lon,max_lat, got {}", viewport)),
+    }
+  }
+
+  /// Number of alternative routes asked for besides the fastest one
+  pub fn alternatives(&self) -> Result<usize, String> {
+    match self.alternatives.as_deref() {
+      None | Some("false") => Ok(0),
+      Some("true") => Ok(1),
+      Some(alternatives) => match alternatives.parse::<usize>() {
+        Ok(number) if number <= MAX_ALTERNATIVES => Ok(number),
+        _ => Err(format!("Invalid alternatives {}, expected true, false or up to {}", alternatives, MAX_ALTERNATIVES)),
+      },
+    }
+  }
+
+  pub fn geometries(&self, default: Geometries) -> Result<Geometries, String> {
+    geometries(&self.geometries, default)
+  }
+
+  /// Whether the routes are described with their geometry and steps
+  pub fn overview(&self) -> Result<bool, String> {
+    match self.overview.as_deref() {
+      None | Some("full") => Ok(true),
+      Some("false") => Ok(false),
+      Some(overview) => Err(format!("Invalid overview {}, expected full or false", overview)),
+    }
+  }
+
+  pub fn hints(&self, waypoints: usize) -> Result<Vec<Option<WaypointHint>>, String> {
+    match &self.hints {
+      Some(hints) => parse_hints(hints, waypoints),
+      None => Ok(vec![None; waypoints]),
+    }
+  }
+
+  pub fn corridor(&self) -> Result<Option<Corridor>, String> {
+    let corridor = match &self.corridor {
+      Some(corridor) => corridor,
+      None if self.corridor_radius.is_some() => return Err("corridor_radius is set without a corridor".to_string()),
+      None => return Ok(None),
+    };
+    let line = decode_polyline(corridor, 5).map_err(|error| format!("Invalid corridor polyline: {}", error))?;
+    let points = line.0.iter().map(|c| Position { x: c.x as f32, y: c.y as f32 }).collect();
+    Corridor::new(points, self.corridor_radius.unwrap_or(DEFAULT_CORRIDOR_RADIUS_M)).map(Some)
+  }
+}
+
+/// Query parameters of a trip request
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OsrmTripOptions {
+  /// The trip returns to the waypoint it started at
+  #[param(default = true)]
+  pub roundtrip: Option<bool>,
+  /// `first` to start the trip at the first coordinate, `any` by default
+  #[param(default = "any")]
+  pub source: Option<String>,
+  /// `last` to end the trip at the last coordinate, `any` by default
+  #[param(default = "any")]
+  pub destination: Option<String>,
+  /// `polyline` or `polyline6` for the precision of the geometry, the `geometries` of the profile by default
+  pub geometries: Option<String>,
+}
+
+// Whether the trip end is fixed to the coordinate named by `fixed`
+fn trip_end(value: &Option<String>, fixed: &str) -> Result<bool, String> {
+  match value.as_deref() {
+    None | Some("any") => Ok(false),
+    Some(value) if value == fixed => Ok(true),
+    Some(value) => Err(format!("Unknown trip end {}, expected any or {}", value, fixed)),
+  }
+}
+
+impl OsrmTripOptions {
+  pub fn trip_options(&self) -> Result<TripOptions, String> {
+    Ok(TripOptions {
+      roundtrip: self.roundtrip.unwrap_or(true),
+      fixed_source: trip_end(&self.source, "first")?,
+      fixed_destination: trip_end(&self.destination, "last")?,
+    })
+  }
+
+  pub fn geometries(&self, default: Geometries) -> Result<Geometries, String> {
+    geometries(&self.geometries, default)
+  }
+}
+
+/// Query parameters of a table request
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TableOptions {
+  /// Send the rows as newline delimited JSON as soon as they are computed
+  #[param(default = false)]
+  pub stream: Option<bool>,
+  /// Comma separated list of `duration` and `distance`, computed with a single search. Only durations are reported
+  /// by default.
+  #[param(default = "duration")]
+  pub annotations: Option<String>,
+  /// Pairs with a longer duration in seconds are reported as `null`, the searches stop at this duration
+  pub max_duration: Option<u32>,
+  /// Multiplier of the travel speeds of the profile, e.g. 0.9 for a cautious driver
+  #[param(minimum = 0.1, maximum = 2.0, default = 1.0)]
+  pub speed_factor: Option<f32>,
+  /// `typical` or `pessimistic` to plan with the 85th percentile of the historical travel times instead of the
+  /// median. Only changes the profiles with `travel_times`.
+  #[param(default = "typical")]
+  pub reliability: Option<String>,
+}
+
+impl TableOptions {
+  pub fn cost_context(&self) -> Result<CostContext, String> {
+    cost_context(self.speed_factor)
+  }
+
+  pub fn reliability(&self) -> Result<Reliability, String> {
+    reliability(&self.reliability)
+  }
+
+  pub fn annotations(&self) -> Result<Annotations, String> {
+    match &self.annotations {
+      Some(annotations) => annotations.parse(),
+      None => Ok(Annotations {
+        duration: true,
+        distance: false,
+      }),
     }
   }
 }
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct OsrmError {
+  code: String,
+  message: String,
+  // Lets the client report errors which can be found in the service log
+  #[serde(skip_serializing_if = "Option::is_none")]
+  request_id: Option<String>,
+}
+
+impl OsrmError {
+  pub fn new(code: &str, message: &str) -> OsrmError {
+    OsrmError {
+      code: String::from(code),
+      message: String::from(message),
+      request_id: None,
+    }
+  }
+
+  pub fn code(&self) -> &str {
+    &self.code
+  }
+
+  pub fn with_request_id(mut self, request_id: &str) -> OsrmError {
+    self.request_id = Some(String::from(request_id));
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_check_coordinates() {
+    let berlin = Position { x: 13.4, y: 52.5 };
+    assert_eq!(check_coordinates(&[berlin, Position { x: -122.4, y: 37.8 }]), Ok(()));
+    // Latitude first, swapped it's in range
+    let message = check_coordinates(&[berlin, Position { x: 37.8, y: -122.4 }]).unwrap_err();
+    assert!(message.starts_with("Coordinate 1 is 37.8,-122.4"));
+    assert!(message.contains("latitude,longitude"));
+    let message = check_coordinates(&[Position { x: 200.0, y: 100.0 }]).unwrap_err();
+    assert!(!message.contains("latitude,longitude"));
+  }
+
+  #[test]
+  fn test_geometries() {
+    let points = vec![Position { x: 13.388_86, y: 52.517_03 }, Position { x: 13.397_63, y: 52.529_4 }];
+    let polyline = EncodedGeometry::new(points.clone(), Geometries::Polyline);
+    let polyline6 = EncodedGeometry::new(points, Geometries::Polyline6);
+    let decoded = decode_polyline(&polyline6.polyline, 6).unwrap();
+    assert!((decoded.0[1].x - 13.397_63).abs() < 1e-5);
+    assert!((decoded.0[1].y - 52.529_4).abs() < 1e-5);
+    assert_ne!(polyline.polyline, polyline6.polyline);
+    assert_eq!(polyline.bbox, polyline6.bbox);
+
+    assert_eq!("polyline6".parse(), Ok(Geometries::Polyline6));
+    assert!("geojson".parse::<Geometries>().is_err());
+    assert_eq!(geometries(&None, Geometries::Polyline6), Ok(Geometries::Polyline6));
+    assert_eq!(geometries(&Some("polyline".to_string()), Geometries::Polyline6), Ok(Geometries::Polyline));
+  }
+
+  #[test]
+  fn test_toll_value() {
+    assert_eq!(seconds_per_cent(Some(50.0)), Ok(Some(0.5)));
+    assert_eq!(seconds_per_cent(Some(0.0)), Ok(Some(0.0)));
+    assert_eq!(seconds_per_cent(None), Ok(None));
+    assert!(seconds_per_cent(Some(-1.0)).is_err());
+    assert!(seconds_per_cent(Some(f32::NAN)).is_err());
+    assert!(seconds_per_cent(Some(f32::INFINITY)).is_err());
+  }
+}
@@ -0,0 +1,225 @@
+use crate::config::{BucketConfig, RateLimitConfig};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::{reject, Filter, Rejection};
+
+/// Header with the API key of a client
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// Clients tracked at most, the least recently seen ones are dropped once a new client would exceed it
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct TokenBucket {
+  // Limit of the client, the IP and the API key buckets have different ones
+  config: BucketConfig,
+  tokens: f64,
+  // Last request of the client
+  updated: Instant,
+}
+
+impl TokenBucket {
+  fn new(config: BucketConfig, now: Instant) -> Self {
+    Self {
+      config,
+      tokens: config.burst as f64,
+      updated: now,
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.config.requests_per_second).min(self.config.burst as f64);
+    self.updated = now;
+  }
+
+  // Takes a token for a request, otherwise returns the time until the next token is available
+  fn take(&mut self, now: Instant) -> Result<(), Duration> {
+    self.refill(now);
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      Ok(())
+    } else if self.config.requests_per_second > 0.0 {
+      Err(Duration::from_secs_f64((1.0 - self.tokens) / self.config.requests_per_second))
+    } else {
+      Err(Duration::from_secs(u64::from(u32::MAX)))
+    }
+  }
+}
+
+/// Reason a request is not served
+#[derive(Debug)]
+pub enum Limited {
+  UnknownApiKey,
+  TooManyRequests { retry_after: Duration },
+}
+
+impl reject::Reject for Limited {}
+
+/// Token bucket rate limiter, with a bucket for each client IP or API key
+pub struct RateLimiter {
+  config: RateLimitConfig,
+  max_clients: usize,
+  buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+  pub fn new(config: RateLimitConfig) -> Self {
+    RateLimiter::with_max_clients(config, MAX_TRACKED_CLIENTS)
+  }
+
+  fn with_max_clients(config: RateLimitConfig, max_clients: usize) -> Self {
+    Self {
+      config,
+      max_clients,
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Takes a token from the bucket of the API key if it's set, or of the client IP otherwise
+  pub fn check(&self, ip: Option<SocketAddr>, api_key: Option<&str>, now: Instant) -> Result<(), Limited> {
+    let (client, config) = match api_key {
+      Some(key) => match self.config.api_keys.get(key) {
+        Some(config) => (format!("key:{}", key), config),
+        None => return Err(Limited::UnknownApiKey),
+      },
+      None => (
+        format!("ip:{}", ip.map(|addr| addr.ip().to_string()).unwrap_or_default()),
+        &self.config.per_ip,
+      ),
+    };
+
+    let mut buckets = self.buckets.lock().unwrap();
+    if buckets.len() >= self.max_clients && !buckets.contains_key(&client) {
+      // A quarter of the buckets is dropped at once, so the map is only scanned once per that many new clients
+      let mut last_requests: Vec<_> = buckets.values().map(|bucket| bucket.updated).collect();
+      last_requests.sort_unstable();
+      match last_requests.get(buckets.len() - self.max_clients * 3 / 4) {
+        Some(&oldest_kept) => buckets.retain(|_, bucket| bucket.updated >= oldest_kept),
+        None => buckets.clear(),
+      }
+    }
+    let config = *config;
+    buckets
+      .entry(client)
+      .or_insert_with(|| TokenBucket::new(config, now))
+      .take(now)
+      .map_err(|retry_after| Limited::TooManyRequests { retry_after })
+  }
+}
+
+/// Rejects the request with [`Limited`] if the client is over its limit. Passes all requests if there is no limiter.
+pub fn rate_limit(limiter: Option<Arc<RateLimiter>>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::addr::remote()
+    .and(warp::header::optional::<String>(API_KEY_HEADER))
+    .and_then(move |ip: Option<SocketAddr>, api_key: Option<String>| {
+      let limiter = limiter.clone();
+      async move {
+        match limiter {
+          Some(limiter) => limiter
+            .check(ip, api_key.as_deref(), Instant::now())
+            .map_err(reject::custom),
+          None => Ok(()),
+        }
+      }
+    })
+    .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limiter_with_max_clients(max_clients: usize) -> RateLimiter {
+    let mut api_keys = HashMap::new();
+    api_keys.insert(
+      String::from("secret"),
+      BucketConfig {
+        requests_per_second: 10.0,
+        burst: 5,
+      },
+    );
+    let config = RateLimitConfig {
+      per_ip: BucketConfig {
+        requests_per_second: 1.0,
+        burst: 2,
+      },
+      api_keys,
+    };
+    RateLimiter::with_max_clients(config, max_clients)
+  }
+
+  fn limiter() -> RateLimiter {
+    limiter_with_max_clients(MAX_TRACKED_CLIENTS)
+  }
+
+  #[test]
+  fn test_limit_per_ip() {
+    let limiter = limiter();
+    let now = Instant::now();
+    let ip = Some(SocketAddr::from(([10, 0, 0, 1], 1234)));
+    let other_ip = Some(SocketAddr::from(([10, 0, 0, 2], 1234)));
+
+    assert!(limiter.check(ip, None, now).is_ok());
+    assert!(limiter.check(ip, None, now).is_ok());
+    match limiter.check(ip, None, now) {
+      Err(Limited::TooManyRequests { retry_after }) => assert_eq!(retry_after, Duration::from_secs(1)),
+      _ => panic!("The third request must be limited"),
+    }
+    assert!(limiter.check(other_ip, None, now).is_ok());
+
+    assert!(limiter.check(ip, None, now + Duration::from_secs(1)).is_ok());
+  }
+
+  #[test]
+  fn test_limit_per_api_key() {
+    let limiter = limiter();
+    let now = Instant::now();
+    let ip = Some(SocketAddr::from(([10, 0, 0, 1], 1234)));
+
+    for _ in 0..5 {
+      assert!(limiter.check(ip, Some("secret"), now).is_ok());
+    }
+    assert!(matches!(
+      limiter.check(ip, Some("secret"), now),
+      Err(Limited::TooManyRequests { .. })
+    ));
+    // The key doesn't use the bucket of the IP
+    assert!(limiter.check(ip, None, now).is_ok());
+
+    assert!(matches!(limiter.check(ip, Some("guess"), now), Err(Limited::UnknownApiKey)));
+  }
+
+  #[test]
+  fn test_least_recently_seen_clients_are_dropped() {
+    let limiter = limiter_with_max_clients(4);
+    let now = Instant::now();
+    let ip = |last: u8| Some(SocketAddr::from(([10, 0, 0, last], 1234)));
+    let later = |millis: u64| now + Duration::from_millis(millis);
+
+    assert!(limiter.check(ip(1), None, now).is_ok());
+    assert!(limiter.check(ip(2), None, later(10)).is_ok());
+    for _ in 0..5 {
+      assert!(limiter.check(ip(3), Some("secret"), later(20)).is_ok());
+    }
+    assert!(limiter.check(ip(3), None, later(30)).is_ok());
+    assert_eq!(limiter.buckets.lock().unwrap().len(), 4);
+
+    // The fifth client drops the least recently seen one, three quarters of the clients are kept
+    assert!(limiter.check(ip(4), None, later(40)).is_ok());
+    assert_eq!(limiter.buckets.lock().unwrap().len(), 4);
+    assert!(!limiter.buckets.lock().unwrap().contains_key("ip:10.0.0.1"));
+
+    // The throttled key is still tracked and refills at its own rate, a token every 100 ms
+    assert!(matches!(
+      limiter.check(ip(3), Some("secret"), later(40)),
+      Err(Limited::TooManyRequests { .. })
+    ));
+    assert!(limiter.check(ip(3), Some("secret"), later(120)).is_ok());
+    assert!(matches!(
+      limiter.check(ip(3), Some("secret"), later(120)),
+      Err(Limited::TooManyRequests { .. })
+    ));
+  }
+}
@@ -0,0 +1,54 @@
+use arli::adaptive::SearchAlgorithm;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Route searches run with an algorithm since the service started
+struct SearchCounters {
+  searches: AtomicU64,
+  predicted: AtomicU64,
+  settled: AtomicU64,
+}
+
+impl SearchCounters {
+  const fn new() -> Self {
+    Self {
+      searches: AtomicU64::new(0),
+      predicted: AtomicU64::new(0),
+      settled: AtomicU64::new(0),
+    }
+  }
+
+  fn metrics(&self) -> Value {
+    json!({
+      "searches": self.searches.load(Ordering::Relaxed),
+      "predicted_settled": self.predicted.load(Ordering::Relaxed),
+      "settled": self.settled.load(Ordering::Relaxed),
+    })
+  }
+}
+
+static DIJKSTRA: SearchCounters = SearchCounters::new();
+static BIDIRECTIONAL: SearchCounters = SearchCounters::new();
+
+fn counters(algorithm: SearchAlgorithm) -> &'static SearchCounters {
+  match algorithm {
+    SearchAlgorithm::Dijkstra => &DIJKSTRA,
+    SearchAlgorithm::Bidirectional => &BIDIRECTIONAL,
+  }
+}
+
+/// Counts a route search together with the number of nodes it was predicted to settle and it settled
+pub fn record_search(algorithm: SearchAlgorithm, predicted: usize, settled: usize) {
+  let counters = counters(algorithm);
+  counters.searches.fetch_add(1, Ordering::Relaxed);
+  counters.predicted.fetch_add(predicted as u64, Ordering::Relaxed);
+  counters.settled.fetch_add(settled as u64, Ordering::Relaxed);
+}
+
+/// Searches of each algorithm, comparing the predicted and the settled nodes shows how well the prediction fits
+pub fn search_metrics() -> Value {
+  json!({
+    "dijkstra": DIJKSTRA.metrics(),
+    "bidirectional": BIDIRECTIONAL.metrics(),
+  })
+}
@@ -0,0 +1,201 @@
+use crate::config::SnapCacheConfig;
+use crate::cost_functions::ProfileId;
+use arli::graph_impl::Idx;
+use arli::spatial::Position;
+use arli::waypoint::MatchedWaypoint;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Waypoints are cached with the coordinates rounded to about a meter
+const COORDINATE_SCALE: f32 = 1e5;
+
+// Generation of the graph the waypoint is matched on, the profile and the rounded coordinate
+type SnapKey = (u64, ProfileId, i32, i32);
+
+struct CachedMatch {
+  created: Instant,
+  matched: MatchedWaypoint<Idx>,
+}
+
+#[derive(Serialize)]
+pub struct SnapCacheMetrics {
+  hits: u64,
+  misses: u64,
+  entries: usize,
+}
+
+/// Recently matched waypoints of each profile.
+///
+/// Clients often request routes from the same pickup points again and again, the cache saves snapping them every
+/// time. Matched waypoints refer to the nodes of the graph they are matched on, so they are cached together with the
+/// generation of the graph and only returned for the requests served from the same generation.
+pub struct SnapCache {
+  ttl: Duration,
+  max_entries: usize,
+  entries: Mutex<HashMap<SnapKey, CachedMatch>>,
+  // Waypoints matched on older generations are not cached anymore, e.g. by the requests still served from the
+  // graph which was just replaced
+  generation: AtomicU64,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+impl SnapCache {
+  pub fn new(config: SnapCacheConfig) -> Self {
+    Self {
+      ttl: Duration::from_secs(config.ttl_s),
+      max_entries: config.max_entries,
+      entries: Mutex::new(HashMap::new()),
+      generation: AtomicU64::new(0),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    }
+  }
+
+  fn key(generation: u64, profile: ProfileId, position: &Position) -> SnapKey {
+    (
+      generation,
+      profile,
+      (position.x * COORDINATE_SCALE).round() as i32,
+      (position.y * COORDINATE_SCALE).round() as i32,
+    )
+  }
+
+  /// Returns the cached waypoint matched close to the position on the graph generation, or matches it with
+  /// `match_waypoint`
+  pub fn get_or_insert_with<F>(
+    &self,
+    generation: u64,
+    profile: ProfileId,
+    position: &Position,
+    match_waypoint: F,
+  ) -> MatchedWaypoint<Idx>
+  where
+    F: FnOnce() -> MatchedWaypoint<Idx>,
+  {
+    if self.max_entries == 0 {
+      return match_waypoint();
+    }
+
+    let key = SnapCache::key(generation, profile, position);
+    let now = Instant::now();
+    if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+      if now.duration_since(cached.created) < self.ttl {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let mut matched = cached.matched.clone();
+        matched.waypoint = *position;
+        return matched;
+      }
+    }
+    self.misses.fetch_add(1, Ordering::Relaxed);
+
+    // The lock isn't held while matching, a concurrent request for the same position may match it as well
+    let matched = match_waypoint();
+    let mut entries = self.entries.lock().unwrap();
+    if generation < self.generation.load(Ordering::Relaxed) {
+      return matched;
+    }
+    if entries.len() >= self.max_entries {
+      let ttl = self.ttl;
+      entries.retain(|_, cached| now.duration_since(cached.created) < ttl);
+      if entries.len() >= self.max_entries {
+        entries.clear();
+      }
+    }
+    entries.insert(
+      key,
+      CachedMatch {
+        created: now,
+        matched: matched.clone(),
+      },
+    );
+    matched
+  }
+
+  /// Drops the waypoints matched on the generations older than the current one, once the graph is reloaded
+  pub fn set_generation(&self, generation: u64) {
+    let mut entries = self.entries.lock().unwrap();
+    self.generation.store(generation, Ordering::Relaxed);
+    entries.retain(|(cached_generation, _, _, _), _| *cached_generation >= generation);
+  }
+
+  pub fn metrics(&self) -> SnapCacheMetrics {
+    SnapCacheMetrics {
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+      entries: self.entries.lock().unwrap().len(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn matched(position: &Position, id: Idx) -> MatchedWaypoint<Idx> {
+    MatchedWaypoint::from_node(id, *position)
+  }
+
+  #[test]
+  fn test_cached_by_rounded_coordinate() {
+    let cache = SnapCache::new(SnapCacheConfig::default());
+    let position = Position { x: 13.388_86, y: 52.517_04 };
+    let nearby = Position {
+      x: position.x + 1e-6,
+      y: position.y + 1e-6,
+    };
+
+    let first = cache.get_or_insert_with(0, ProfileId(0), &position, || matched(&position, 1));
+    let second = cache.get_or_insert_with(0, ProfileId(0), &nearby, || matched(&nearby, 2));
+    let other_profile = cache.get_or_insert_with(0, ProfileId(1), &nearby, || matched(&nearby, 3));
+
+    assert_eq!(first.snapped[0].1, 1);
+    assert_eq!(second.snapped[0].1, 1);
+    assert_eq!(second.waypoint, nearby);
+    assert_eq!(other_profile.snapped[0].1, 3);
+
+    let metrics = cache.metrics();
+    assert_eq!((metrics.hits, metrics.misses, metrics.entries), (1, 2, 2));
+
+  }
+
+  #[test]
+  fn test_graph_generations() {
+    let cache = SnapCache::new(SnapCacheConfig::default());
+    let position = Position { x: 13.4, y: 52.5 };
+    cache.get_or_insert_with(0, ProfileId(0), &position, || matched(&position, 1));
+
+    // The nodes of a reloaded graph are matched again, even before the old waypoints are dropped
+    let reloaded = cache.get_or_insert_with(1, ProfileId(0), &position, || matched(&position, 2));
+    assert_eq!(reloaded.snapped[0].1, 2);
+
+    cache.set_generation(1);
+    assert_eq!(cache.metrics().entries, 1);
+    // A request still served from the old graph doesn't cache its waypoints anymore
+    let other = Position { x: 13.5, y: 52.5 };
+    cache.get_or_insert_with(0, ProfileId(0), &other, || matched(&other, 3));
+    assert_eq!(cache.metrics().entries, 1);
+    let cached = cache.get_or_insert_with(1, ProfileId(0), &position, || matched(&position, 4));
+    assert_eq!(cached.snapped[0].1, 2);
+  }
+
+  #[test]
+  fn test_expired_entries() {
+    let cache = SnapCache::new(SnapCacheConfig {
+      ttl_s: 0,
+      max_entries: 1,
+    });
+    let position = Position { x: 13.4, y: 52.5 };
+    let other = Position { x: 13.5, y: 52.5 };
+
+    cache.get_or_insert_with(0, ProfileId(0), &position, || matched(&position, 1));
+    let expired = cache.get_or_insert_with(0, ProfileId(0), &position, || matched(&position, 2));
+    cache.get_or_insert_with(0, ProfileId(0), &other, || matched(&other, 3));
+
+    assert_eq!(expired.snapped[0].1, 2);
+    assert_eq!(cache.metrics().entries, 1);
+  }
+}
@@ -0,0 +1,197 @@
+use crate::config::TileCacheConfig;
+use crate::introspection::segment_properties;
+use arli::graph::{IntoGeometry, Spatial};
+use arli::spatial::{BoundingBox, Position};
+use arli_osm::OsmGraph;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use warp::hyper::body::Bytes;
+
+/// Lower zoom levels cover too many segments to render them on demand
+pub const MIN_TILE_ZOOM: u8 = 12;
+pub const MAX_TILE_ZOOM: u8 = 22;
+
+/// Tile coordinates together with the generation of the graph it was rendered from
+pub type TileKey = (u8, u32, u32, u64);
+
+/// Bounding box of the web mercator tile
+pub fn tile_bounds(z: u8, x: u32, y: u32) -> BoundingBox {
+  let tiles = f64::from(1u32 << z);
+  let lon = |x: u32| f64::from(x) / tiles * 360.0 - 180.0;
+  let lat = |y: u32| (PI * (1.0 - 2.0 * f64::from(y) / tiles)).sinh().atan().to_degrees();
+  BoundingBox::new(
+    Position {
+      x: lon(x) as f32,
+      y: lat(y + 1) as f32,
+    },
+    Position {
+      x: lon(x + 1) as f32,
+      y: lat(y) as f32,
+    },
+  )
+}
+
+pub fn is_valid_tile(z: u8, x: u32, y: u32) -> bool {
+  (MIN_TILE_ZOOM..=MAX_TILE_ZOOM).contains(&z) && x < (1 << z) && y < (1 << z)
+}
+
+/// Renders the segments of the tile as a GeoJSON feature collection, e.g. to check the access flags and the
+/// junctions of a road on the debug map
+pub fn render_tile(graph: &OsmGraph, z: u8, x: u32, y: u32) -> Bytes {
+  let features: Vec<_> = graph
+    .find_nodes(&tile_bounds(z, x, y))
+    .map(|id| {
+      let coordinates: Vec<_> = graph.geometry(id).map(|p| [p.x, p.y]).collect();
+      let properties = segment_properties(graph, id);
+      serde_json::json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": properties,
+      })
+    })
+    .collect();
+  let collection = serde_json::json!({ "type": "FeatureCollection", "features": features });
+  Bytes::from(serde_json::to_vec(&collection).unwrap())
+}
+
+#[derive(Serialize)]
+pub struct TileCacheMetrics {
+  hits: u64,
+  misses: u64,
+  tiles: usize,
+  bytes: usize,
+}
+
+#[derive(Default)]
+struct LruTiles {
+  // Encoded tile with the time it was used last
+  tiles: HashMap<TileKey, (Bytes, u64)>,
+  // Tiles by the time they were used last, the least recently used one first
+  recency: BTreeMap<u64, TileKey>,
+  bytes: usize,
+  clock: u64,
+}
+
+impl LruTiles {
+  fn get(&mut self, key: &TileKey) -> Option<Bytes> {
+    self.clock += 1;
+    let clock = self.clock;
+    let (tile, used) = self.tiles.get_mut(key)?;
+    self.recency.remove(used);
+    self.recency.insert(clock, *key);
+    *used = clock;
+    Some(tile.clone())
+  }
+
+  fn insert(&mut self, key: TileKey, tile: Bytes, max_bytes: usize) {
+    if self.tiles.contains_key(&key) {
+      return;
+    }
+    while self.bytes + tile.len() > max_bytes {
+      match self.recency.iter().next().map(|(used, key)| (*used, *key)) {
+        Some((used, evicted)) => {
+          self.recency.remove(&used);
+          if let Some((tile, _)) = self.tiles.remove(&evicted) {
+            self.bytes -= tile.len();
+          }
+        }
+        None => break,
+      }
+    }
+    self.clock += 1;
+    self.bytes += tile.len();
+    self.recency.insert(self.clock, key);
+    self.tiles.insert(key, (tile, self.clock));
+  }
+}
+
+/// Encoded tiles, the least recently used ones are dropped when the cache is full.
+///
+/// Tiles of a dense city center take a while to render, the cache saves rendering them again while the debug map
+/// is panned. Tiles of an older graph generation are never requested again and age out.
+pub struct TileCache {
+  max_bytes: usize,
+  tiles: Mutex<LruTiles>,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+impl TileCache {
+  pub fn new(config: TileCacheConfig) -> Self {
+    Self {
+      max_bytes: config.max_bytes,
+      tiles: Mutex::new(LruTiles::default()),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    }
+  }
+
+  /// Returns the cached tile or renders it with `render`. Tiles larger than the cache aren't cached.
+  pub fn get_or_render<F: FnOnce() -> Bytes>(&self, key: TileKey, render: F) -> Bytes {
+    if let Some(tile) = self.tiles.lock().unwrap().get(&key) {
+      self.hits.fetch_add(1, Ordering::Relaxed);
+      return tile;
+    }
+    self.misses.fetch_add(1, Ordering::Relaxed);
+
+    // The lock isn't held while rendering, other tiles can be served meanwhile
+    let tile = render();
+    if tile.len() <= self.max_bytes {
+      self.tiles.lock().unwrap().insert(key, tile.clone(), self.max_bytes);
+    }
+    tile
+  }
+
+  pub fn metrics(&self) -> TileCacheMetrics {
+    let tiles = self.tiles.lock().unwrap();
+    TileCacheMetrics {
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+      tiles: tiles.tiles.len(),
+      bytes: tiles.bytes,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_tile_bounds() {
+    // Tile with the Brandenburg Gate
+    let bounds = tile_bounds(14, 8800, 5373);
+    let gate = Position { x: 13.3777, y: 52.5163 };
+    assert!(bounds.min().x <= gate.x && gate.x <= bounds.max().x);
+    assert!(bounds.min().y <= gate.y && gate.y <= bounds.max().y);
+
+    assert!(is_valid_tile(14, 8800, 5373));
+    assert!(!is_valid_tile(14, 1 << 14, 0));
+    assert!(!is_valid_tile(MIN_TILE_ZOOM - 1, 0, 0));
+  }
+
+  #[test]
+  fn test_least_recently_used_tile_is_evicted() {
+    let cache = TileCache::new(TileCacheConfig { max_bytes: 10 });
+    let tile = |content: &'static str| move || Bytes::from(content);
+
+    cache.get_or_render((14, 1, 1, 0), tile("aaaa"));
+    cache.get_or_render((14, 2, 2, 0), tile("bbbb"));
+    // The first tile is used again, so the second one is evicted for the third one
+    assert_eq!(cache.get_or_render((14, 1, 1, 0), tile("xxxx")), Bytes::from("aaaa"));
+    cache.get_or_render((14, 3, 3, 0), tile("cccc"));
+
+    assert_eq!(cache.get_or_render((14, 2, 2, 0), tile("dddd")), Bytes::from("dddd"));
+    // A new graph generation renders the tile again
+    assert_eq!(cache.get_or_render((14, 1, 1, 1), tile("eeee")), Bytes::from("eeee"));
+    // Larger than the whole cache
+    cache.get_or_render((14, 4, 4, 1), tile("ffffffffffff"));
+
+    let metrics = cache.metrics();
+    assert_eq!((metrics.hits, metrics.misses), (1, 6));
+    assert_eq!((metrics.tiles, metrics.bytes), (2, 8));
+  }
+}
@@ -0,0 +1,105 @@
+use crate::config::TollConfig;
+use arli::graph::{BaseNodeIds, GraphBase, GraphData, Weighted};
+use arli::graph_impl::Idx;
+use arli_osm::OsmGraph;
+use std::collections::HashMap;
+
+/// Toll prices of the toll roads in each region, in cents per kilometer
+pub struct TollTariffs {
+  cents_per_km: HashMap<String, f32>,
+  default_cents_per_km: f32,
+}
+
+impl TollTariffs {
+  pub fn new(config: &TollConfig) -> Self {
+    Self {
+      cents_per_km: config
+        .per_km
+        .iter()
+        .map(|(region, price)| (region.clone(), price * 100.0))
+        .collect(),
+      default_cents_per_km: config.default_per_km * 100.0,
+    }
+  }
+
+  /// No tariffs are set, so no tolls are charged
+  pub fn is_empty(&self) -> bool {
+    self.cents_per_km.is_empty() && self.default_cents_per_km <= 0.0
+  }
+
+  fn cents_per_km(&self, region: Option<&str>) -> f32 {
+    region
+      .and_then(|region| self.cents_per_km.get(region))
+      .cloned()
+      .unwrap_or(self.default_cents_per_km)
+  }
+
+  /// Toll of traveling along the segment, in cents. Zero for the segments which aren't toll roads.
+  pub fn segment_toll(&self, graph: &OsmGraph, id: Idx) -> i32 {
+    let segment = graph.data(id);
+    if !segment.is_toll() {
+      return 0;
+    }
+    (segment.length() * self.cents_per_km(graph.region(id)) / 1000.0).round() as i32
+  }
+}
+
+/// Tolls of a route request
+#[derive(Clone, Copy)]
+pub struct TollOptions<'a> {
+  pub tariffs: &'a TollTariffs,
+  /// Seconds of travel time worth a cent of toll, the route isn't affected by the tolls if not set
+  pub seconds_per_cent: Option<f32>,
+}
+
+/// Toll as the weight of a graph built on top of the OSM graph, e.g. the waypoint overlay, in cents.
+///
+/// Used as the secondary weight of a search, to report the toll of a route or to trade it against the travel time.
+/// A segment partially traveled from a waypoint is charged in full, like a toll section is.
+#[derive(Clone, Copy)]
+pub struct TollWeight<'a, G> {
+  graph: G,
+  osm_graph: &'a OsmGraph,
+  tariffs: &'a TollTariffs,
+}
+
+impl<'a, G: BaseNodeIds<NodeId = Idx>> TollWeight<'a, G> {
+  pub fn new(graph: G, osm_graph: &'a OsmGraph, tariffs: &'a TollTariffs) -> Self {
+    Self {
+      graph,
+      osm_graph,
+      tariffs,
+    }
+  }
+}
+
+impl<'a, G: GraphBase> GraphBase for TollWeight<'a, G> {
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: BaseNodeIds<NodeId = Idx>> Weighted for TollWeight<'a, G> {
+  type Weight = i32;
+
+  fn transition_weight(&self, from: Idx, _to: Idx) -> i32 {
+    self.tariffs.segment_toll(self.osm_graph, self.graph.base_id(from))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_tariffs() {
+    let mut config = TollConfig::default();
+    assert!(TollTariffs::new(&config).is_empty());
+
+    config.per_km.insert("AT".to_string(), 0.25);
+    config.default_per_km = 0.1;
+    let tariffs = TollTariffs::new(&config);
+    assert!(!tariffs.is_empty());
+    assert_eq!(tariffs.cents_per_km(Some("AT")), 25.0);
+    assert_eq!(tariffs.cents_per_km(Some("DE")), 10.0);
+    assert_eq!(tariffs.cents_per_km(None), 10.0);
+  }
+}
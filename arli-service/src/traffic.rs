@@ -0,0 +1,130 @@
+use crate::config::TrafficConfig;
+use arli::graph::{Backward, Forward, GraphBase, IntoNeighbors, ScalableWeight, TimeDependent, Weighted};
+
+const SECONDS_PER_HOUR: i64 = 3600;
+const HOURS_PER_DAY: i64 = 24;
+
+/// Time a route request departs at or has to arrive by, in seconds since the Unix epoch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteTime {
+  DepartAt(i64),
+  ArriveBy(i64),
+}
+
+impl RouteTime {
+  /// Time of a search offset, the search runs forward from the departure or backward from the arrival
+  fn clock(&self, offset: i32) -> i64 {
+    match *self {
+      RouteTime::DepartAt(departure) => departure + i64::from(offset),
+      RouteTime::ArriveBy(arrival) => arrival - i64::from(offset),
+    }
+  }
+
+  /// Departure and arrival of a route taking the travel time
+  pub fn departure_and_arrival(&self, travel_time: i32) -> (i64, i64) {
+    match *self {
+      RouteTime::DepartAt(departure) => (departure, departure + i64::from(travel_time)),
+      RouteTime::ArriveBy(arrival) => (arrival - i64::from(travel_time), arrival),
+    }
+  }
+}
+
+/// Typical driving speeds in each hour of the day
+pub struct TrafficPattern {
+  hourly_speed_factors: Vec<f32>,
+  utc_offset_s: i64,
+}
+
+impl TrafficPattern {
+  pub fn new(config: &TrafficConfig) -> Result<Self, String> {
+    if config.hourly_speed_factors.len() != HOURS_PER_DAY as usize {
+      return Err(format!("Traffic needs a speed factor for each of the {} hours", HOURS_PER_DAY));
+    }
+    if config.hourly_speed_factors.iter().any(|factor| *factor <= 0.0) {
+      return Err("Traffic speed factors must be positive".to_string());
+    }
+    Ok(Self {
+      hourly_speed_factors: config.hourly_speed_factors.clone(),
+      utc_offset_s: config.utc_offset_s,
+    })
+  }
+
+  /// Multiplier of the speeds at the time, in seconds since the Unix epoch
+  pub fn speed_factor(&self, time: i64) -> f32 {
+    let hour = (time + self.utc_offset_s).div_euclid(SECONDS_PER_HOUR).rem_euclid(HOURS_PER_DAY);
+    self.hourly_speed_factors[hour as usize]
+  }
+}
+
+/// Weighted graph slowed down by the traffic at the time the transitions happen, for the searches running from the
+/// departure or the arrival time of a request. The weights are travel times in seconds.
+#[derive(Clone, Copy)]
+pub struct TimeDependentGraph<'a, G> {
+  graph: G,
+  // Weights don't depend on the time without a pattern, e.g. for walking
+  traffic: Option<&'a TrafficPattern>,
+  time: RouteTime,
+}
+
+impl<'a, G: Copy + Weighted<Weight = i32>> TimeDependentGraph<'a, G> {
+  pub fn new(graph: G, traffic: Option<&'a TrafficPattern>, time: RouteTime) -> Self {
+    Self { graph, traffic, time }
+  }
+}
+
+impl<'a, G: GraphBase> GraphBase for TimeDependentGraph<'a, G> {
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: IntoNeighbors<Forward>> IntoNeighbors<Forward> for TimeDependentGraph<'a, G> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<'a, G: IntoNeighbors<Backward>> IntoNeighbors<Backward> for TimeDependentGraph<'a, G> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<'a, G: Weighted<Weight = i32>> TimeDependent for TimeDependentGraph<'a, G> {
+  type Weight = i32;
+
+  fn transition_weight_at(&self, from: Self::NodeId, to: Self::NodeId, time: i32) -> i32 {
+    let weight = self.graph.transition_weight(from, to);
+    match self.traffic {
+      Some(traffic) => weight.scale(1.0 / traffic.speed_factor(self.time.clock(time))),
+      None => weight,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_speed_factor_by_local_hour() {
+    let mut config = TrafficConfig::default();
+    config.hourly_speed_factors[8] = 0.5;
+    config.utc_offset_s = 2 * SECONDS_PER_HOUR;
+    let traffic = TrafficPattern::new(&config).unwrap();
+
+    // 2021-06-01 06:30 UTC is 08:30 local time
+    let morning = 1_622_529_000;
+    assert_eq!(traffic.speed_factor(morning), 0.5);
+    assert_eq!(traffic.speed_factor(morning + SECONDS_PER_HOUR), 1.0);
+
+    let time = RouteTime::ArriveBy(morning);
+    assert_eq!(time.clock(60), morning - 60);
+    assert_eq!(time.departure_and_arrival(600), (morning - 600, morning));
+
+    config.hourly_speed_factors.pop();
+    assert!(TrafficPattern::new(&config).is_err());
+  }
+}
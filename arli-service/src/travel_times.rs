@@ -0,0 +1,114 @@
+use arli::graph_impl::Idx;
+use arli_osm::Segment;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Percentile of the historical travel times a request plans with
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Reliability {
+  /// Median travel time, half of the trips are faster
+  #[default]
+  Typical,
+  /// Travel time 85% of the trips are faster than, a buffer against the variability of the traffic
+  Pessimistic,
+}
+
+impl FromStr for Reliability {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "typical" => Ok(Reliability::Typical),
+      "pessimistic" => Ok(Reliability::Pessimistic),
+      _ => Err(format!("Unknown reliability {}, expected typical or pessimistic", name)),
+    }
+  }
+}
+
+// Travel times of a segment in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Percentiles {
+  p50: f32,
+  p85: f32,
+}
+
+/// Historical travel times of the segments, by the segment ids of `/edge/{id}`
+#[derive(Clone, Debug)]
+pub struct TravelTimes {
+  percentiles: HashMap<Idx, Percentiles>,
+}
+
+impl TravelTimes {
+  /// Seconds to travel the whole segment at the percentile of the reliability, `None` without a history
+  pub fn travel_time(&self, id: Idx, reliability: Reliability) -> Option<f32> {
+    self.percentiles.get(&id).map(|percentiles| match reliability {
+      Reliability::Typical => percentiles.p50,
+      Reliability::Pessimistic => percentiles.p85,
+    })
+  }
+
+  /// Multiplier of the travel time at the speed limit of the segment which makes it the historical one, 1 for the
+  /// segments without a history or a speed limit
+  pub fn slowdown(&self, id: Idx, segment: &Segment, reliability: Reliability) -> f32 {
+    let free_flow = segment.length() * 3.6 / f32::from(segment.speed_limit());
+    match self.travel_time(id, reliability) {
+      Some(travel_time) if free_flow > 0.0 && free_flow.is_finite() => travel_time / free_flow,
+      _ => 1.0,
+    }
+  }
+}
+
+/// Reads the travel times of a profile, see [`parse_travel_times`] for the file format
+pub fn read_travel_times(path: &str) -> Result<TravelTimes, String> {
+  let content = std::fs::read_to_string(path).map_err(|e| format!("Can't read travel times {}: {}", path, e))?;
+  parse_travel_times(&content).map_err(|e| format!("Invalid travel times {}: {}", path, e))
+}
+
+/// Parses an `edge_id,p50,p85` line per segment, the median and the 85th percentile of the travel times of the whole
+/// segment in seconds. Empty lines and the lines starting with `#` are skipped.
+pub fn parse_travel_times(content: &str) -> Result<TravelTimes, String> {
+  let mut percentiles = HashMap::new();
+  for (i, line) in content.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let invalid = || format!("line {} isn't an edge id and two travel times: {}", i + 1, line);
+    let fields: Vec<_> = line.split(',').map(str::trim).collect();
+    let (id, p50, p85) = match fields.as_slice() {
+      [id, p50, p85] => (id, p50, p85),
+      _ => return Err(invalid()),
+    };
+    let id: Idx = id.parse().map_err(|_| invalid())?;
+    let p50: f32 = p50.parse().map_err(|_| invalid())?;
+    let p85: f32 = p85.parse().map_err(|_| invalid())?;
+    if !(p50 > 0.0 && p50.is_finite() && p85 >= p50 && p85.is_finite()) {
+      return Err(format!("line {} needs positive travel times with p85 at least p50: {}", i + 1, line));
+    }
+    percentiles.insert(id, Percentiles { p50, p85 });
+  }
+  Ok(TravelTimes { percentiles })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_travel_times() {
+    let content = "# edge_id,p50,p85\n12,30,45\n\n7, 10.5, 12\n12,40,60\n";
+    let travel_times = parse_travel_times(content).unwrap();
+    assert_eq!(travel_times.travel_time(12, Reliability::Typical), Some(40.0));
+    assert_eq!(travel_times.travel_time(12, Reliability::Pessimistic), Some(60.0));
+    assert_eq!(travel_times.travel_time(7, Reliability::Typical), Some(10.5));
+    assert_eq!(travel_times.travel_time(8, Reliability::Pessimistic), None);
+
+    assert!(parse_travel_times("3,30").is_err());
+    assert!(parse_travel_times("3,30,20").is_err());
+    assert!(parse_travel_times("3,0,20").is_err());
+    assert!(parse_travel_times("3;30;45").is_err());
+    assert!(parse_travel_times("-3,30,45").is_err());
+    assert_eq!("pessimistic".parse::<Reliability>(), Ok(Reliability::Pessimistic));
+    assert!("optimistic".parse::<Reliability>().is_err());
+  }
+}
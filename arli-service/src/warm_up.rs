@@ -0,0 +1,119 @@
+use crate::config::WarmUpConfig;
+use crate::cost_functions::{Profile, ProfileRegistry};
+use crate::osrm_api::Waypoints;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Route run on every loaded graph before it serves requests. A graph on which a canary finds no route, or takes too
+/// long to find it, is a bad build.
+pub struct Canary {
+  pub name: String,
+  pub profile: Arc<Profile>,
+  pub waypoints: Waypoints,
+  pub max_latency: Duration,
+}
+
+/// Canary which failed, reported by `/ready`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CanaryFailure {
+  pub name: String,
+  pub reason: String,
+}
+
+impl Canary {
+  /// Canaries of the config, an invalid one is an error, so a typo doesn't pass the warm-up unnoticed
+  pub fn from_config(config: &WarmUpConfig, profiles: &ProfileRegistry) -> Result<Vec<Canary>, String> {
+    config
+      .routes
+      .iter()
+      .map(|canary| {
+        Ok(Canary {
+          name: canary.name.clone(),
+          profile: profiles
+            .find(&canary.profile)
+            .ok_or_else(|| format!("Unknown profile {} of canary {}", canary.profile, canary.name))?,
+          waypoints: canary
+            .waypoints
+            .parse()
+            .map_err(|e| format!("Invalid waypoints of canary {}: {:?}", canary.name, e))?,
+          max_latency: Duration::from_millis(canary.max_latency_ms.unwrap_or(config.max_latency_ms)),
+        })
+      })
+      .collect()
+  }
+
+  /// Checks a run of the canary which took `latency`, `error` is the code of the error if no route was found
+  pub fn check(&self, latency: Duration, error: Option<&str>) -> Option<CanaryFailure> {
+    let reason = match error {
+      Some(code) => format!("No route: {}", code),
+      None if latency > self.max_latency => format!(
+        "Took {} ms, more than {} ms",
+        latency.as_millis(),
+        self.max_latency.as_millis()
+      ),
+      None => return None,
+    };
+    Some(CanaryFailure {
+      name: self.name.clone(),
+      reason,
+    })
+  }
+}
+
+/// Whether the service is ready to take traffic: the canaries passed on the graph it serves from
+#[derive(Default)]
+pub struct Readiness {
+  failures: RwLock<Vec<CanaryFailure>>,
+}
+
+impl Readiness {
+  pub fn set(&self, failures: Vec<CanaryFailure>) {
+    *self.failures.write().unwrap() = failures;
+  }
+
+  /// Canaries which failed on the served graph, the service is ready if there are none
+  pub fn failures(&self) -> Vec<CanaryFailure> {
+    self.failures.read().unwrap().clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::CanaryConfig;
+
+  fn canary_config(name: &str, profile: &str, max_latency_ms: Option<u64>) -> CanaryConfig {
+    CanaryConfig {
+      name: name.to_string(),
+      profile: profile.to_string(),
+      waypoints: "13.388,52.517;13.397,52.529".to_string(),
+      max_latency_ms,
+    }
+  }
+
+  #[test]
+  fn test_canaries() {
+    let config = WarmUpConfig {
+      routes: vec![canary_config("center", "car", None), canary_config("slow", "foot", Some(5000))],
+      max_latency_ms: 100,
+    };
+    let profiles = ProfileRegistry::new(&[]).unwrap();
+    let canaries = Canary::from_config(&config, &profiles).unwrap();
+    assert_eq!(canaries[0].profile.name, "driving");
+    assert_eq!(canaries[0].max_latency, Duration::from_millis(100));
+    assert_eq!(canaries[1].max_latency, Duration::from_millis(5000));
+
+    assert_eq!(canaries[0].check(Duration::from_millis(50), None), None);
+    let too_slow = canaries[0].check(Duration::from_millis(150), None).unwrap();
+    assert_eq!(too_slow.name, "center");
+    assert_eq!(too_slow.reason, "Took 150 ms, more than 100 ms");
+    assert!(canaries[1].check(Duration::from_millis(1), Some("NoRoute")).is_some());
+
+    let invalid = WarmUpConfig {
+      routes: vec![canary_config("boat", "sailing", None)],
+      ..Default::default()
+    };
+    assert!(Canary::from_config(&invalid, &profiles).is_err());
+  }
+}
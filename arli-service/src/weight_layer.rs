@@ -0,0 +1,67 @@
+use crate::config::{WeightKind, WeightLayerConfig};
+use arli::graph_impl::{Idx, NodeWeight, WeightLayer};
+
+/// Segment weights of an external model, by the segment ids of `/edge/{id}`
+pub type SegmentWeights = WeightLayer<Idx, i32>;
+
+/// Reads the weight layer of a profile, see [`parse_weight_layer`] for the file format
+pub fn read_weight_layer(config: &WeightLayerConfig) -> Result<SegmentWeights, String> {
+  let content =
+    std::fs::read_to_string(&config.path).map_err(|e| format!("Can't read weight layer {}: {}", config.path, e))?;
+  parse_weight_layer(&content, config.kind).map_err(|e| format!("Invalid weight layer {}: {}", config.path, e))
+}
+
+/// Parses an `edge_id,value` line per segment. The values are factors of the segment costs or absolute costs in the
+/// units of the profile cost, e.g. seconds. Empty lines and the lines starting with `#` are skipped.
+pub fn parse_weight_layer(content: &str, kind: WeightKind) -> Result<SegmentWeights, String> {
+  let mut weights = Vec::new();
+  for (i, line) in content.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let invalid = || format!("line {} isn't an edge id and a value: {}", i + 1, line);
+    let mut fields = line.split(',').map(str::trim);
+    let (id, value) = match (fields.next(), fields.next(), fields.next()) {
+      (Some(id), Some(value), None) => (id, value),
+      _ => return Err(invalid()),
+    };
+    let id: Idx = id.parse().map_err(|_| invalid())?;
+    let weight = match kind {
+      WeightKind::Factor => match value.parse::<f32>() {
+        Ok(factor) if factor > 0.0 && factor.is_finite() => NodeWeight::Factor(factor),
+        _ => return Err(format!("line {} has no positive factor: {}", i + 1, line)),
+      },
+      WeightKind::Cost => match value.parse::<i32>() {
+        Ok(cost) if cost >= 0 => NodeWeight::Cost(cost),
+        _ => return Err(format!("line {} has no non-negative integer cost: {}", i + 1, line)),
+      },
+    };
+    weights.push((id, weight));
+  }
+  Ok(WeightLayer::new(weights))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_weight_layer() {
+    let content = "# edge_id,factor\n12,1.5\n\n7, 0.8\n12,2\n";
+    let layer = parse_weight_layer(content, WeightKind::Factor).unwrap();
+    assert_eq!(layer.len(), 2);
+    assert_eq!(layer.get(&12), Some(NodeWeight::Factor(2.0)));
+    assert_eq!(layer.get(&7), Some(NodeWeight::Factor(0.8)));
+    assert_eq!(layer.get(&8), None);
+
+    let layer = parse_weight_layer("3,120", WeightKind::Cost).unwrap();
+    assert_eq!(layer.get(&3), Some(NodeWeight::Cost(120)));
+
+    assert!(parse_weight_layer("3,0", WeightKind::Factor).is_err());
+    assert!(parse_weight_layer("3,1.5", WeightKind::Cost).is_err());
+    assert!(parse_weight_layer("3;1.5", WeightKind::Factor).is_err());
+    assert!(parse_weight_layer("3,1.5,2", WeightKind::Factor).is_err());
+    assert!(parse_weight_layer("-3,1.5", WeightKind::Factor).is_err());
+  }
+}
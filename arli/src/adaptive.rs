@@ -0,0 +1,146 @@
+//! Choice of the search algorithm for each route query.
+//!
+//! A Dijkstra search settles about all the nodes closer to the origin than the destination, so its search space grows
+//! with the square of the distance and with the density of the road network. A bidirectional search settles two
+//! circles of half the radius, about half as many nodes, but it pays for a second queue and for looking for a meeting
+//! on every settled node. The search space of a query is predicted from the straight-line distance and the density of
+//! the graph around the waypoints, and the bidirectional search only runs when the saving outweighs its overhead.
+//!
+//! Algorithms with a preprocessed graph, like contraction hierarchies or ALT, join the choice once the graphs carry
+//! their preprocessing.
+
+use crate::error::Result;
+use crate::graph::*;
+use crate::route::{route, route_bidir, Route};
+use crate::spatial::{envelope, haversine_distance, Position};
+use crate::waypoint::MatchedWaypoint;
+
+use std::f32::consts::PI;
+use std::fmt;
+
+/// Search algorithm of a route query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchAlgorithm {
+  Dijkstra,
+  Bidirectional,
+}
+
+impl fmt::Display for SearchAlgorithm {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SearchAlgorithm::Dijkstra => write!(f, "dijkstra"),
+      SearchAlgorithm::Bidirectional => write!(f, "bidirectional"),
+    }
+  }
+}
+
+/// Search space predicted for a query and the algorithm chosen for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchPrediction {
+  /// Straight-line distance between the waypoints in meters
+  pub distance: f32,
+  /// Graph nodes per square kilometer around the waypoints
+  pub density: f32,
+  /// Nodes a Dijkstra search is expected to settle
+  pub settled: usize,
+  pub algorithm: SearchAlgorithm,
+}
+
+/// Predicts the search space of the queries to choose their algorithm
+#[derive(Clone, Copy, Debug)]
+pub struct AlgorithmChooser {
+  /// Queries predicted to settle fewer nodes run Dijkstra
+  pub min_bidirectional_settled: usize,
+  /// Half size of the squares around the waypoints the density is sampled in, in meters
+  pub density_radius: f32,
+}
+
+impl Default for AlgorithmChooser {
+  fn default() -> Self {
+    Self {
+      min_bidirectional_settled: 5000,
+      density_radius: 500.0,
+    }
+  }
+}
+
+impl AlgorithmChooser {
+  /// Predicts the search space of the query from the number of graph nodes around both waypoints
+  pub fn predict<G: Spatial>(&self, graph: &G, from: &Position, to: &Position) -> SearchPrediction {
+    let nodes: usize = [from, to]
+      .iter()
+      .map(|position| graph.find_nodes(&envelope(position, self.density_radius)).into_iter().count())
+      .sum();
+    let side_km = 2.0 * self.density_radius / 1000.0;
+    let density = nodes as f32 / (2.0 * side_km * side_km);
+    let distance = haversine_distance(from, to);
+    let settled = (density * PI * (distance / 1000.0).powi(2)) as usize;
+    let algorithm = if settled < self.min_bidirectional_settled {
+      SearchAlgorithm::Dijkstra
+    } else {
+      SearchAlgorithm::Bidirectional
+    };
+    SearchPrediction {
+      distance,
+      density,
+      settled,
+      algorithm,
+    }
+  }
+}
+
+/// Finds the cheapest route with the algorithm, see [`route`] and [`route_bidir`]. Fails with
+/// [`Error::NoRoute`](crate::Error::NoRoute) if the destination can't be reached.
+pub fn route_with_algorithm<G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  algorithm: SearchAlgorithm,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  match algorithm {
+    SearchAlgorithm::Dijkstra => route(graph, from, to),
+    SearchAlgorithm::Bidirectional => route_bidir(graph, from, to),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_utils::{graph_from_intersections, simple_segment_length_cost};
+  use super::*;
+
+  #[test]
+  fn test_choose_algorithm() {
+    // Streets of a town on a 100 m grid, in both directions
+    let size = 10;
+    let positions: Vec<_> = (0..size * size)
+      .map(|i| Position::from((13.4 + 0.0015 * (i % size) as f32, 52.5 + 0.0009 * (i / size) as f32)))
+      .collect();
+    let mut streets = Vec::new();
+    for y in 0..size {
+      for x in 0..size - 1 {
+        streets.push((y * size + x, y * size + x + 1));
+        streets.push((x * size + y, (x + 1) * size + y));
+      }
+    }
+    let reversed: Vec<_> = streets.iter().map(|(from, to)| (*to, *from)).collect();
+    streets.extend(reversed);
+    let graph = graph_from_intersections(positions.clone(), streets);
+    let chooser = AlgorithmChooser::default();
+
+    let nearby = chooser.predict(&graph, &positions[44], &positions[45]);
+    assert!(nearby.density > 100.0);
+    assert_eq!(nearby.algorithm, SearchAlgorithm::Dijkstra);
+
+    let far = Position::from((13.8, 52.5));
+    let distant = chooser.predict(&graph, &positions[44], &far);
+    assert!(distant.distance > 25_000.0);
+    assert_eq!(distant.algorithm, SearchAlgorithm::Bidirectional);
+
+    let from = MatchedWaypoint::from_node(0, positions[0]);
+    let to = MatchedWaypoint::from_node(graph.number_of_nodes() as u32 - 1, positions[99]);
+    let weighted = (&graph, simple_segment_length_cost);
+    let dijkstra = route_with_algorithm(weighted, &from, &to, SearchAlgorithm::Dijkstra).unwrap();
+    let bidirectional = route_with_algorithm(weighted, &from, &to, SearchAlgorithm::Bidirectional).unwrap();
+    assert_eq!(dijkstra.cost, bidirectional.cost);
+  }
+}
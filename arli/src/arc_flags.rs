@@ -0,0 +1,209 @@
+//! Arc-flags: preprocessing which lets a search skip the transitions leading away from the destination.
+//!
+//! The nodes are partitioned into cells. Every transition gets a flag per cell, set if the transition is a part of
+//! a cheapest route to some node of the cell. A search for a destination in the cell only relaxes the flagged
+//! transitions, see [`route_with_arc_flags`](crate::route::route_with_arc_flags), so it settles mostly the nodes
+//! along the way instead of the whole circle around the origin.
+//!
+//! The flags are computed by a backward search from every boundary node of every cell, so the preprocessing is slow
+//! and the flags only hold for the weights they are computed with.
+
+use crate::graph::*;
+use crate::graph_impl::{Idx, ReversedGraph};
+use crate::partition::Partition;
+use crate::search_space::SearchSpace;
+
+/// Flags of the transitions of a graph for the cells of a partition.
+///
+/// The transitions of a node are numbered in the order of its forward neighbors, so the graph must return the
+/// neighbors in the same order every time, like [`CompactGraph`](crate::graph_impl::CompactGraph) does.
+pub struct ArcFlags {
+  partition: Partition,
+  // Index of the first transition of each node, with a sentinel at the end
+  first_transition: Vec<usize>,
+  // Flags of each transition, `words` bit words per transition
+  flags: Vec<u64>,
+  words: usize,
+}
+
+impl ArcFlags {
+  /// Computes the flags of all the transitions of the graph in its weights, the graph must have the nodes of the
+  /// partition
+  pub fn compute<G>(graph: G, partition: Partition) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + IntoNeighbors<Backward> + Weighted,
+  {
+    let number_of_nodes = partition.number_of_nodes();
+    let mut first_transition = Vec::with_capacity(number_of_nodes + 1);
+    first_transition.push(0);
+    for id in 0..number_of_nodes as Idx {
+      first_transition.push(first_transition[id as usize] + neighbors_forward(graph, id).count());
+    }
+    let words = (partition.number_of_cells() + 63) >> 6;
+    let mut arc_flags = Self {
+      flags: vec![0; first_transition[number_of_nodes] * words],
+      partition,
+      first_transition,
+      words,
+    };
+
+    for id in 0..number_of_nodes as Idx {
+      let cell = arc_flags.partition.cell(id);
+      let mut boundary = false;
+      for (i, next) in neighbors_forward(graph, id).enumerate() {
+        // Every transition within the cell or into it is a part of the cheapest route to its head
+        let transition = arc_flags.first_transition[id as usize] + i;
+        arc_flags.set(transition, arc_flags.partition.cell(next));
+      }
+      for previous in neighbors_backward(graph, id) {
+        boundary |= arc_flags.partition.cell(previous) != cell;
+      }
+      if boundary {
+        arc_flags.flag_routes_to(graph, id, cell);
+      }
+    }
+    arc_flags
+  }
+
+  // Flags the transitions of all the cheapest routes to the boundary node of the cell
+  fn flag_routes_to<G>(&mut self, graph: G, boundary: Idx, cell: u32)
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + IntoNeighbors<Backward> + Weighted,
+  {
+    let reversed = ReversedGraph::new(graph);
+    let mut search: SearchSpace<G::Weight, Idx> = SearchSpace::new();
+    search.init(boundary);
+    while search.update(reversed) {}
+
+    for id in 0..self.partition.number_of_nodes() as Idx {
+      let cost = match search.cost(id) {
+        Some(cost) => cost,
+        None => continue,
+      };
+      for (i, next) in neighbors_forward(graph, id).enumerate() {
+        // Ties are flagged as well, a search may take either of the cheapest routes
+        if search.cost(next).map(|next_cost| next_cost + graph.transition_weight(id, next)) == Some(cost) {
+          self.set(self.first_transition[id as usize] + i, cell);
+        }
+      }
+    }
+  }
+
+  fn set(&mut self, transition: usize, cell: u32) {
+    self.flags[transition * self.words + cell as usize / 64] |= 1 << (cell % 64);
+  }
+
+  /// Cells of the nodes as a mask of the flags, a transition with any of the flags of the mask leads to some of the
+  /// nodes
+  pub fn mask<I: IntoIterator<Item = Idx>>(&self, ids: I) -> Vec<u64> {
+    let mut mask = vec![0; self.words];
+    for id in ids {
+      let cell = self.partition.cell(id);
+      mask[cell as usize / 64] |= 1 << (cell % 64);
+    }
+    mask
+  }
+
+  /// Whether the `i`-th transition of the node has any of the flags of the mask
+  pub fn is_flagged(&self, id: Idx, i: usize, mask: &[u64]) -> bool {
+    let transition = self.first_transition[id as usize] + i;
+    let flags = &self.flags[transition * self.words..(transition + 1) * self.words];
+    flags.iter().zip(mask).any(|(flags, mask)| flags & mask != 0)
+  }
+
+  pub fn partition(&self) -> &Partition {
+    &self.partition
+  }
+}
+
+/// Graph adaptor which only keeps the transitions flagged for the cells of the mask, the view of the graph a search
+/// for a destination in those cells needs
+#[derive(Clone, Copy)]
+pub struct FlaggedGraph<'a, G> {
+  graph: G,
+  flags: &'a ArcFlags,
+  mask: &'a [u64],
+}
+
+impl<'a, G> FlaggedGraph<'a, G> {
+  pub fn new(graph: G, flags: &'a ArcFlags, mask: &'a [u64]) -> Self {
+    Self { graph, flags, mask }
+  }
+}
+
+/// Iterator over the neighbors the transitions to which are flagged for the mask of the [`FlaggedGraph`]
+pub struct FlaggedNeighbors<'a, Nodes> {
+  id: Idx,
+  flags: &'a ArcFlags,
+  mask: &'a [u64],
+  nodes: std::iter::Enumerate<Nodes>,
+}
+
+impl<'a, Nodes: Iterator<Item = Idx>> Iterator for FlaggedNeighbors<'a, Nodes> {
+  type Item = Idx;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (id, flags, mask) = (self.id, self.flags, self.mask);
+    self.nodes.find(|(i, _)| flags.is_flagged(id, *i, mask)).map(|(_, next)| next)
+  }
+}
+
+impl<'a, G: GraphBase> GraphBase for FlaggedGraph<'a, G> {
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: GraphData> GraphData for FlaggedGraph<'a, G> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<'a, G: IntoNeighbors<Forward, NodeId = Idx>> IntoNeighbors<Forward> for FlaggedGraph<'a, G> {
+  type Neighbors = FlaggedNeighbors<'a, <G as IntoNeighbors<Forward>>::Neighbors>;
+
+  fn neighbors(self, node: Idx) -> Self::Neighbors {
+    FlaggedNeighbors {
+      id: node,
+      flags: self.flags,
+      mask: self.mask,
+      nodes: <G as IntoNeighbors<Forward>>::neighbors(self.graph, node).enumerate(),
+    }
+  }
+}
+
+impl<'a, G: Weighted> Weighted for FlaggedGraph<'a, G> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::graph_impl::CompactGraph;
+  use super::*;
+
+  #[test]
+  fn test_flags() {
+    // 0 -> 1 -> 3 is cheaper than 0 -> 2 -> 3, node 3 is in a cell of its own
+    let graph = CompactGraph::from_row_data(vec![1, 1, 5, 1], vec![0, 2, 3, 4], vec![1, 2, 3, 3]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+    let flags = ArcFlags::compute(weighted, Partition::new(vec![0, 0, 0, 1]));
+
+    let to_3 = flags.mask(vec![3]);
+    assert!(flags.is_flagged(0, 0, &to_3));
+    assert!(!flags.is_flagged(0, 1, &to_3));
+    // Transitions into the cell are flagged even if they aren't the cheapest
+    assert!(flags.is_flagged(2, 0, &to_3));
+    // Within the cell every transition is flagged
+    let to_cell_0 = flags.mask(vec![1]);
+    assert!(flags.is_flagged(0, 0, &to_cell_0) && flags.is_flagged(0, 1, &to_cell_0));
+    assert!(!flags.is_flagged(1, 0, &to_cell_0));
+
+    let flagged = FlaggedGraph::new(weighted, &flags, &to_3);
+    assert_eq!(neighbors_forward(flagged, 0).collect::<Vec<_>>(), vec![1]);
+  }
+}
@@ -0,0 +1,395 @@
+//! A* search towards a fixed destination.
+//!
+//! When many routes end at the same destination, e.g. dispatching vehicles to a depot, the cost from every node to
+//! the destination can be computed once with a backward search. It's an exact potential for A*: the forward search
+//! of each following query only settles the nodes on the shortest path and the nodes tied with it.
+//!
+//! Single queries use estimated potentials instead, e.g. the straight-line distance to the destination at the
+//! highest speed. [`route_bidir_astar`] runs A* from both waypoints, each search towards the other waypoint.
+
+use crate::graph::*;
+use crate::graph_impl::ReversedGraph;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::SearchSpace;
+use crate::spatial::{haversine_distance, Position};
+use crate::waypoint::*;
+
+use std::collections::{HashMap, HashSet};
+
+/// Cost of the cheapest route from every node to a destination
+pub struct TargetPotentials<W: Weight, N: Identifier> {
+  tree: SearchSpace<W, N>,
+  target_costs: HashMap<N, W>,
+  candidates: Vec<SnappedOnEdge<N>>,
+}
+
+impl<W: Weight, N: Identifier> TargetPotentials<W, N> {
+  /// Runs a backward search over the whole graph from all destination candidates.
+  ///
+  /// The potentials are only valid for the graph and weights they were computed with.
+  pub fn new<G>(graph: G, to: &MatchedWaypoint<N>) -> Self
+  where
+    G: Copy + Weighted<Weight = W> + IntoNeighbors<Backward, NodeId = N>,
+  {
+    let reversed = ReversedGraph::new(graph);
+    let mut tree = SearchSpace::new();
+    let mut target_costs = HashMap::new();
+    for SnappedOnEdge(_, id) in &to.snapped {
+      // The destination node itself is paid for as well
+      let target_cost = graph.transition_weight(*id, *id);
+      tree.init_with_cost(*id, target_cost);
+      target_costs.insert(*id, target_cost);
+    }
+    while tree.update(reversed) {}
+
+    Self {
+      tree,
+      target_costs,
+      candidates: to.snapped.clone(),
+    }
+  }
+
+  /// Cost from the node to the destination, `None` if the destination can't be reached
+  pub fn potential(&self, node: N) -> Option<W> {
+    self.tree.cost(node)
+  }
+}
+
+/// Finds the cheapest route from any origin candidate to the destination of the precomputed `potentials`.
+pub fn route_to_target<G>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  potentials: &TargetPotentials<G::Weight, G::NodeId>,
+) -> Option<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+{
+  let mut forward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    if let Some(potential) = potentials.potential(*id) {
+      forward_search.init_with_potential(*id, Default::default(), potential);
+    }
+  }
+
+  let mut best: Option<(G::Weight, G::NodeId)> = None;
+  while let Some((id, key)) = forward_search.min() {
+    if let Some((best_cost, _)) = best {
+      if key >= best_cost {
+        break;
+      }
+    }
+    if let Some(target_cost) = potentials.target_costs.get(&id) {
+      let total = forward_search.cost(id).unwrap() + *target_cost;
+      match best {
+        Some((best_cost, _)) if best_cost <= total => {}
+        _ => best = Some((total, id)),
+      }
+    }
+    forward_search.update_with_potential(graph, |node| potentials.potential(node));
+  }
+
+  best.map(|(cost, id)| {
+    // Need to reverse the list to get elements in the routing order
+    let ids: Vec<_> = forward_search.unwind(id).iter().rev().cloned().collect();
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+    let destination_candidate = potentials.candidates.iter().position(|c| c.1 == id).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: forward_search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &potentials.candidates[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+/// Potential estimating the cost between a node and the nearest of the positions from the straight-line distance.
+///
+/// Nodes are measured from the first point of their geometry. In the edge-based graph a transition costs at least the
+/// length of the segment it leaves, so the estimate is consistent as long as no meter of the graph costs less than
+/// `cost_per_meter`, e.g. the travel time of a meter at the highest speed of the profile. Weights rounded to whole
+/// units can be cheaper than the exact cost, keep `cost_per_meter` a bit lower to leave a margin for the rounding.
+pub fn distance_potential<G, W>(graph: G, positions: Vec<Position>, cost_per_meter: f32) -> impl Fn(G::NodeId) -> W
+where
+  G: Copy + IntoGeometry,
+  W: ScalableWeight + From<u8>,
+{
+  move |node: G::NodeId| {
+    let start = match graph.geometry(node).next() {
+      Some(start) => start.into(),
+      None => return W::default(),
+    };
+    let distance = positions
+      .iter()
+      .map(|position| haversine_distance(&start, position))
+      .fold(f32::INFINITY, f32::min);
+    if distance.is_finite() {
+      // Rounded down, so the estimate never exceeds the exact cost
+      W::from(1).scale((distance * cost_per_meter).floor())
+    } else {
+      W::default()
+    }
+  }
+}
+
+// Settles the next node of one side of the bidirectional A*.
+//
+// A node is settled by at most one side. It's only expanded if a path through it can still be cheaper than the best
+// meeting found so far, judging by both its own key and the lowest key of the other side.
+fn settle_next<G, P, Q>(
+  graph: G,
+  search: &mut SearchSpace<G::Weight, G::NodeId>,
+  other: &SearchSpace<G::Weight, G::NodeId>,
+  (potential, other_potential): (P, Q),
+  settled: &mut HashSet<G::NodeId>,
+  best: &mut Option<(G::Weight, G::NodeId)>,
+)
+where
+  G: Copy + Weighted + IntoNeighbors<Forward>,
+  P: Fn(G::NodeId) -> G::Weight,
+  Q: Fn(G::NodeId) -> G::Weight,
+{
+  let (id, key) = match search.pop() {
+    Some(next) => next,
+    None => return,
+  };
+  if !settled.insert(id) {
+    return;
+  }
+  let cost = search.cost(id).unwrap();
+  let other_min = other.min().map(|(_, other_key)| other_key).unwrap_or_default();
+  if let Some((best_cost, _)) = *best {
+    // The second check is `cost - other_potential + other_min >= best_cost`, without subtracting the weights
+    if key >= best_cost || cost + other_min >= best_cost + other_potential(id) {
+      return;
+    }
+  }
+  for next in neighbors_forward(graph, id) {
+    if settled.contains(&next) {
+      continue;
+    }
+    let path_cost = cost + graph.transition_weight(id, next);
+    if search.relax_with_key(next, id, path_cost, path_cost + potential(next)) {
+      if let Some(other_cost) = other.cost(next) {
+        update_best(best, path_cost + other_cost, next);
+      }
+    }
+  }
+}
+
+fn update_best<W: Weight, N: Identifier>(best: &mut Option<(W, N)>, cost: W, id: N) {
+  match best {
+    Some((best_cost, _)) if *best_cost <= cost => {}
+    _ => *best = Some((cost, id)),
+  }
+}
+
+/// Same as [`route_bidir`](crate::route::route_bidir), but both searches are A* searches: the forward one guided by
+/// `to_destination`, an estimate of the cost from a node to the destination, and the backward one by `from_origin`,
+/// an estimate of the cost from the origin to a node, e.g. both made with [`distance_potential`].
+///
+/// The route is exact if both potentials are consistent, i.e. the estimate of a node never exceeds the weight of a
+/// transition from it plus the estimate of the next node. It's the new bidirectional A* (NBA*) of Pijls and Post:
+/// every node is settled by one of the searches at most, and the nodes which can't be a part of a cheaper route than
+/// the best one found so far are dropped without expanding them.
+pub fn route_bidir_astar<G, P, Q>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  to_destination: P,
+  from_origin: Q,
+) -> Option<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted,
+  P: Fn(G::NodeId) -> G::Weight,
+  Q: Fn(G::NodeId) -> G::Weight,
+{
+  let reversed = ReversedGraph::new(graph);
+
+  let mut forward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    forward_search.init_with_potential(*id, Default::default(), to_destination(*id));
+  }
+  // Cost of the backward search includes the cost of the destination node
+  let mut backward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &to.snapped {
+    backward_search.init_with_potential(*id, graph.transition_weight(*id, *id), from_origin(*id));
+  }
+
+  let mut best: Option<(G::Weight, G::NodeId)> = None;
+  for SnappedOnEdge(_, id) in &from.snapped {
+    if let Some(backward_cost) = backward_search.cost(*id) {
+      update_best(&mut best, backward_cost, *id);
+    }
+  }
+
+  let mut settled = HashSet::new();
+  // A route cheaper than the best meeting has to go through the nodes of both queues, so the search ends once
+  // either of them is empty
+  while let (Some((_, f)), Some((_, b))) = (forward_search.min(), backward_search.min()) {
+    if f <= b {
+      let potentials = (&to_destination, &from_origin);
+      settle_next(graph, &mut forward_search, &backward_search, potentials, &mut settled, &mut best);
+    } else {
+      let potentials = (&from_origin, &to_destination);
+      settle_next(reversed, &mut backward_search, &forward_search, potentials, &mut settled, &mut best);
+    }
+  }
+
+  best.map(|(cost, id)| {
+    // Forward part is unwound from the meeting towards the origin, backward part is already in the routing order
+    let mut ids: Vec<_> = forward_search.unwind(id).iter().rev().cloned().collect();
+    ids.extend(backward_search.unwind(id).iter().skip(1));
+
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+    let destination_candidate = to.snapped.iter().position(|s| s.1 == ids[ids.len() - 1]).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: forward_search.num_resolved() + backward_search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::route::{route, route_bidir};
+  use super::super::spatial::Position;
+  use super::super::test_utils::{graph_from_data_and_edges, graph_from_intersections, simple_segment_length_cost};
+  use super::*;
+
+  fn matched(ids: &[u32]) -> MatchedWaypoint<u32> {
+    let mut matched = MatchedWaypoint::from_node(ids[0], Position { x: 0.0, y: 0.0 });
+    for id in &ids[1..] {
+      matched.snapped.push(SnappedOnEdge(matched.snapped[0].0, *id));
+    }
+    matched
+  }
+
+  fn node_cost(from: &u32, _to: &u32) -> u32 {
+    *from
+  }
+
+  #[test]
+  fn test_same_result_as_dijkstra() {
+    // Routes from 0 and 2 have equal cost alternatives, A* picks the same ones as Dijkstra
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0)],
+    );
+    let weighted_graph = (&graph, node_cost);
+    let to = matched(&[6]);
+    let potentials = TargetPotentials::new(weighted_graph, &to);
+
+    for origin in 0..6 {
+      let from = matched(&[origin]);
+      let expected = route(weighted_graph, &from, &to).unwrap();
+      let actual = route_to_target(weighted_graph, &from, &potentials).unwrap();
+      assert_eq!(actual.cost, expected.cost);
+      assert_eq!(actual.ids, expected.ids, "origin: {}", origin);
+    }
+  }
+
+  #[test]
+  fn test_only_path_is_settled() {
+    // A long chain with a dead-end branch at every node
+    let mut data = vec![1; 10];
+    let mut edges = Vec::new();
+    for id in 0..9 {
+      edges.push((id, id + 1));
+      edges.push((id, data.len()));
+      data.push(1);
+    }
+    let graph = graph_from_data_and_edges(data, edges);
+    let weighted_graph = (&graph, node_cost);
+    let potentials = TargetPotentials::new(weighted_graph, &matched(&[9]));
+
+    let route = route_to_target(weighted_graph, &matched(&[0]), &potentials).unwrap();
+
+    assert_eq!(route.ids, (0..10).collect::<Vec<_>>());
+    assert_eq!(route.cost, 10);
+    assert_eq!(route.num_resolved, 10);
+  }
+
+  #[test]
+  fn test_best_candidates() {
+    let graph = graph_from_data_and_edges(vec![1, 5, 100, 50, 1], vec![(0, 3), (1, 4), (3, 2)]);
+    let weighted_graph = (&graph, node_cost);
+    let potentials = TargetPotentials::new(weighted_graph, &matched(&[3, 4]));
+
+    let route = route_to_target(weighted_graph, &matched(&[0, 1]), &potentials).unwrap();
+
+    assert_eq!(route.ids, vec![1, 4]);
+    assert_eq!(route.cost, 6);
+    assert_eq!(route.origin_candidate, 1);
+    assert_eq!(route.destination_candidate, 1);
+  }
+
+  #[test]
+  fn test_unreachable_target() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1)]);
+    let weighted_graph = (&graph, node_cost);
+    let potentials = TargetPotentials::new(weighted_graph, &matched(&[2]));
+
+    assert_eq!(potentials.potential(0), None);
+    assert!(route_to_target(weighted_graph, &matched(&[0]), &potentials).is_none());
+  }
+
+  #[test]
+  fn test_bidir_astar_same_cost_as_dijkstra() {
+    // Streets on a 100 m grid in both directions, with a few one-way streets
+    let size = 8;
+    let mut positions = Vec::new();
+    for y in 0..size {
+      for x in 0..size {
+        positions.push(Position::from((13.4 + 0.0015 * x as f32, 52.5 + 0.0009 * y as f32)));
+      }
+    }
+    let mut streets = Vec::new();
+    for y in 0..size {
+      for x in 0..size - 1 {
+        streets.push((y * size + x, y * size + x + 1));
+        streets.push((x * size + y, (x + 1) * size + y));
+        if x != y {
+          streets.push((y * size + x + 1, y * size + x));
+          streets.push(((x + 1) * size + y, x * size + y));
+        }
+      }
+    }
+    let graph = graph_from_intersections(positions, streets);
+    let weighted = (&graph, simple_segment_length_cost);
+    let matched = |id: u32| {
+      let start = (&graph).geometry(id).next().unwrap();
+      MatchedWaypoint::from_node(id, start)
+    };
+
+    let nodes = graph.number_of_nodes() as u32;
+    for from in (0..nodes).step_by(7) {
+      for to in (0..nodes).step_by(5) {
+        let (from, to) = (matched(from), matched(to));
+        let expected = route(weighted, &from, &to).ok().map(|route| route.cost);
+        let to_destination = distance_potential(&graph, vec![to.waypoint], 0.9);
+        let from_origin = distance_potential(&graph, vec![from.waypoint], 0.9);
+        let actual = route_bidir_astar(weighted, &from, &to, to_destination, from_origin);
+        assert_eq!(actual.map(|route| route.cost), expected);
+        // Without an estimate it's a plain bidirectional search
+        let unguided = route_bidir_astar(weighted, &from, &to, |_| 0, |_| 0);
+        assert_eq!(unguided.map(|route| route.cost), expected);
+      }
+    }
+
+    // Across the grid the estimates keep the searches close to the diagonal
+    let (from, to) = (matched(0), matched(nodes - 1));
+    let to_destination = distance_potential(&graph, vec![to.waypoint], 0.9);
+    let from_origin = distance_potential(&graph, vec![from.waypoint], 0.9);
+    let guided = route_bidir_astar(weighted, &from, &to, to_destination, from_origin).unwrap();
+    let unguided = route_bidir(weighted, &from, &to).unwrap();
+    assert_eq!(guided.cost, unguided.cost);
+    assert_eq!(guided.ids.first(), Some(&0));
+    assert!(guided.num_resolved < unguided.num_resolved);
+  }
+}
@@ -0,0 +1,477 @@
+//! Customizable contraction hierarchies (CCH).
+//!
+//! A contraction hierarchy adds shortcuts to the graph, so a query only searches upward from both waypoints and
+//! settles a few hundred nodes even on a continental graph. A plain hierarchy picks the contraction order and the
+//! shortcuts by the weights, so it has to be preprocessed again for every cost function.
+//!
+//! A CCH splits the preprocessing in two:
+//! - The contraction of the topology, [`Cch::new`], only depends on the graph and on an order of its nodes, e.g. the
+//!   [`nested_dissection_order`]. Every shortcut a contraction may add in any metric is added, so it's done once per
+//!   graph.
+//! - The customization, [`Cch::customize`], fills the weights of all the shortcuts for one cost function. It only
+//!   runs over the triangles of the hierarchy, so it takes seconds and one contracted graph serves any number of
+//!   cost functions, e.g. a metric per travel profile.
+//!
+//! The queries, [`route_cch`], find the same routes as [`route`](crate::route::route).
+
+use crate::graph::*;
+use crate::graph_impl::Idx;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::SearchSpace;
+use crate::spatial::Position;
+use crate::waypoint::*;
+
+use serde::{Deserialize, Serialize};
+
+// Cells with fewer nodes are not dissected any further
+const MIN_DISSECTED_CELL: usize = 16;
+
+// Weight of a shortcut is the weight of the input arc, not of a triangle
+const NO_MIDDLE: u32 = u32::MAX;
+
+/// Orders the nodes by nested dissection of their positions, the first point of the node geometry.
+///
+/// The nodes are split in two halves of the longer side of their bounding box, the nodes of one half connected to the
+/// other half are the separator. The separator comes last in the order and both halves are dissected the same way.
+/// Contracting the separators last keeps the shortcuts within the cells, so the hierarchy stays small. The geometric
+/// split gives larger separators than a graph partitioner, but any order gives the same routes.
+pub fn nested_dissection_order<G>(graph: G, number_of_nodes: usize) -> Vec<Idx>
+where
+  G: Copy + IntoNeighbors<Forward> + IntoGeometry<NodeId = Idx>,
+{
+  let positions: Vec<Position> = (0..number_of_nodes as Idx)
+    .map(|id| graph.geometry(id).next().map(Into::into).unwrap_or(Position { x: 0.0, y: 0.0 }))
+    .collect();
+  let neighbors = undirected_neighbors(graph, number_of_nodes);
+  let mut order = Vec::with_capacity(number_of_nodes);
+  let mut in_lower_half = vec![false; number_of_nodes];
+  dissect((0..number_of_nodes as Idx).collect(), &positions, &neighbors, &mut in_lower_half, &mut order);
+  order
+}
+
+fn undirected_neighbors<G>(graph: G, number_of_nodes: usize) -> Vec<Vec<Idx>>
+where
+  G: Copy + IntoNeighbors<Forward, NodeId = Idx>,
+{
+  let mut neighbors = vec![Vec::new(); number_of_nodes];
+  for id in 0..number_of_nodes as Idx {
+    for next in neighbors_forward(graph, id) {
+      if next != id {
+        neighbors[id as usize].push(next);
+        neighbors[next as usize].push(id);
+      }
+    }
+  }
+  neighbors
+}
+
+fn dissect(
+  mut cell: Vec<Idx>,
+  positions: &[Position],
+  neighbors: &[Vec<Idx>],
+  in_lower_half: &mut [bool],
+  order: &mut Vec<Idx>,
+) {
+  if cell.len() < MIN_DISSECTED_CELL {
+    order.extend(cell);
+    return;
+  }
+  let (min_x, max_x, min_y, max_y) = cell.iter().map(|id| positions[*id as usize]).fold(
+    (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+    |(min_x, max_x, min_y, max_y), p| (min_x.min(p.x), max_x.max(p.x), min_y.min(p.y), max_y.max(p.y)),
+  );
+  let coordinate = |id: &Idx| {
+    let position = positions[*id as usize];
+    if max_x - min_x >= max_y - min_y {
+      position.x
+    } else {
+      position.y
+    }
+  };
+  cell.sort_by(|a, b| coordinate(a).partial_cmp(&coordinate(b)).unwrap());
+  let upper = cell.split_off(cell.len() / 2);
+  let lower = cell;
+
+  for id in &lower {
+    in_lower_half[*id as usize] = true;
+  }
+  let (separator, upper): (Vec<Idx>, Vec<Idx>) = upper
+    .into_iter()
+    .partition(|id| neighbors[*id as usize].iter().any(|next| in_lower_half[*next as usize]));
+  for id in &lower {
+    in_lower_half[*id as usize] = false;
+  }
+
+  dissect(lower, positions, neighbors, in_lower_half, order);
+  dissect(upper, positions, neighbors, in_lower_half, order);
+  order.extend(separator);
+}
+
+/// Graph contracted in an order of its nodes, with the shortcuts of any metric but without any weights.
+///
+/// The nodes are identified by their rank in the order inside the hierarchy. Every pair of nodes connected in either
+/// direction, directly or by a shortcut, is an arc from the lower to the higher rank, which has a weight in each
+/// direction once the hierarchy is customized.
+#[derive(Serialize, Deserialize)]
+pub struct Cch {
+  // Node id of each rank
+  order: Vec<Idx>,
+  // Rank of each node id
+  ranks: Vec<u32>,
+  // Arcs of each rank to the higher ranks, sorted by the higher rank
+  first_out: Vec<u32>,
+  head: Vec<u32>,
+  // Arcs of the graph with the arc of the hierarchy they are a part of
+  inputs: Vec<(Idx, Idx, u32)>,
+}
+
+impl Cch {
+  /// Contracts the graph in the order, which must contain every node id of the graph exactly once
+  pub fn new<G>(graph: G, order: Vec<Idx>) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx>,
+  {
+    let mut ranks = vec![0; order.len()];
+    for (rank, id) in order.iter().enumerate() {
+      ranks[*id as usize] = rank as u32;
+    }
+
+    let mut upward: Vec<Vec<u32>> = vec![Vec::new(); order.len()];
+    for (from, id) in order.iter().enumerate() {
+      for next in neighbors_forward(graph, *id) {
+        let to = ranks[next as usize] as usize;
+        if from != to {
+          upward[from.min(to)].push(from.max(to) as u32);
+        }
+      }
+    }
+    // Contracting a rank connects all its upward neighbors, which are the upward neighbors of the lowest of them
+    // once its own contraction adds the rest
+    for rank in 0..upward.len() {
+      let mut neighbors = std::mem::take(&mut upward[rank]);
+      neighbors.sort_unstable();
+      neighbors.dedup();
+      if let Some((parent, rest)) = neighbors.split_first() {
+        upward[*parent as usize].extend_from_slice(rest);
+      }
+      upward[rank] = neighbors;
+    }
+
+    let mut first_out = Vec::with_capacity(upward.len() + 1);
+    let mut head = Vec::new();
+    for neighbors in upward {
+      first_out.push(head.len() as u32);
+      head.extend(neighbors);
+    }
+    first_out.push(head.len() as u32);
+
+    let mut cch = Self {
+      order,
+      ranks,
+      first_out,
+      head,
+      inputs: Vec::new(),
+    };
+    for id in 0..cch.order.len() as Idx {
+      for next in neighbors_forward(graph, id) {
+        if next != id {
+          let arc = cch.arc(cch.ranks[id as usize], cch.ranks[next as usize]);
+          cch.inputs.push((id, next, arc));
+        }
+      }
+    }
+    cch
+  }
+
+  /// Number of the arcs of the hierarchy, both of the graph and the shortcuts
+  pub fn number_of_arcs(&self) -> usize {
+    self.head.len()
+  }
+
+  /// Weights of the arcs of the hierarchy in the weights of the graph, which must be the contracted one
+  pub fn customize<G>(&self, graph: G) -> CchMetric<G::Weight>
+  where
+    G: Weighted<NodeId = Idx>,
+  {
+    let mut metric = CchMetric {
+      up: vec![None; self.head.len()],
+      down: vec![None; self.head.len()],
+      up_middle: vec![NO_MIDDLE; self.head.len()],
+      down_middle: vec![NO_MIDDLE; self.head.len()],
+      target_costs: (0..self.order.len() as Idx).map(|id| graph.transition_weight(id, id)).collect(),
+    };
+    for (from, to, arc) in &self.inputs {
+      let weight = graph.transition_weight(*from, *to);
+      let (weights, middles) = if self.ranks[*from as usize] < self.ranks[*to as usize] {
+        (&mut metric.up, &mut metric.up_middle)
+      } else {
+        (&mut metric.down, &mut metric.down_middle)
+      };
+      improve(&mut weights[*arc as usize], &mut middles[*arc as usize], weight, NO_MIDDLE);
+    }
+
+    // The arcs of a rank are final once the lower ranks are processed, since all the triangles an arc is the top of
+    // have a lower rank at the bottom
+    for rank in 0..self.order.len() {
+      let arcs = self.arcs(rank as u32);
+      for lower in arcs.clone() {
+        for higher in lower + 1..arcs.end {
+          let top = self.arc(self.head[lower], self.head[higher]) as usize;
+          // Going up from the lower neighbor through the rank, and down to it the other way around
+          if let (Some(down), Some(up)) = (metric.down[lower], metric.up[higher]) {
+            improve(&mut metric.up[top], &mut metric.up_middle[top], down + up, rank as u32);
+          }
+          if let (Some(down), Some(up)) = (metric.down[higher], metric.up[lower]) {
+            improve(&mut metric.down[top], &mut metric.down_middle[top], down + up, rank as u32);
+          }
+        }
+      }
+    }
+    metric
+  }
+
+  fn arcs(&self, rank: u32) -> std::ops::Range<usize> {
+    self.first_out[rank as usize] as usize..self.first_out[rank as usize + 1] as usize
+  }
+
+  // Arc between the ranks, the contraction adds one between every pair of connected ranks
+  fn arc(&self, a: u32, b: u32) -> u32 {
+    let (lower, higher) = (a.min(b), a.max(b));
+    let arcs = self.arcs(lower);
+    let position = self.head[arcs.clone()].binary_search(&higher).unwrap();
+    (arcs.start + position) as u32
+  }
+}
+
+fn improve<W: Weight>(weight: &mut Option<W>, middle: &mut u32, candidate: W, candidate_middle: u32) {
+  match weight {
+    Some(current) if *current <= candidate => {}
+    _ => {
+      *weight = Some(candidate);
+      *middle = candidate_middle;
+    }
+  }
+}
+
+/// Weights of a [`Cch`] for one cost function, made by [`Cch::customize`]
+pub struct CchMetric<W: Weight> {
+  // Weights from the lower to the higher rank of each arc, `None` if the arc can't be traveled in that direction
+  up: Vec<Option<W>>,
+  // Weights from the higher to the lower rank
+  down: Vec<Option<W>>,
+  // Middle rank of the triangle each shortcut weight comes from
+  up_middle: Vec<u32>,
+  down_middle: Vec<u32>,
+  // Cost of each node as the last node of a route
+  target_costs: Vec<W>,
+}
+
+/// Finds the cheapest route in the customized hierarchy, the same route as [`route`](crate::route::route) finds in
+/// the graph the metric is made from.
+///
+/// Both searches only go up the hierarchy and meet at the highest rank of the route, the shortcuts are unpacked into
+/// the nodes of the graph afterwards.
+pub fn route_cch<W: Weight>(
+  cch: &Cch,
+  metric: &CchMetric<W>,
+  from: &MatchedWaypoint<Idx>,
+  to: &MatchedWaypoint<Idx>,
+) -> Option<Route<W, Idx>> {
+  let mut forward: SearchSpace<W, u32> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    forward.init(cch.ranks[*id as usize]);
+  }
+  let mut backward: SearchSpace<W, u32> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &to.snapped {
+    backward.init_with_cost(cch.ranks[*id as usize], metric.target_costs[*id as usize]);
+  }
+
+  let mut best: Option<(W, u32)> = None;
+  loop {
+    let forward_key = open_key(&forward, &best);
+    let backward_key = open_key(&backward, &best);
+    let forward_turn = match (forward_key, backward_key) {
+      (Some(f), Some(b)) => f <= b,
+      (Some(_), None) => true,
+      (None, Some(_)) => false,
+      (None, None) => break,
+    };
+    if forward_turn {
+      settle_next(cch, &metric.up, &mut forward, &backward, &mut best);
+    } else {
+      settle_next(cch, &metric.down, &mut backward, &forward, &mut best);
+    }
+  }
+
+  best.map(|(cost, meeting)| {
+    let mut ranks: Vec<u32> = forward.unwind(meeting).into_iter().rev().collect();
+    ranks.extend(backward.unwind(meeting).into_iter().skip(1));
+    let mut ids = vec![cch.order[ranks[0] as usize]];
+    for pair in ranks.windows(2) {
+      unpack(cch, metric, pair[0], pair[1], &mut ids);
+    }
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+    let destination_candidate = to.snapped.iter().position(|s| Some(&s.1) == ids.last()).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: forward.num_resolved() + backward.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+// Lowest key of the search if it may still find a cheaper route
+fn open_key<W: Weight>(search: &SearchSpace<W, u32>, best: &Option<(W, u32)>) -> Option<W> {
+  match (search.min(), best) {
+    (Some((_, key)), Some((best_cost, _))) if key >= *best_cost => None,
+    (min, _) => min.map(|(_, key)| key),
+  }
+}
+
+// Settles the next rank of the search and relaxes its arcs to the higher ranks, weighted in the direction of the
+// search
+fn settle_next<W: Weight>(
+  cch: &Cch,
+  weights: &[Option<W>],
+  search: &mut SearchSpace<W, u32>,
+  other: &SearchSpace<W, u32>,
+  best: &mut Option<(W, u32)>,
+) {
+  let (rank, key) = match search.pop() {
+    Some(next) => next,
+    None => return,
+  };
+  if search.cost(rank) != Some(key) {
+    return;
+  }
+  if let Some(other_cost) = other.cost(rank) {
+    match best {
+      Some((best_cost, _)) if *best_cost <= key + other_cost => {}
+      _ => *best = Some((key + other_cost, rank)),
+    }
+  }
+  for arc in cch.arcs(rank) {
+    if let Some(weight) = weights[arc] {
+      search.relax_with_key(cch.head[arc], rank, key + weight, key + weight);
+    }
+  }
+}
+
+// Appends the nodes of the graph from one rank to the other, without the first one
+fn unpack<W: Weight>(cch: &Cch, metric: &CchMetric<W>, from: u32, to: u32, ids: &mut Vec<Idx>) {
+  let mut stack = vec![(from, to)];
+  while let Some((from, to)) = stack.pop() {
+    let arc = cch.arc(from, to) as usize;
+    let middle = if from < to {
+      metric.up_middle[arc]
+    } else {
+      metric.down_middle[arc]
+    };
+    if middle == NO_MIDDLE {
+      ids.push(cch.order[to as usize]);
+    } else {
+      stack.push((middle, to));
+      stack.push((from, middle));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::route::route;
+  use super::super::test_utils::{graph_from_data_and_edges, graph_from_intersections, matched, simple_segment_length_cost};
+  use super::*;
+
+  #[test]
+  fn test_same_cost_as_dijkstra() {
+    // Streets on a 100 m grid in both directions, with a few one-way streets
+    let size = 8;
+    let mut positions = Vec::new();
+    for y in 0..size {
+      for x in 0..size {
+        positions.push(Position::from((13.4 + 0.0015 * x as f32, 52.5 + 0.0009 * y as f32)));
+      }
+    }
+    let mut streets = Vec::new();
+    for y in 0..size {
+      for x in 0..size - 1 {
+        streets.push((y * size + x, y * size + x + 1));
+        streets.push((x * size + y, (x + 1) * size + y));
+        if x != y {
+          streets.push((y * size + x + 1, y * size + x));
+          streets.push(((x + 1) * size + y, x * size + y));
+        }
+      }
+    }
+    let graph = graph_from_intersections(positions, streets);
+    let nodes = graph.number_of_nodes();
+    let order = nested_dissection_order(&graph, nodes);
+    let mut sorted = order.clone();
+    sorted.sort_unstable();
+    assert_eq!(sorted, (0..nodes as u32).collect::<Vec<_>>());
+
+    let cch = Cch::new(&graph, order);
+    let weighted = (&graph, simple_segment_length_cost);
+    let metric = cch.customize(weighted);
+    for from in (0..nodes as u32).step_by(7) {
+      for to in (0..nodes as u32).step_by(5) {
+        let expected = route(weighted, &matched(from), &matched(to));
+        let actual = route_cch(&cch, &metric, &matched(from), &matched(to));
+        assert_eq!(actual.as_ref().map(|route| route.cost), expected.ok().map(|route| route.cost));
+        if let Some(actual) = actual {
+          // The unpacked route is a path of the graph with the same cost
+          assert_eq!((actual.ids[0], *actual.ids.last().unwrap()), (from, to));
+          assert!(actual.ids.windows(2).all(|pair| neighbors_forward(&graph, pair[0]).any(|next| next == pair[1])));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_metrics_of_one_hierarchy() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0)],
+    );
+    let cch = Cch::new(&graph, vec![3, 0, 5, 1, 4, 2, 6]);
+    let by_from = (&graph, |from: &u32, _to: &u32| *from);
+    let by_to = (&graph, |_from: &u32, to: &u32| 10 - *to);
+    let (from_metric, to_metric) = (cch.customize(by_from), cch.customize(by_to));
+
+    for origin in 0..7 {
+      for destination in 0..7 {
+        let (from, to) = (matched(origin), matched(destination));
+        let expected = route(by_from, &from, &to).unwrap();
+        let actual = route_cch(&cch, &from_metric, &from, &to).unwrap();
+        assert_eq!(actual.cost, expected.cost);
+        let last = *actual.ids.last().unwrap();
+        let transitions: u32 = actual.ids.windows(2).map(|pair| by_from.transition_weight(pair[0], pair[1])).sum();
+        assert_eq!(transitions + by_from.transition_weight(last, last), actual.cost);
+
+        let expected = route(by_to, &from, &to).unwrap();
+        assert_eq!(route_cch(&cch, &to_metric, &from, &to).unwrap().cost, expected.cost);
+      }
+    }
+  }
+
+  #[test]
+  fn test_unreachable_and_candidates() {
+    let graph = graph_from_data_and_edges(vec![1, 5, 100, 50, 1], vec![(0, 3), (1, 4), (3, 2)]);
+    let cch = Cch::new(&graph, vec![0, 1, 2, 3, 4]);
+    let metric = cch.customize((&graph, |from: &u32, _to: &u32| *from));
+
+    assert!(route_cch(&cch, &metric, &matched(2), &matched(0)).is_none());
+
+    let mut from = matched(0);
+    from.snapped.push(SnappedOnEdge(from.snapped[0].0, 1));
+    let mut to = matched(3);
+    to.snapped.push(SnappedOnEdge(to.snapped[0].0, 4));
+    let route = route_cch(&cch, &metric, &from, &to).unwrap();
+    assert_eq!(route.ids, vec![1, 4]);
+    assert_eq!(route.cost, 6);
+    assert_eq!((route.origin_candidate, route.destination_candidate), (1, 1));
+  }
+}
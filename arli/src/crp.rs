@@ -0,0 +1,424 @@
+//! Customizable route planning (CRP) on a multilevel overlay.
+//!
+//! The nodes are partitioned into cells on several levels, each cell of a level is made of whole cells of the level
+//! below. The entries and the exits of a cell are the nodes of the transitions crossing its boundary. Like the
+//! [`cch`](crate::cch), the preprocessing is split in two:
+//! - The overlay, [`Crp::new`], only depends on the graph and the partitions, so it's built once per graph.
+//! - The customization, [`Crp::customize`], computes the cheapest costs from every entry to every exit of each cell,
+//!   a clique of the boundary. The cliques of a level are computed from the cliques of the level below, so the
+//!   customization is fast enough to run again whenever the weights change, e.g. with the traffic.
+//!
+//! The queries, [`route_crp`], only use the graph in the cells of the waypoints. Elsewhere they cross each cell in
+//! one step, through the clique of the highest level cell without a waypoint, and find the same routes as
+//! [`route`](crate::route::route).
+
+use crate::graph::*;
+use crate::graph_impl::{BlockedGraph, Idx};
+use crate::partition::Partition;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::SearchSpace;
+use crate::waypoint::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// Index of a node which isn't an entry of its cell
+const NOT_ON_BOUNDARY: u32 = u32::MAX;
+
+// State of a node in the overlay search, a node can be both an entry and an exit of its cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum OverlayNode {
+  // Reached through a transition, the search goes on through the graph or through the clique of the cell
+  Arrived(Idx),
+  // Reached through a clique, the search goes on through the transitions leaving the cell
+  Leaving(Idx),
+}
+
+use OverlayNode::*;
+
+// Entries and exits of a cell
+#[derive(Serialize, Deserialize)]
+struct Boundary {
+  entries: Vec<Idx>,
+  exits: Vec<Idx>,
+}
+
+/// Multilevel overlay of a graph, independent of the weights.
+#[derive(Serialize, Deserialize)]
+pub struct Crp {
+  // Partitions of the levels, from the finest one
+  levels: Vec<Partition>,
+  // Boundary of each cell of each level
+  boundaries: Vec<Vec<Boundary>>,
+  // Position of each node among the entries of its cell on each level
+  entry_index: Vec<Vec<u32>>,
+}
+
+impl Crp {
+  /// Builds the overlay of the partitions, from the finest one. Each cell of a level must be made of whole cells of
+  /// the level below, e.g. made by [`Partition::coarsen`].
+  pub fn new<G>(graph: G, levels: Vec<Partition>) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx>,
+  {
+    for pair in levels.windows(2) {
+      let mut coarse_cells = HashMap::new();
+      for id in 0..pair[0].number_of_nodes() as Idx {
+        let coarse_cell = *coarse_cells.entry(pair[0].cell(id)).or_insert_with(|| pair[1].cell(id));
+        assert_eq!(coarse_cell, pair[1].cell(id), "Cells of a level must be made of whole cells of the level below");
+      }
+    }
+
+    let mut crp = Self {
+      boundaries: Vec::with_capacity(levels.len()),
+      entry_index: Vec::with_capacity(levels.len()),
+      levels,
+    };
+    for partition in &crp.levels {
+      let number_of_nodes = partition.number_of_nodes();
+      let (mut is_entry, mut is_exit) = (vec![false; number_of_nodes], vec![false; number_of_nodes]);
+      for id in 0..number_of_nodes as Idx {
+        for next in neighbors_forward(graph, id) {
+          if partition.cell(next) != partition.cell(id) {
+            is_exit[id as usize] = true;
+            is_entry[next as usize] = true;
+          }
+        }
+      }
+
+      let mut boundaries: Vec<_> = (0..partition.number_of_cells())
+        .map(|_| Boundary {
+          entries: Vec::new(),
+          exits: Vec::new(),
+        })
+        .collect();
+      let mut entry_index = vec![NOT_ON_BOUNDARY; number_of_nodes];
+      for id in 0..number_of_nodes as Idx {
+        let boundary = &mut boundaries[partition.cell(id) as usize];
+        if is_entry[id as usize] {
+          entry_index[id as usize] = boundary.entries.len() as u32;
+          boundary.entries.push(id);
+        }
+        if is_exit[id as usize] {
+          boundary.exits.push(id);
+        }
+      }
+      crp.boundaries.push(boundaries);
+      crp.entry_index.push(entry_index);
+    }
+    crp
+  }
+
+  pub fn number_of_levels(&self) -> usize {
+    self.levels.len()
+  }
+
+  /// Cliques of all the cells in the weights of the graph, which must be the one the overlay is built for. Only the
+  /// cliques of the finest level search the graph, the other levels search the cliques of the level below.
+  pub fn customize<G>(&self, graph: G) -> CrpMetric<G::Weight>
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted,
+  {
+    let mut metric = CrpMetric { cliques: Vec::new() };
+    for level in 0..self.levels.len() {
+      let cliques = self.boundaries[level]
+        .iter()
+        .enumerate()
+        .map(|(cell, boundary)| {
+          let mut clique = Vec::with_capacity(boundary.entries.len() * boundary.exits.len());
+          for entry in &boundary.entries {
+            clique.extend(self.costs_within_cell(graph, &metric, level, cell as u32, *entry));
+          }
+          clique
+        })
+        .collect();
+      metric.cliques.push(cliques);
+    }
+    metric
+  }
+
+  // Cheapest costs from the entry to each exit of its cell without leaving the cell
+  fn costs_within_cell<G>(
+    &self,
+    graph: G,
+    metric: &CrpMetric<G::Weight>,
+    level: usize,
+    cell: u32,
+    entry: Idx,
+  ) -> Vec<Option<G::Weight>>
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted,
+  {
+    let exits = &self.boundaries[level][cell as usize].exits;
+    if level == 0 {
+      let partition = &self.levels[0];
+      let within = BlockedGraph::new(graph, move |id: Idx| partition.cell(id) != cell);
+      let mut search: SearchSpace<G::Weight, Idx> = SearchSpace::new();
+      search.init(entry);
+      while search.update(within) {}
+      return exits.iter().map(|exit| search.cost(*exit)).collect();
+    }
+
+    // The entry of the cell is an entry of its cell on the level below as well
+    let mut search: SearchSpace<G::Weight, OverlayNode> = SearchSpace::new();
+    search.init(Arrived(entry));
+    while let Some((node, key)) = search.pop() {
+      if search.cost(node) != Some(key) {
+        continue;
+      }
+      match node {
+        Arrived(id) => self.relax_clique(metric, level - 1, id, key, &mut search),
+        Leaving(id) => self.relax_cut_transitions(graph, level - 1, id, key, &mut search, Some(cell)),
+      }
+    }
+    exits.iter().map(|exit| search.cost(Leaving(*exit))).collect()
+  }
+
+  // Relaxes the clique of the cell of the entry on the level
+  fn relax_clique<W: Weight>(
+    &self,
+    metric: &CrpMetric<W>,
+    level: usize,
+    entry: Idx,
+    cost: W,
+    search: &mut SearchSpace<W, OverlayNode>,
+  ) {
+    let cell = self.levels[level].cell(entry) as usize;
+    let exits = &self.boundaries[level][cell].exits;
+    let row = self.entry_index[level][entry as usize] as usize * exits.len();
+    let costs = &metric.cliques[level][cell][row..row + exits.len()];
+    for (exit, exit_cost) in exits.iter().zip(costs) {
+      if let Some(exit_cost) = exit_cost {
+        search.relax_with_key(Leaving(*exit), Arrived(entry), cost + *exit_cost, cost + *exit_cost);
+      }
+    }
+  }
+
+  // Relaxes the transitions from the exit leaving its cell on the level, only to the given cell of the level above if
+  // it's set
+  fn relax_cut_transitions<G>(
+    &self,
+    graph: G,
+    level: usize,
+    exit: Idx,
+    cost: G::Weight,
+    search: &mut SearchSpace<G::Weight, OverlayNode>,
+    enclosing_cell: Option<u32>,
+  ) where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted,
+  {
+    let partition = &self.levels[level];
+    for next in neighbors_forward(graph, exit) {
+      let enclosed = match enclosing_cell {
+        Some(cell) => self.levels[level + 1].cell(next) == cell,
+        None => true,
+      };
+      if enclosed && partition.cell(next) != partition.cell(exit) {
+        let next_cost = cost + graph.transition_weight(exit, next);
+        search.relax_with_key(Arrived(next), Leaving(exit), next_cost, next_cost);
+      }
+    }
+  }
+
+  // Appends the nodes of a cheapest route from the entry to the exit within their cell on the level, without the entry
+  fn unpack<G>(&self, graph: G, level: usize, entry: Idx, exit: Idx, ids: &mut Vec<Idx>)
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted,
+  {
+    let partition = &self.levels[level];
+    let cell = partition.cell(entry);
+    let within = BlockedGraph::new(graph, move |id: Idx| partition.cell(id) != cell);
+    let mut search: SearchSpace<G::Weight, Idx> = SearchSpace::new();
+    search.init(entry);
+    // The exit is final once it has the lowest key
+    while let Some((id, _)) = search.min() {
+      if id == exit {
+        break;
+      }
+      search.update(within);
+    }
+    ids.extend(search.unwind(exit).into_iter().rev().skip(1));
+  }
+}
+
+/// Cliques of a [`Crp`] for one cost function, made by [`Crp::customize`]
+pub struct CrpMetric<W: Weight> {
+  // Costs from each entry to each exit of each cell of each level, by the entry and then by the exit. `None` if the
+  // exit can't be reached within the cell.
+  cliques: Vec<Vec<Vec<Option<W>>>>,
+}
+
+/// Finds the cheapest route through the overlay, the same route as [`route`](crate::route::route) finds in the graph
+/// the metric is made from.
+///
+/// A node is searched through the graph if its finest cell has a waypoint, otherwise through the clique of its
+/// highest level cell without a waypoint. The cliques of the route are unpacked by a search within their cells.
+pub fn route_crp<G>(
+  graph: G,
+  crp: &Crp,
+  metric: &CrpMetric<G::Weight>,
+  from: &MatchedWaypoint<Idx>,
+  to: &MatchedWaypoint<Idx>,
+) -> Option<Route<G::Weight, Idx>>
+where
+  G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted,
+{
+  let waypoint_cells: Vec<HashSet<u32>> = crp
+    .levels
+    .iter()
+    .map(|partition| {
+      let candidates = from.snapped.iter().chain(&to.snapped);
+      candidates.map(|SnappedOnEdge(_, id)| partition.cell(*id)).collect()
+    })
+    .collect();
+  // Number of the levels on which the cell of the node has no waypoint, the cells of a level are within the cells of
+  // the level above, so these are the lowest levels
+  let query_level = |id: Idx| {
+    crp
+      .levels
+      .iter()
+      .zip(&waypoint_cells)
+      .take_while(|(partition, cells)| !cells.contains(&partition.cell(id)))
+      .count()
+  };
+
+  let mut search: SearchSpace<G::Weight, OverlayNode> = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    search.init(Arrived(*id));
+  }
+  let target_costs: HashMap<Idx, G::Weight> = to
+    .snapped
+    .iter()
+    .map(|SnappedOnEdge(_, id)| (*id, graph.transition_weight(*id, *id)))
+    .collect();
+
+  let mut best: Option<(G::Weight, Idx)> = None;
+  while let Some((node, key)) = search.pop() {
+    if let Some((best_cost, _)) = best {
+      if key >= best_cost {
+        break;
+      }
+    }
+    if search.cost(node) != Some(key) {
+      continue;
+    }
+    match node {
+      Arrived(id) => {
+        if let Some(target_cost) = target_costs.get(&id) {
+          match best {
+            Some((best_cost, _)) if best_cost <= key + *target_cost => {}
+            _ => best = Some((key + *target_cost, id)),
+          }
+        }
+        // A node reached in a cell without a waypoint is an entry of the cell
+        match query_level(id) {
+          0 => {
+            for next in neighbors_forward(graph, id) {
+              let next_cost = key + graph.transition_weight(id, next);
+              search.relax_with_key(Arrived(next), node, next_cost, next_cost);
+            }
+          }
+          level => crp.relax_clique(metric, level - 1, id, key, &mut search),
+        }
+      }
+      Leaving(id) => crp.relax_cut_transitions(graph, query_level(id) - 1, id, key, &mut search, None),
+    }
+  }
+
+  best.map(|(cost, target)| {
+    let nodes: Vec<_> = search.unwind(Arrived(target)).into_iter().rev().collect();
+    let mut ids = Vec::new();
+    if let Arrived(origin) = nodes[0] {
+      ids.push(origin);
+    }
+    for pair in nodes.windows(2) {
+      match (pair[0], pair[1]) {
+        (Arrived(entry), Leaving(exit)) => crp.unpack(graph, query_level(entry) - 1, entry, exit, &mut ids),
+        (_, Arrived(id)) => ids.push(id),
+        (Leaving(_), Leaving(_)) => unreachable!("Cliques only lead from an entry to an exit"),
+      }
+    }
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+    let destination_candidate = to.snapped.iter().position(|s| s.1 == target).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::route::route;
+  use super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  // Cost of leaving a node is the node data
+  fn node_cost(from: &u32, _to: &u32) -> u32 {
+    *from
+  }
+
+  // Costs more to turn onto some of the nodes
+  fn turn_cost(from: &u32, to: &u32) -> u32 {
+    *from + *to % 3
+  }
+
+  #[test]
+  fn test_same_cost_as_dijkstra() {
+    // Grid of 12 x 12 nodes with transitions to the right and down neighbors, and back on every other row and column
+    let side = 12;
+    let mut edges = Vec::new();
+    for y in 0..side {
+      for x in 0..side - 1 {
+        edges.push((y * side + x, y * side + x + 1));
+        edges.push((x * side + y, (x + 1) * side + y));
+        if y % 2 == 0 {
+          edges.push((y * side + x + 1, y * side + x));
+          edges.push(((x + 1) * side + y, x * side + y));
+        }
+      }
+    }
+    let data: Vec<u32> = (0..side * side).map(|id| 1 + (id * 7 % 5) as u32).collect();
+    let graph = graph_from_data_and_edges(data, edges);
+    let nodes = side * side;
+    let finest = Partition::grow(&graph, nodes, 6);
+    let middle = finest.coarsen(&graph, 24);
+    let top = middle.coarsen(&graph, 72);
+    let crp = Crp::new(&graph, vec![finest, middle, top]);
+    assert_eq!(crp.number_of_levels(), 3);
+
+    // The same overlay is customized again for other weights
+    let costs: [fn(&u32, &u32) -> u32; 2] = [node_cost, turn_cost];
+    for cost in costs.iter() {
+      let weighted = (&graph, *cost);
+      let metric = crp.customize(weighted);
+      for from in (0..nodes as u32).step_by(5) {
+        for to in (0..nodes as u32).step_by(3) {
+          let expected = route(weighted, &matched(from), &matched(to));
+          let actual = route_crp(weighted, &crp, &metric, &matched(from), &matched(to));
+          assert_eq!(actual.as_ref().map(|r| r.cost), expected.as_ref().ok().map(|r| r.cost));
+          if let Some(actual) = actual {
+            assert_eq!((actual.ids[0], *actual.ids.last().unwrap()), (from, to));
+            let last = *actual.ids.last().unwrap();
+            let transitions = actual.ids.windows(2).map(|pair| weighted.transition_weight(pair[0], pair[1]));
+            assert_eq!(transitions.sum::<u32>() + weighted.transition_weight(last, last), actual.cost);
+            for pair in actual.ids.windows(2) {
+              assert!(neighbors_forward(weighted, pair[0]).any(|next| next == pair[1]));
+            }
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "whole cells")]
+  fn test_levels_must_be_nested() {
+    let graph = graph_from_data_and_edges(vec![0; 4], vec![(0, 1), (1, 2), (2, 3)]);
+    Crp::new(&graph, vec![Partition::new(vec![0, 0, 1, 1]), Partition::new(vec![0, 1, 1, 1])]);
+  }
+}
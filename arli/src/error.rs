@@ -0,0 +1,57 @@
+//! Errors returned by the public APIs of the crate.
+
+use crate::matrix::MatrixError;
+use crate::progress::Cancelled;
+use crate::trip::TripError;
+use thiserror::Error;
+
+/// Reason a routing, snapping or loading request failed.
+///
+/// The errors of the modules with their own error type, like [`MatrixError`], convert into it, so a caller using
+/// several of them handles a single type. The searches return [`Error::NoRoute`] if the destination can't be reached
+/// and the functions snapping or connecting the waypoints return [`Error::NotSnapped`] for a waypoint off the graph.
+#[derive(Debug, Error)]
+pub enum Error {
+  /// The destination can't be reached from the origin
+  #[error("No route found between the waypoints")]
+  NoRoute,
+  /// No segment accepted for the waypoint within the snapping radius
+  #[error("Could not find a matching segment for the waypoint")]
+  NotSnapped,
+  /// The request is larger than the configured limits
+  #[error(transparent)]
+  LimitExceeded(#[from] MatrixError),
+  #[error(transparent)]
+  Trip(#[from] TripError),
+  #[error(transparent)]
+  Cancelled(#[from] Cancelled),
+  /// Stored graph can't be read back, e.g. it's truncated or written by an incompatible version
+  #[error("Corrupt graph: {0}")]
+  CorruptGraph(String),
+  /// Preprocessed data is built from another graph than the one it's loaded for, e.g. after the graph was rebuilt
+  #[error("{0} isn't built from this graph")]
+  GraphMismatch(String),
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_error_conversions() {
+    let limit: Error = MatrixError::TooManyPairs { pairs: 60, max: 50 }.into();
+    assert!(matches!(limit, Error::LimitExceeded(MatrixError::TooManyPairs { pairs: 60, max: 50 })));
+    assert_eq!(limit.to_string(), "Too many waypoint pairs: 60, at most 50 are allowed");
+    assert!(matches!(Error::from(TripError::Unreachable), Error::Trip(TripError::Unreachable)));
+    assert!(matches!(Error::from(Cancelled), Error::Cancelled(Cancelled)));
+    assert_eq!(Error::CorruptGraph("truncated".to_string()).to_string(), "Corrupt graph: truncated");
+    assert_eq!(
+      Error::GraphMismatch("berlin.graph.cch".to_string()).to_string(),
+      "berlin.graph.cch isn't built from this graph"
+    );
+  }
+}
@@ -0,0 +1,274 @@
+//! Routes of electric vehicles, which must not run out of charge on the way and may stop at charging stations.
+//!
+//! The energy of a transition is a signed weight in Wh, negative where the vehicle recovers more energy than it spends,
+//! e.g. down a long descent. The battery can't be charged above its capacity on the way, so the charge at a node isn't
+//! a sum along the path and the search keeps the routes to a node which are either faster or arrive with more charge
+//! than the others.
+
+use crate::graph::*;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::ParetoSearchSpace;
+use crate::waypoint::*;
+
+use std::collections::{HashMap, HashSet};
+
+// Acceleration of gravity, in m/s²
+const GRAVITY: f32 = 9.81;
+
+/// Energy a vehicle spends on the roads, see [`EnergyModel::energy_wh`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnergyModel {
+  /// Energy spent on a kilometer of a flat road, in Wh
+  pub consumption_wh_per_km: f32,
+  /// Mass of the vehicle with its load, in kg
+  pub mass_kg: f32,
+  /// Share of the potential energy of a descent recovered by the regenerative braking, between 0 and 1
+  pub recuperation: f32,
+}
+
+impl EnergyModel {
+  /// Energy spent on a road of the length climbing by `climb_m` meters, in Wh. A descent recovers a part of its
+  /// potential energy, so the energy is negative where the descent is steep enough.
+  ///
+  /// The roads have no elevation yet, a climb of 0 gives the energy of a flat road.
+  pub fn energy_wh(&self, length_m: f32, climb_m: f32) -> i32 {
+    let potential = self.mass_kg * GRAVITY * climb_m / 3600.0;
+    let potential = if potential < 0.0 { potential * self.recuperation } else { potential };
+    (self.consumption_wh_per_km * length_m / 1000.0 + potential).round() as i32
+  }
+}
+
+/// State of the battery at the departure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Battery {
+  pub capacity_wh: i32,
+  /// Charge at the departure
+  pub charge_wh: i32,
+  /// Charge the vehicle must keep all the way, e.g. to reach a charger after the arrival
+  pub reserve_wh: i32,
+}
+
+/// Charging station at a node, e.g. one of a user provided list of the stations matched to the graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargingStation<N> {
+  pub node: N,
+  pub power_kw: f32,
+}
+
+impl<N> ChargingStation<N> {
+  /// Seconds it takes to charge the energy at the full power of the station
+  pub fn charging_time_s(&self, energy_wh: i32) -> f32 {
+    energy_wh as f32 / self.power_kw * 3.6
+  }
+}
+
+/// Stop of an [`EvRoute`] at a charging station, the vehicle charges to the full capacity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargingStop<W, N> {
+  pub node: N,
+  /// Index of the station in the stations of the search
+  pub station: usize,
+  /// Energy charged, in Wh
+  pub energy_wh: i32,
+  pub duration: W,
+}
+
+pub struct EvRoute<W: Weight, N: Identifier> {
+  /// Route including the charging stops, its cost is the travel time plus the time of the charging
+  pub route: Route<W, N>,
+  pub charging_stops: Vec<ChargingStop<W, N>>,
+  /// Charge left at the arrival, in Wh
+  pub arrival_charge_wh: i32,
+}
+
+/// Finds the fastest route of an electric vehicle which never runs below the reserve of the battery.
+///
+/// `energy` weights the transitions in Wh, like the graph weights them in time: a node is paid for when the route
+/// leaves it. The vehicle may charge to the full capacity at the `stations`, `charging_time` gives the time a station
+/// takes to charge the energy. Charging to the full capacity is what the search considers, a route charging less at
+/// some of the stations can be faster.
+pub fn route_ev<G, E, C>(
+  graph: G,
+  energy: E,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  battery: &Battery,
+  stations: &[ChargingStation<G::NodeId>],
+  charging_time: C,
+) -> Option<EvRoute<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  E: Weighted<Weight = i32, NodeId = G::NodeId>,
+  C: Fn(&ChargingStation<G::NodeId>, i32) -> G::Weight,
+{
+  // Labels are the time and the energy missing to the full capacity, so that both are better when lower
+  let max_missing = battery.capacity_wh - battery.reserve_wh;
+  let missing = battery.capacity_wh - battery.charge_wh;
+  if missing > max_missing {
+    return None;
+  }
+  let spend = |from: G::NodeId, to: G::NodeId, missing: i32| (missing + energy.transition_weight(from, to)).max(0);
+
+  let mut search: ParetoSearchSpace<Bicriteria<G::Weight, i32>, G::NodeId> = ParetoSearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    search.init_with_cost(*id, Bicriteria(Default::default(), missing));
+  }
+  let targets: HashSet<G::NodeId> = to.snapped.iter().map(|SnappedOnEdge(_, id)| *id).collect();
+  let station_at: HashMap<G::NodeId, usize> =
+    stations.iter().enumerate().rev().map(|(k, station)| (station.node, k)).collect();
+
+  // Arrival time and missing energy, destination node and label of the fastest route so far
+  let mut best: Option<(G::Weight, i32, G::NodeId, usize)> = None;
+  while let Some((id, label, Bicriteria(time, missing))) = search.pop() {
+    if let Some((best_time, _, _, _)) = best {
+      if time >= best_time {
+        break;
+      }
+    }
+    if targets.contains(&id) {
+      let arrival = time + graph.transition_weight(id, id);
+      let arrival_missing = spend(id, id, missing);
+      match best {
+        Some((best_time, _, _, _)) if best_time <= arrival => {}
+        _ if arrival_missing > max_missing => {}
+        _ => best = Some((arrival, arrival_missing, id, label)),
+      }
+    }
+    // Charging is a label of the same node, the path of the label has the node twice
+    if let Some(k) = station_at.get(&id) {
+      if missing > 0 {
+        search.relax(id, id, label, Bicriteria(time + charging_time(&stations[*k], missing), 0));
+      }
+    }
+    for target_id in neighbors_forward(graph, id) {
+      let target_missing = spend(id, target_id, missing);
+      if target_missing <= max_missing {
+        let cost = Bicriteria(time + graph.transition_weight(id, target_id), target_missing);
+        search.relax(target_id, id, label, cost);
+      }
+    }
+  }
+
+  best.map(|(cost, arrival_missing, id, label)| {
+    let mut path = vec![(id, label)];
+    let mut current = search.parent(id, label);
+    while let Some((node, label)) = current {
+      path.push((node, label));
+      current = search.parent(node, label);
+    }
+    path.reverse();
+
+    let mut ids = vec![path[0].0];
+    let mut charging_stops = Vec::new();
+    for pair in path.windows(2) {
+      let node = pair[1].0;
+      if node != pair[0].0 {
+        ids.push(node);
+        continue;
+      }
+      let Bicriteria(_, missing) = search.cost(node, pair[0].1);
+      let station = station_at[&node];
+      charging_stops.push(ChargingStop {
+        node,
+        station,
+        energy_wh: missing,
+        duration: charging_time(&stations[station], missing),
+      });
+    }
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+    let destination_candidate = to.snapped.iter().position(|s| s.1 == id).unwrap();
+    EvRoute {
+      route: Route {
+        cost,
+        origin_candidate,
+        destination_candidate,
+        num_resolved: search.num_settled(),
+        quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+        ids,
+      },
+      charging_stops,
+      arrival_charge_wh: battery.capacity_wh - arrival_missing,
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  #[test]
+  fn test_energy_model() {
+    let model = EnergyModel {
+      consumption_wh_per_km: 150.0,
+      mass_kg: 2000.0,
+      recuperation: 0.6,
+    };
+    assert_eq!(model.energy_wh(1000.0, 0.0), 150);
+    assert_eq!(model.energy_wh(1000.0, 50.0), 150 + 273);
+    assert_eq!(model.energy_wh(1000.0, -100.0), 150 - 327);
+  }
+
+  #[test]
+  fn test_route_ev() {
+    // Nodes are (time, energy): node 1 is fast but takes more energy than the battery has left, node 2 is slow
+    let data = vec![(1, 1), (10, 60), (30, 20), (1, 0)];
+    let graph = graph_from_data_and_edges(data, vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let time = (&graph, |from: &(u32, i32), _to: &(u32, i32)| from.0);
+    let energy = (&graph, |from: &(u32, i32), _to: &(u32, i32)| from.1);
+    let battery = Battery {
+      capacity_wh: 100,
+      charge_wh: 50,
+      reserve_wh: 0,
+    };
+    let minute_per_10_wh = |_: &ChargingStation<u32>, energy_wh: i32| energy_wh as u32 / 10;
+
+    let ev_route = route_ev(time, energy, &matched(0), &matched(3), &battery, &[], minute_per_10_wh).unwrap();
+    assert_eq!(ev_route.route.ids, vec![0, 2, 3]);
+    assert_eq!(ev_route.route.cost, 32);
+    assert!(ev_route.charging_stops.is_empty());
+    assert_eq!(ev_route.arrival_charge_wh, 29);
+
+    // Charging to the full capacity at the origin makes the fast route possible
+    let stations = [ChargingStation { node: 0, power_kw: 50.0 }];
+    let ev_route = route_ev(time, energy, &matched(0), &matched(3), &battery, &stations, minute_per_10_wh).unwrap();
+    assert_eq!(ev_route.route.ids, vec![0, 1, 3]);
+    assert_eq!(ev_route.route.cost, 5 + 1 + 10 + 1);
+    let stop = ChargingStop {
+      node: 0,
+      station: 0,
+      energy_wh: 50,
+      duration: 5,
+    };
+    assert_eq!(ev_route.charging_stops, vec![stop]);
+    assert_eq!(ev_route.arrival_charge_wh, 39);
+
+    // The slow route would leave less than the reserve
+    let reserve = Battery { reserve_wh: 35, ..battery };
+    assert!(route_ev(time, energy, &matched(0), &matched(3), &reserve, &[], minute_per_10_wh).is_none());
+  }
+
+  #[test]
+  fn test_recuperation() {
+    // Going down from the origin recovers enough energy for the fast route
+    let data = vec![(1, -40), (10, 60), (30, 20), (1, 0)];
+    let graph = graph_from_data_and_edges(data, vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let time = (&graph, |from: &(u32, i32), _to: &(u32, i32)| from.0);
+    let energy = (&graph, |from: &(u32, i32), _to: &(u32, i32)| from.1);
+    let no_charging = |_: &ChargingStation<u32>, _: i32| 0;
+    let battery = Battery {
+      capacity_wh: 100,
+      charge_wh: 50,
+      reserve_wh: 0,
+    };
+
+    let ev_route = route_ev(time, energy, &matched(0), &matched(3), &battery, &[], no_charging).unwrap();
+    assert_eq!(ev_route.route.ids, vec![0, 1, 3]);
+    assert_eq!(ev_route.arrival_charge_wh, 30);
+
+    // A full battery can't take the recovered energy
+    let full = Battery { charge_wh: 100, ..battery };
+    let ev_route = route_ev(time, energy, &matched(0), &matched(3), &full, &[], no_charging).unwrap();
+    assert_eq!(ev_route.arrival_charge_wh, 40);
+  }
+}
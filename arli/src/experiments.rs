@@ -0,0 +1,214 @@
+//! Tooling for evaluating routing algorithms.
+//!
+//! Queries are generated by Dijkstra rank, the way it's done in the literature: a Dijkstra search is run from a
+//! random source node and the `2^r`-th node it settles becomes the target of the query with rank `r`. Every
+//! configured algorithm is then run for each query and the results are written as CSV, so the search effort of
+//! algorithms can be compared for short and long queries separately.
+
+use crate::error;
+use crate::graph::*;
+use crate::graph_impl::Idx;
+use crate::route::{route, route_bidir, Route};
+use crate::search_space::SearchSpace;
+use crate::spatial::Position;
+use crate::waypoint::MatchedWaypoint;
+
+use std::collections::HashSet;
+use std::io::{Result, Write};
+use std::time::Instant;
+
+/// A query between two nodes with a known Dijkstra rank
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RankQuery<N> {
+  pub source: N,
+  pub target: N,
+  pub rank: u32,
+}
+
+type RouteFn<'a, G> = Box<
+  dyn Fn(
+      G,
+      &MatchedWaypoint<<G as GraphBase>::NodeId>,
+      &MatchedWaypoint<<G as GraphBase>::NodeId>,
+    ) -> error::Result<Route<<G as Weighted>::Weight, <G as GraphBase>::NodeId>>
+    + 'a,
+>;
+
+/// A named routing algorithm under evaluation
+pub struct Algorithm<'a, G: Weighted> {
+  pub name: String,
+  run: RouteFn<'a, G>,
+}
+
+impl<'a, G: Weighted> Algorithm<'a, G> {
+  pub fn new<F>(name: &str, run: F) -> Self
+  where
+    F: Fn(G, &MatchedWaypoint<G::NodeId>, &MatchedWaypoint<G::NodeId>) -> error::Result<Route<G::Weight, G::NodeId>> + 'a,
+  {
+    Self {
+      name: String::from(name),
+      run: Box::new(run),
+    }
+  }
+}
+
+impl<'a, G: 'a + Copy + IntoNeighbors<Forward> + Weighted> Algorithm<'a, G> {
+  /// Plain unidirectional Dijkstra, the baseline for other algorithms
+  pub fn dijkstra() -> Self {
+    Self::new("dijkstra", route::<G>)
+  }
+}
+
+impl<'a, G: 'a + Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted> Algorithm<'a, G> {
+  /// Bidirectional Dijkstra
+  pub fn bidirectional() -> Self {
+    Self::new("bidirectional", route_bidir::<G>)
+  }
+}
+
+/// Picks `count` pseudo-random node ids below `num_nodes`. The same seed always gives the same sample. The sample of
+/// an empty graph is empty.
+pub fn sample_nodes(num_nodes: usize, count: usize, seed: u64) -> Vec<Idx> {
+  if num_nodes == 0 {
+    return Vec::new();
+  }
+  // xorshift64, good enough for picking the sources
+  let mut state = seed.max(1);
+  (0..count)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      (state % num_nodes as u64) as Idx
+    })
+    .collect()
+}
+
+/// Generates queries of rank `0..=max_rank` for every source. Ranks which exceed the number of nodes reachable
+/// from a source are skipped.
+pub fn dijkstra_rank_queries<G>(graph: G, sources: &[G::NodeId], max_rank: u32) -> Vec<RankQuery<G::NodeId>>
+where
+  G: Copy + Weighted + IntoNeighbors<Forward>,
+{
+  let mut queries = Vec::new();
+  for source in sources {
+    let mut search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+    search.init(*source);
+
+    let mut settled = HashSet::new();
+    let mut next_rank = 0;
+    while let Some((id, cost)) = search.min() {
+      // Outdated queue entries are skipped, the node was settled with a lower cost. The source is settled first.
+      let newly_settled = search.cost(id) == Some(cost) && settled.insert(id);
+      // Ranks past the width of `usize` can't be reached, the search settles every node without a match
+      if newly_settled && Some(settled.len() - 1) == 1usize.checked_shl(next_rank) {
+        queries.push(RankQuery {
+          source: *source,
+          target: id,
+          rank: next_rank,
+        });
+        if next_rank == max_rank {
+          break;
+        }
+        next_rank += 1;
+      }
+      search.update(graph);
+    }
+  }
+  queries
+}
+
+/// Runs every algorithm for every query and writes a CSV line with the result of each run
+pub fn run_experiments<G, Out>(
+  graph: G,
+  queries: &[RankQuery<G::NodeId>],
+  algorithms: &[Algorithm<G>],
+  mut out: Out,
+) -> Result<()>
+where
+  G: Copy + Weighted,
+  Out: Write,
+{
+  writeln!(out, "algorithm,source,target,rank,cost,num_resolved,time_us")?;
+  // Node positions are not needed, the algorithms run between nodes
+  let position = Position { x: 0.0, y: 0.0 };
+  for query in queries {
+    let from = MatchedWaypoint::from_node(query.source, position);
+    let to = MatchedWaypoint::from_node(query.target, position);
+    for algorithm in algorithms {
+      let timer = Instant::now();
+      let result = (algorithm.run)(graph, &from, &to);
+      let elapsed = timer.elapsed();
+
+      let (cost, num_resolved) = match result {
+        Ok(route) => (format!("{:?}", route.cost), route.num_resolved),
+        Err(_) => (String::new(), 0),
+      };
+      writeln!(
+        out,
+        "{},{:?},{:?},{},{},{},{}",
+        algorithm.name,
+        query.source,
+        query.target,
+        query.rank,
+        cost,
+        num_resolved,
+        elapsed.as_micros()
+      )?;
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_utils::graph_from_data_and_edges;
+  use super::*;
+
+  #[test]
+  fn test_dijkstra_rank_queries() {
+    let graph = graph_from_data_and_edges(
+      vec![1; 10],
+      vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7), (7, 8), (8, 9)],
+    );
+    let weighted_graph = (&graph, |from: &u32, _to: &u32| *from);
+
+    let queries = dijkstra_rank_queries(weighted_graph, &[0], 4);
+
+    let targets: Vec<_> = queries.iter().map(|q| (q.rank, q.target)).collect();
+    assert_eq!(targets, vec![(0, 1), (1, 2), (2, 4), (3, 8)]);
+    assert_eq!(dijkstra_rank_queries(weighted_graph, &[0], u32::MAX), queries);
+  }
+
+  #[test]
+  fn test_run_experiments() {
+    let graph = graph_from_data_and_edges(vec![1; 4], vec![(0, 1), (1, 2), (2, 3)]);
+    let weighted_graph = (&graph, |from: &u32, _to: &u32| *from);
+
+    let queries = dijkstra_rank_queries(weighted_graph, &[0], 2);
+    let algorithms = vec![
+      Algorithm::dijkstra(),
+      Algorithm::bidirectional(),
+      Algorithm::new("none", |_, _, _| Err(error::Error::NoRoute)),
+    ];
+
+    let mut out = Vec::new();
+    run_experiments(weighted_graph, &queries, &algorithms, &mut out).unwrap();
+
+    let csv = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 1 + queries.len() * algorithms.len());
+    assert!(lines[1].starts_with("dijkstra,0,1,0,2,"));
+    assert!(lines[2].starts_with("bidirectional,0,1,0,2,"));
+    assert!(lines[3].starts_with("none,0,1,0,,0,"));
+  }
+
+  #[test]
+  fn test_sample_nodes() {
+    let sample = sample_nodes(100, 10, 42);
+    assert_eq!(sample.len(), 10);
+    assert!(sample.iter().all(|id| *id < 100));
+    assert_eq!(sample, sample_nodes(100, 10, 42));
+    assert!(sample_nodes(0, 10, 42).is_empty());
+  }
+}
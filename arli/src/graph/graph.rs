@@ -1,18 +1,25 @@
-use std::fmt::Debug;
-use std::hash::Hash;
-use std::iter::Iterator;
+use core::fmt::Debug;
+use core::hash::Hash;
+use core::iter::Iterator;
 
 // ====== Basic traits =====
 
-/// Requirements for a node identifier
-pub trait Identifier: Eq + Hash + Copy + Debug {}
-impl<T> Identifier for T where T: Eq + Hash + Copy + Debug {}
+/// Requirements for a node identifier, the order breaks the ties between nodes of the same cost in the searches
+pub trait Identifier: Ord + Hash + Copy + Debug {}
+impl<T> Identifier for T where T: Ord + Hash + Copy + Debug {}
 
 /// Defines type of the identifier for the graph
 pub trait GraphBase {
   type NodeId: Identifier;
 }
 
+/// Graph which can be shared between threads, e.g. one loaded graph serving the requests of all worker threads.
+///
+/// Implemented for every graph which is `Send + Sync`. Searches only read the graph, so a graph can't be shared
+/// only if it has interior mutability. Use the trait as a bound to catch that at compile time.
+pub trait SyncGraph: GraphBase + Send + Sync {}
+impl<T> SyncGraph for T where T: GraphBase + Send + Sync {}
+
 /// Defines data associated with each node. The data can be used in order to calculate the weight of each edge in weighted graph.
 pub trait GraphData: GraphBase {
   type Data;
@@ -63,7 +70,7 @@ pub fn neighbors_backward<G: IntoNeighbors<Backward>>(
 
 /// A generator of valid node identifiers
 pub trait NodesExtension<NodeId: Identifier> {
-  fn new_node_id(&self) -> Option<NodeId>;
+  fn new_node_id(&mut self) -> Option<NodeId>;
   fn contains(&self, id: NodeId) -> bool;
 }
 
@@ -74,6 +81,16 @@ pub trait Extensible: GraphBase {
   fn new_extension(&self) -> Self::Extension;
 }
 
+/// Maps the nodes added by a graph extension back to the base nodes they were created from, e.g. a waypoint
+/// overlay node to the segment it splits.
+///
+/// Data keyed on the base node ids, like turn restrictions, must be looked up with the base ids to apply to the added
+/// nodes as well.
+pub trait BaseNodeIds: GraphBase {
+  /// The node itself if it's not an added node
+  fn base_id(&self, node_id: Self::NodeId) -> Self::NodeId;
+}
+
 // ====== Blanket implementations =====
 
 impl<'a, G: GraphBase> GraphBase for &'a G {
@@ -124,3 +141,15 @@ impl<G: Extensible, T> Extensible for (G, T) {
     self.0.new_extension()
   }
 }
+
+impl<'a, G: BaseNodeIds> BaseNodeIds for &'a G {
+  fn base_id(&self, node_id: Self::NodeId) -> Self::NodeId {
+    (*self).base_id(node_id)
+  }
+}
+
+impl<G: BaseNodeIds, T> BaseNodeIds for (G, T) {
+  fn base_id(&self, node_id: Self::NodeId) -> Self::NodeId {
+    self.0.base_id(node_id)
+  }
+}
@@ -3,9 +3,11 @@
 //! The module contains traits for defining directed, edge-based and weighted graph.
 //! 
 mod graph;
+#[cfg(feature = "std")]
 mod spatial;
 mod weighted;
 
 pub use graph::*;
+#[cfg(feature = "std")]
 pub use spatial::*;
 pub use weighted::*;
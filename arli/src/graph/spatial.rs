@@ -25,6 +25,14 @@ impl<'a, G: Spatial> Spatial for &'a G {
   }
 }
 
+impl<G: Spatial, T> Spatial for (G, T) {
+  type Nodes = G::Nodes;
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Self::Nodes {
+    self.0.find_nodes(bbox)
+  }
+}
+
 impl<G: IntoGeometry, T> IntoGeometry for (G, T) {
   type P = G::P;
   type Geometry = G::Geometry;
@@ -1,5 +1,5 @@
-use std::fmt::Debug;
-use std::ops::Add;
+use core::fmt::Debug;
+use core::ops::Add;
 use crate::graph::{GraphBase, GraphData};
 
 /// Trait representing an edge weight(cost) in weighted graph
@@ -24,10 +24,73 @@ impl<G: GraphData, W: Weight, C: Fn(&G::Data, &G::Data) -> W> Weighted for (G, C
   }
 }
 
+/// Graph with transition weights depending on the time the transition happens at, e.g. roads which are slower in the
+/// rush hour.
+///
+/// The time is measured in the units of the weight from the reference time of the search, see
+/// [`route_departing`](crate::time_dependent::route_departing) and
+/// [`route_arriving`](crate::time_dependent::route_arriving).
+pub trait TimeDependent: GraphBase {
+  type Weight: Weight;
+  fn transition_weight_at(&self, from: Self::NodeId, to: Self::NodeId, time: Self::Weight) -> Self::Weight;
+}
+
+impl<'a, G: TimeDependent> TimeDependent for &'a G {
+  type Weight = G::Weight;
+  fn transition_weight_at(&self, from: Self::NodeId, to: Self::NodeId, time: Self::Weight) -> Self::Weight {
+    (*self).transition_weight_at(from, to, time)
+  }
+}
+
 impl<'a, G: Weighted> Weighted for &'a G
 {
   type Weight = G::Weight;
   fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
     (*self).transition_weight(from, to)
   }
-}
\ No newline at end of file
+}
+/// Weight which can be multiplied by a real factor, e.g. to make some of the nodes more expensive
+pub trait ScalableWeight: Weight {
+  /// The weight multiplied by the factor, rounded to the nearest weight
+  fn scale(self, factor: f32) -> Self;
+}
+
+macro_rules! scalable_weight {
+  ($($t:ty),*) => {
+    $(impl ScalableWeight for $t {
+      fn scale(self, factor: f32) -> Self {
+        // Rounds half away from zero like `f64::round`, which isn't available without std
+        let scaled = self as f64 * factor as f64;
+        (if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 }) as $t
+      }
+    })*
+  };
+}
+
+scalable_weight!(i32, i64, u32, u64);
+
+/// Weight of two criteria searched at once, e.g. the travel time and the toll money. The weights are ordered by the
+/// first criterion and then by the second one, so any search finds the cheapest route by the first criterion, and
+/// [`route_pareto`](crate::route::route_pareto) finds all the routes which are the cheapest by some trade-off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bicriteria<A, B>(pub A, pub B);
+
+impl<A: Add<Output = A>, B: Add<Output = B>> Add for Bicriteria<A, B> {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    Bicriteria(self.0 + other.0, self.1 + other.1)
+  }
+}
+
+/// Weight of several criteria, which can be better by some of them and worse by the others
+pub trait ParetoWeight: Weight {
+  /// The weight is at least as good as the other one by every criterion
+  fn dominates(&self, other: &Self) -> bool;
+}
+
+impl<A: Weight, B: Weight> ParetoWeight for Bicriteria<A, B> {
+  fn dominates(&self, other: &Self) -> bool {
+    self.0 <= other.0 && self.1 <= other.1
+  }
+}
@@ -0,0 +1,162 @@
+use crate::graph::*;
+use crate::spatial::{bounding_box, polyline_intersects_polygon, BoundingBox, Polygon, Position};
+use std::collections::HashSet;
+
+/// Graph adaptor which hides the nodes with a geometry inside or crossing any of the areas, e.g. a city center a truck
+/// must not drive through.
+///
+/// The nodes are found when the adaptor is created, by a spatial query of the bounding box of each area and a precise
+/// check of the node geometry. Hidden nodes are never returned as neighbors or as spatial query results, so a waypoint
+/// matched on top of the adaptor is never in an area. Any cost function can be applied to the wrapped graph.
+pub struct AvoidAreas<G: GraphBase> {
+  graph: G,
+  avoided: HashSet<G::NodeId>,
+}
+
+impl<G: Copy + Spatial + IntoGeometry> AvoidAreas<G> {
+  pub fn new(graph: G, areas: &[Polygon]) -> Self {
+    let mut avoided = HashSet::new();
+    for area in areas {
+      let bbox = match bounding_box(area.exterior().0.iter().cloned()) {
+        Some(bbox) => bbox,
+        None => continue,
+      };
+      for id in graph.find_nodes(&bbox) {
+        let geometry: Vec<Position> = graph.geometry(id).map(Into::into).collect();
+        if polyline_intersects_polygon(&geometry, area) {
+          avoided.insert(id);
+        }
+      }
+    }
+    Self { graph, avoided }
+  }
+}
+
+impl<G: GraphBase> AvoidAreas<G> {
+  pub fn avoids(&self, node: G::NodeId) -> bool {
+    self.avoided.contains(&node)
+  }
+
+  /// Number of the hidden nodes
+  pub fn number_of_avoided(&self) -> usize {
+    self.avoided.len()
+  }
+}
+
+/// Iterator over the neighbors outside of the [`AvoidAreas`] areas
+pub struct UnavoidedNodes<'a, N, Nodes> {
+  avoided: &'a HashSet<N>,
+  nodes: Nodes,
+}
+
+impl<'a, N: Identifier, Nodes: Iterator<Item = N>> Iterator for UnavoidedNodes<'a, N, Nodes> {
+  type Item = N;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let avoided = self.avoided;
+    self.nodes.find(|id| !avoided.contains(id))
+  }
+}
+
+impl<G: GraphBase> GraphBase for AvoidAreas<G> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData> GraphData for AvoidAreas<G> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<'a, Direction, G> IntoNeighbors<Direction> for &'a AvoidAreas<G>
+where
+  Direction: ForwardOrBackward,
+  G: Copy + IntoNeighbors<Direction>,
+{
+  type Neighbors = UnavoidedNodes<'a, G::NodeId, <G as IntoNeighbors<Direction>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    UnavoidedNodes {
+      avoided: &self.avoided,
+      nodes: <G as IntoNeighbors<Direction>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G: Copy + IntoGeometry> IntoGeometry for &AvoidAreas<G> {
+  type P = G::P;
+  type Geometry = G::Geometry;
+
+  fn geometry(self, node: Self::NodeId) -> Self::Geometry {
+    self.graph.geometry(node)
+  }
+}
+
+impl<G: Spatial> Spatial for AvoidAreas<G> {
+  type Nodes = Vec<G::NodeId>;
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Self::Nodes {
+    self
+      .graph
+      .find_nodes(bbox)
+      .into_iter()
+      .filter(|id| !self.avoids(*id))
+      .collect()
+  }
+}
+
+impl<G: Weighted> Weighted for AvoidAreas<G> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::test_utils::{graph_from_intersections, simple_segment_length_cost};
+  use super::super::super::waypoint::MatchedWaypoint;
+  use super::*;
+  use geo::LineString;
+
+  fn square(x: f32, y: f32, size: f32) -> Polygon {
+    let ring = vec![(x - size, y - size), (x + size, y - size), (x + size, y + size), (x - size, y + size)];
+    Polygon::new(LineString::from(ring), vec![])
+  }
+
+  #[test]
+  fn test_avoid_areas() {
+    // A street going east splits at B into a short way north through C and a longer one south through E
+    let positions = vec![
+      Position { x: 13.40, y: 52.5 },
+      Position { x: 13.41, y: 52.5 },
+      Position { x: 13.42, y: 52.505 },
+      Position { x: 13.43, y: 52.5 },
+      Position { x: 13.42, y: 52.49 },
+      Position { x: 13.44, y: 52.5 },
+    ];
+    let graph = graph_from_intersections(positions, vec![(0, 1), (1, 2), (2, 3), (1, 4), (4, 3), (3, 5)]);
+    let weighted = (&graph, simple_segment_length_cost);
+    let from = MatchedWaypoint::from_node(0, Position { x: 13.40, y: 52.5 });
+    let to = MatchedWaypoint::from_node(5, Position { x: 13.44, y: 52.5 });
+    assert_eq!(route(weighted, &from, &to).unwrap().ids, vec![0, 1, 2, 5]);
+
+    // Both segments through C end in the area
+    let around_c = AvoidAreas::new(weighted, &[square(13.42, 52.505, 0.001)]);
+    assert_eq!(around_c.number_of_avoided(), 2);
+    assert_eq!(route(&around_c, &from, &to).unwrap().ids, vec![0, 3, 4, 5]);
+    let bbox = BoundingBox::new(Position { x: 13.39, y: 52.48 }, Position { x: 13.45, y: 52.51 });
+    assert_eq!(around_c.find_nodes(&bbox).len(), 4);
+
+    // The segment from E is crossed by the area, none of its points are in it
+    let areas = [square(13.42, 52.505, 0.001), square(13.425, 52.495, 0.001)];
+    let both_ways = AvoidAreas::new(weighted, &areas);
+    assert!(both_ways.avoids(4));
+    assert!(!both_ways.avoids(3));
+    assert!(route(&both_ways, &from, &to).is_err());
+  }
+}
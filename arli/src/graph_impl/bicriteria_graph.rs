@@ -0,0 +1,54 @@
+use crate::graph::*;
+
+/// Graph adaptor which weights every transition by two criteria, the weights of two graphs with the same nodes.
+///
+/// Unlike [`TradeOffGraph`](super::TradeOffGraph), which prices the second criterion in the units of the first one,
+/// the criteria are kept apart, so [`route_pareto`](crate::route::route_pareto) can find the routes of every
+/// trade-off between them, e.g. the fastest route, the toll-free one and the ones in between.
+#[derive(Clone, Copy)]
+pub struct BicriteriaGraph<G, S> {
+  graph: G,
+  secondary: S,
+}
+
+impl<G, S> BicriteriaGraph<G, S>
+where
+  G: Copy + Weighted,
+  S: Copy + Weighted<NodeId = G::NodeId>,
+{
+  pub fn new(graph: G, secondary: S) -> Self {
+    Self { graph, secondary }
+  }
+}
+
+impl<G: GraphBase, S> GraphBase for BicriteriaGraph<G, S> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: IntoNeighbors<Forward>, S> IntoNeighbors<Forward> for BicriteriaGraph<G, S> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, S> IntoNeighbors<Backward> for BicriteriaGraph<G, S> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G, S> Weighted for BicriteriaGraph<G, S>
+where
+  G: Weighted,
+  S: Weighted<NodeId = G::NodeId>,
+{
+  type Weight = Bicriteria<G::Weight, S::Weight>;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    Bicriteria(self.graph.transition_weight(from, to), self.secondary.transition_weight(from, to))
+  }
+}
@@ -0,0 +1,107 @@
+use crate::graph::*;
+
+/// Graph adaptor which hides the nodes blocked by a predicate over the node ids.
+///
+/// Unlike [`FilteredGraph`](super::FilteredGraph), which decides by the node data, the predicate gets the node id, so
+/// it can block nodes by attributes kept apart from the graph, e.g. the segments of a road closed for construction.
+/// Blocked nodes are never returned as neighbors, the search can still start at one, so drop the waypoints matched to
+/// them first.
+#[derive(Clone, Copy)]
+pub struct BlockedGraph<G, B> {
+  graph: G,
+  blocked: B,
+}
+
+impl<G: GraphBase, B: Copy + Fn(G::NodeId) -> bool> BlockedGraph<G, B> {
+  pub fn new(graph: G, blocked: B) -> Self {
+    Self { graph, blocked }
+  }
+}
+
+/// Iterator over the neighbors not blocked by the [`BlockedGraph`] predicate
+pub struct UnblockedNodes<B, Nodes> {
+  blocked: B,
+  nodes: Nodes,
+}
+
+impl<Id, B: Fn(Id) -> bool, Nodes: Iterator<Item = Id>> Iterator for UnblockedNodes<B, Nodes>
+where
+  Id: Copy,
+{
+  type Item = Id;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let blocked = &self.blocked;
+    self.nodes.find(|id| !blocked(*id))
+  }
+}
+
+impl<G: GraphBase, B> GraphBase for BlockedGraph<G, B> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData, B> GraphData for BlockedGraph<G, B> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<G: IntoNeighbors<Forward>, B: Copy + Fn(G::NodeId) -> bool> IntoNeighbors<Forward> for BlockedGraph<G, B> {
+  type Neighbors = UnblockedNodes<B, <G as IntoNeighbors<Forward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    UnblockedNodes {
+      blocked: self.blocked,
+      nodes: <G as IntoNeighbors<Forward>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, B: Copy + Fn(G::NodeId) -> bool> IntoNeighbors<Backward> for BlockedGraph<G, B> {
+  type Neighbors = UnblockedNodes<B, <G as IntoNeighbors<Backward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    UnblockedNodes {
+      blocked: self.blocked,
+      nodes: <G as IntoNeighbors<Backward>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G: Weighted, B> Weighted for BlockedGraph<G, B> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::spatial::Position;
+  use super::super::super::test_utils::graph_from_data_and_edges;
+  use super::super::super::waypoint::MatchedWaypoint;
+  use super::*;
+
+  #[test]
+  fn test_blocked_nodes() {
+    // 0 -> 1 -> 3 is shorter than 0 -> 2 -> 3
+    let graph = graph_from_data_and_edges(vec![1, 1, 5, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+    let from = MatchedWaypoint::from_node(0, Position { x: 0.0, y: 0.0 });
+    let to = MatchedWaypoint::from_node(3, Position { x: 0.0, y: 0.0 });
+
+    let open = BlockedGraph::new(weighted, |_: u32| false);
+    assert_eq!(route(open, &from, &to).unwrap().ids, vec![0, 1, 3]);
+
+    let closed = BlockedGraph::new(weighted, |id: u32| id == 1);
+    assert_eq!(route(closed, &from, &to).unwrap().ids, vec![0, 2, 3]);
+    assert!(neighbors_backward(closed, 3).all(|id| id != 1));
+
+    let all_closed = BlockedGraph::new(weighted, |id: u32| id == 1 || id == 2);
+    assert!(route(all_closed, &from, &to).is_err());
+  }
+}
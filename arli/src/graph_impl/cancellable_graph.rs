@@ -0,0 +1,86 @@
+use crate::graph::*;
+use crate::progress::CancellationToken;
+
+use std::iter::Take;
+
+/// Graph adaptor which hides the neighbors of all nodes once the token is cancelled.
+///
+/// A search on top of it settles the nodes it has already reached and ends, so it's abandoned quickly whatever the
+/// search is, e.g. a route, a matrix row or a time dependent search. The result of a cancelled search is incomplete,
+/// check the token once the search returns and drop it.
+///
+/// A token with a deadline cancels itself once it's past, so the adaptor also bounds the time a search takes. The
+/// deadline is checked in batches of expansions, see [`CancellationToken::is_cancelled_in_batches`].
+#[derive(Clone, Copy)]
+pub struct CancellableGraph<'a, G> {
+  graph: G,
+  token: &'a CancellationToken,
+}
+
+impl<'a, G: Copy> CancellableGraph<'a, G> {
+  pub fn new(graph: G, token: &'a CancellationToken) -> Self {
+    Self { graph, token }
+  }
+
+  // Neighbors are cut off rather than skipped, the search doesn't look at the rest of them
+  fn visible(&self) -> usize {
+    if self.token.is_cancelled_in_batches() {
+      0
+    } else {
+      usize::MAX
+    }
+  }
+}
+
+impl<'a, G: GraphBase> GraphBase for CancellableGraph<'a, G> {
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: Copy + IntoNeighbors<Forward>> IntoNeighbors<Forward> for CancellableGraph<'a, G> {
+  type Neighbors = Take<<G as IntoNeighbors<Forward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node).take(self.visible())
+  }
+}
+
+impl<'a, G: Copy + IntoNeighbors<Backward>> IntoNeighbors<Backward> for CancellableGraph<'a, G> {
+  type Neighbors = Take<<G as IntoNeighbors<Backward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node).take(self.visible())
+  }
+}
+
+impl<'a, G: Weighted> Weighted for CancellableGraph<'a, G> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::spatial::Position;
+  use super::super::super::test_utils::graph_from_data_and_edges;
+  use super::super::super::waypoint::MatchedWaypoint;
+  use super::*;
+
+  #[test]
+  fn test_cancelled_search() {
+    let graph = graph_from_data_and_edges(vec![1, 1, 1], vec![(0, 1), (1, 2)]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+    let from = MatchedWaypoint::from_node(0, Position { x: 0.0, y: 0.0 });
+    let to = MatchedWaypoint::from_node(2, Position { x: 0.0, y: 0.0 });
+    let token = CancellationToken::new();
+
+    let cancellable = CancellableGraph::new(weighted, &token);
+    assert_eq!(route(cancellable, &from, &to).unwrap().ids, vec![0, 1, 2]);
+
+    token.cancel();
+    // Only the origin is settled
+    assert!(route(cancellable, &from, &to).is_err());
+  }
+}
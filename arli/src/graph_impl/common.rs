@@ -1,73 +1,279 @@
 use crate::graph::NodesExtension;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
 use serde::{Deserialize, Serialize};
-use std::cell::Cell;
 
 // Node id
 pub type Idx = u32;
 
-/// Iterator over elements of the target array defined by RangeRef
-pub struct RefIterator<'a, T> {
-  items: &'a Vec<T>,
-  next: usize,
-  last: usize,
+/// Half-open range `start..end` of the elements of a contiguous array, iterated from `start` up to `end - 1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForwardRange {
+  pub start: Idx,
+  pub end: Idx,
+}
+
+/// Half-open range `start..end` of the elements of a contiguous array, iterated from `end - 1` down to `start`, e.g.
+/// the geometry of a road traveled in the opposite direction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReverseRange {
+  pub start: Idx,
+  pub end: Idx,
+}
+
+/// Range of the elements as stored in the graph, either a [`ForwardRange`] or a [`ReverseRange`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElementRange {
+  Forward(ForwardRange),
+  Reverse(ReverseRange),
 }
 
-/// A  [start, end) range of elements in the contiguous array.
+/// Stored form of an [`ElementRange`].
+///
+/// `RangeRef(first, last)` with `first <= last` is the forward range `first..last`, otherwise it's the reverse range
+/// `last + 1..first + 1`. So a reverse range can't start at the first element of the array, keep a sentinel element
+/// in front of the reversed ones.
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct RangeRef(pub Idx, pub Idx);
 
+impl RangeRef {
+  pub fn forward(range: ForwardRange) -> Self {
+    debug_assert!(range.start <= range.end, "Forward range {:?} ends before it starts", range);
+    RangeRef(range.start, range.end)
+  }
+
+  /// Panics if the range starts at the first element of the array, which can't be stored
+  pub fn reverse(range: ReverseRange) -> Self {
+    debug_assert!(range.start <= range.end, "Reverse range {:?} ends before it starts", range);
+    assert!(range.start > 0, "Reverse range {:?} starts at the first element", range);
+    RangeRef(range.end - 1, range.start - 1)
+  }
+
+  pub fn range(&self) -> ElementRange {
+    if self.0 <= self.1 {
+      ElementRange::Forward(ForwardRange {
+        start: self.0,
+        end: self.1,
+      })
+    } else {
+      ElementRange::Reverse(ReverseRange {
+        start: self.1 + 1,
+        end: self.0 + 1,
+      })
+    }
+  }
+}
+
+/// Iterator over the elements of an array in a [`ForwardRange`] or a [`ReverseRange`].
+///
+/// A range ending before it starts or beyond the end of the array panics, instead of returning the wrong elements.
+pub struct RefIterator<'a, T> {
+  items: core::slice::Iter<'a, T>,
+  reverse: bool,
+}
+
 impl<'a, T: Copy> Iterator for RefIterator<'a, T> {
   type Item = T;
 
   fn next(&mut self) -> Option<Self::Item> {
-    if self.next == self.last {
-      return None;
-    }
-    let index = self.next;
-    if self.next < self.last {
-      self.next += 1;
-    }
-    if self.next > self.last {
-      self.next -= 1;
-    }
-    Some(self.items[index])
+    let item = if self.reverse {
+      self.items.next_back()
+    } else {
+      self.items.next()
+    };
+    item.copied()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.items.size_hint()
   }
 }
 
 impl<'a, T: Copy> RefIterator<'a, T> {
-  pub fn new(items: &'a Vec<T>, first: Idx, last: Idx) -> Self {
+  pub fn forward(items: &'a [T], range: ForwardRange) -> Self {
+    RefIterator {
+      items: Self::slice(items, range.start, range.end).iter(),
+      reverse: false,
+    }
+  }
+
+  pub fn reverse(items: &'a [T], range: ReverseRange) -> Self {
     RefIterator {
-      items: items,
-      next: first as usize,
-      last: last as usize,
+      items: Self::slice(items, range.start, range.end).iter(),
+      reverse: true,
+    }
+  }
+
+  pub fn from_range(items: &'a [T], range: &RangeRef) -> Self {
+    match range.range() {
+      ElementRange::Forward(range) => RefIterator::forward(items, range),
+      ElementRange::Reverse(range) => RefIterator::reverse(items, range),
     }
   }
 
-  pub fn from_range(items: &'a Vec<T>, range: &RangeRef) -> Self {
-    RefIterator::new(items, range.0, range.1)
+  fn slice(items: &'a [T], start: Idx, end: Idx) -> &'a [T] {
+    debug_assert!(start <= end, "Range {}..{} ends before it starts", start, end);
+    debug_assert!(end as usize <= items.len(), "Range {}..{} is beyond {} elements", start, end, items.len());
+    &items[start as usize..end as usize]
   }
 }
 
 pub struct MoreNodes {
   max_id: Idx,
-  next: Cell<Idx>
+  next: Idx,
 }
 
 impl MoreNodes {
   pub fn new(max_id: Idx) -> Self {
     Self {
       max_id: max_id,
-      next: Cell::new(max_id + 1)
+      next: max_id + 1,
     }
   }
 }
 
 impl NodesExtension<Idx> for MoreNodes {
-  fn new_node_id(&self) -> Option<Idx> {
-    Some(self.next.replace(1 + self.next.get()))
+  fn new_node_id(&mut self) -> Option<Idx> {
+    self.next += 1;
+    Some(self.next - 1)
   }
 
   fn contains(&self, id: Idx) -> bool {
-    id > self.max_id && id < self.next.get()
+    id > self.max_id && id < self.next
+  }
+}
+
+/// Memory allocated for one array of a data structure
+#[derive(Clone, Debug, Serialize)]
+pub struct ArrayUsage {
+  pub name: String,
+  pub count: usize,
+  pub capacity: usize,
+  pub element_size: usize,
+}
+
+impl ArrayUsage {
+  pub fn bytes(&self) -> usize {
+    self.capacity * self.element_size
+  }
+}
+
+/// Breakdown of the memory allocated by a data structure, per array.
+///
+/// Only the arrays themselves are counted, memory owned by the elements (e.g. strings) is not included.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MemoryUsage {
+  pub arrays: Vec<ArrayUsage>,
+}
+
+impl MemoryUsage {
+  pub fn add<T>(&mut self, name: &str, array: &Vec<T>) {
+    self.arrays.push(ArrayUsage {
+      name: String::from(name),
+      count: array.len(),
+      capacity: array.capacity(),
+      element_size: size_of::<T>(),
+    });
+  }
+
+  /// Adds all arrays of a nested data structure, the names are prefixed with `prefix.`
+  pub fn add_nested(&mut self, prefix: &str, usage: MemoryUsage) {
+    for mut array in usage.arrays {
+      array.name = format!("{}.{}", prefix, array.name);
+      self.arrays.push(array);
+    }
+  }
+
+  pub fn total_bytes(&self) -> usize {
+    self.arrays.iter().map(|array| array.bytes()).sum()
+  }
+}
+
+impl fmt::Display for MemoryUsage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for array in &self.arrays {
+      writeln!(
+        f,
+        "\t{}: count = {}/{}, element = {}, total = {} Mb",
+        array.name,
+        array.count,
+        array.capacity,
+        array.element_size,
+        array.bytes() / 1024 / 1024
+      )?;
+    }
+    write!(f, "\ttotal = {} Mb", self.total_bytes() / 1024 / 1024)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::test_utils::next_random;
+  use super::*;
+
+  #[test]
+  fn test_memory_usage() {
+    let mut inner = MemoryUsage::default();
+    inner.add("ids", &Vec::<Idx>::with_capacity(10));
+
+    let mut usage = MemoryUsage::default();
+    usage.add("points", &vec![0u64; 4]);
+    usage.add_nested("graph", inner);
+
+    let names: Vec<_> = usage.arrays.iter().map(|array| array.name.as_str()).collect();
+    assert_eq!(names, vec!["points", "graph.ids"]);
+    assert_eq!(usage.arrays[0].count, 4);
+    assert_eq!(usage.total_bytes(), 4 * 8 + 10 * 4);
+  }
+
+  #[test]
+  fn test_random_ranges() {
+    let items: Vec<Idx> = (0..50).collect();
+    let mut state = 7;
+    for _ in 0..1000 {
+      let a = (next_random(&mut state) % 51) as Idx;
+      let b = (next_random(&mut state) % 51) as Idx;
+      let (start, end) = (a.min(b), a.max(b));
+      let expected: Vec<Idx> = (start..end).collect();
+
+      let forward = RangeRef::forward(ForwardRange { start, end });
+      assert_eq!(forward.range(), ElementRange::Forward(ForwardRange { start, end }));
+      assert_eq!(RefIterator::from_range(&items, &forward).collect::<Vec<_>>(), expected);
+
+      if start > 0 {
+        let reverse = RangeRef::reverse(ReverseRange { start, end });
+        let reversed: Vec<Idx> = expected.iter().rev().cloned().collect();
+        assert_eq!(RefIterator::from_range(&items, &reverse).collect::<Vec<_>>(), reversed);
+        assert_eq!(RefIterator::from_range(&items, &reverse).size_hint(), (reversed.len(), Some(reversed.len())));
+      }
+    }
+  }
+
+  #[test]
+  fn test_reverse_range() {
+    // The geometry of the opposite direction reuses the points of the forward one, after a sentinel point
+    let points = vec![0, 1, 2, 3];
+    let reverse = RangeRef::reverse(ReverseRange { start: 1, end: 4 });
+    assert_eq!(reverse.range(), ElementRange::Reverse(ReverseRange { start: 1, end: 4 }));
+    assert_eq!(RefIterator::from_range(&points, &reverse).collect::<Vec<_>>(), vec![3, 2, 1]);
+    // An empty reverse range is stored as an empty forward one
+    let empty = RangeRef::reverse(ReverseRange { start: 2, end: 2 });
+    assert_eq!(RefIterator::from_range(&points, &empty).count(), 0);
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_range_beyond_array() {
+    let points = vec![0, 1, 2, 3];
+    RefIterator::reverse(&points, ReverseRange { start: 2, end: 5 }).count();
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_reverse_range_at_first_element() {
+    RangeRef::reverse(ReverseRange { start: 0, end: 3 });
   }
 }
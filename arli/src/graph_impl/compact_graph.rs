@@ -1,7 +1,7 @@
 use crate::graph::*;
 use super::common::*;
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
-use std::mem::size_of;
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 struct Node {
@@ -30,7 +30,7 @@ impl<'a, NodeData> IntoNeighbors<Forward> for &'a CompactGraph<NodeData> {
   fn neighbors(self, node_id: Idx) -> Self::Neighbors {
     let start = self.nodes[node_id as usize].out_edges_offset;
     let end = self.nodes[node_id as usize + 1].out_edges_offset; // Safe to do +1 since we added a sentinel node
-    RefIterator::new(&self.edge_references, start, end)
+    RefIterator::forward(&self.edge_references, ForwardRange { start, end })
   }
 }
 
@@ -40,7 +40,7 @@ impl<'a, NodeData> IntoNeighbors<Backward> for &'a CompactGraph<NodeData> {
   fn neighbors(self, node_id: Idx) -> Self::Neighbors {
     let start = self.nodes[node_id as usize].in_edges_offset;
     let end = self.nodes[node_id as usize + 1].in_edges_offset; // Safe to do +1 since we added a sentinel node
-    RefIterator::new(&self.edge_references, start, end)
+    RefIterator::forward(&self.edge_references, ForwardRange { start, end })
   }
 }
 
@@ -78,9 +78,11 @@ impl<NodeData> CompactGraph<NodeData> {
     // 1. Collect all pairs of `(to, from)` and sort them by `to`
     let mut in_references_tmp = Vec::with_capacity(num_edges);
     for from in 0..num_nodes {
-      let range_start = nodes[from].out_edges_offset;
-      let range_end = nodes[from + 1].out_edges_offset;
-      for to in RefIterator::new(&edge_references, range_start, range_end) {
+      let range = ForwardRange {
+        start: nodes[from].out_edges_offset,
+        end: nodes[from + 1].out_edges_offset,
+      };
+      for to in RefIterator::forward(&edge_references, range) {
         in_references_tmp.push((to as usize, from as Idx))
       }
     }
@@ -107,17 +109,20 @@ impl<NodeData> CompactGraph<NodeData> {
   }
 
   pub fn number_of_nodes(&self) -> usize {
-    self.nodes.len()
+    // Without the sentinel node
+    self.nodes.len() - 1
   }
 
   pub fn number_of_edges(&self) -> usize {
     self.edge_references.len() / 2
   }
 
-  pub fn print_stats(&self) {
-    print_vector_size("self.nodes", &self.nodes);
-    print_vector_size("self.data", &self.data);
-    print_vector_size("self.edge_references", &self.edge_references);
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add("nodes", &self.nodes);
+    usage.add("data", &self.data);
+    usage.add("edge_references", &self.edge_references);
+    usage
   }
 
   pub fn shrink(&mut self) {
@@ -127,17 +132,6 @@ impl<NodeData> CompactGraph<NodeData> {
   }
 }
 
-pub fn print_vector_size<T>(name: &str, v: &Vec<T>) {
-  println!(
-    "\t{}: count = {}/{}, element = {}, total = {} Mb",
-    name,
-    v.len(),
-    v.capacity(),
-    size_of::<T>(),
-    v.capacity() * size_of::<T>() / 1024 / 1024
-  );
-}
-
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -148,6 +142,7 @@ mod tests {
     let data = vec!["node0", "node1", "node1-", "node2"];
 
     let graph = CompactGraph::from_row_data(data, vec![0, 2, 3, 4], vec![1, 3, 2, 3]);
+    assert_eq!(graph.number_of_nodes(), 4);
 
     let out_edges_0: HashSet<_> = neighbors_forward(&graph, 0).collect();
     assert_eq!(out_edges_0.len(), 2);
@@ -18,8 +18,67 @@ pub struct CompactSpatialGraph<NodeData> {
   geometry_refs: Vec<RangeRef>,
   // All geometry points are stored in this array.
   points: Vec<Position>,
-  // S2-based spatial index, sorted list of tuples
-  blocks: Vec<(s2::cellid::CellID, Idx)>,
+  // S2-based spatial index, sorted list of cells. Nodes in the cell `block_cells[i]` are stored in `block_nodes[i]`.
+  // Cells are stored separately, so binary search only touches the cells array.
+  #[serde(with = "delta_encoded_cells")]
+  block_cells: Vec<s2::cellid::CellID>,
+  block_nodes: Vec<Idx>,
+}
+
+/// Sorted cell ids are stored as varint encoded differences between consecutive cells. Neighboring cells share
+/// most of the id bits, so the differences are small.
+mod delta_encoded_cells {
+  use s2::cellid::CellID;
+  use serde::de::Error;
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+  pub fn encode(cells: &[CellID]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(cells.len() * 2);
+    let mut previous = 0;
+    for cell in cells {
+      let mut delta = cell.0 - previous;
+      previous = cell.0;
+      while delta >= 0x80 {
+        result.push((delta & 0x7f) as u8 | 0x80);
+        delta >>= 7;
+      }
+      result.push(delta as u8);
+    }
+    result
+  }
+
+  pub fn decode(bytes: &[u8]) -> Option<Vec<CellID>> {
+    let mut result = Vec::new();
+    let mut previous: u64 = 0;
+    let mut delta: u64 = 0;
+    let mut shift = 0;
+    for byte in bytes {
+      if shift >= 64 {
+        return None;
+      }
+      delta |= ((byte & 0x7f) as u64) << shift;
+      shift += 7;
+      if byte & 0x80 == 0 {
+        previous = previous.checked_add(delta)?;
+        result.push(CellID(previous));
+        delta = 0;
+        shift = 0;
+      }
+    }
+    if shift != 0 {
+      return None;
+    }
+    Some(result)
+  }
+
+  pub fn serialize<S: Serializer>(cells: &[CellID], serializer: S) -> Result<S::Ok, S::Error> {
+    encode(cells).serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<CellID>, D::Error> {
+    let bytes = Vec::<u8>::deserialize(deserializer)?;
+    decode(&bytes).ok_or_else(|| D::Error::custom("invalid delta encoded cell ids"))
+  }
 }
 
 impl<NodeData> GraphBase for CompactSpatialGraph<NodeData> {
@@ -42,9 +101,8 @@ impl<'a, Data> Spatial for CompactSpatialGraph<Data> {
     let mut result = Vec::new();
     let cover = s2_cover(bbox, SPATIAL_INDEX_S2_LEVEL as u8);
     for cell_id in cover.0 {
-      let rng = self.blocks.equal_range_by_key(&cell_id, |i| i.0);
-      let ids = self.blocks.get(rng).unwrap_or(&[]);
-      result.extend(ids.iter().map(|i| i.1));
+      let rng = self.block_cells.equal_range(&cell_id);
+      result.extend_from_slice(&self.block_nodes[rng]);
     }
     result.into_iter()
   }
@@ -83,16 +141,12 @@ impl<NodeData> GraphData for CompactSpatialGraph<NodeData> {
 }
 
 impl<NodeData> CompactSpatialGraph<NodeData> {
+  /// Graph with the geometry of each node in a range of the points, see [`RangeRef`]
   pub fn from_row_data(
     base_graph: CompactGraph<NodeData>,
-    offsets: Vec<(usize, usize)>,
+    geometry_refs: Vec<RangeRef>,
     points: Vec<Position>,
   ) -> Self {
-    let geometry_refs: Vec<RangeRef> = offsets
-      .iter()
-      .map(|(start, end)| RangeRef(*start as Idx, *end as Idx))
-      .collect();
-
     // Build spatial index
     let mut blocks = Vec::with_capacity(2 * base_graph.number_of_nodes());
     for (idx, geom_ref) in geometry_refs.iter().enumerate() {
@@ -104,12 +158,14 @@ impl<NodeData> CompactSpatialGraph<NodeData> {
       }
     }
     blocks.sort_unstable_by_key(|(cell_id, _)| *cell_id);
+    let (block_cells, block_nodes) = blocks.into_iter().unzip();
 
     CompactSpatialGraph {
       graph: base_graph,
       geometry_refs: geometry_refs,
       points: points,
-      blocks: blocks,
+      block_cells,
+      block_nodes,
     }
   }
 
@@ -121,18 +177,22 @@ impl<NodeData> CompactSpatialGraph<NodeData> {
     self.graph.number_of_edges()
   }
 
-  pub fn print_stats(&self) {
-    self.graph.print_stats();
-    print_vector_size("self.geom_refs", &self.geometry_refs);
-    print_vector_size("self.points", &self.points);
-    print_vector_size("self.blocks", &self.blocks);
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add_nested("graph", self.graph.memory_usage());
+    usage.add("geometry_refs", &self.geometry_refs);
+    usage.add("points", &self.points);
+    usage.add("block_cells", &self.block_cells);
+    usage.add("block_nodes", &self.block_nodes);
+    usage
   }
 
   pub fn shrink(&mut self) {
     self.graph.shrink();
     self.geometry_refs.shrink_to_fit();
     self.points.shrink_to_fit();
-    self.blocks.shrink_to_fit();
+    self.block_cells.shrink_to_fit();
+    self.block_nodes.shrink_to_fit();
   }
 }
 
@@ -156,9 +216,14 @@ mod tests {
 
     let base_graph = CompactGraph::from_row_data(data, vec![0, 2, 3, 4], vec![1, 3, 2, 3]);
 
-    let geom_offsets: Vec<(usize, usize)> = vec![(0, 2), (2, 5), (4, 1), (5, 7)];
+    let geometry_refs = vec![
+      RangeRef::forward(ForwardRange { start: 0, end: 2 }),
+      RangeRef::forward(ForwardRange { start: 2, end: 5 }),
+      RangeRef::reverse(ReverseRange { start: 2, end: 5 }),
+      RangeRef::forward(ForwardRange { start: 5, end: 7 }),
+    ];
 
-    let graph = CompactSpatialGraph::from_row_data(base_graph, geom_offsets, points);
+    let graph = CompactSpatialGraph::from_row_data(base_graph, geometry_refs, points);
 
     let out_edges_0: HashSet<_> = neighbors_forward(&graph, 0).collect();
     assert_eq!(out_edges_0.len(), 2);
@@ -183,4 +248,23 @@ mod tests {
     assert_eq!((&graph).geometry(2).collect::<Vec<_>>(), vec![d, c, b]);
     assert_eq!((&graph).geometry(3).collect::<Vec<_>>(), vec![b, e]);
   }
+
+  #[test]
+  fn test_delta_encoded_cells() {
+    let cells = vec![
+      s2::cellid::CellID(5),
+      s2::cellid::CellID(5),
+      s2::cellid::CellID(300),
+      s2::cellid::CellID(0x47a8_5000_0000_0000),
+      s2::cellid::CellID(u64::MAX),
+    ];
+
+    let bytes = delta_encoded_cells::encode(&cells);
+    assert_eq!(&bytes[..4], &[5, 0, 0xa7, 0x02]);
+
+    let decoded = delta_encoded_cells::decode(&bytes).unwrap();
+    assert_eq!(decoded, cells);
+
+    assert!(delta_encoded_cells::decode(&bytes[..bytes.len() - 1]).is_none());
+  }
 }
@@ -0,0 +1,177 @@
+use crate::graph::*;
+use crate::spatial::{BoundingBox, Position};
+use super::common::*;
+
+/// Object safe view of a routable graph, so an application can choose the graph type at runtime, e.g. a
+/// [`DynamicSpatialGraph`](super::DynamicSpatialGraph) built in memory or a
+/// [`CompactSpatialGraph`](super::CompactSpatialGraph) loaded from a file, and hold it as
+/// `Box<dyn RoutableGraphDyn<Data>>`.
+///
+/// Implemented for every graph with [`Idx`] node ids which is routable through a reference. The searches take the
+/// graph wrapped into a [`DynGraph`], so they are compiled once for all the graph types. The neighbors and the
+/// geometry are boxed iterators, which makes the searches slower than on the concrete graph.
+pub trait RoutableGraphDyn<Data> {
+  fn data(&self, node: Idx) -> &Data;
+  fn forward_neighbors(&self, node: Idx) -> Box<dyn Iterator<Item = Idx> + '_>;
+  fn backward_neighbors(&self, node: Idx) -> Box<dyn Iterator<Item = Idx> + '_>;
+  fn geometry(&self, node: Idx) -> Box<dyn Iterator<Item = Position> + '_>;
+  fn find_nodes(&self, bbox: &BoundingBox) -> Vec<Idx>;
+  fn new_extension(&self) -> MoreNodes;
+}
+
+impl<G, Data> RoutableGraphDyn<Data> for G
+where
+  G: GraphData<NodeId = Idx, Data = Data> + Spatial + Extensible<Extension = MoreNodes>,
+  for<'a> &'a G:
+    IntoNeighbors<Forward, NodeId = Idx> + IntoNeighbors<Backward, NodeId = Idx> + IntoGeometry<NodeId = Idx>,
+{
+  fn data(&self, node: Idx) -> &Data {
+    GraphData::data(self, node)
+  }
+
+  fn forward_neighbors(&self, node: Idx) -> Box<dyn Iterator<Item = Idx> + '_> {
+    Box::new(neighbors_forward(self, node))
+  }
+
+  fn backward_neighbors(&self, node: Idx) -> Box<dyn Iterator<Item = Idx> + '_> {
+    Box::new(neighbors_backward(self, node))
+  }
+
+  fn geometry(&self, node: Idx) -> Box<dyn Iterator<Item = Position> + '_> {
+    Box::new(IntoGeometry::geometry(self, node).map(Into::into))
+  }
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Vec<Idx> {
+    Spatial::find_nodes(self, bbox).into_iter().collect()
+  }
+
+  fn new_extension(&self) -> MoreNodes {
+    Extensible::new_extension(self)
+  }
+}
+
+/// Graph adaptor which routes on a [`RoutableGraphDyn`] trait object, it implements the graph traits the searches,
+/// the waypoint matching and the overlays need.
+pub struct DynGraph<'a, Data> {
+  graph: &'a dyn RoutableGraphDyn<Data>,
+}
+
+impl<'a, Data> DynGraph<'a, Data> {
+  pub fn new(graph: &'a dyn RoutableGraphDyn<Data>) -> Self {
+    Self { graph }
+  }
+}
+
+impl<'a, Data> Clone for DynGraph<'a, Data> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, Data> Copy for DynGraph<'a, Data> {}
+
+impl<'a, Data> GraphBase for DynGraph<'a, Data> {
+  type NodeId = Idx;
+}
+
+impl<'a, Data> GraphData for DynGraph<'a, Data> {
+  type Data = Data;
+
+  fn data(&self, node: Idx) -> &Data {
+    self.graph.data(node)
+  }
+}
+
+impl<'a, Data> IntoNeighbors<Forward> for DynGraph<'a, Data> {
+  type Neighbors = Box<dyn Iterator<Item = Idx> + 'a>;
+
+  fn neighbors(self, node: Idx) -> Self::Neighbors {
+    self.graph.forward_neighbors(node)
+  }
+}
+
+impl<'a, Data> IntoNeighbors<Backward> for DynGraph<'a, Data> {
+  type Neighbors = Box<dyn Iterator<Item = Idx> + 'a>;
+
+  fn neighbors(self, node: Idx) -> Self::Neighbors {
+    self.graph.backward_neighbors(node)
+  }
+}
+
+impl<'a, Data> IntoGeometry for DynGraph<'a, Data> {
+  type P = Position;
+  type Geometry = Box<dyn Iterator<Item = Position> + 'a>;
+
+  fn geometry(self, node: Idx) -> Self::Geometry {
+    self.graph.geometry(node)
+  }
+}
+
+impl<'a, Data> Spatial for DynGraph<'a, Data> {
+  type Nodes = Vec<Idx>;
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Self::Nodes {
+    self.graph.find_nodes(bbox)
+  }
+}
+
+impl<'a, Data> Extensible for DynGraph<'a, Data> {
+  type Extension = MoreNodes;
+
+  fn new_extension(&self) -> Self::Extension {
+    self.graph.new_extension()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::{connect_waypoints_to_graph, route};
+  use super::super::super::test_utils::{graph_from_intersections, Segment};
+  use super::super::super::waypoint::{MatchedWaypoint, SnappedOnEdge, SnappedPosition};
+  use super::super::{CompactGraph, CompactSpatialGraph};
+  use super::*;
+
+  #[test]
+  fn test_route_on_graph_chosen_at_runtime() {
+    // Three roads in a row
+    let positions = vec![
+      Position { x: 13.40, y: 52.50 },
+      Position { x: 13.40, y: 52.501 },
+      Position { x: 13.40, y: 52.502 },
+      Position { x: 13.40, y: 52.503 },
+    ];
+    let load = |compact: bool| -> Box<dyn RoutableGraphDyn<Segment>> {
+      if !compact {
+        return Box::new(graph_from_intersections(positions.clone(), vec![(0, 1), (1, 2), (2, 3)]));
+      }
+      let segments = (0..3).map(|i| Segment::new(positions[i], positions[i + 1])).collect();
+      let base_graph = CompactGraph::from_row_data(segments, vec![0, 1, 2], vec![1, 2]);
+      let points = vec![positions[0], positions[1], positions[1], positions[2], positions[2], positions[3]];
+      let geometry_refs = (0..3).map(|i| RangeRef::forward(ForwardRange { start: 2 * i, end: 2 * i + 2 })).collect();
+      Box::new(CompactSpatialGraph::from_row_data(base_graph, geometry_refs, points))
+    };
+    // Segments are 100 long, a partially traveled one costs its traveled share
+    let cost = |_: &Segment, _: &Segment, snapped: Option<SnappedPosition>| match snapped {
+      Some(snapped) => (100.0 * snapped.factor).round() as i32,
+      None => 100,
+    };
+
+    // Waypoints in the middle of the first and the last road
+    let on_road = |id: Idx, position: Position| MatchedWaypoint {
+      waypoint: position,
+      snapped: vec![SnappedOnEdge(SnappedPosition { snapped: position, distance: 0.0, factor: 0.5 }, id)],
+    };
+
+    for compact in [false, true] {
+      let loaded = load(compact);
+      let graph = DynGraph::new(loaded.as_ref());
+      let mut from = on_road(0, Position { x: 13.40, y: 52.5005 });
+      let mut to = on_road(2, Position { x: 13.40, y: 52.5025 });
+      let overlay = connect_waypoints_to_graph(graph, &mut from, &mut to).unwrap();
+      let found = route((&overlay, cost), &from, &to).unwrap();
+      let base_ids: Vec<_> = found.ids.iter().map(|id| overlay.base_id(*id)).collect();
+      assert_eq!(base_ids, vec![0, 1, 2], "compact: {}", compact);
+      assert_eq!(found.cost, 50 + 100 + 50, "compact: {}", compact);
+    }
+  }
+}
@@ -0,0 +1,145 @@
+use crate::graph::*;
+use crate::spatial::BoundingBox;
+
+/// Graph adaptor which hides nodes rejected by a predicate over the node data.
+///
+/// Hidden nodes are never returned as neighbors or as spatial query results, so a search or a waypoint matcher
+/// running on top of the adaptor can't enter them. It's used to route a single travel mode on a multi-modal graph.
+pub struct FilteredGraph<G, F> {
+  graph: G,
+  filter: F,
+}
+
+impl<G: GraphData, F: Fn(&G::Data) -> bool> FilteredGraph<G, F> {
+  pub fn new(graph: G, filter: F) -> Self {
+    Self { graph, filter }
+  }
+
+  pub fn accepts(&self, node: G::NodeId) -> bool {
+    (self.filter)(self.graph.data(node))
+  }
+}
+
+/// Iterator over the neighbors accepted by the [`FilteredGraph`] predicate
+pub struct FilteredNodes<'a, G, F, Nodes> {
+  graph: &'a FilteredGraph<G, F>,
+  nodes: Nodes,
+}
+
+impl<'a, G, F, Nodes> Iterator for FilteredNodes<'a, G, F, Nodes>
+where
+  G: GraphData,
+  F: Fn(&G::Data) -> bool,
+  Nodes: Iterator<Item = G::NodeId>,
+{
+  type Item = G::NodeId;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let graph = self.graph;
+    self.nodes.find(|id| graph.accepts(*id))
+  }
+}
+
+impl<G: GraphBase, F> GraphBase for FilteredGraph<G, F> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData, F> GraphData for FilteredGraph<G, F> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<'a, Direction, G, F> IntoNeighbors<Direction> for &'a FilteredGraph<G, F>
+where
+  Direction: ForwardOrBackward,
+  G: Copy + GraphData + IntoNeighbors<Direction>,
+  F: Fn(&G::Data) -> bool,
+{
+  type Neighbors = FilteredNodes<'a, G, F, <G as IntoNeighbors<Direction>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    FilteredNodes {
+      graph: self,
+      nodes: <G as IntoNeighbors<Direction>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G: Copy + IntoGeometry, F> IntoGeometry for &FilteredGraph<G, F> {
+  type P = G::P;
+  type Geometry = G::Geometry;
+
+  fn geometry(self, node: Self::NodeId) -> Self::Geometry {
+    self.graph.geometry(node)
+  }
+}
+
+impl<G: GraphData + Spatial, F: Fn(&G::Data) -> bool> Spatial for FilteredGraph<G, F> {
+  type Nodes = Vec<G::NodeId>;
+
+  fn find_nodes(&self, bbox: &BoundingBox) -> Self::Nodes {
+    self
+      .graph
+      .find_nodes(bbox)
+      .into_iter()
+      .filter(|id| self.accepts(*id))
+      .collect()
+  }
+}
+
+impl<G: Extensible, F> Extensible for FilteredGraph<G, F> {
+  type Extension = G::Extension;
+
+  fn new_extension(&self) -> Self::Extension {
+    self.graph.new_extension()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::spatial::*;
+  use super::super::super::test_utils::{graph_from_data_and_edges, graph_from_intersections, Segment};
+  use super::super::HasGeometry;
+  use super::*;
+  use std::collections::HashSet;
+
+  #[test]
+  fn test_filtered_neighbors() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 2, 3, 4, 5],
+      vec![(0, 1), (0, 2), (0, 3), (3, 4), (2, 4)],
+    );
+    let filtered = FilteredGraph::new(&graph, |data: &u32| data % 2 == 1);
+
+    let out_edges: HashSet<_> = neighbors_forward(&filtered, 0).collect();
+    assert_eq!(out_edges, [2].iter().cloned().collect());
+
+    let in_edges: HashSet<_> = neighbors_backward(&filtered, 4).collect();
+    assert_eq!(in_edges, [2].iter().cloned().collect());
+  }
+
+  #[test]
+  fn test_filtered_spatial() {
+    let graph = graph_from_intersections(
+      vec![
+        Position { x: 1.0, y: 1.0 },
+        Position { x: 1.0, y: 3.0 },
+        Position { x: 3.0, y: 3.0 },
+      ],
+      vec![(0, 1), (1, 2), (2, 0)],
+    );
+    // Hide the segment 1 -> 2
+    let filtered = FilteredGraph::new(&graph, |segment: &Segment| {
+      segment.geometry().next() != Some(Position { x: 1.0, y: 3.0 })
+    });
+
+    let found: HashSet<_> = filtered
+      .find_nodes(&BoundingBox::new((0.0, 0.0), (4.0, 4.0)))
+      .into_iter()
+      .collect();
+    assert_eq!(found, [0, 2].iter().cloned().collect());
+  }
+}
@@ -0,0 +1,126 @@
+use crate::graph::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Weight of a node set by a [`WeightLayer`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeWeight<W> {
+  /// Multiplies the weight of leaving the node
+  Factor(f32),
+  /// Replaces the weight of leaving the node, whichever node is next
+  Cost(W),
+}
+
+/// Weights of the nodes computed outside of the router, e.g. by a risk or an emissions model. The nodes which aren't
+/// in the layer keep their weights.
+#[derive(Clone, Debug, Default)]
+pub struct WeightLayer<N: Eq + Hash, W> {
+  weights: HashMap<N, NodeWeight<W>>,
+}
+
+impl<N: Eq + Hash, W: Copy> WeightLayer<N, W> {
+  /// Layer of the node weights, a node listed twice gets the last weight
+  pub fn new(weights: impl IntoIterator<Item = (N, NodeWeight<W>)>) -> Self {
+    Self {
+      weights: weights.into_iter().collect(),
+    }
+  }
+
+  pub fn get(&self, node: &N) -> Option<NodeWeight<W>> {
+    self.weights.get(node).cloned()
+  }
+
+  pub fn len(&self) -> usize {
+    self.weights.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.weights.is_empty()
+  }
+}
+
+/// Graph adaptor which takes the weights of leaving the nodes from a layer, e.g. a [`WeightLayer`], where it has them.
+///
+/// The layer is looked up by a function of the node id, so the nodes added by a graph extension can be mapped to their
+/// base ids first, see [`BaseNodeIds`]. Unlike [`PreferenceWeighted`](super::PreferenceWeighted), which only scales
+/// the weights, a layer can replace them as well, e.g. with costs in the units of an external model.
+#[derive(Clone, Copy)]
+pub struct LayerWeighted<G, L> {
+  graph: G,
+  layer: L,
+}
+
+impl<G, L> LayerWeighted<G, L>
+where
+  G: Copy + Weighted,
+  G::Weight: ScalableWeight,
+  L: Copy + Fn(G::NodeId) -> Option<NodeWeight<G::Weight>>,
+{
+  pub fn new(graph: G, layer: L) -> Self {
+    Self { graph, layer }
+  }
+}
+
+impl<G: GraphBase, L> GraphBase for LayerWeighted<G, L> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: IntoNeighbors<Forward>, L> IntoNeighbors<Forward> for LayerWeighted<G, L> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, L> IntoNeighbors<Backward> for LayerWeighted<G, L> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G, L> Weighted for LayerWeighted<G, L>
+where
+  G: Weighted,
+  G::Weight: ScalableWeight,
+  L: Fn(G::NodeId) -> Option<NodeWeight<G::Weight>>,
+{
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    match (self.layer)(from) {
+      Some(NodeWeight::Factor(factor)) => self.graph.transition_weight(from, to).scale(factor),
+      Some(NodeWeight::Cost(cost)) => cost,
+      None => self.graph.transition_weight(from, to),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  #[test]
+  fn test_layer_weights() {
+    // 0 -> 1 -> 3 is shorter than 0 -> 2 -> 3
+    let graph = graph_from_data_and_edges(vec![1, 10, 20, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+
+    let risky = WeightLayer::new(vec![(1, NodeWeight::Factor(3.0)), (1, NodeWeight::Factor(2.5))]);
+    assert_eq!(risky.len(), 1);
+    let layered = LayerWeighted::new(weighted, |id: u32| risky.get(&id));
+    assert_eq!(layered.transition_weight(1, 3), 25);
+    assert_eq!(route(layered, &matched(0), &matched(3)).unwrap().ids, vec![0, 2, 3]);
+
+    // The external model rates node 2 even worse, in its own units
+    let costs = WeightLayer::new(vec![(1, NodeWeight::Cost(50)), (2, NodeWeight::Cost(100))]);
+    let layered = LayerWeighted::new(weighted, |id: u32| costs.get(&id));
+    let route = route(layered, &matched(0), &matched(3)).unwrap();
+    assert_eq!(route.ids, vec![0, 1, 3]);
+    assert_eq!(route.cost, 1 + 50 + 1);
+  }
+}
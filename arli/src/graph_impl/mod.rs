@@ -3,15 +3,89 @@
 //! The module defined two types of in-memory graph data structures:
 //! - [`DynamicGraph`] allows growing a graph by adding nodes or edges. It's recommended for smaller graphs and testing, since memory layout is not optimal.
 //! - [`CompactGraph`] is a static graph which cannot be modified after creation. But it can store big graphs in a memory efficient way.
+//!
+//! [`RoutableGraphDyn`] is an object safe view of either of them, for applications choosing the graph type at runtime. The searches take it wrapped into a [`DynGraph`].
+//!
+//! Graph adaptors wrap another graph and change what a search can see:
+//! - [`AvoidAreas`] hides the nodes whose geometry is inside or crosses any of the polygons, e.g. an area a route must avoid.
+//! - [`BicriteriaGraph`] weights the transitions by two criteria at once, e.g. the travel time and the toll money.
+//! - [`BlockedGraph`] hides nodes blocked by a predicate over the node ids, e.g. segments of a closed road.
+//! - [`CancellableGraph`] hides all edges once a cancellation token is set, so a search on top of it stops early.
+//! - [`FilteredGraph`] hides nodes rejected by a predicate over the node data, e.g. segments not accessible by a travel mode.
+//! - [`LayerWeighted`] takes the weights of the nodes from a layer computed outside of the router, e.g. a [`WeightLayer`] of an emissions model.
+//! - [`PreferenceWeighted`] multiplies the weights of the nodes by a factor, e.g. to avoid unpaved roads unless there is no other way.
+//! - [`RestrictedGraph`] hides the forbidden transitions between two nodes, e.g. the turns forbidden by [`TurnRestrictions`].
+//! - [`ReversedGraph`] swaps the direction of all edges, so a forward search runs backward on the wrapped graph.
+//! - [`TrafficOverlay`] applies the speeds and penalties of a snapshot of the [`TrafficOverrides`], e.g. of a live traffic feed.
+//! - [`TradeOffGraph`] adds a priced secondary weight to the weights, e.g. toll money to the travel time.
+//! - [`TurnWeighted`] adds a cost of the turn angle to the weights, e.g. to avoid the left turns.
 
+#[cfg(feature = "std")]
 mod dynamic_graph;
+#[cfg(feature = "std")]
 mod dynamic_spatial_graph;
 mod compact_graph;
+#[cfg(feature = "std")]
 mod compact_spatial_graph;
+#[cfg(feature = "std")]
+mod dyn_graph;
+#[cfg(feature = "std")]
+mod avoid_areas;
+#[cfg(feature = "std")]
+mod bicriteria_graph;
+#[cfg(feature = "std")]
+mod blocked_graph;
+#[cfg(feature = "std")]
+mod cancellable_graph;
 mod common;
+#[cfg(feature = "std")]
+mod filtered_graph;
+#[cfg(feature = "std")]
+mod layer_weighted;
+#[cfg(feature = "std")]
+mod preference_weighted;
+#[cfg(feature = "std")]
+mod restricted_graph;
+#[cfg(feature = "std")]
+mod reversed_graph;
+#[cfg(feature = "std")]
+mod trade_off_graph;
+#[cfg(feature = "std")]
+mod traffic_overlay;
+#[cfg(feature = "std")]
+mod turn_weighted;
 
+#[cfg(feature = "std")]
 pub use dynamic_graph::*;
+#[cfg(feature = "std")]
 pub use dynamic_spatial_graph::*;
 pub use compact_graph::*;
+#[cfg(feature = "std")]
 pub use compact_spatial_graph::*;
+#[cfg(feature = "std")]
+pub use dyn_graph::*;
+#[cfg(feature = "std")]
+pub use avoid_areas::*;
+#[cfg(feature = "std")]
+pub use bicriteria_graph::*;
+#[cfg(feature = "std")]
+pub use blocked_graph::*;
+#[cfg(feature = "std")]
+pub use cancellable_graph::*;
 pub use common::*;
+#[cfg(feature = "std")]
+pub use filtered_graph::*;
+#[cfg(feature = "std")]
+pub use layer_weighted::*;
+#[cfg(feature = "std")]
+pub use preference_weighted::*;
+#[cfg(feature = "std")]
+pub use restricted_graph::*;
+#[cfg(feature = "std")]
+pub use reversed_graph::*;
+#[cfg(feature = "std")]
+pub use trade_off_graph::*;
+#[cfg(feature = "std")]
+pub use traffic_overlay::*;
+#[cfg(feature = "std")]
+pub use turn_weighted::*;
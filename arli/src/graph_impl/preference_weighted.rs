@@ -0,0 +1,80 @@
+use crate::graph::*;
+
+/// Graph adaptor which multiplies the weight of leaving a node by a factor of the node.
+///
+/// Unlike [`FilteredGraph`](super::FilteredGraph), which hides the nodes, the adaptor only makes them more or less
+/// expensive: the search avoids them while there is a reasonable alternative and still uses them if there isn't. It's
+/// used for soft preferences, e.g. avoiding unpaved roads.
+#[derive(Clone, Copy)]
+pub struct PreferenceWeighted<G, P> {
+  graph: G,
+  factor: P,
+}
+
+impl<G, P> PreferenceWeighted<G, P>
+where
+  G: Copy + Weighted,
+  G::Weight: ScalableWeight,
+  P: Copy + Fn(G::NodeId) -> f32,
+{
+  pub fn new(graph: G, factor: P) -> Self {
+    Self { graph, factor }
+  }
+}
+
+impl<G: GraphBase, P> GraphBase for PreferenceWeighted<G, P> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: IntoNeighbors<Forward>, P> IntoNeighbors<Forward> for PreferenceWeighted<G, P> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, P> IntoNeighbors<Backward> for PreferenceWeighted<G, P> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G, P> Weighted for PreferenceWeighted<G, P>
+where
+  G: Weighted,
+  G::Weight: ScalableWeight,
+  P: Fn(G::NodeId) -> f32,
+{
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to).scale((self.factor)(from))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  #[test]
+  fn test_avoided_nodes_are_still_used() {
+    // Node 1 is avoided, 0 -> 1 -> 3 is shorter than 0 -> 2 -> 3
+    let graph = graph_from_data_and_edges(vec![1, 10, 20, 1, 5], vec![(0, 1), (0, 2), (1, 3), (2, 3), (4, 1)]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+    let avoid = |factor: f32| PreferenceWeighted::new(weighted, move |id: u32| if id == 1 { factor } else { 1.0 });
+
+    assert_eq!(route(avoid(1.5), &matched(0), &matched(3)).unwrap().ids, vec![0, 1, 3]);
+    assert_eq!(route(avoid(2.5), &matched(0), &matched(3)).unwrap().ids, vec![0, 2, 3]);
+    assert_eq!(avoid(2.5).transition_weight(1, 3), 25);
+
+    // The only route from 4 goes through the avoided node
+    let only = route(avoid(2.5), &matched(4), &matched(3)).unwrap();
+    assert_eq!(only.ids, vec![4, 1, 3]);
+    assert_eq!(only.cost, 5 + 25 + 1);
+  }
+}
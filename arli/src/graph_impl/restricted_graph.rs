@@ -0,0 +1,218 @@
+use super::MemoryUsage;
+use crate::graph::*;
+use serde::{Deserialize, Serialize};
+
+/// Transitions between two nodes which must never be taken, e.g. the turns forbidden by the OSM turn restrictions.
+///
+/// A restriction via a way forbids a sequence of three or more segments, a search which only sees one transition at
+/// a time can't tell it apart from the allowed ones, so only the transitions between two nodes are stored.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TurnRestrictions<N> {
+  // Sorted forbidden transitions, for the binary search
+  forbidden: Vec<(N, N)>,
+}
+
+impl<N: Ord + Copy> TurnRestrictions<N> {
+  pub fn new(forbidden: impl IntoIterator<Item = (N, N)>) -> Self {
+    let mut forbidden: Vec<_> = forbidden.into_iter().collect();
+    forbidden.sort_unstable();
+    forbidden.dedup();
+    Self { forbidden }
+  }
+
+  /// The transition from one node to the other is forbidden
+  pub fn forbids(&self, from: N, to: N) -> bool {
+    self.forbidden.binary_search(&(from, to)).is_ok()
+  }
+
+  pub fn len(&self) -> usize {
+    self.forbidden.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.forbidden.is_empty()
+  }
+
+  pub fn memory_usage(&self) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    usage.add("forbidden", &self.forbidden);
+    usage
+  }
+
+  pub fn shrink(&mut self) {
+    self.forbidden.shrink_to_fit();
+  }
+}
+
+/// Graph adaptor which hides the transitions forbidden by a predicate over the ids of both nodes.
+///
+/// Unlike [`BlockedGraph`](super::BlockedGraph), which hides whole nodes, the nodes stay reachable through the other
+/// transitions, e.g. a street can't be entered by a forbidden left turn, but it can after a right turn. A forward
+/// neighbor is hidden if the transition from the node to it is forbidden, a backward neighbor if the transition from
+/// it to the node is, so the bidirectional searches never meet on a forbidden transition either.
+///
+/// The transitions forbidden by [`TurnRestrictions`] are hidden with [`RestrictedGraph::with_restrictions`], which
+/// looks the nodes up by their base ids, so the restrictions apply to the nodes added by a graph extension too, e.g.
+/// a route can't start on a waypoint and take a turn forbidden from the segment the waypoint splits.
+#[derive(Clone, Copy)]
+pub struct RestrictedGraph<G, R> {
+  graph: G,
+  forbidden: R,
+}
+
+impl<G: GraphBase, R: Copy + Fn(G::NodeId, G::NodeId) -> bool> RestrictedGraph<G, R> {
+  pub fn new(graph: G, forbidden: R) -> Self {
+    Self { graph, forbidden }
+  }
+}
+
+impl<G: Copy + BaseNodeIds> RestrictedGraph<G, ()> {
+  /// Hides the transitions forbidden by the restrictions between the base nodes of both nodes, see [`BaseNodeIds`]
+  pub fn with_restrictions<'r>(
+    graph: G,
+    restrictions: &'r TurnRestrictions<G::NodeId>,
+  ) -> RestrictedGraph<G, impl Copy + Fn(G::NodeId, G::NodeId) -> bool + 'r>
+  where
+    G: 'r,
+    G::NodeId: Ord,
+  {
+    let forbidden = move |from, to| restrictions.forbids(graph.base_id(from), graph.base_id(to));
+    RestrictedGraph::new(graph, forbidden)
+  }
+}
+
+/// Iterator over the neighbors reached by the transitions allowed by the [`RestrictedGraph`] predicate
+pub struct AllowedTransitions<Id, R, Nodes> {
+  node: Id,
+  // Neighbors are the sources of the transitions to the node, not their targets
+  backward: bool,
+  forbidden: R,
+  nodes: Nodes,
+}
+
+impl<Id: Copy, R: Fn(Id, Id) -> bool, Nodes: Iterator<Item = Id>> Iterator for AllowedTransitions<Id, R, Nodes> {
+  type Item = Id;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (node, backward, forbidden) = (self.node, self.backward, &self.forbidden);
+    self.nodes.find(|id| {
+      if backward {
+        !forbidden(*id, node)
+      } else {
+        !forbidden(node, *id)
+      }
+    })
+  }
+}
+
+impl<G: GraphBase, R> GraphBase for RestrictedGraph<G, R> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData, R> GraphData for RestrictedGraph<G, R> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<G, R> IntoNeighbors<Forward> for RestrictedGraph<G, R>
+where
+  G: IntoNeighbors<Forward>,
+  R: Copy + Fn(G::NodeId, G::NodeId) -> bool,
+{
+  type Neighbors = AllowedTransitions<G::NodeId, R, <G as IntoNeighbors<Forward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    AllowedTransitions {
+      node,
+      backward: false,
+      forbidden: self.forbidden,
+      nodes: <G as IntoNeighbors<Forward>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G, R> IntoNeighbors<Backward> for RestrictedGraph<G, R>
+where
+  G: IntoNeighbors<Backward>,
+  R: Copy + Fn(G::NodeId, G::NodeId) -> bool,
+{
+  type Neighbors = AllowedTransitions<G::NodeId, R, <G as IntoNeighbors<Backward>>::Neighbors>;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    AllowedTransitions {
+      node,
+      backward: true,
+      forbidden: self.forbidden,
+      nodes: <G as IntoNeighbors<Backward>>::neighbors(self.graph, node),
+    }
+  }
+}
+
+impl<G: IntoGeometry, R> IntoGeometry for RestrictedGraph<G, R> {
+  type P = G::P;
+  type Geometry = G::Geometry;
+
+  fn geometry(self, node: Self::NodeId) -> Self::Geometry {
+    self.graph.geometry(node)
+  }
+}
+
+impl<G: Weighted, R> Weighted for RestrictedGraph<G, R> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::{route, route_bidir};
+  use super::super::super::spatial::Position;
+  use super::super::super::test_utils::{graph_from_intersections, Segment};
+  use super::super::super::waypoint::MatchedWaypoint;
+  use super::*;
+
+  #[test]
+  fn test_forbidden_turns() {
+    // North from 0 to 1, then either left to 2 and right to 5, or straight on through 3 to 4 and left to 5. The
+    // route leaves 5 to the west.
+    let positions = vec![
+      Position { x: 13.400, y: 52.500 },
+      Position { x: 13.400, y: 52.501 },
+      Position { x: 13.399, y: 52.501 },
+      Position { x: 13.400, y: 52.5015 },
+      Position { x: 13.400, y: 52.502 },
+      Position { x: 13.399, y: 52.502 },
+      Position { x: 13.398, y: 52.502 },
+    ];
+    let streets = vec![(0, 1), (1, 2), (2, 5), (1, 3), (3, 4), (4, 5), (5, 6)];
+    let graph = graph_from_intersections(positions, streets);
+    let every_segment = (&graph, |_: &Segment, _: &Segment| 10);
+    let from = MatchedWaypoint::from_node(0, Position { x: 0.0, y: 0.0 });
+    let to = MatchedWaypoint::from_node(6, Position { x: 0.0, y: 0.0 });
+    assert_eq!(route(every_segment, &from, &to).unwrap().ids, vec![0, 1, 2, 6]);
+
+    // No left turn from the segment 0 into the segment 1
+    let restrictions = TurnRestrictions::new(vec![(0, 1), (0, 1)]);
+    assert_eq!(restrictions.len(), 1);
+    assert!(restrictions.forbids(0, 1) && !restrictions.forbids(1, 0));
+
+    let restricted = RestrictedGraph::new(every_segment, |from: u32, to: u32| restrictions.forbids(from, to));
+    assert_eq!(route(restricted, &from, &to).unwrap().ids, vec![0, 3, 4, 5, 6]);
+    assert_eq!(route_bidir(restricted, &from, &to).unwrap().ids, vec![0, 3, 4, 5, 6]);
+    assert!(neighbors_backward(restricted, 1).all(|id| id != 0));
+
+    // The segment 1 is still reachable by a route starting on it
+    let from_segment = MatchedWaypoint::from_node(1, Position { x: 0.0, y: 0.0 });
+    assert_eq!(route(restricted, &from_segment, &to).unwrap().ids, vec![1, 2, 6]);
+
+    let no_way = TurnRestrictions::new(vec![(0, 1), (0, 3)]);
+    let blocked = RestrictedGraph::new(every_segment, |from: u32, to: u32| no_way.forbids(from, to));
+    assert!(route(blocked, &from, &to).is_err());
+    assert!(route_bidir(blocked, &from, &to).is_err());
+  }
+}
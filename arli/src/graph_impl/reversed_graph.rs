@@ -0,0 +1,88 @@
+use crate::graph::*;
+
+/// Graph adaptor which swaps the direction of all edges.
+///
+/// Forward neighbors of the adaptor are the backward neighbors of the wrapped graph and the weight of a transition
+/// `u -> v` is the weight of `v -> u` in the wrapped graph. A forward search on top of the adaptor is a backward
+/// search on the original graph, the cost of a node is then the cost of traveling from it to the search origin.
+#[derive(Clone, Copy)]
+pub struct ReversedGraph<G> {
+  graph: G,
+}
+
+impl<G: Copy> ReversedGraph<G> {
+  pub fn new(graph: G) -> Self {
+    Self { graph }
+  }
+}
+
+impl<G: GraphBase> GraphBase for ReversedGraph<G> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData> GraphData for ReversedGraph<G> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>> IntoNeighbors<Forward> for ReversedGraph<G> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Forward>> IntoNeighbors<Backward> for ReversedGraph<G> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: Weighted> Weighted for ReversedGraph<G> {
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(to, from)
+  }
+}
+
+impl<G: TimeDependent> TimeDependent for ReversedGraph<G> {
+  type Weight = G::Weight;
+
+  fn transition_weight_at(&self, from: Self::NodeId, to: Self::NodeId, time: Self::Weight) -> Self::Weight {
+    self.graph.transition_weight_at(to, from, time)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::test_utils::graph_from_data_and_edges;
+  use super::*;
+  use std::collections::HashSet;
+
+  #[test]
+  fn test_reversed_neighbors() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3, 4], vec![(0, 1), (0, 2), (2, 3), (1, 3)]);
+    let reversed = ReversedGraph::new(&graph);
+
+    let out_edges: HashSet<_> = neighbors_forward(reversed, 3).collect();
+    assert_eq!(out_edges, [1, 2].iter().cloned().collect());
+
+    let in_edges: HashSet<_> = neighbors_backward(reversed, 0).collect();
+    assert_eq!(in_edges, [1, 2].iter().cloned().collect());
+  }
+
+  #[test]
+  fn test_reversed_weight() {
+    let graph = graph_from_data_and_edges(vec![1, 2], vec![(0, 1)]);
+    let reversed = ReversedGraph::new((&graph, |from: &u32, _to: &u32| *from));
+
+    assert_eq!(reversed.transition_weight(1, 0), 1);
+  }
+}
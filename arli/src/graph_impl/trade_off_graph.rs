@@ -0,0 +1,85 @@
+use crate::graph::*;
+
+/// Graph adaptor which adds a secondary weight to the weight of every transition.
+///
+/// The secondary weight is converted to the units of the primary one by `price`, e.g. toll money to the seconds of
+/// travel time a driver would spend to save it. A search on top of the adaptor finds the route with the best trade-off
+/// between the two criteria, the secondary weight of the route can be summed up with
+/// [`calculate_weight`](crate::route::calculate_weight) afterwards.
+#[derive(Clone, Copy)]
+pub struct TradeOffGraph<G, S, F> {
+  graph: G,
+  secondary: S,
+  price: F,
+}
+
+impl<G, S, F> TradeOffGraph<G, S, F>
+where
+  G: Copy + Weighted,
+  S: Copy + Weighted<NodeId = G::NodeId>,
+  F: Copy + Fn(S::Weight) -> G::Weight,
+{
+  pub fn new(graph: G, secondary: S, price: F) -> Self {
+    Self { graph, secondary, price }
+  }
+}
+
+impl<G: GraphBase, S, F> GraphBase for TradeOffGraph<G, S, F> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: IntoNeighbors<Forward>, S, F> IntoNeighbors<Forward> for TradeOffGraph<G, S, F> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, S, F> IntoNeighbors<Backward> for TradeOffGraph<G, S, F> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G, S, F> Weighted for TradeOffGraph<G, S, F>
+where
+  G: Weighted,
+  S: Weighted<NodeId = G::NodeId>,
+  F: Fn(S::Weight) -> G::Weight,
+{
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    self.graph.transition_weight(from, to) + (self.price)(self.secondary.transition_weight(from, to))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  #[test]
+  fn test_trade_off_secondary_weight() {
+    // The route 0 -> 1 -> 3 is faster, but only 0 -> 2 -> 3 has no toll
+    let graph = graph_from_data_and_edges(vec![1, 1, 3, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let time = (&graph, |from: &u32, _to: &u32| *from as i32);
+    let toll = (&graph, |from: &u32, _to: &u32| if *from == 1 { 5 } else { 0 });
+
+    let fastest = route(time, &matched(0), &matched(3)).unwrap();
+    assert_eq!(fastest.ids, vec![0, 1, 3]);
+
+    // Paying 5 is worth more than the 2 saved
+    let cheap = TradeOffGraph::new(time, toll, |toll: i32| toll);
+    assert_eq!(route(cheap, &matched(0), &matched(3)).unwrap().ids, vec![0, 2, 3]);
+
+    let hurried = TradeOffGraph::new(time, toll, |toll: i32| toll / 5);
+    let hurried_route = route(hurried, &matched(0), &matched(3)).unwrap();
+    assert_eq!(hurried_route.ids, vec![0, 1, 3]);
+    assert_eq!(hurried.transition_weight(1, 3), 2);
+  }
+}
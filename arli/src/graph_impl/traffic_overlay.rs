@@ -0,0 +1,241 @@
+use crate::graph::*;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Current traffic on a node, relative to the weight of the wrapped graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedOverride<W> {
+  /// Factor the speed is multiplied by, the weight of leaving the node is divided by it
+  pub speed_factor: f32,
+  /// Added to the weight of leaving the node, e.g. the wait in front of a blocked lane
+  pub penalty: W,
+}
+
+impl<W: Weight> SpeedOverride<W> {
+  /// Override of the speed only
+  pub fn speed(speed_factor: f32) -> Self {
+    Self {
+      speed_factor,
+      penalty: W::default(),
+    }
+  }
+
+  /// Override of the penalty only
+  pub fn penalty(penalty: W) -> Self {
+    Self {
+      speed_factor: 1.0,
+      penalty,
+    }
+  }
+}
+
+/// Overrides of the nodes at one point in time. A snapshot never changes, the updates make a new one.
+#[derive(Clone, Debug)]
+pub struct OverrideSnapshot<N: Eq + Hash, W> {
+  overrides: HashMap<N, SpeedOverride<W>>,
+  generation: u64,
+}
+
+impl<N: Eq + Hash, W: Copy> OverrideSnapshot<N, W> {
+  pub fn get(&self, node: &N) -> Option<SpeedOverride<W>> {
+    self.overrides.get(node).cloned()
+  }
+
+  /// Number of updates applied before the snapshot was made
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  pub fn len(&self) -> usize {
+    self.overrides.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.overrides.is_empty()
+  }
+}
+
+/// Speed and penalty overrides shared by the queries and an updater, e.g. a thread reading a traffic feed.
+///
+/// Queries take the current snapshot and keep using it for the whole search, so a route never mixes two states of
+/// the traffic and never waits for an update. The updates are applied to a copy of the current snapshot one at a time,
+/// the lock the queries take is only held to swap the snapshot pointer.
+pub struct TrafficOverrides<N: Eq + Hash, W> {
+  current: RwLock<Arc<OverrideSnapshot<N, W>>>,
+  update: Mutex<()>,
+}
+
+impl<N: Copy + Eq + Hash, W: Copy> TrafficOverrides<N, W> {
+  /// Overrides without any node
+  pub fn new() -> Self {
+    Self {
+      current: RwLock::new(Arc::new(OverrideSnapshot {
+        overrides: HashMap::new(),
+        generation: 0,
+      })),
+      update: Mutex::new(()),
+    }
+  }
+
+  pub fn snapshot(&self) -> Arc<OverrideSnapshot<N, W>> {
+    Arc::clone(&self.current.read().unwrap())
+  }
+
+  /// Sets the overrides of the nodes, `None` removes the override of a node. The queries see all of the update or
+  /// none of it.
+  pub fn update(&self, overrides: impl IntoIterator<Item = (N, Option<SpeedOverride<W>>)>) {
+    let _update = self.update.lock().unwrap();
+    let mut next = OverrideSnapshot::clone(&self.snapshot());
+    for (node, speed) in overrides {
+      match speed {
+        Some(speed) => next.overrides.insert(node, speed),
+        None => next.overrides.remove(&node),
+      };
+    }
+    next.generation += 1;
+    *self.current.write().unwrap() = Arc::new(next);
+  }
+}
+
+impl<N: Copy + Eq + Hash, W: Copy> Default for TrafficOverrides<N, W> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Graph adaptor which applies a snapshot of the [`TrafficOverrides`] to the weights of the wrapped graph, whatever
+/// cost function the graph is weighted by.
+///
+/// The adaptor borrows the snapshot, so a search sees the same traffic from the start to the end while the overrides
+/// are updated.
+pub struct TrafficOverlay<'a, G: Weighted>
+where
+  G::NodeId: Eq + Hash,
+{
+  graph: G,
+  snapshot: &'a OverrideSnapshot<G::NodeId, G::Weight>,
+}
+
+impl<'a, G> TrafficOverlay<'a, G>
+where
+  G: Copy + Weighted,
+  G::NodeId: Eq + Hash,
+  G::Weight: ScalableWeight,
+{
+  pub fn new(graph: G, snapshot: &'a OverrideSnapshot<G::NodeId, G::Weight>) -> Self {
+    Self { graph, snapshot }
+  }
+}
+
+impl<'a, G: Copy + Weighted> Clone for TrafficOverlay<'a, G>
+where
+  G::NodeId: Eq + Hash,
+{
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, G: Copy + Weighted> Copy for TrafficOverlay<'a, G> where G::NodeId: Eq + Hash {}
+
+impl<'a, G: Weighted> GraphBase for TrafficOverlay<'a, G>
+where
+  G::NodeId: Eq + Hash,
+{
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: Weighted + IntoNeighbors<Forward>> IntoNeighbors<Forward> for TrafficOverlay<'a, G>
+where
+  G::NodeId: Eq + Hash,
+{
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<'a, G: Weighted + IntoNeighbors<Backward>> IntoNeighbors<Backward> for TrafficOverlay<'a, G>
+where
+  G::NodeId: Eq + Hash,
+{
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<'a, G> Weighted for TrafficOverlay<'a, G>
+where
+  G: Weighted,
+  G::NodeId: Eq + Hash,
+  G::Weight: ScalableWeight,
+{
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    let weight = self.graph.transition_weight(from, to);
+    match self.snapshot.get(&from) {
+      Some(speed) => weight.scale(1.0 / speed.speed_factor) + speed.penalty,
+      None => weight,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::route;
+  use super::super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+
+  #[test]
+  fn test_traffic_overlay() {
+    // 0 -> 1 -> 3 is shorter than 0 -> 2 -> 3
+    let graph = graph_from_data_and_edges(vec![1, 10, 20, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let weighted = (&graph, |from: &u32, _to: &u32| *from);
+    let overrides = TrafficOverrides::new();
+
+    let free_flow = overrides.snapshot();
+    overrides.update(vec![(1, Some(SpeedOverride::speed(0.4)))]);
+    let jam = overrides.snapshot();
+    assert_eq!(jam.generation(), 1);
+    assert_eq!(TrafficOverlay::new(weighted, &jam).transition_weight(1, 3), 25);
+    assert_eq!(route(TrafficOverlay::new(weighted, &jam), &matched(0), &matched(3)).unwrap().ids, vec![0, 2, 3]);
+    // A snapshot taken before the update doesn't see it
+    assert!(free_flow.is_empty());
+    assert_eq!(route(TrafficOverlay::new(weighted, &free_flow), &matched(0), &matched(3)).unwrap().ids, vec![0, 1, 3]);
+
+    overrides.update(vec![(1, None), (2, Some(SpeedOverride::penalty(5)))]);
+    let cleared = overrides.snapshot();
+    let cleared_route = route(TrafficOverlay::new(weighted, &cleared), &matched(0), &matched(3)).unwrap();
+    assert_eq!(cleared_route.ids, vec![0, 1, 3]);
+    assert_eq!(cleared_route.cost, 1 + 10 + 1);
+    assert_eq!(TrafficOverlay::new(weighted, &cleared).transition_weight(2, 3), 25);
+  }
+
+  #[test]
+  fn test_consistent_snapshots() {
+    // The updater always slows down both nodes by the same factor, a snapshot never has them apart
+    let overrides = Arc::new(TrafficOverrides::<u32, i32>::new());
+    let updater = {
+      let overrides = Arc::clone(&overrides);
+      std::thread::spawn(move || {
+        for i in 1..=200 {
+          let speed = SpeedOverride::speed(1.0 / i as f32);
+          overrides.update(vec![(1, Some(speed)), (2, Some(speed))]);
+        }
+      })
+    };
+    let mut generation = 0;
+    while generation < 200 {
+      let snapshot = overrides.snapshot();
+      assert!(snapshot.generation() >= generation);
+      generation = snapshot.generation();
+      assert_eq!(snapshot.get(&1), snapshot.get(&2));
+    }
+    updater.join().unwrap();
+  }
+}
@@ -0,0 +1,118 @@
+use crate::graph::*;
+use crate::guidance::turn_angle;
+
+/// Graph adaptor which adds a cost of the turn to the weight of every transition, e.g. a penalty for the left turns
+/// across the oncoming traffic or for the sharp turns a truck can hardly take.
+///
+/// The cost function gets the angle of the turn from [`turn_angle`], right turns are positive and left turns
+/// negative. The geometries of both nodes are read for every transition, so the adaptor is slower than the weights
+/// over the node data only.
+#[derive(Clone, Copy)]
+pub struct TurnWeighted<G, T> {
+  graph: G,
+  turn_cost: T,
+}
+
+impl<G, T> TurnWeighted<G, T>
+where
+  G: Copy + Weighted + IntoGeometry,
+  T: Copy + Fn(f32) -> G::Weight,
+{
+  pub fn new(graph: G, turn_cost: T) -> Self {
+    Self { graph, turn_cost }
+  }
+}
+
+impl<G: GraphBase, T> GraphBase for TurnWeighted<G, T> {
+  type NodeId = G::NodeId;
+}
+
+impl<G: GraphData, T> GraphData for TurnWeighted<G, T> {
+  type Data = G::Data;
+
+  fn data(&self, node: Self::NodeId) -> &Self::Data {
+    self.graph.data(node)
+  }
+}
+
+impl<G: IntoNeighbors<Forward>, T> IntoNeighbors<Forward> for TurnWeighted<G, T> {
+  type Neighbors = <G as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Forward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoNeighbors<Backward>, T> IntoNeighbors<Backward> for TurnWeighted<G, T> {
+  type Neighbors = <G as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node: Self::NodeId) -> Self::Neighbors {
+    <G as IntoNeighbors<Backward>>::neighbors(self.graph, node)
+  }
+}
+
+impl<G: IntoGeometry, T> IntoGeometry for TurnWeighted<G, T> {
+  type P = G::P;
+  type Geometry = G::Geometry;
+
+  fn geometry(self, node: Self::NodeId) -> Self::Geometry {
+    self.graph.geometry(node)
+  }
+}
+
+impl<G, T> Weighted for TurnWeighted<G, T>
+where
+  G: Copy + Weighted + IntoGeometry,
+  T: Fn(f32) -> G::Weight,
+{
+  type Weight = G::Weight;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    let weight = self.graph.transition_weight(from, to);
+    // The weight of the last node of a route is the transition to itself, which isn't a turn
+    if from == to {
+      return weight;
+    }
+    match turn_angle(self.graph, from, to) {
+      Some(angle) => weight + (self.turn_cost)(angle),
+      None => weight,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::super::route::{route, route_bidir};
+  use super::super::super::spatial::Position;
+  use super::super::super::test_utils::{graph_from_intersections, matched, Segment};
+  use super::*;
+
+  #[test]
+  fn test_left_turns_are_avoided() {
+    // North from 0 to 1, then either right to 2 and left to 5, or straight on through 3 to 4 and right to 5. The
+    // route leaves 5 to the east.
+    let positions = vec![
+      Position { x: 13.400, y: 52.500 },
+      Position { x: 13.400, y: 52.501 },
+      Position { x: 13.401, y: 52.501 },
+      Position { x: 13.400, y: 52.5015 },
+      Position { x: 13.400, y: 52.502 },
+      Position { x: 13.401, y: 52.502 },
+      Position { x: 13.402, y: 52.502 },
+    ];
+    let streets = vec![(0, 1), (1, 2), (2, 5), (1, 3), (3, 4), (4, 5), (5, 6)];
+    let graph = graph_from_intersections(positions, streets);
+    let every_segment = |_: &Segment, _: &Segment| 10;
+
+    let shortest = route((&graph, every_segment), &matched(0), &matched(6)).unwrap();
+    assert_eq!(shortest.ids, vec![0, 1, 2, 6]);
+    assert_eq!(turn_angle(&graph, 1, 2).map(f32::round), Some(-90.0));
+    assert_eq!(turn_angle(&graph, 0, 1).map(f32::round), Some(90.0));
+
+    let no_left_turns = TurnWeighted::new((&graph, every_segment), |angle: f32| if angle < -45.0 { 20 } else { 0 });
+    let avoiding = route(no_left_turns, &matched(0), &matched(6)).unwrap();
+    assert_eq!(avoiding.ids, vec![0, 3, 4, 5, 6]);
+    assert_eq!(avoiding.cost, 50);
+    assert_eq!(route_bidir(no_left_turns, &matched(0), &matched(6)).unwrap().cost, 50);
+  }
+}
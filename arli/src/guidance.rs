@@ -0,0 +1,310 @@
+//! Turn-by-turn guidance for a found route.
+
+use crate::graph::*;
+use crate::spatial::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManeuverKind {
+  Depart,
+  Turn,
+  Arrive,
+}
+
+/// Point of the route where the traveler may change direction.
+///
+/// Bearings are in degrees clockwise from north. The bearing before departure and the bearing after arrival are
+/// `0`, like in OSRM.
+#[derive(Clone, Copy, Debug)]
+pub struct Maneuver {
+  pub kind: ManeuverKind,
+  pub location: Position,
+  pub bearing_before: f32,
+  pub bearing_after: f32,
+}
+
+// Bearings at the start and at the end of a node geometry. Zero length pieces are skipped, since they have no
+// direction, e.g. the partial geometry of a node cut at its very end.
+fn entry_and_exit_bearings(geometry: &[Position]) -> (Option<f32>, Option<f32>) {
+  let bearings = || geometry.windows(2).filter(|line| line[0] != line[1]);
+  (
+    bearings().next().map(|line| bearing(&line[0], &line[1])),
+    bearings().next_back().map(|line| bearing(&line[0], &line[1])),
+  )
+}
+
+/// Angle of the turn from the end of one node to the start of the next one, in degrees in `(-180, 180]`. Right turns
+/// are positive and left turns negative, a U-turn is `180`. `None` if either of the nodes has no direction.
+pub fn turn_angle<G: Copy + IntoGeometry>(graph: G, from: G::NodeId, to: G::NodeId) -> Option<f32> {
+  let from: Vec<Position> = graph.geometry(from).map(|p| p.into()).collect();
+  let to: Vec<Position> = graph.geometry(to).map(|p| p.into()).collect();
+  match (entry_and_exit_bearings(&from).1, entry_and_exit_bearings(&to).0) {
+    (Some(before), Some(after)) => {
+      let angle = (after - before + 360.0) % 360.0;
+      Some(if angle > 180.0 { angle - 360.0 } else { angle })
+    }
+    _ => None,
+  }
+}
+
+/// Returns the departure, a maneuver at each transition between route nodes and the arrival.
+pub fn route_maneuvers<G: Copy + IntoGeometry>(graph: G, ids: &[G::NodeId]) -> Vec<Maneuver> {
+  let geometries: Vec<Vec<Position>> = ids
+    .iter()
+    .map(|id| graph.geometry(*id).map(|p| p.into()).collect())
+    .collect();
+
+  let mut result = Vec::new();
+  let mut last_bearing = None;
+  for (index, geometry) in geometries.iter().enumerate() {
+    let (entry, exit) = entry_and_exit_bearings(geometry);
+    if let Some(location) = geometry.first() {
+      result.push(Maneuver {
+        kind: if index == 0 {
+          ManeuverKind::Depart
+        } else {
+          ManeuverKind::Turn
+        },
+        location: *location,
+        bearing_before: last_bearing.unwrap_or(0.0),
+        bearing_after: entry.or(last_bearing).unwrap_or(0.0),
+      });
+    }
+    last_bearing = exit.or(last_bearing);
+  }
+
+  if let Some(location) = geometries.last().and_then(|geometry| geometry.last()) {
+    result.push(Maneuver {
+      kind: ManeuverKind::Arrive,
+      location: *location,
+      bearing_before: last_bearing.unwrap_or(0.0),
+      bearing_after: 0.0,
+    });
+  }
+  result
+}
+
+// Bearings closer than this are the same road, e.g. a node of the graph and its partial copy added for a waypoint
+const SAME_ROAD_BEARING: f32 = 0.5;
+
+/// Roads meeting where a maneuver takes place, in the format of the OSRM intersections.
+///
+/// The roads are the nodes the route may turn into, which the route may leave along, and the nodes which may turn
+/// into the next node of the route, which only lead into the intersection unless the route may leave along them too.
+/// Roads nobody may turn into or out of from the route aren't known, e.g. a one-way road leaving the intersection
+/// which the route may not enter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Intersection {
+  pub location: Position,
+  /// Bearings of the roads away from the intersection, in degrees clockwise from north in ascending order
+  pub bearings: Vec<f32>,
+  /// Whether the route may leave the intersection along each road
+  pub entry: Vec<bool>,
+  /// Index of the road the route arrives along, `None` at the departure
+  pub arrival: Option<usize>,
+  /// Index of the road the route leaves along, `None` at the arrival
+  pub departure: Option<usize>,
+}
+
+impl Intersection {
+  fn new(location: Position) -> Self {
+    Self {
+      location,
+      bearings: Vec::new(),
+      entry: Vec::new(),
+      arrival: None,
+      departure: None,
+    }
+  }
+
+  // Adds the road unless it's already known, a road the route may leave along is an entry even if it's added as a
+  // road leading into the intersection first
+  fn add_road(&mut self, bearing: f32, entry: bool) {
+    match self.road(bearing) {
+      Some(index) => self.entry[index] |= entry,
+      None => {
+        let index = self.bearings.iter().filter(|known| **known < bearing).count();
+        self.bearings.insert(index, bearing);
+        self.entry.insert(index, entry);
+      }
+    }
+  }
+
+  fn road(&self, bearing: f32) -> Option<usize> {
+    self.bearings.iter().position(|known| {
+      let difference = (known - bearing).abs();
+      difference.min(360.0 - difference) < SAME_ROAD_BEARING
+    })
+  }
+}
+
+fn node_bearings<G: Copy + IntoGeometry>(graph: G, id: G::NodeId) -> (Option<f32>, Option<f32>) {
+  let geometry: Vec<Position> = graph.geometry(id).map(|p| p.into()).collect();
+  entry_and_exit_bearings(&geometry)
+}
+
+// Bearing back along a road arriving with the bearing
+fn reversed(bearing: f32) -> f32 {
+  (bearing + 180.0) % 360.0
+}
+
+/// Returns the intersection of each maneuver of [`route_maneuvers`] for the same route, the departure and the arrival
+/// have a single road. The graph tells the allowed turns, so it should be the one the route is found on.
+pub fn route_intersections<G>(graph: G, ids: &[G::NodeId]) -> Vec<Intersection>
+where
+  G: Copy + IntoGeometry + IntoNeighbors<Forward> + IntoNeighbors<Backward>,
+{
+  let mut result = Vec::new();
+  let mut last_bearing = None;
+  let mut previous = None;
+  for id in ids {
+    let geometry: Vec<Position> = graph.geometry(*id).map(|p| p.into()).collect();
+    let (entry, exit) = entry_and_exit_bearings(&geometry);
+    if let Some(location) = geometry.first() {
+      let mut intersection = Intersection::new(*location);
+      if let Some(previous) = previous {
+        for next in neighbors_forward(graph, previous) {
+          if let (Some(bearing), _) = node_bearings(graph, next) {
+            intersection.add_road(bearing, true);
+          }
+        }
+        for arriving in neighbors_backward(graph, *id) {
+          if let (_, Some(bearing)) = node_bearings(graph, arriving) {
+            intersection.add_road(reversed(bearing), false);
+          }
+        }
+      }
+      let arrival = previous.and(last_bearing).map(reversed);
+      let departure = entry.or(last_bearing);
+      if let Some(bearing) = arrival {
+        intersection.add_road(bearing, false);
+      }
+      if let Some(bearing) = departure {
+        intersection.add_road(bearing, true);
+      }
+      // Indices are looked up once all the roads are added, they move as the roads are inserted in order
+      intersection.arrival = arrival.and_then(|bearing| intersection.road(bearing));
+      intersection.departure = departure.and_then(|bearing| intersection.road(bearing));
+      result.push(intersection);
+    }
+    last_bearing = exit.or(last_bearing);
+    previous = Some(*id);
+  }
+
+  if let Some(location) = ids.last().and_then(|id| graph.geometry(*id).map(|p| p.into()).last()) {
+    let mut intersection = Intersection::new(location);
+    if let Some(bearing) = last_bearing {
+      intersection.add_road(reversed(bearing), true);
+      intersection.arrival = Some(0);
+    }
+    result.push(intersection);
+  }
+  result
+}
+
+/// Names of the roads the route spends the longest distance on, at most `max_names` of them, in the order they are
+/// traveled. OSRM uses such names as the summary of a route leg.
+///
+/// `name_and_length` returns the road name of a node, if it has one, together with the length of the node.
+pub fn route_summary<'a, N: Copy, F>(ids: &[N], name_and_length: F, max_names: usize) -> Vec<&'a str>
+where
+  F: Fn(N) -> Option<(&'a str, f32)>,
+{
+  // Total length and the first appearance of each name
+  let mut names: Vec<(&str, f32)> = Vec::new();
+  for (name, length) in ids.iter().filter_map(|id| name_and_length(*id)) {
+    match names.iter_mut().find(|(known, _)| *known == name) {
+      Some((_, total)) => *total += length,
+      None => names.push((name, length)),
+    }
+  }
+
+  let mut longest: Vec<usize> = (0..names.len()).collect();
+  // Stable sort, so the earlier name wins a tie
+  longest.sort_by(|a, b| names[*b].1.partial_cmp(&names[*a].1).unwrap());
+  longest.truncate(max_names);
+  longest.sort();
+  longest.into_iter().map(|index| names[index].0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_utils::graph_from_intersections;
+  use super::*;
+
+  #[test]
+  fn test_route_maneuvers() {
+    // Go north, then turn east
+    let graph = graph_from_intersections(
+      vec![
+        Position { x: 13.4, y: 52.5 },
+        Position { x: 13.4, y: 52.6 },
+        Position { x: 13.5, y: 52.6 },
+      ],
+      vec![(0, 1), (1, 2)],
+    );
+
+    let maneuvers = route_maneuvers(&graph, &[0, 1]);
+
+    let kinds: Vec<_> = maneuvers.iter().map(|m| m.kind).collect();
+    assert_eq!(kinds, vec![ManeuverKind::Depart, ManeuverKind::Turn, ManeuverKind::Arrive]);
+
+    let bearings: Vec<_> = maneuvers
+      .iter()
+      .map(|m| (m.bearing_before.round(), m.bearing_after.round()))
+      .collect();
+    assert_eq!(bearings, vec![(0.0, 0.0), (0.0, 90.0), (90.0, 0.0)]);
+
+    assert_eq!(maneuvers[1].location, Position { x: 13.4, y: 52.6 });
+    assert_eq!(maneuvers[2].location, Position { x: 13.5, y: 52.6 });
+  }
+
+  #[test]
+  fn test_empty_route() {
+    let graph = graph_from_intersections(vec![Position { x: 13.4, y: 52.5 }], vec![]);
+
+    assert!(route_maneuvers(&graph, &[]).is_empty());
+  }
+
+  #[test]
+  fn test_route_intersections() {
+    // North to the crossing, then east. A one-way road from the west leads into the crossing, the road north and
+    // the way back south lead out of it.
+    let graph = graph_from_intersections(
+      vec![
+        Position { x: 13.4, y: 52.499 },
+        Position { x: 13.4, y: 52.5 },
+        Position { x: 13.401, y: 52.5 },
+        Position { x: 13.4, y: 52.501 },
+        Position { x: 13.399, y: 52.5 },
+      ],
+      vec![(0, 1), (1, 2), (1, 3), (4, 1), (1, 0)],
+    );
+
+    let intersections = route_intersections(&graph, &[0, 1]);
+    assert_eq!(intersections.len(), route_maneuvers(&graph, &[0, 1]).len());
+    let rounded = |bearings: &[f32]| bearings.iter().map(|bearing| bearing.round()).collect::<Vec<_>>();
+
+    assert_eq!(rounded(&intersections[0].bearings), vec![0.0]);
+    assert_eq!((intersections[0].arrival, intersections[0].departure), (None, Some(0)));
+
+    let crossing = &intersections[1];
+    assert_eq!(crossing.location, Position { x: 13.4, y: 52.5 });
+    assert_eq!(rounded(&crossing.bearings), vec![0.0, 90.0, 180.0, 270.0]);
+    assert_eq!(crossing.entry, vec![true, true, true, false]);
+    assert_eq!((crossing.arrival, crossing.departure), (Some(2), Some(1)));
+
+    assert_eq!(rounded(&intersections[2].bearings), vec![270.0]);
+    assert_eq!((intersections[2].arrival, intersections[2].departure), (Some(0), None));
+  }
+
+  #[test]
+  fn test_route_summary() {
+    let roads = [("A100", 3000.0), ("Tempelhofer Damm", 500.0), ("Alt-Tempelhof", 200.0), ("A100", 1000.0)];
+    let name_and_length = |id: usize| if id < roads.len() { Some(roads[id]) } else { None };
+
+    assert_eq!(route_summary(&[4, 0, 1, 2, 3], name_and_length, 2), vec!["A100", "Tempelhofer Damm"]);
+    assert_eq!(route_summary(&[2, 1], name_and_length, 2), vec!["Alt-Tempelhof", "Tempelhofer Damm"]);
+    assert_eq!(route_summary(&[2, 1], name_and_length, 1), vec!["Tempelhofer Damm"]);
+    assert!(route_summary(&[4], name_and_length, 2).is_empty());
+  }
+}
@@ -1,13 +1,60 @@
 //! Generic algorithms for finding shortest routes in the road networks.
+//!
+//! The graph traits, [`CompactGraph`](graph_impl::CompactGraph) and the search space build without std, only with
+//! alloc, when the default `std` feature is disabled. The searches themselves and everything else need std.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
 pub mod waypoint;
+#[cfg(feature = "std")]
 pub mod spatial;
+#[cfg(feature = "std")]
 pub mod route;
 pub mod graph;
 pub mod graph_impl;
+#[cfg(feature = "std")]
+pub mod adaptive;
+#[cfg(feature = "std")]
+pub mod arc_flags;
+#[cfg(feature = "std")]
+pub mod astar;
+#[cfg(feature = "std")]
+pub mod cch;
+#[cfg(feature = "std")]
+pub mod crp;
+#[cfg(feature = "std")]
+pub mod ev;
+#[cfg(feature = "std")]
+pub mod experiments;
+#[cfg(feature = "std")]
+pub mod guidance;
+#[cfg(feature = "std")]
+pub mod matcher;
+#[cfg(feature = "std")]
+pub mod matrix;
+#[cfg(feature = "std")]
+pub mod partition;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod route_diff;
+#[cfg(feature = "std")]
+pub mod time_dependent;
+#[cfg(feature = "std")]
+pub mod trip;
 
-mod search_space;
+pub mod search_space;
+#[cfg(feature = "std")]
 mod overlay;
 
+#[cfg(feature = "std")]
 mod test_utils;
 
+#[cfg(feature = "std")]
+pub use error::{Error, Result};
+
@@ -0,0 +1,321 @@
+//! Map matching of GPS traces.
+//!
+//! The trace is matched with a hidden Markov model: the snap candidates of each fix are the hidden states, a candidate
+//! is likely if it's close to its fix, and a transition between the candidates of consecutive fixes is likely if the
+//! route between them is about as long as the straight line between the fixes. The Viterbi algorithm finds the most
+//! likely sequence of candidates, the driven path is made of the routes between them.
+//!
+//! The routes can be searched on another graph than the one the driven length is measured on, see
+//! [`match_trace_with_router`], so a path matched on a graph with turn restrictions never takes a forbidden turn.
+
+use crate::error::{Error, Result};
+use crate::graph::*;
+use crate::matrix::FrozenSearch;
+use crate::spatial::*;
+use crate::waypoint::*;
+
+/// Position of the vehicle reported by the GPS receiver
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+  pub position: Position,
+  /// Seconds since any fixed moment, e.g. the UNIX epoch
+  pub timestamp: Option<f64>,
+  /// Horizontal accuracy reported by the receiver, in meters
+  pub accuracy_m: Option<f32>,
+}
+
+impl Fix {
+  /// Fix without a timestamp and an accuracy
+  pub fn new(position: Position) -> Self {
+    Self {
+      position,
+      timestamp: None,
+      accuracy_m: None,
+    }
+  }
+}
+
+/// Parameters of the matching model, see [`match_trace_with`]
+#[derive(Clone, Copy, Debug)]
+pub struct MatchOptions<W> {
+  /// Standard deviation of the GPS error of the fixes without an accuracy, in meters
+  pub gps_sigma_m: f32,
+  /// Scale of the difference between the route length and the straight-line distance of consecutive fixes, in
+  /// meters. Larger values tolerate longer detours between the fixes.
+  pub beta_m: f32,
+  /// Highest speed between two fixes with timestamps, faster transitions are impossible
+  pub max_speed_mps: f32,
+  /// Largest route cost between the candidates of consecutive fixes, in the weight of the graph the routes are
+  /// searched on. Unbounded if not set, the search for an unreachable candidate explores all of the reachable graph
+  /// then.
+  pub max_cost: Option<W>,
+}
+
+impl<W> Default for MatchOptions<W> {
+  fn default() -> Self {
+    Self {
+      gps_sigma_m: 5.0,
+      beta_m: 5.0,
+      max_speed_mps: 60.0,
+      max_cost: None,
+    }
+  }
+}
+
+/// Driven path recovered from a trace
+pub struct TraceMatch<N: Identifier> {
+  /// Nodes of the path in the driving order
+  pub ids: Vec<N>,
+  /// Candidate each fix is matched to, `None` for the fixes without any node nearby, which are skipped
+  pub matched: Vec<Option<SnappedOnEdge<N>>>,
+}
+
+// Candidates of a matched fix with their Viterbi state
+struct Layer<N: Identifier> {
+  fix: usize,
+  candidates: MatchedWaypoint<N>,
+  // Log probability of the most likely sequence ending at each candidate
+  scores: Vec<f64>,
+  // Candidate of the previous layer the sequence comes from, and the nodes of the route from it
+  previous: Vec<Option<(usize, Vec<N>)>>,
+}
+
+/// Same as [`match_trace_with`] with the default model parameters
+pub fn match_trace<W, G, C>(graph: G, cost: C, fixes: &[Fix]) -> Result<TraceMatch<G::NodeId>>
+where
+  W: Weight + Into<f64>,
+  G: Copy + GraphData + IntoNeighbors<Forward> + IntoGeometry + Spatial,
+  C: Copy + Fn(&G::Data, &G::Data) -> W,
+{
+  match_trace_with(graph, cost, fixes, &MatchOptions::default())
+}
+
+/// Recovers the driven path of a GPS trace as the sequence of nodes, see the [module](self) docs for the model.
+///
+/// The candidates of each fix are found by [`match_waypoint`]. The cost is expected to be a length in meters, e.g.
+/// the distance of a profile, as the transitions compare it with the straight-line distance of the fixes. Fails with
+/// [`Error::NotSnapped`] if no fix has a candidate, and with [`Error::NoRoute`] if no candidate of a fix can be
+/// reached from the candidates of the previous one, e.g. the trace leaves the graph for a while.
+pub fn match_trace_with<W, G, C>(
+  graph: G,
+  cost: C,
+  fixes: &[Fix],
+  options: &MatchOptions<W>,
+) -> Result<TraceMatch<G::NodeId>>
+where
+  W: Weight + Into<f64>,
+  G: Copy + GraphData + IntoNeighbors<Forward> + IntoGeometry + Spatial,
+  C: Copy + Fn(&G::Data, &G::Data) -> W,
+{
+  match_trace_with_router(graph, (graph, cost), cost, fixes, options)
+}
+
+/// Same as [`match_trace_with`], but the routes between the candidates of consecutive fixes are searched on the
+/// `router`, e.g. a [`RestrictedGraph`](crate::graph_impl::RestrictedGraph) hiding the turns forbidden by the turn
+/// restrictions, weighted by a [`TurnWeighted`](crate::graph_impl::TurnWeighted) cost of the turns. The matched path
+/// then never takes a forbidden turn, which matters when the matched traces are used to derive the speeds of the
+/// roads. The driven length of a route, which the transitions compare with the straight-line distance, is still
+/// measured by the `length` cost on the `graph`.
+pub fn match_trace_with_router<W, G, R, C>(
+  graph: G,
+  router: R,
+  length: C,
+  fixes: &[Fix],
+  options: &MatchOptions<R::Weight>,
+) -> Result<TraceMatch<G::NodeId>>
+where
+  W: Weight + Into<f64>,
+  G: Copy + GraphData + IntoGeometry + Spatial,
+  R: Copy + IntoNeighbors<Forward> + Weighted<NodeId = G::NodeId>,
+  C: Copy + Fn(&G::Data, &G::Data) -> W,
+{
+  let length = |id: G::NodeId| -> f64 { (graph, length).transition_weight(id, id).into() };
+  let mut layers: Vec<Layer<G::NodeId>> = Vec::new();
+  for (index, fix) in fixes.iter().enumerate() {
+    let candidates = match_waypoint(graph, &fix.position);
+    if candidates.snapped.is_empty() {
+      continue;
+    }
+    let sigma = fix.accuracy_m.unwrap_or(options.gps_sigma_m).max(1.0) as f64;
+    let emission = |candidate: &SnappedOnEdge<G::NodeId>| -0.5 * (candidate.0.distance as f64 / sigma).powi(2);
+    let mut layer = Layer {
+      fix: index,
+      scores: candidates.snapped.iter().map(emission).collect(),
+      previous: vec![None; candidates.snapped.len()],
+      candidates,
+    };
+    if let Some(last) = layers.last() {
+      transition(router, length, last, &fixes[last.fix], &mut layer, fix, options);
+      if layer.scores.iter().all(|score| *score == f64::NEG_INFINITY) {
+        return Err(Error::NoRoute);
+      }
+    }
+    layers.push(layer);
+  }
+
+  let last = layers.last().ok_or(Error::NotSnapped)?;
+  let mut best = (0..last.scores.len())
+    .max_by(|a, b| last.scores[*a].partial_cmp(&last.scores[*b]).unwrap())
+    .unwrap();
+  let mut matched = vec![None; fixes.len()];
+  let mut routes: Vec<&[G::NodeId]> = Vec::new();
+  for layer in layers.iter().rev() {
+    matched[layer.fix] = Some(layer.candidates.snapped[best]);
+    if let Some((previous, ids)) = &layer.previous[best] {
+      routes.push(ids);
+      best = *previous;
+    }
+  }
+
+  // Each route starts at the node the previous one ends at
+  let first = layers[0].candidates.snapped[best].1;
+  let mut ids = vec![first];
+  for route in routes.into_iter().rev() {
+    ids.extend(route.iter().skip(1));
+  }
+  Ok(TraceMatch { ids, matched })
+}
+
+// Updates the scores of the layer with the most likely transitions from the candidates of the previous layer, the
+// routes are searched on the graph and measured by the length of their nodes
+fn transition<G: Copy + IntoNeighbors<Forward> + Weighted, L: Fn(G::NodeId) -> f64>(
+  graph: G,
+  length: L,
+  previous: &Layer<G::NodeId>,
+  previous_fix: &Fix,
+  layer: &mut Layer<G::NodeId>,
+  fix: &Fix,
+  options: &MatchOptions<G::Weight>,
+) {
+  let straight = haversine_distance(&previous_fix.position, &fix.position) as f64;
+  let max_length = match (previous_fix.timestamp, fix.timestamp) {
+    (Some(from), Some(to)) => (to - from).abs() * options.max_speed_mps as f64,
+    _ => f64::INFINITY,
+  };
+  let mut scores = vec![f64::NEG_INFINITY; layer.scores.len()];
+  for (from, origin) in previous.candidates.snapped.iter().enumerate() {
+    if previous.scores[from] == f64::NEG_INFINITY {
+      continue;
+    }
+    let origin = MatchedWaypoint {
+      waypoint: previous.candidates.waypoint,
+      snapped: vec![*origin],
+    };
+    let mut search = FrozenSearch::new(graph, &origin);
+    if let Some(max_cost) = options.max_cost {
+      search = search.with_max_cost(max_cost);
+    }
+    for (to, destination) in layer.candidates.snapped.iter().enumerate() {
+      let destination = MatchedWaypoint {
+        waypoint: layer.candidates.waypoint,
+        snapped: vec![*destination],
+      };
+      let route = match search.route_to(&origin, &destination) {
+        Some(route) => route,
+        None => continue,
+      };
+      // The route covers the whole origin and destination nodes, the path only covers them from and to the snapped
+      // positions. A fix a bit behind the previous one on the same node is a GPS error, not a loop around the block.
+      let (SnappedOnEdge(start, first), SnappedOnEdge(end, last)) = (origin.snapped[0], destination.snapped[0]);
+      let whole: f64 = route.ids.iter().map(|id| length(*id)).sum();
+      let driven = whole - start.factor as f64 * length(first) - (1.0 - end.factor as f64) * length(last);
+      let driven = driven.abs();
+      if driven > max_length {
+        continue;
+      }
+      let score = previous.scores[from] + layer.scores[to] - (driven - straight).abs() / options.beta_m as f64;
+      if score > scores[to] {
+        scores[to] = score;
+        layer.previous[to] = Some((from, route.ids));
+      }
+    }
+  }
+  layer.scores = scores;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::graph_impl::{RestrictedGraph, TurnRestrictions};
+  use super::super::test_utils::{graph_from_intersections, simple_segment_length_cost};
+  use super::*;
+
+  #[test]
+  fn test_match_trace() {
+    // A street going east through 0, 1, 2 and 3, with a detour north of it from 1 through 4 and 5 back to 2
+    let positions = vec![
+      Position { x: 13.400, y: 52.500 },
+      Position { x: 13.401, y: 52.500 },
+      Position { x: 13.402, y: 52.500 },
+      Position { x: 13.403, y: 52.500 },
+      Position { x: 13.401, y: 52.5002 },
+      Position { x: 13.402, y: 52.5002 },
+    ];
+    let graph = graph_from_intersections(positions, vec![(0, 1), (1, 2), (2, 3), (1, 4), (4, 5), (5, 2)]);
+
+    // The second fix is closer to the detour, but the detour is much longer than the straight line between the fixes
+    let fixes = vec![
+      Fix::new(Position { x: 13.4005, y: 52.49998 }),
+      Fix::new(Position { x: 13.4015, y: 52.50014 }),
+      Fix::new(Position { x: 13.4025, y: 52.49998 }),
+    ];
+    assert_eq!(match_waypoint(&graph, &fixes[1].position).snapped[0].1, 4);
+    let matched = match_trace(&graph, simple_segment_length_cost, &fixes).unwrap();
+    assert_eq!(matched.ids, vec![0, 1, 2]);
+    let matched_ids: Vec<_> = matched.matched.iter().map(|candidate| candidate.unwrap().1).collect();
+    assert_eq!(matched_ids, vec![0, 1, 2]);
+
+    // A fix far away from the street is skipped
+    let mut with_outlier = fixes.clone();
+    with_outlier.insert(1, Fix::new(Position { x: 13.5, y: 52.6 }));
+    let matched = match_trace(&graph, simple_segment_length_cost, &with_outlier).unwrap();
+    assert_eq!(matched.ids, vec![0, 1, 2]);
+    assert!(matched.matched[1].is_none());
+
+    // Fixes a tenth of a second apart are too close in time for any of the transitions between their candidates
+    let timed: Vec<_> = fixes
+      .iter()
+      .enumerate()
+      .map(|(i, fix)| Fix {
+        timestamp: Some(i as f64 / 10.0),
+        ..*fix
+      })
+      .collect();
+    let result = match_trace(&graph, simple_segment_length_cost, &timed);
+    assert!(matches!(result, Err(Error::NoRoute)));
+
+    let nowhere = match_trace(&graph, simple_segment_length_cost, &with_outlier[1..2]);
+    assert!(matches!(nowhere, Err(Error::NotSnapped)));
+  }
+
+  #[test]
+  fn test_match_trace_with_turn_restrictions() {
+    // Same street and detour as above, but turning from the segment 0 into the segment 1 at the intersection 1 is
+    // forbidden, so the street can only be followed by the detour through 4 and 5
+    let positions = vec![
+      Position { x: 13.400, y: 52.500 },
+      Position { x: 13.401, y: 52.500 },
+      Position { x: 13.402, y: 52.500 },
+      Position { x: 13.403, y: 52.500 },
+      Position { x: 13.401, y: 52.5002 },
+      Position { x: 13.402, y: 52.5002 },
+    ];
+    let graph = graph_from_intersections(positions, vec![(0, 1), (1, 2), (2, 3), (1, 4), (4, 5), (5, 2)]);
+    let restrictions = TurnRestrictions::new(vec![(0, 1)]);
+    let router = RestrictedGraph::new((&graph, simple_segment_length_cost), |from, to| restrictions.forbids(from, to));
+
+    let fixes = vec![
+      Fix::new(Position { x: 13.4005, y: 52.49998 }),
+      Fix::new(Position { x: 13.4015, y: 52.49998 }),
+      Fix::new(Position { x: 13.4025, y: 52.49998 }),
+    ];
+    let unrestricted = match_trace(&graph, simple_segment_length_cost, &fixes).unwrap();
+    assert_eq!(unrestricted.ids, vec![0, 1, 2]);
+
+    // The fix on the street after the forbidden turn is matched to the detour instead
+    let options = MatchOptions::default();
+    let matched = match_trace_with_router(&graph, router, simple_segment_length_cost, &fixes, &options).unwrap();
+    assert_eq!(matched.ids, vec![0, 3, 4, 5, 2]);
+    assert!(matched.ids.windows(2).all(|pair| !restrictions.forbids(pair[0], pair[1])));
+    assert_ne!(matched.matched[1].unwrap().1, 1);
+  }
+}
@@ -0,0 +1,506 @@
+//! Distance matrix (table) computations.
+
+use crate::graph::*;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::SearchSpace;
+use crate::waypoint::*;
+
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Forward search from a fixed origin which can be resumed for new destinations.
+///
+/// The search is explored only as far as needed to answer a query and is kept between queries, so a destination
+/// closer than the ones already queried is answered without any further exploration.
+pub struct FrozenSearch<G: Weighted> {
+  graph: G,
+  search: SearchSpace<G::Weight, G::NodeId>,
+  // Nodes with the final cost
+  settled: HashMap<G::NodeId, G::Weight>,
+  // Nodes costing more aren't explored
+  max_cost: Option<G::Weight>,
+}
+
+impl<G: Copy + IntoNeighbors<Forward> + Weighted> FrozenSearch<G> {
+  pub fn new(graph: G, from: &MatchedWaypoint<G::NodeId>) -> Self {
+    let mut search = SearchSpace::new();
+    for SnappedOnEdge(_, id) in &from.snapped {
+      search.init(*id);
+    }
+    Self {
+      graph,
+      search,
+      settled: HashMap::new(),
+      max_cost: None,
+    }
+  }
+
+  /// Stops exploring the graph beyond the cost, destinations costing more are reported as not reachable.
+  ///
+  /// Useful when only the nearby destinations matter, since the search for an unreachable destination explores all
+  /// the reachable graph otherwise.
+  pub fn with_max_cost(mut self, max_cost: G::Weight) -> Self {
+    self.max_cost = Some(max_cost);
+    self
+  }
+
+  fn exceeds_max_cost(&self, cost: G::Weight) -> bool {
+    match self.max_cost {
+      Some(max_cost) => cost > max_cost,
+      None => false,
+    }
+  }
+
+  /// Cost of the cheapest route from the origin to any destination candidate, including the cost of the
+  /// destination node. `None` when the destination isn't matched or can't be reached.
+  pub fn cost_to(&mut self, to: &MatchedWaypoint<G::NodeId>) -> Option<G::Weight> {
+    self.cheapest_candidate(to).map(|(cost, _)| cost)
+  }
+
+  /// Same as [`FrozenSearch::cost_to`], with the nodes of the cheapest route unwound from the search
+  pub fn route_to(
+    &mut self,
+    from: &MatchedWaypoint<G::NodeId>,
+    to: &MatchedWaypoint<G::NodeId>,
+  ) -> Option<Route<G::Weight, G::NodeId>> {
+    let (cost, id) = self.cheapest_candidate(to)?;
+    let ids: Vec<_> = self.search.unwind(id).into_iter().rev().collect();
+    let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0])?;
+    let destination_candidate = to.snapped.iter().position(|s| s.1 == id)?;
+    Some(Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: self.search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    })
+  }
+
+  /// Number of nodes with the final cost, a measure of how far the search is explored
+  pub fn num_settled(&self) -> usize {
+    self.settled.len()
+  }
+
+  // Cost of the cheapest route to any destination candidate together with that candidate
+  fn cheapest_candidate(&mut self, to: &MatchedWaypoint<G::NodeId>) -> Option<(G::Weight, G::NodeId)> {
+    for SnappedOnEdge(_, id) in &to.snapped {
+      self.settle(*id);
+    }
+
+    let graph = self.graph;
+    to.snapped
+      .iter()
+      .filter_map(|SnappedOnEdge(_, id)| {
+        let cost = *self.settled.get(id)?;
+        Some((cost + graph.transition_weight(*id, *id), *id))
+      })
+      .min_by_key(|(cost, _)| *cost)
+      .filter(|(cost, _)| !self.exceeds_max_cost(*cost))
+  }
+
+  // Resumes the search until the node is settled or the search space is exhausted
+  fn settle(&mut self, node: G::NodeId) {
+    while !self.settled.contains_key(&node) {
+      match self.search.min() {
+        Some((_, cost)) if self.exceeds_max_cost(cost) => break,
+        Some((id, cost)) => {
+          // Outdated queue entries have a higher cost than the resolved one
+          if self.search.cost(id) == Some(cost) {
+            self.settled.entry(id).or_insert(cost);
+          }
+          self.search.update(self.graph);
+        }
+        None => break,
+      }
+    }
+  }
+}
+
+/// [`FrozenSearch`] which also sums up a secondary weight along the cheapest routes, e.g. the distance of the
+/// fastest routes, without running a second search.
+pub struct AnnotatedSearch<G: Weighted, S: Weighted<NodeId = G::NodeId>> {
+  search: FrozenSearch<G>,
+  secondary: S,
+  // Secondary weight of the path to each node computed so far
+  secondary_totals: HashMap<G::NodeId, S::Weight>,
+}
+
+impl<G, S> AnnotatedSearch<G, S>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  S: Copy + Weighted<NodeId = G::NodeId>,
+{
+  pub fn new(graph: G, secondary: S, from: &MatchedWaypoint<G::NodeId>) -> Self {
+    Self {
+      search: FrozenSearch::new(graph, from),
+      secondary,
+      secondary_totals: HashMap::new(),
+    }
+  }
+
+  /// See [`FrozenSearch::with_max_cost`]
+  pub fn with_max_cost(mut self, max_cost: G::Weight) -> Self {
+    self.search = self.search.with_max_cost(max_cost);
+    self
+  }
+
+  /// Cost of the cheapest route to any destination candidate together with the secondary weight of that route
+  pub fn cost_to(&mut self, to: &MatchedWaypoint<G::NodeId>) -> Option<(G::Weight, S::Weight)> {
+    let best = self.search.cheapest_candidate(to);
+    best.map(|(cost, id)| (cost, self.secondary_total(id) + self.secondary.transition_weight(id, id)))
+  }
+
+  pub fn num_settled(&self) -> usize {
+    self.search.num_settled()
+  }
+
+  // Secondary weight of the path to a settled node. The parents of settled nodes are settled and final, so the
+  // path is walked back only to the first node with a known total.
+  fn secondary_total(&mut self, node: G::NodeId) -> S::Weight {
+    let mut path = Vec::new();
+    let mut current = node;
+    let mut total = loop {
+      if let Some(total) = self.secondary_totals.get(&current) {
+        break *total;
+      }
+      match self.search.search.parent(current) {
+        Some(parent) if parent != current => {
+          path.push((parent, current));
+          current = parent;
+        }
+        // One of the start nodes
+        _ => {
+          self.secondary_totals.insert(current, Default::default());
+          break Default::default();
+        }
+      }
+    };
+    for (parent, id) in path.into_iter().rev() {
+      total = total + self.secondary.transition_weight(parent, id);
+      self.secondary_totals.insert(id, total);
+    }
+    total
+  }
+}
+
+/// Upper bounds of a matrix request. Every source runs a search which answers all the destinations, so the work
+/// grows with the number of source and destination pairs.
+#[derive(Clone, Copy, Debug)]
+pub struct MatrixLimits {
+  /// Largest number of sources, and of destinations
+  pub max_waypoints: usize,
+  /// Largest number of source and destination pairs
+  pub max_pairs: usize,
+}
+
+impl MatrixLimits {
+  /// Limits of a square matrix with up to `max_waypoints` sources and destinations
+  pub fn square(max_waypoints: usize) -> Self {
+    Self {
+      max_waypoints,
+      max_pairs: max_waypoints.saturating_mul(max_waypoints),
+    }
+  }
+}
+
+/// Reason a matrix request is rejected
+#[derive(Clone, Copy, Debug, PartialEq, Error)]
+pub enum MatrixError {
+  #[error("Too many waypoints: {waypoints}, at most {max} are allowed")]
+  TooManyWaypoints { waypoints: usize, max: usize },
+  #[error("Too many waypoint pairs: {pairs}, at most {max} are allowed")]
+  TooManyPairs { pairs: usize, max: usize },
+}
+
+/// Dimensions of a matrix request which are within the limits.
+///
+/// Checked before the waypoints are matched, so a request which is too big is rejected before any work is done.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatrixSize {
+  sources: usize,
+  destinations: usize,
+}
+
+impl MatrixSize {
+  pub fn new(sources: usize, destinations: usize, limits: &MatrixLimits) -> Result<Self, MatrixError> {
+    let waypoints = sources.max(destinations);
+    if waypoints > limits.max_waypoints {
+      return Err(MatrixError::TooManyWaypoints {
+        waypoints,
+        max: limits.max_waypoints,
+      });
+    }
+    let pairs = sources.saturating_mul(destinations);
+    if pairs > limits.max_pairs {
+      return Err(MatrixError::TooManyPairs {
+        pairs,
+        max: limits.max_pairs,
+      });
+    }
+    Ok(Self { sources, destinations })
+  }
+
+  pub fn sources(&self) -> usize {
+    self.sources
+  }
+
+  pub fn destinations(&self) -> usize {
+    self.destinations
+  }
+
+  pub fn pairs(&self) -> usize {
+    self.sources * self.destinations
+  }
+}
+
+/// Finds the cost of the cheapest route from the origin to each destination with a single search.
+///
+/// The result has one entry per destination, `None` when the destination isn't matched or can't be reached.
+pub fn one_to_many<G: Copy + IntoNeighbors<Forward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &[MatchedWaypoint<G::NodeId>],
+) -> Vec<Option<G::Weight>> {
+  let mut search = FrozenSearch::new(graph, from);
+  to.iter().map(|destination| search.cost_to(destination)).collect()
+}
+
+/// Same as [`one_to_many`], each entry is the cheapest route with its nodes. The routes are unwound from the same
+/// single search, which stops once all the destinations are settled.
+pub fn one_to_many_routes<G: Copy + IntoNeighbors<Forward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &[MatchedWaypoint<G::NodeId>],
+) -> Vec<Option<Route<G::Weight, G::NodeId>>> {
+  let mut search = FrozenSearch::new(graph, from);
+  to.iter().map(|destination| search.route_to(from, destination)).collect()
+}
+
+/// Same as [`one_to_many`], each entry has the secondary weight of the cheapest route as well.
+pub fn one_to_many_annotated<G, S>(
+  graph: G,
+  secondary: S,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &[MatchedWaypoint<G::NodeId>],
+) -> Vec<Option<(G::Weight, S::Weight)>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  S: Copy + Weighted<NodeId = G::NodeId>,
+{
+  let mut search = AnnotatedSearch::new(graph, secondary, from);
+  to.iter().map(|destination| search.cost_to(destination)).collect()
+}
+
+/// Finds the costs of the cheapest routes from each source to each destination, one row per source. Rejects the
+/// request if it exceeds the limits.
+pub fn many_to_many<G: Copy + IntoNeighbors<Forward> + Weighted>(
+  graph: G,
+  sources: &[MatchedWaypoint<G::NodeId>],
+  destinations: &[MatchedWaypoint<G::NodeId>],
+  limits: &MatrixLimits,
+) -> Result<Vec<Vec<Option<G::Weight>>>, MatrixError> {
+  MatrixSize::new(sources.len(), destinations.len(), limits)?;
+  Ok(sources.iter().map(|from| one_to_many(graph, from, destinations)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::route::{calculate_weight, route};
+  use super::super::spatial::Position;
+  use super::super::test_utils::graph_from_data_and_edges;
+  use super::*;
+
+  fn matched(ids: &[u32]) -> MatchedWaypoint<u32> {
+    let position = Position { x: 0.0, y: 0.0 };
+    let snapped = SnappedPosition {
+      snapped: position,
+      distance: 0.0,
+      factor: 0.0,
+    };
+    MatchedWaypoint {
+      waypoint: position,
+      snapped: ids.iter().map(|id| SnappedOnEdge(snapped, *id)).collect(),
+    }
+  }
+
+  fn node_cost(from: &u32, _to: &u32) -> u32 {
+    *from
+  }
+
+  #[test]
+  fn test_same_costs_as_route() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0)],
+    );
+    let weighted_graph = (&graph, node_cost);
+    let destinations: Vec<_> = (0..7).map(|id| matched(&[id])).collect();
+
+    for origin in 0..7 {
+      let from = matched(&[origin]);
+      let costs = one_to_many(weighted_graph, &from, &destinations);
+      for (to, cost) in destinations.iter().zip(costs) {
+        assert_eq!(cost, route(weighted_graph, &from, to).ok().map(|r| r.cost));
+      }
+    }
+  }
+
+  #[test]
+  fn test_unreachable_and_unmatched() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1)]);
+    let destinations = vec![matched(&[1]), matched(&[2]), matched(&[])];
+
+    let costs = one_to_many((&graph, node_cost), &matched(&[0]), &destinations);
+
+    assert_eq!(costs, vec![Some(3), None, None]);
+  }
+
+  #[test]
+  fn test_one_to_many_routes() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0)],
+    );
+    let weighted_graph = (&graph, node_cost);
+    let mut destinations: Vec<_> = (0..7).map(|id| matched(&[id])).collect();
+    destinations.push(matched(&[]));
+
+    let from = matched(&[2]);
+    let routes = one_to_many_routes(weighted_graph, &from, &destinations);
+    assert!(routes[7].is_none());
+    for (to, found) in destinations.iter().zip(&routes).take(7) {
+      let found = found.as_ref().unwrap();
+      let expected = route(weighted_graph, &from, to).unwrap();
+      assert_eq!(found.cost, expected.cost);
+      assert_eq!((found.ids[0], *found.ids.last().unwrap()), (2, to.snapped[0].1));
+      assert_eq!(calculate_weight(weighted_graph, found.ids.iter().cloned()), found.cost);
+    }
+    assert_eq!(routes[4].as_ref().unwrap().ids, vec![2, 3, 4]);
+
+    // The destinations closer than the farthest one are answered by the same search
+    let mut search = FrozenSearch::new(weighted_graph, &from);
+    search.route_to(&from, &destinations[6]);
+    let settled = search.num_settled();
+    assert_eq!(search.route_to(&from, &destinations[3]).unwrap().ids, vec![2, 3]);
+    assert_eq!(search.num_settled(), settled);
+  }
+
+  #[test]
+  fn test_best_destination_candidate() {
+    let graph = graph_from_data_and_edges(vec![1, 1, 50, 1], vec![(0, 2), (0, 1), (1, 3)]);
+
+    let costs = one_to_many((&graph, node_cost), &matched(&[0]), &[matched(&[2, 3])]);
+
+    assert_eq!(costs, vec![Some(3)]);
+  }
+
+  #[test]
+  fn test_frozen_search_is_resumed() {
+    let graph = graph_from_data_and_edges(vec![1; 6], vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+    let mut search = FrozenSearch::new((&graph, node_cost), &matched(&[0]));
+
+    assert_eq!(search.cost_to(&matched(&[3])), Some(4));
+    let explored = search.num_settled();
+
+    assert_eq!(search.cost_to(&matched(&[1])), Some(2));
+    assert_eq!(search.num_settled(), explored);
+
+    assert_eq!(search.cost_to(&matched(&[5])), Some(6));
+    assert_eq!(search.num_settled(), 6);
+  }
+
+  #[test]
+  fn test_secondary_weight_of_cheapest_route() {
+    // The route 0 -> 2 -> 3 is cheaper, but 0 -> 1 -> 3 has the lower secondary weight
+    let edges = vec![(0, 1), (0, 2), (1, 3), (2, 3)];
+    let graph = graph_from_data_and_edges(vec![1, 5, 1, 1], edges.clone());
+    let secondary_graph = graph_from_data_and_edges(vec![10, 10, 100, 10], edges);
+    let destinations: Vec<_> = (0..4).map(|id| matched(&[id])).collect();
+
+    let costs = one_to_many_annotated(
+      (&graph, node_cost),
+      (&secondary_graph, node_cost),
+      &matched(&[0]),
+      &destinations,
+    );
+
+    assert_eq!(costs, vec![Some((1, 10)), Some((6, 20)), Some((2, 110)), Some((3, 120))]);
+  }
+
+  #[test]
+  fn test_secondary_weight_same_as_route() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0)],
+    );
+    let weighted_graph = (&graph, node_cost);
+    let secondary_graph = (&graph, |from: &u32, to: &u32| from * 10 + to);
+    let destinations: Vec<_> = (0..7).map(|id| matched(&[id])).collect();
+
+    for origin in 0..7 {
+      let from = matched(&[origin]);
+      let costs = one_to_many_annotated(weighted_graph, secondary_graph, &from, &destinations);
+      for (to, cost) in destinations.iter().zip(costs) {
+        let expected = route(weighted_graph, &from, to).ok().map(|r| {
+          let secondary = r.ids.windows(2).map(|t| secondary_graph.transition_weight(t[0], t[1])).sum::<u32>();
+          let last = *r.ids.last().unwrap();
+          (r.cost, secondary + secondary_graph.transition_weight(last, last))
+        });
+        assert_eq!(cost, expected);
+      }
+    }
+  }
+
+  #[test]
+  fn test_max_cost() {
+    let graph = graph_from_data_and_edges(vec![1; 6], vec![(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+    let mut search = FrozenSearch::new((&graph, node_cost), &matched(&[0])).with_max_cost(3);
+
+    assert_eq!(search.cost_to(&matched(&[2])), Some(3));
+    assert_eq!(search.cost_to(&matched(&[3])), None);
+    assert_eq!(search.cost_to(&matched(&[5])), None);
+    // Nodes beyond the cost aren't explored, even when a destination can't be reached. Node 3 is reached within the
+    // cost, only leaving it costs more.
+    assert_eq!(search.num_settled(), 4);
+
+    let mut annotated = AnnotatedSearch::new((&graph, node_cost), (&graph, node_cost), &matched(&[0]));
+    annotated = annotated.with_max_cost(3);
+    assert_eq!(annotated.cost_to(&matched(&[2])), Some((3, 3)));
+    assert_eq!(annotated.cost_to(&matched(&[4])), None);
+  }
+
+  #[test]
+  fn test_matrix_limits() {
+    let limits = MatrixLimits {
+      max_waypoints: 10,
+      max_pairs: 50,
+    };
+
+    assert_eq!(MatrixSize::new(5, 10, &limits).unwrap().pairs(), 50);
+    assert_eq!(
+      MatrixSize::new(11, 1, &limits),
+      Err(MatrixError::TooManyWaypoints { waypoints: 11, max: 10 })
+    );
+    assert_eq!(
+      MatrixSize::new(6, 10, &limits),
+      Err(MatrixError::TooManyPairs { pairs: 60, max: 50 })
+    );
+    assert!(MatrixSize::new(10, 10, &MatrixLimits::square(10)).is_ok());
+  }
+
+  #[test]
+  fn test_many_to_many() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2)]);
+    let waypoints: Vec<_> = (0..3).map(|id| matched(&[id])).collect();
+
+    let costs = many_to_many((&graph, node_cost), &waypoints, &waypoints, &MatrixLimits::square(3)).unwrap();
+    assert_eq!(
+      costs,
+      vec![vec![Some(1), Some(3), Some(6)], vec![None, Some(2), Some(5)], vec![None, None, Some(3)]]
+    );
+
+    let too_big = many_to_many((&graph, node_cost), &waypoints, &waypoints, &MatrixLimits::square(2));
+    assert_eq!(too_big, Err(MatrixError::TooManyWaypoints { waypoints: 3, max: 2 }));
+  }
+}
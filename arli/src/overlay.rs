@@ -10,6 +10,8 @@ struct OverlayNode<N: Identifier> {
   pub in_edges: Vec<N>,
   pub geometry: Vec<Position>,
   pub snapped_position: SnappedPosition,
+  // The route turns around at a waypoint on this node
+  pub u_turn: bool,
 }
 
 impl<N: Identifier> OverlayNode<N> {
@@ -20,6 +22,7 @@ impl<N: Identifier> OverlayNode<N> {
       in_edges: Vec::new(),
       geometry: positions,
       snapped_position: snapped_position,
+      u_turn: false,
     }
   }
 }
@@ -27,6 +30,8 @@ impl<N: Identifier> OverlayNode<N> {
 pub struct OverlayGraph<G: Extensible> {
   base_graph: G,
   overlay_nodes: HashMap<G::NodeId, OverlayNode<G::NodeId>>,
+  // Destination nodes following each base node, a forward search reaches them from the nodes before their base node
+  destination_edges: HashMap<G::NodeId, Vec<G::NodeId>>,
   extended_ids: G::Extension,
 }
 
@@ -36,6 +41,7 @@ impl<G: Copy + Extensible> OverlayGraph<G> {
     Self {
       base_graph: graph,
       overlay_nodes: HashMap::new(),
+      destination_edges: HashMap::new(),
       extended_ids: ext,
     }
   }
@@ -48,6 +54,31 @@ impl<G: Copy + Extensible> OverlayGraph<G> {
       (node_id, None)
     }
   }
+
+  /// The node was added for a route turning around at a waypoint, see [`OverlayGraph::add_u_turn`]
+  pub fn is_u_turn(&self, node_id: G::NodeId) -> bool {
+    self.extended_ids.contains(node_id) && self.overlay_nodes.get(&node_id).unwrap().u_turn
+  }
+
+  /// Overlay weighted by the cost function, leaving a node added for a U-turn costs the penalty on top
+  pub fn with_u_turn_penalty<C, W>(&self, cost: C, penalty: W) -> UTurnWeighted<'_, G, C, W> {
+    UTurnWeighted {
+      overlay: self,
+      cost,
+      penalty,
+    }
+  }
+
+  fn destinations_after(&self, base_node_id: G::NodeId) -> &[G::NodeId] {
+    self.destination_edges.get(&base_node_id).map_or(&[], Vec::as_slice)
+  }
+
+  fn mark_u_turn(&mut self, node_id: Option<G::NodeId>) -> Option<G::NodeId> {
+    if let Some(id) = node_id {
+      self.overlay_nodes.get_mut(&id).unwrap().u_turn = true;
+    }
+    node_id
+  }
 }
 
 impl<G: Copy + IntoNeighbors<Forward> + IntoGeometry + Extensible> OverlayGraph<G> {
@@ -74,13 +105,33 @@ impl<G: Copy + IntoNeighbors<Forward> + IntoGeometry + Extensible> OverlayGraph<
             distance: snapped_position.distance,
           },
         ))
-        .out_edges = neighbors_forward(self.base_graph, base_node_id).collect();
+        .out_edges = neighbors_forward(self.base_graph, base_node_id)
+        .chain(self.destinations_after(base_node_id).iter().cloned())
+        .collect();
     }
     new_id
   }
+
+  /// Adds an origin node on the reverse of an origin candidate, starting at the snapped position, for a route turning
+  /// around at the origin. `snapped_position` is the position on the origin candidate.
+  pub fn add_u_turn(
+    &mut self,
+    reverse_node_id: G::NodeId,
+    snapped_position: SnappedPosition,
+  ) -> Option<G::NodeId> {
+    let on_reverse = SnappedPosition {
+      factor: 1.0 - snapped_position.factor,
+      ..snapped_position
+    };
+    let new_id = self.add_origin(reverse_node_id, on_reverse);
+    self.mark_u_turn(new_id)
+  }
 }
 
 impl<G: Copy + IntoNeighbors<Backward> + IntoGeometry + Extensible> OverlayGraph<G> {
+  /// Adds a destination node ending at the snapped position, the route pays for the share of the segment before it.
+  /// The node has no forward neighbors, a forward search reaches it from the nodes before the base node, including
+  /// the origin nodes on them.
   pub fn add_destination(
     &mut self,
     base_node_id: G::NodeId,
@@ -88,36 +139,88 @@ impl<G: Copy + IntoNeighbors<Backward> + IntoGeometry + Extensible> OverlayGraph
   ) -> Option<G::NodeId> {
     let new_id = self.extended_ids.new_node_id();
     if let Some(id) = new_id {
-      self
-        .overlay_nodes
-        .entry(id)
-        .or_insert(OverlayNode::new(
+      let in_edges: Vec<_> = neighbors_backward(self.base_graph, base_node_id).collect();
+      for previous in &in_edges {
+        self.destination_edges.entry(*previous).or_default().push(id);
+      }
+      // Only the origin nodes have neighbors, the destination and the partial nodes end the route
+      for node in self.overlay_nodes.values_mut() {
+        if !node.out_edges.is_empty() && in_edges.contains(&node.base_id) {
+          node.out_edges.push(id);
+        }
+      }
+      let geometry = cut_geometry_after(self.base_graph.geometry(base_node_id), snapped_position.snapped);
+      let mut node = OverlayNode::new(base_node_id, geometry, snapped_position);
+      node.in_edges = in_edges;
+      self.overlay_nodes.insert(id, node);
+    }
+    new_id
+  }
+}
+
+impl<G: Copy + IntoGeometry + Extensible> OverlayGraph<G> {
+  /// Adds a node for the part of the segment between two snapped positions, for a route starting and ending on the
+  /// same segment with the destination ahead of the origin. The node has no neighbors, it's both the first and the
+  /// last node of the route.
+  pub fn add_partial(
+    &mut self,
+    base_node_id: G::NodeId,
+    from: SnappedPosition,
+    to: SnappedPosition,
+  ) -> Option<G::NodeId> {
+    let new_id = self.extended_ids.new_node_id();
+    if let Some(id) = new_id {
+      let geometry = cut_geometry_before(self.base_graph.geometry(base_node_id), from.snapped);
+      self.overlay_nodes.insert(
+        id,
+        OverlayNode::new(
           base_node_id,
-          cut_geometry_after(
-            self.base_graph.geometry(base_node_id),
-            snapped_position.snapped,
-          ),
-          snapped_position,
-        ))
-        .in_edges = neighbors_backward(self.base_graph, base_node_id).collect();
+          cut_geometry_after(geometry.into_iter(), to.snapped),
+          // Share of the segment traveled, like for the origin
+          SnappedPosition {
+            snapped: from.snapped,
+            factor: to.factor - from.factor,
+            distance: from.distance,
+          },
+        ),
+      );
     }
     new_id
   }
+
+  /// Same as [`OverlayGraph::add_partial`], but the route turns around at one of the waypoints, e.g. at the
+  /// destination on the other side of the road
+  pub fn add_partial_u_turn(
+    &mut self,
+    base_node_id: G::NodeId,
+    from: SnappedPosition,
+    to: SnappedPosition,
+  ) -> Option<G::NodeId> {
+    let new_id = self.add_partial(base_node_id, from, to);
+    self.mark_u_turn(new_id)
+  }
 }
 
 impl<G: Extensible + GraphBase> GraphBase for OverlayGraph<G> {
   type NodeId = G::NodeId;
 }
 
+impl<G: Copy + Extensible> BaseNodeIds for OverlayGraph<G> {
+  fn base_id(&self, node_id: G::NodeId) -> G::NodeId {
+    self.find_node(node_id).0
+  }
+}
+
 impl<'a, G: Copy + Extensible + IntoNeighbors<Forward>> IntoNeighbors<Forward>
   for &'a OverlayGraph<G>
 {
-  type Neighbors = OverlayIterator<
-    <G as IntoNeighbors<Forward>>::Neighbors,
+  type Neighbors = std::iter::Chain<
+    OverlayIterator<<G as IntoNeighbors<Forward>>::Neighbors, std::iter::Cloned<std::slice::Iter<'a, G::NodeId>>>,
     std::iter::Cloned<std::slice::Iter<'a, G::NodeId>>,
   >;
 
   fn neighbors(self, node_id: G::NodeId) -> Self::Neighbors {
+    // Overlay nodes already have the destination nodes among their neighbors
     if self.extended_ids.contains(node_id) {
       OverlayIterator::Overlay(
         self
@@ -128,8 +231,10 @@ impl<'a, G: Copy + Extensible + IntoNeighbors<Forward>> IntoNeighbors<Forward>
           .iter()
           .cloned(),
       )
+      .chain([].iter().cloned())
     } else {
       OverlayIterator::Base(neighbors_forward(self.base_graph, node_id))
+        .chain(self.destinations_after(node_id).iter().cloned())
     }
   }
 }
@@ -180,6 +285,77 @@ impl<
   }
 }
 
+/// Overlay weighted by a cost function with a penalty for the U-turns at the waypoints, see
+/// [`OverlayGraph::with_u_turn_penalty`]
+pub struct UTurnWeighted<'a, G: Extensible, C, W> {
+  overlay: &'a OverlayGraph<G>,
+  cost: C,
+  penalty: W,
+}
+
+impl<'a, G: Extensible, C: Copy, W: Copy> Clone for UTurnWeighted<'a, G, C, W> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, G: Extensible, C: Copy, W: Copy> Copy for UTurnWeighted<'a, G, C, W> {}
+
+impl<'a, G: Extensible, C, W> GraphBase for UTurnWeighted<'a, G, C, W> {
+  type NodeId = G::NodeId;
+}
+
+impl<'a, G: Copy + Extensible, C, W> BaseNodeIds for UTurnWeighted<'a, G, C, W> {
+  fn base_id(&self, node_id: G::NodeId) -> G::NodeId {
+    self.overlay.base_id(node_id)
+  }
+}
+
+impl<'a, G: Copy + Extensible + IntoNeighbors<Forward>, C, W> IntoNeighbors<Forward> for UTurnWeighted<'a, G, C, W> {
+  type Neighbors = <&'a OverlayGraph<G> as IntoNeighbors<Forward>>::Neighbors;
+
+  fn neighbors(self, node_id: G::NodeId) -> Self::Neighbors {
+    neighbors_forward(self.overlay, node_id)
+  }
+}
+
+impl<'a, G: Copy + Extensible + IntoNeighbors<Backward>, C, W> IntoNeighbors<Backward>
+  for UTurnWeighted<'a, G, C, W>
+{
+  type Neighbors = <&'a OverlayGraph<G> as IntoNeighbors<Backward>>::Neighbors;
+
+  fn neighbors(self, node_id: G::NodeId) -> Self::Neighbors {
+    neighbors_backward(self.overlay, node_id)
+  }
+}
+
+impl<'a, G: Copy + Extensible + IntoGeometry<P = Position>, C, W> IntoGeometry for UTurnWeighted<'a, G, C, W> {
+  type P = G::P;
+  type Geometry = <&'a OverlayGraph<G> as IntoGeometry>::Geometry;
+
+  fn geometry(self, node_id: G::NodeId) -> Self::Geometry {
+    self.overlay.geometry(node_id)
+  }
+}
+
+impl<'a, G, W, C> Weighted for UTurnWeighted<'a, G, C, W>
+where
+  G: Copy + Extensible + GraphData,
+  W: Weight,
+  C: Copy + Fn(&G::Data, &G::Data, Option<SnappedPosition>) -> W,
+{
+  type Weight = W;
+
+  fn transition_weight(&self, from: Self::NodeId, to: Self::NodeId) -> Self::Weight {
+    let weight = (self.overlay, self.cost).transition_weight(from, to);
+    if self.overlay.is_u_turn(from) {
+      weight + self.penalty
+    } else {
+      weight
+    }
+  }
+}
+
 impl<'a, G: Copy + Extensible + IntoGeometry<P = Position>> IntoGeometry for &'a OverlayGraph<G> {
   // TODO: I had to constraint G::P to be equal to Position, since overlay iterator currently require both iters to have the same item
   type P = G::P;
@@ -222,8 +398,10 @@ impl<BaseIter: Iterator, OverlayIter: Iterator<Item = BaseIter::Item>> Iterator
 
 #[cfg(test)]
 mod tests {
-  use super::super::test_utils::graph_from_intersections;
-  use super::super::waypoint::SnappedPosition;
+  use super::super::graph_impl::{FilteredGraph, RestrictedGraph, ReversedGraph, TurnRestrictions};
+  use super::super::route::{connect_waypoints_to_graph, route, route_bidir};
+  use super::super::test_utils::{graph_from_intersections, Segment};
+  use super::super::waypoint::{MatchedWaypoint, SnappedOnEdge, SnappedPosition};
   use super::*;
   use std::collections::HashSet;
 
@@ -270,6 +448,27 @@ mod tests {
   //   │                         ▼
   //   0                         5
 
+  fn assert_sync_graph<G: SyncGraph>(_: &G) {}
+
+  #[test]
+  fn test_graphs_can_be_shared_between_threads() {
+    let graph = graph_from_intersections(Vec::from(POSITIONS), vec![(0, 2), (2, 3)]);
+    let access = |_: &Segment| true;
+
+    assert_sync_graph(&graph);
+    assert_sync_graph(&FilteredGraph::new(&graph, access));
+    assert_sync_graph(&ReversedGraph::new(&graph));
+
+    let mut overlay = OverlayGraph::new(&graph);
+    let snapped = SnappedPosition {
+      snapped: POSITIONS[0],
+      distance: 0.0,
+      factor: 0.5,
+    };
+    overlay.add_origin(0, snapped).unwrap();
+    assert_sync_graph(&overlay);
+  }
+
   #[test]
   fn test_empty_overlay_not_affect_graph() {
     let graph = graph_from_intersections(
@@ -313,6 +512,42 @@ mod tests {
     assert!(overlay_in_edges.is_empty());
   }
 
+  #[test]
+  fn test_banned_turn_applies_to_overlay_nodes() {
+    let graph = graph_from_intersections(
+      Vec::from(POSITIONS),
+      vec![(0, 2), (1, 2), (2, 3), (3, 4), (3, 5)],
+    );
+    let cost = |_: &Segment, _: &Segment, _: Option<SnappedPosition>| 10;
+    let on_segment = |id: u32| MatchedWaypoint {
+      waypoint: POSITIONS[0],
+      snapped: vec![SnappedOnEdge(SnappedPosition { snapped: POSITIONS[0], distance: 0.0, factor: 0.4 }, id)],
+    };
+    // The turn from the segment 2 into the segment 4 is banned, the ban is keyed on the base ids
+    let restrictions = TurnRestrictions::new(vec![(2, 4)]);
+
+    // Both the origin splitting the segment 2 and the destination splitting the segment 4 are added nodes
+    for origin in [0, 2] {
+      let (mut from, mut to) = (on_segment(origin), on_segment(4));
+      let overlay = connect_waypoints_to_graph(&graph, &mut from, &mut to).unwrap();
+      assert!(from.snapped.iter().all(|snapped| overlay.base_id(snapped.1) == origin));
+      assert!(to.snapped.iter().all(|snapped| snapped.1 > 4 && overlay.base_id(snapped.1) == 4));
+      assert!(route((&overlay, cost), &from, &to).is_ok(), "origin: {}", origin);
+      let restricted = RestrictedGraph::with_restrictions((&overlay, cost), &restrictions);
+      assert!(route(restricted, &from, &to).is_err(), "origin: {}", origin);
+      assert!(route_bidir(restricted, &from, &to).is_err(), "origin: {}", origin);
+
+      // Other turns from the split segment are still allowed
+      let (mut from, mut to) = (on_segment(origin), on_segment(3));
+      let overlay = connect_waypoints_to_graph(&graph, &mut from, &mut to).unwrap();
+      let restricted = RestrictedGraph::with_restrictions((&overlay, cost), &restrictions);
+      let found = route(restricted, &from, &to).unwrap();
+      let base_ids: Vec<_> = found.ids.iter().map(|id| overlay.base_id(*id)).collect();
+      assert_eq!(base_ids.last(), Some(&3), "origin: {}", origin);
+      assert_eq!(route_bidir(restricted, &from, &to).unwrap().cost, found.cost, "origin: {}", origin);
+    }
+  }
+
   #[test]
   fn test_overlay_split_adjusts_geometry() {
     let graph = graph_from_intersections(
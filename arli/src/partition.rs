@@ -0,0 +1,434 @@
+//! Partitions of the graph nodes into cells, for the algorithms which preprocess the graph per cell.
+
+use crate::graph::*;
+use crate::graph_impl::Idx;
+use crate::spatial::Position;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+// Share of the nodes at each end of a direction taken as the sources and the sinks of a bisection, the smaller side
+// of the cut has at least as many nodes
+const BISECTION_TERMINALS: f32 = 0.25;
+
+// Directions the nodes are ordered by in a bisection: west-east, south-north and both diagonals
+const BISECTION_DIRECTIONS: [(f32, f32); 4] = [(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, -1.0)];
+
+pub type CellId = u32;
+
+/// Cell of every node of the graph, the cells are numbered from zero
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Partition {
+  cells: Vec<CellId>,
+  number_of_cells: usize,
+}
+
+impl Partition {
+  /// Partition with the cell of every node, e.g. made by an external partitioner
+  pub fn new(cells: Vec<CellId>) -> Self {
+    let number_of_cells = cells.iter().max().map(|max| *max as usize + 1).unwrap_or(0);
+    Self { cells, number_of_cells }
+  }
+
+  /// Grows the cells from the nodes not in any cell yet by a breadth-first search in both directions, until they have
+  /// `max_cell_size` nodes or the search runs out of nodes.
+  ///
+  /// The cells are connected and compact enough for the pruning, but their boundaries are longer than the ones of a
+  /// partitioner minimizing the cut like [`Partition::inertial_flow`].
+  pub fn grow<G>(graph: G, number_of_nodes: usize, max_cell_size: usize) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + IntoNeighbors<Backward>,
+  {
+    assert!(max_cell_size > 0, "Cells must have at least one node");
+    let mut cells: Vec<Option<u32>> = vec![None; number_of_nodes];
+    let mut number_of_cells = 0;
+    let mut queue = VecDeque::new();
+    for seed in 0..number_of_nodes {
+      if cells[seed].is_some() {
+        continue;
+      }
+      let cell = number_of_cells as u32;
+      number_of_cells += 1;
+      cells[seed] = Some(cell);
+      let mut size = 1;
+      queue.clear();
+      queue.push_back(seed as Idx);
+      while let Some(id) = queue.pop_front() {
+        for next in neighbors_forward(graph, id).chain(neighbors_backward(graph, id)) {
+          if size < max_cell_size && cells[next as usize].is_none() {
+            cells[next as usize] = Some(cell);
+            size += 1;
+            queue.push_back(next);
+          }
+        }
+      }
+    }
+    Self {
+      cells: cells.into_iter().map(Option::unwrap).collect(),
+      number_of_cells,
+    }
+  }
+
+  /// Bisects the nodes recursively by inertial flow until the cells have at most `max_cell_size` nodes.
+  ///
+  /// The nodes, at the first point of their geometry, are ordered along a few directions. For each one the first and
+  /// the last quarter of the nodes are connected by a maximum flow over the transitions in either direction, each with
+  /// a capacity of one, and the cells are split along the minimum cut of the direction with the smallest one. The
+  /// cuts follow the natural borders like rivers and railways, so the cells have far fewer boundary nodes than the
+  /// grown ones, and the quarters keep them balanced.
+  pub fn inertial_flow<G>(graph: G, number_of_nodes: usize, max_cell_size: usize) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + IntoGeometry,
+  {
+    assert!(max_cell_size > 0, "Cells must have at least one node");
+    let positions: Vec<Position> = (0..number_of_nodes as Idx)
+      .map(|id| graph.geometry(id).next().map(Into::into).unwrap_or(Position { x: 0.0, y: 0.0 }))
+      .collect();
+    let mut flow = UnitFlow::new(graph, number_of_nodes);
+    let mut cells = vec![0; number_of_nodes];
+    let mut number_of_cells = 0;
+    let mut stack = vec![(0..number_of_nodes as Idx).collect::<Vec<_>>()];
+    while let Some(nodes) = stack.pop() {
+      if nodes.len() <= max_cell_size {
+        for id in nodes {
+          cells[id as usize] = number_of_cells;
+        }
+        number_of_cells += 1;
+        continue;
+      }
+      let (first, second) = flow.bisect(nodes, &positions);
+      stack.push(second);
+      stack.push(first);
+    }
+    Self {
+      cells,
+      number_of_cells: number_of_cells as usize,
+    }
+  }
+
+  /// Coarser partition made of whole cells of this one, grown by a breadth-first search over the adjacent cells until
+  /// they have about `max_cell_size` nodes. Each coarse cell has at least one of the cells, even a larger one.
+  pub fn coarsen<G>(&self, graph: G, max_cell_size: usize) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx> + IntoNeighbors<Backward>,
+  {
+    let mut sizes = vec![0; self.number_of_cells];
+    let mut adjacent: Vec<Vec<u32>> = vec![Vec::new(); self.number_of_cells];
+    for id in 0..self.number_of_nodes() as Idx {
+      let cell = self.cell(id);
+      sizes[cell as usize] += 1;
+      for next in neighbors_forward(graph, id).chain(neighbors_backward(graph, id)) {
+        if self.cell(next) != cell {
+          adjacent[cell as usize].push(self.cell(next));
+        }
+      }
+    }
+
+    let mut coarse: Vec<Option<u32>> = vec![None; self.number_of_cells];
+    let mut number_of_cells = 0;
+    let mut queue = VecDeque::new();
+    for seed in 0..self.number_of_cells {
+      if coarse[seed].is_some() {
+        continue;
+      }
+      let coarse_cell = number_of_cells as u32;
+      number_of_cells += 1;
+      coarse[seed] = Some(coarse_cell);
+      let mut size = sizes[seed];
+      queue.clear();
+      queue.push_back(seed as u32);
+      while let Some(cell) = queue.pop_front() {
+        for next in &adjacent[cell as usize] {
+          if coarse[*next as usize].is_none() && size + sizes[*next as usize] <= max_cell_size {
+            coarse[*next as usize] = Some(coarse_cell);
+            size += sizes[*next as usize];
+            queue.push_back(*next);
+          }
+        }
+      }
+    }
+    Self {
+      cells: self.cells.iter().map(|cell| coarse[*cell as usize].unwrap()).collect(),
+      number_of_cells,
+    }
+  }
+
+  pub fn cell(&self, id: Idx) -> CellId {
+    self.cells[id as usize]
+  }
+
+  /// Cell of every node
+  pub fn cells(&self) -> &[CellId] {
+    &self.cells
+  }
+
+  /// Nodes of each cell with a transition from or to another cell, sorted by id
+  pub fn boundary_nodes<G>(&self, graph: G) -> Vec<Vec<Idx>>
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx>,
+  {
+    let mut boundary = vec![false; self.number_of_nodes()];
+    for id in 0..self.number_of_nodes() as Idx {
+      for next in neighbors_forward(graph, id) {
+        if self.cell(next) != self.cell(id) {
+          boundary[id as usize] = true;
+          boundary[next as usize] = true;
+        }
+      }
+    }
+    let mut boundary_nodes = vec![Vec::new(); self.number_of_cells];
+    for id in 0..self.number_of_nodes() as Idx {
+      if boundary[id as usize] {
+        boundary_nodes[self.cell(id) as usize].push(id);
+      }
+    }
+    boundary_nodes
+  }
+
+  pub fn number_of_cells(&self) -> usize {
+    self.number_of_cells
+  }
+
+  pub fn number_of_nodes(&self) -> usize {
+    self.cells.len()
+  }
+}
+
+// Transitions of the graph as undirected edges with a capacity of one in each direction, and the flow on them
+struct UnitFlow {
+  edges: Vec<(Idx, Idx)>,
+  // Edges of each node with the other end
+  adjacent: Vec<Vec<(Idx, usize)>>,
+  // Flow along each edge, positive from its first end to the second one
+  flow: Vec<i8>,
+  // Marks of the nodes of the current bisection, its sinks and the visits of the current search
+  member: Vec<u32>,
+  sink: Vec<u32>,
+  visited: Vec<u32>,
+  parent: Vec<Option<(Idx, usize)>>,
+  bisections: u32,
+  searches: u32,
+}
+
+impl UnitFlow {
+  fn new<G>(graph: G, number_of_nodes: usize) -> Self
+  where
+    G: Copy + IntoNeighbors<Forward, NodeId = Idx>,
+  {
+    let mut edges = Vec::new();
+    for id in 0..number_of_nodes as Idx {
+      edges.extend(neighbors_forward(graph, id).filter(|next| *next != id).map(|next| (id.min(next), id.max(next))));
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    let mut adjacent = vec![Vec::new(); number_of_nodes];
+    for (i, (a, b)) in edges.iter().enumerate() {
+      adjacent[*a as usize].push((*b, i));
+      adjacent[*b as usize].push((*a, i));
+    }
+    Self {
+      flow: vec![0; edges.len()],
+      edges,
+      adjacent,
+      member: vec![0; number_of_nodes],
+      sink: vec![0; number_of_nodes],
+      visited: vec![0; number_of_nodes],
+      parent: vec![None; number_of_nodes],
+      bisections: 0,
+      searches: 0,
+    }
+  }
+
+  // Splits the nodes in two along the smallest of the minimum cuts of the directions, ties go to the more balanced one
+  fn bisect(&mut self, nodes: Vec<Idx>, positions: &[Position]) -> (Vec<Idx>, Vec<Idx>) {
+    self.bisections += 1;
+    for id in &nodes {
+      self.member[*id as usize] = self.bisections;
+    }
+    let terminals = ((nodes.len() as f32 * BISECTION_TERMINALS) as usize).max(1);
+    let mut best: Option<(usize, Reverse<usize>, Vec<Idx>)> = None;
+    for (dx, dy) in BISECTION_DIRECTIONS.iter() {
+      let projection = |id: &Idx| positions[*id as usize].x * dx + positions[*id as usize].y * dy;
+      let mut ordered = nodes.clone();
+      ordered.sort_by(|a, b| projection(a).partial_cmp(&projection(b)).unwrap());
+      let (cut, source_side) = self.min_cut(&ordered[..terminals], &ordered[ordered.len() - terminals..]);
+      // The flow is reset for the next direction
+      for id in &nodes {
+        for (_, edge) in &self.adjacent[*id as usize] {
+          self.flow[*edge] = 0;
+        }
+      }
+      let balance = Reverse(source_side.len().min(nodes.len() - source_side.len()));
+      let better = match &best {
+        Some((best_cut, best_balance, _)) => (cut, balance) < (*best_cut, *best_balance),
+        None => true,
+      };
+      if better {
+        best = Some((cut, balance, source_side));
+      }
+    }
+
+    let source_side = best.unwrap().2;
+    self.searches += 1;
+    for id in &source_side {
+      self.visited[*id as usize] = self.searches;
+    }
+    let searches = self.searches;
+    let other_side = nodes.into_iter().filter(|id| self.visited[*id as usize] != searches).collect();
+    (source_side, other_side)
+  }
+
+  // Size of the minimum cut between the sources and the sinks within the nodes of the bisection and the nodes on the
+  // side of the sources, by augmenting paths found with a breadth-first search
+  fn min_cut(&mut self, sources: &[Idx], sinks: &[Idx]) -> (usize, Vec<Idx>) {
+    for id in sinks {
+      self.sink[*id as usize] = self.bisections;
+    }
+    let mut cut = 0;
+    let source_side = loop {
+      match self.augmenting_path(sources) {
+        Ok(()) => cut += 1,
+        Err(source_side) => break source_side,
+      }
+    };
+    for id in sinks {
+      self.sink[*id as usize] = 0;
+    }
+    (cut, source_side)
+  }
+
+  // Pushes a unit of flow from the sources to a sink, or returns the nodes reachable from the sources if there is no
+  // residual path
+  fn augmenting_path(&mut self, sources: &[Idx]) -> Result<(), Vec<Idx>> {
+    self.searches += 1;
+    let mut reached = Vec::new();
+    let mut queue = VecDeque::new();
+    for id in sources {
+      self.visited[*id as usize] = self.searches;
+      self.parent[*id as usize] = None;
+      queue.push_back(*id);
+    }
+    while let Some(id) = queue.pop_front() {
+      reached.push(id);
+      for (next, edge) in &self.adjacent[id as usize] {
+        let next_index = *next as usize;
+        if self.member[next_index] != self.bisections
+          || self.visited[next_index] == self.searches
+          || self.residual(id, *edge) == 0
+        {
+          continue;
+        }
+        self.visited[next_index] = self.searches;
+        self.parent[next_index] = Some((id, *edge));
+        if self.sink[next_index] == self.bisections {
+          let mut at = *next;
+          while let Some((previous, edge)) = self.parent[at as usize] {
+            self.flow[edge] += if self.edges[edge].0 == previous { 1 } else { -1 };
+            at = previous;
+          }
+          return Ok(());
+        }
+        queue.push_back(*next);
+      }
+    }
+    Err(reached)
+  }
+
+  // Capacity left on the edge from the node to its other end
+  fn residual(&self, from: Idx, edge: usize) -> i8 {
+    if self.edges[edge].0 == from {
+      1 - self.flow[edge]
+    } else {
+      1 + self.flow[edge]
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::test_utils::{graph_from_data_and_edges, graph_from_intersections};
+  use super::*;
+
+  #[test]
+  fn test_grown_cells() {
+    // Two chains of five nodes, connected at the ends
+    let mut edges: Vec<_> = (0..4).map(|id| (id, id + 1)).collect();
+    edges.extend((5..9).map(|id| (id + 1, id)));
+    edges.push((4, 9));
+    let graph = graph_from_data_and_edges(vec![0; 10], edges);
+
+    let partition = Partition::grow(&graph, 10, 5);
+    assert_eq!(partition.number_of_cells(), 2);
+    assert!((0..5).all(|id| partition.cell(id) == 0));
+    // The backward edges are followed as well
+    assert!((5..10).all(|id| partition.cell(id) == 1));
+
+    let singletons = Partition::grow(&graph, 10, 1);
+    assert_eq!(singletons.number_of_cells(), 10);
+    assert_eq!(Partition::new(vec![0, 2, 1]).number_of_cells(), 3);
+  }
+
+  #[test]
+  fn test_coarsened_cells() {
+    // Chain of twelve nodes in cells of two
+    let graph = graph_from_data_and_edges(vec![0; 12], (0..11).map(|id| (id, id + 1)).collect());
+    let fine = Partition::grow(&graph, 12, 2);
+    assert_eq!(fine.number_of_cells(), 6);
+
+    let coarse = fine.coarsen(&graph, 5);
+    assert_eq!(coarse.number_of_cells(), 3);
+    // Cells of the fine partition are never split
+    for id in (0..12).step_by(2) {
+      assert_eq!(coarse.cell(id), coarse.cell(id + 1));
+    }
+    assert_eq!(fine.coarsen(&graph, 1), fine);
+  }
+
+  #[test]
+  fn test_inertial_flow_cells() {
+    // Two towns, grids of 4x4 two-way streets, connected by a two-way bridge from the east of the first one
+    let mut positions = Vec::new();
+    let mut streets = Vec::new();
+    for town in 0..2 {
+      for i in 0..16 {
+        let (x, y) = (i % 4, i / 4);
+        positions.push(Position {
+          x: 13.40 + 0.02 * town as f32 + 0.001 * x as f32,
+          y: 52.50 + 0.001 * y as f32,
+        });
+        let id = 16 * town + i;
+        if x < 3 {
+          streets.extend(vec![(id, id + 1), (id + 1, id)]);
+        }
+        if y < 3 {
+          streets.extend(vec![(id, id + 4), (id + 4, id)]);
+        }
+      }
+    }
+    let first_town_segments = streets.len() / 2;
+    streets.extend(vec![(3, 16), (16, 3)]);
+    let graph = graph_from_intersections(positions, streets.clone());
+
+    let partition = Partition::inertial_flow(&graph, streets.len(), 50);
+    assert_eq!(partition.number_of_cells(), 2);
+    assert_eq!(partition.cells().len(), streets.len());
+    // The cut is at the bridge, not across a town
+    let first_town = partition.cell(0);
+    assert!((0..first_town_segments as Idx).all(|id| partition.cell(id) == first_town));
+    assert!((first_town_segments as Idx..2 * first_town_segments as Idx).all(|id| partition.cell(id) != first_town));
+    // Only the streets at the ends of the bridge and the bridge itself are on the boundary
+    let boundary = partition.boundary_nodes(&graph);
+    assert_eq!(boundary.len(), 2);
+    for nodes in &boundary {
+      assert!(!nodes.is_empty() && nodes.len() <= 5);
+    }
+
+    let small = Partition::inertial_flow(&graph, streets.len(), 10);
+    let mut sizes = vec![0; small.number_of_cells()];
+    for cell in small.cells() {
+      sizes[*cell as usize] += 1;
+    }
+    assert!(sizes.iter().all(|size| *size > 0 && *size <= 10));
+  }
+}
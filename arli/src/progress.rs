@@ -0,0 +1,214 @@
+//! Progress reporting and cancellation of long computations, e.g. importing a graph or a search in a large one.
+
+use thiserror::Error;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Steps of a stage done between the progress reports and the cancellation checks
+const PROGRESS_BATCH: usize = 10_000;
+// Checks of the flag between the readings of the clock, see `CancellationToken::is_cancelled_in_batches`
+const DEADLINE_BATCH: u32 = 256;
+
+thread_local! {
+  // Checks left on the thread before the clock is read again, shared by all tokens
+  static CHECKS_TO_DEADLINE: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Receives the progress of a computation made of consecutive stages, e.g. to render a progress bar in a CLI
+pub trait ProgressSink {
+  /// A new stage starts, `total` is the number of its steps if it's known upfront
+  fn stage(&mut self, name: &str, total: Option<usize>);
+  /// Number of steps of the current stage done so far
+  fn progress(&mut self, done: usize);
+}
+
+/// Sink which ignores the progress
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+  fn stage(&mut self, _name: &str, _total: Option<usize>) {}
+
+  fn progress(&mut self, _done: usize) {}
+}
+
+/// Computation was cancelled before it finished
+#[derive(Clone, Copy, Debug, PartialEq, Error)]
+#[error("Computation is cancelled")]
+pub struct Cancelled;
+
+/// Flag cancelling a computation, shared by its clones. The computation checks it from time to time and stops once
+/// it's set, e.g. by the service when the client disconnects or by a CLI on Ctrl+C. A token with a deadline is also
+/// cancelled once the deadline passes, e.g. at the timeout of a request.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+  deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Token cancelled at the deadline, or earlier with [`cancel`](Self::cancel)
+  pub fn with_deadline(self, deadline: Instant) -> Self {
+    Self {
+      deadline: Some(deadline),
+      ..self
+    }
+  }
+
+  /// Token cancelled once the timeout from now passes, see [`with_deadline`](Self::with_deadline)
+  pub fn with_timeout(self, timeout: Duration) -> Self {
+    self.with_deadline(Instant::now() + timeout)
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed) || self.is_past_deadline()
+  }
+
+  /// Same as [`is_cancelled`](Self::is_cancelled), but only reads the clock once in a batch of calls on the thread, for
+  /// the checks in the tight loop of a search. A deadline found passed cancels the token, so its clones don't read the
+  /// clock anymore either.
+  pub fn is_cancelled_in_batches(&self) -> bool {
+    if self.cancelled.load(Ordering::Relaxed) {
+      return true;
+    }
+    if self.deadline.is_none() {
+      return false;
+    }
+    let read_clock = CHECKS_TO_DEADLINE.with(|checks| match checks.get() {
+      0 => {
+        checks.set(DEADLINE_BATCH - 1);
+        true
+      }
+      left => {
+        checks.set(left - 1);
+        false
+      }
+    });
+    if read_clock && self.is_past_deadline() {
+      self.cancel();
+      return true;
+    }
+    false
+  }
+
+  /// Whether the deadline has passed, e.g. to tell a timeout from a cancellation
+  pub fn is_past_deadline(&self) -> bool {
+    matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+  }
+
+  /// Stops the computation with `?` once it's cancelled
+  pub fn check(&self) -> Result<(), Cancelled> {
+    if self.is_cancelled() {
+      Err(Cancelled)
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Stage of a computation in progress. The steps are reported to the sink and the token is checked in batches, so
+/// they don't slow down a tight loop.
+pub struct StageProgress<'a> {
+  sink: &'a mut dyn ProgressSink,
+  token: &'a CancellationToken,
+  done: usize,
+  next_batch: usize,
+}
+
+impl<'a> StageProgress<'a> {
+  pub fn new(sink: &'a mut dyn ProgressSink, token: &'a CancellationToken, name: &str, total: Option<usize>) -> Self {
+    sink.stage(name, total);
+    Self {
+      sink,
+      token,
+      done: 0,
+      next_batch: PROGRESS_BATCH,
+    }
+  }
+
+  pub fn step(&mut self) -> Result<(), Cancelled> {
+    self.done += 1;
+    if self.done == self.next_batch {
+      self.next_batch += PROGRESS_BATCH;
+      self.sink.progress(self.done);
+      self.token.check()?;
+    }
+    Ok(())
+  }
+
+  pub fn finish(self) {
+    self.sink.progress(self.done);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Default)]
+  struct Recorded {
+    stages: Vec<(String, Option<usize>)>,
+    done: Vec<usize>,
+  }
+
+  impl ProgressSink for Recorded {
+    fn stage(&mut self, name: &str, total: Option<usize>) {
+      self.stages.push((name.to_string(), total));
+    }
+
+    fn progress(&mut self, done: usize) {
+      self.done.push(done);
+    }
+  }
+
+  #[test]
+  fn test_stage_progress() {
+    let mut sink = Recorded::default();
+    let token = CancellationToken::new();
+
+    let mut stage = StageProgress::new(&mut sink, &token, "Counting", Some(25_000));
+    for _ in 0..25_000 {
+      stage.step().unwrap();
+    }
+    stage.finish();
+    assert_eq!(sink.stages, vec![("Counting".to_string(), Some(25_000))]);
+    assert_eq!(sink.done, vec![10_000, 20_000, 25_000]);
+
+    // Clones share the flag, the stage stops at the next batch
+    token.clone().cancel();
+    let mut stage = StageProgress::new(&mut sink, &token, "Cancelled", None);
+    let steps = (1..).find(|_| stage.step().is_err());
+    assert_eq!(steps, Some(PROGRESS_BATCH));
+  }
+
+  #[test]
+  fn test_deadline() {
+    let token = CancellationToken::new().with_timeout(Duration::from_secs(3600));
+    assert!(!token.is_cancelled());
+    token.cancel();
+    assert!(token.is_cancelled());
+    assert!(!token.is_past_deadline());
+
+    let expired = CancellationToken::new().with_deadline(Instant::now());
+    assert!(expired.is_cancelled());
+    assert!(expired.is_past_deadline());
+    assert_eq!(expired.clone().check(), Err(Cancelled));
+
+    // The clock is read once in a batch, then the expired token stays cancelled
+    let expired = CancellationToken::new().with_deadline(Instant::now());
+    let checks = (1..).find(|_| expired.is_cancelled_in_batches()).unwrap();
+    assert!(checks <= DEADLINE_BATCH);
+    assert!(expired.clone().is_cancelled_in_batches());
+    assert!(expired.cancelled.load(Ordering::Relaxed));
+    assert!(!CancellationToken::new().is_cancelled_in_batches());
+  }
+}
@@ -1,61 +1,267 @@
 //! Route finding algorithms.
 
+use crate::arc_flags::{ArcFlags, FlaggedGraph};
+use crate::error::{Error, Result};
 use crate::graph::*;
+use crate::graph_impl::{Idx, PreferenceWeighted, ReversedGraph};
+use crate::matrix::{many_to_many, MatrixLimits};
 use crate::overlay::OverlayGraph;
 use crate::search_space::*;
 use crate::spatial::*;
+use crate::trip::{optimize_order, TripOptions};
 use crate::waypoint::*;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Range, Sub};
+use std::time::{Duration, Instant};
 
 pub trait RoutableGraph: GraphData + IntoNeighbors<Forward> + IntoNeighbors<Backward> + IntoGeometry + Spatial {}
 impl<T> RoutableGraph for T where T: GraphData + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Spatial + IntoGeometry {}
 
 pub struct Route<W: Weight, N: Identifier> {
+  /// Total cost of the route, including the cost of the last node
   pub cost: W,
   pub ids: Vec<N>,
+  /// Index of the origin snap candidate the route starts from
+  pub origin_candidate: usize,
+  /// Index of the destination snap candidate the route ends at
+  pub destination_candidate: usize,
+  /// Number of nodes reached by the search, a measure of the search effort
+  pub num_resolved: usize,
+  pub quality: RouteQuality,
 }
 
-pub fn connect_waypoints_to_graph<G: Copy + IntoNeighbors<Forward> + IntoGeometry + Extensible>(
+// Snapping further than this gives the lowest snapping score
+const POOR_SNAP_DISTANCE_M: f32 = 200.0;
+
+/// Signs of an unreliable route, e.g. a bad GPS fix or a waypoint away from any road, so clients can warn the user.
+///
+/// The snapping distance is set by the search, the other signs depend on the graph data and the matching and are
+/// filled in by the caller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RouteQuality {
+  /// Larger of the origin and the destination snapping distances, in meters
+  pub snap_distance: f32,
+  /// Share of the route length on low class roads, see [`low_class_share`]
+  pub low_class_share: f32,
+  /// A waypoint was matched with a fallback, e.g. to the nearest node beyond the snapping radius
+  pub fallback: bool,
+}
+
+impl RouteQuality {
+  pub(crate) fn snapped<N: Identifier>(from: &SnappedOnEdge<N>, to: &SnappedOnEdge<N>) -> Self {
+    Self {
+      snap_distance: from.0.distance.max(to.0.distance),
+      ..Default::default()
+    }
+  }
+
+  /// Score between 0 and 1, higher is more reliable. It's a heuristic for the comparison of routes and for a
+  /// warning threshold, not a probability.
+  pub fn score(&self) -> f32 {
+    let snapping = 1.0 - 0.5 * (self.snap_distance / POOR_SNAP_DISTANCE_M).min(1.0);
+    let road_class = 1.0 - 0.3 * self.low_class_share.min(1.0);
+    let fallback = if self.fallback { 0.5 } else { 1.0 };
+    snapping * road_class * fallback
+  }
+}
+
+/// Share of the route length on low class roads, e.g. tracks and service roads which are often mapped poorly.
+/// `length_and_class` returns the length of a node and whether it's a low class road.
+pub fn low_class_share<N: Identifier, F: Fn(N) -> (f32, bool)>(ids: &[N], length_and_class: F) -> f32 {
+  let (total, low_class) = ids.iter().fold((0.0, 0.0), |(total, low_class), id| {
+    let (length, is_low_class) = length_and_class(*id);
+    (total + length, if is_low_class { low_class + length } else { low_class })
+  });
+  if total > 0.0 {
+    low_class / total
+  } else {
+    0.0
+  }
+}
+
+/// Time spent in each phase of a routing query
+#[derive(Default, Clone, Copy, Debug)]
+pub struct QueryTiming {
+  pub snapping: Duration,
+  pub overlay_build: Duration,
+  pub search: Duration,
+  pub geometry: Duration,
+  pub serialization: Duration,
+}
+
+impl QueryTiming {
+  pub fn total(&self) -> Duration {
+    self.snapping + self.overlay_build + self.search + self.geometry + self.serialization
+  }
+}
+
+/// Route, if one was found, together with the time spent on each phase of the query
+pub type TimedRoute<W, N> = (Result<Route<W, N>>, QueryTiming);
+
+type MatchedPair<N> = (MatchedWaypoint<N>, MatchedWaypoint<N>);
+
+/// Runs `f` and adds the time it took to `phase`
+pub fn timed<T, F: FnOnce() -> T>(phase: &mut Duration, f: F) -> T {
+  let timer = Instant::now();
+  let result = f();
+  *phase += timer.elapsed();
+  result
+}
+
+/// Replaces the origin candidates with overlay nodes starting at the snapped positions and the destination candidates
+/// with overlay nodes ending at them, see [`connect_origin_to_graph`] and [`connect_destination_to_graph`]. Both
+/// searches of [`route_bidir`] start from the overlay nodes, and the route only pays for the parts of the first and
+/// the last segment it travels along.
+///
+/// An origin and a destination candidate on the same segment with the destination ahead of the origin get a node for
+/// the part of the segment between them, added to both waypoints, so the route doesn't have to loop around to reach
+/// the segment again. With the destination behind the origin the route still has to loop around.
+///
+/// Fails with [`Error::NotSnapped`] if a waypoint has no candidates or the graph can't be extended with a node for
+/// one of them.
+pub fn connect_waypoints_to_graph<G>(
   graph: G,
   origin: &mut MatchedWaypoint<G::NodeId>,
-  _: &mut MatchedWaypoint<G::NodeId>,
-) -> OverlayGraph<G> {
+  destination: &mut MatchedWaypoint<G::NodeId>,
+) -> Result<OverlayGraph<G>>
+where
+  G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + IntoGeometry + Extensible,
+{
+  connect_waypoints_with_u_turns(graph, origin, destination, |_| None)
+}
+
+/// Same as [`connect_waypoints_to_graph`], but the route may also turn around at the waypoints. `reverse` maps a
+/// node to the node of the same road in opposite direction.
+///
+/// Each origin candidate gets an origin node on its reverse, starting at the snapped position, so the route can turn
+/// around right at the origin instead of going around the block. A destination candidate whose reverse is an origin
+/// candidate with the destination ahead gets a node for the part of the reverse between the waypoints, so the route
+/// can drive up to the destination on the other side of the road and turn around there. The U-turn nodes are free
+/// unless the overlay is weighted with [`OverlayGraph::with_u_turn_penalty`].
+pub fn connect_waypoints_with_u_turns<G, R>(
+  graph: G,
+  origin: &mut MatchedWaypoint<G::NodeId>,
+  destination: &mut MatchedWaypoint<G::NodeId>,
+  reverse: R,
+) -> Result<OverlayGraph<G>>
+where
+  G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + IntoGeometry + Extensible,
+  R: Fn(G::NodeId) -> Option<G::NodeId>,
+{
+  if destination.snapped.is_empty() {
+    return Err(Error::NotSnapped);
+  }
+  let mut same_segment = Vec::new();
+  let mut u_turns = Vec::new();
+  let mut partial_u_turns = Vec::new();
+  for SnappedOnEdge(from, from_id) in &origin.snapped {
+    let from_reverse = reverse(*from_id);
+    if let Some(reverse_id) = from_reverse {
+      u_turns.push((reverse_id, *from));
+    }
+    for SnappedOnEdge(to, to_id) in &destination.snapped {
+      if from_id == to_id && from.factor <= to.factor {
+        same_segment.push((*from_id, *from, *to));
+      }
+      // Turning around at the origin, the destination is ahead on the reverse
+      if from_reverse == Some(*to_id) && 1.0 - from.factor <= to.factor {
+        let from = SnappedPosition {
+          factor: 1.0 - from.factor,
+          ..*from
+        };
+        partial_u_turns.push((*to_id, from, *to, *to));
+      }
+      // Turning around at the destination, which is ahead of the origin on the other side of the road
+      if reverse(*to_id) == Some(*from_id) && from.factor <= 1.0 - to.factor {
+        let on_reverse = SnappedPosition {
+          factor: 1.0 - to.factor,
+          ..*to
+        };
+        partial_u_turns.push((*from_id, *from, on_reverse, *to));
+      }
+    }
+  }
+
+  let mut overlay = connect_origin_to_graph(graph, origin)?;
+  for snapped in &mut destination.snapped {
+    snapped.1 = overlay.add_destination(snapped.1, snapped.0).ok_or(Error::NotSnapped)?;
+  }
+  for (id, from, to) in same_segment {
+    let partial = overlay.add_partial(id, from, to).ok_or(Error::NotSnapped)?;
+    origin.snapped.push(SnappedOnEdge(from, partial));
+    destination.snapped.push(SnappedOnEdge(to, partial));
+  }
+  for (reverse_id, from) in u_turns {
+    let u_turn = overlay.add_u_turn(reverse_id, from).ok_or(Error::NotSnapped)?;
+    let on_reverse = SnappedPosition {
+      factor: 1.0 - from.factor,
+      ..from
+    };
+    origin.snapped.push(SnappedOnEdge(on_reverse, u_turn));
+  }
+  // The destination keeps its snapped position, the route ends next to it
+  for (id, from, to, destination_snapped) in partial_u_turns {
+    let partial = overlay.add_partial_u_turn(id, from, to).ok_or(Error::NotSnapped)?;
+    origin.snapped.push(SnappedOnEdge(from, partial));
+    destination.snapped.push(SnappedOnEdge(destination_snapped, partial));
+  }
+  Ok(overlay)
+}
+
+/// Replaces the origin candidates with overlay nodes starting at the snapped positions. Fails with
+/// [`Error::NotSnapped`] like [`connect_waypoints_to_graph`].
+pub fn connect_origin_to_graph<G: Copy + IntoNeighbors<Forward> + IntoGeometry + Extensible>(
+  graph: G,
+  origin: &mut MatchedWaypoint<G::NodeId>,
+) -> Result<OverlayGraph<G>> {
+  if origin.snapped.is_empty() {
+    return Err(Error::NotSnapped);
+  }
   let mut overlay = OverlayGraph::new(graph);
 
   for snapped in &mut origin.snapped {
-    snapped.1 = overlay.add_origin(snapped.1, snapped.0).unwrap();
+    snapped.1 = overlay.add_origin(snapped.1, snapped.0).ok_or(Error::NotSnapped)?;
   }
 
-  overlay
+  Ok(overlay)
 }
 
-pub fn snap_and_route<G: Copy + RoutableGraph<P = Position> + Weighted>(
+/// Replaces the destination candidates with overlay nodes ending at the snapped positions, for the searches running
+/// backward from the destination. Fails with [`Error::NotSnapped`] like [`connect_waypoints_to_graph`].
+pub fn connect_destination_to_graph<G: Copy + IntoNeighbors<Backward> + IntoGeometry + Extensible>(
   graph: G,
-  from: &Position,
-  to: &Position,
-) -> Option<Route<G::Weight, G::NodeId>> {
-  let from_matched = match_waypoint(graph, from);
-  if from_matched.snapped.is_empty() {
-    println!(
-      "From ({}, {}) isn't snapped",
-      from_matched.waypoint.x, from_matched.waypoint.y
-    );
-    return None;
+  destination: &mut MatchedWaypoint<G::NodeId>,
+) -> Result<OverlayGraph<G>> {
+  if destination.snapped.is_empty() {
+    return Err(Error::NotSnapped);
   }
+  let mut overlay = OverlayGraph::new(graph);
 
-  let to_matched = match_waypoint(graph, to);
-  if to_matched.snapped.is_empty() {
-    println!(
-      "To ({}, {}) isn't snapped",
-      to_matched.waypoint.x, to_matched.waypoint.y
-    );
-    return None;
+  for snapped in &mut destination.snapped {
+    snapped.1 = overlay.add_destination(snapped.1, snapped.0).ok_or(Error::NotSnapped)?;
   }
 
-  route(graph, &from_matched, &to_matched)
+  Ok(overlay)
+}
+
+/// Snaps both positions to the graph and routes between them. The route fails with [`Error::NotSnapped`] if a
+/// position has no segment nearby and with [`Error::NoRoute`] if the destination can't be reached.
+pub fn snap_and_route<G: Copy + RoutableGraph<P = Position> + Weighted>(
+  graph: G,
+  from: &Position,
+  to: &Position,
+) -> TimedRoute<G::Weight, G::NodeId> {
+  let mut timing = QueryTiming::default();
+  let matched = timed(&mut timing.snapping, || snap_waypoints(graph, from, to));
+
+  let route = matched.and_then(|(from_matched, to_matched)| {
+    timed(&mut timing.search, || route(graph, &from_matched, &to_matched))
+  });
+  (route, timing)
 }
 
+/// Same as [`snap_and_route`], but the graph is weighted with the cost function
 pub fn snap_and_route_with_cost<
   W: Weight,
   G: Copy + RoutableGraph,
@@ -65,67 +271,848 @@ pub fn snap_and_route_with_cost<
   cost: C,
   from: &Position,
   to: &Position,
-) -> Option<Route<W, G::NodeId>> {
+) -> TimedRoute<W, G::NodeId> {
+  let mut timing = QueryTiming::default();
+  let matched = timed(&mut timing.snapping, || snap_waypoints(graph, from, to));
+
+  let route = matched.and_then(|(from_matched, to_matched)| {
+    timed(&mut timing.search, || route((graph, cost), &from_matched, &to_matched))
+  });
+  (route, timing)
+}
+
+fn snap_waypoints<G: Copy + IntoGeometry + Spatial>(
+  graph: G,
+  from: &Position,
+  to: &Position,
+) -> Result<MatchedPair<G::NodeId>> {
   let from_matched = match_waypoint(graph, from);
   if from_matched.snapped.is_empty() {
-    println!(
-      "From ({}, {}) isn't snapped",
-      from_matched.waypoint.x, from_matched.waypoint.y
-    );
-    return None;
+    return Err(Error::NotSnapped);
   }
-
   let to_matched = match_waypoint(graph, to);
   if to_matched.snapped.is_empty() {
-    println!(
-      "To ({}, {}) isn't snapped",
-      to_matched.waypoint.x, to_matched.waypoint.y
-    );
-    return None;
+    return Err(Error::NotSnapped);
   }
+  Ok((from_matched, to_matched))
+}
 
-  route((graph, cost), &from_matched, &to_matched)
+// Slower vehicles are treated as stopped, they can start in any direction
+const MOVING_SPEED_MPS: f32 = 2.0;
+// Origin candidates turned further than this from the heading can only be reached by turning around
+const U_TURN_ANGLE: f32 = 120.0;
+// Origin candidates turned further than this from the heading can only be reached after a stop
+const STOP_ANGLE: f32 = 60.0;
+
+/// Consecutive nodes of a route in the same region
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionSection<K> {
+  /// `None` for the nodes outside of all regions
+  pub region: Option<K>,
+  /// Positions of the section nodes in the route
+  pub nodes: Range<usize>,
 }
 
+/// Splits the route into sections of consecutive nodes in the same region, e.g. to report the distance driven in
+/// each country for toll estimation. A region the route enters twice has two sections.
+pub fn split_by_region<N: Identifier, K: PartialEq, F: Fn(N) -> Option<K>>(
+  ids: &[N],
+  region_of: F,
+) -> Vec<RegionSection<K>> {
+  let mut sections: Vec<RegionSection<K>> = Vec::new();
+  for (index, id) in ids.iter().enumerate() {
+    let region = region_of(*id);
+    match sections.last_mut() {
+      Some(section) if section.region == region => section.nodes.end = index + 1,
+      _ => sections.push(RegionSection {
+        region,
+        nodes: index..index + 1,
+      }),
+    }
+  }
+  sections
+}
+
+/// Heading and speed of a vehicle at the origin, e.g. when a moving vehicle is re-routed during navigation
+#[derive(Clone, Copy, Debug)]
+pub struct StartMotion {
+  /// Direction of travel in degrees clockwise from north
+  pub heading: f32,
+  pub speed_mps: f32,
+}
+
+/// Options for [`route_with_options`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteOptions<W: Weight> {
+  /// Motion of the vehicle at the origin, the route can start on any origin candidate if it's not set
+  pub start: Option<StartMotion>,
+  /// Start cost of the candidates going against the heading of a moving vehicle
+  pub u_turn_penalty: W,
+  /// Start cost of the candidates at a sharp angle to the heading of a moving vehicle
+  pub stop_penalty: W,
+}
+
+impl<W: Weight> RouteOptions<W> {
+  /// Cost of starting the route on the origin candidate
+  pub fn start_cost<G: Copy + IntoGeometry>(&self, graph: G, candidate: &SnappedOnEdge<G::NodeId>) -> W {
+    let motion = match self.start {
+      Some(motion) if motion.speed_mps >= MOVING_SPEED_MPS => motion,
+      _ => return Default::default(),
+    };
+    let geometry = Polyline::from(graph.geometry(candidate.1).collect::<Vec<_>>());
+    match heading_difference(&geometry, &candidate.0.snapped, motion.heading) {
+      Some(difference) if difference > U_TURN_ANGLE => self.u_turn_penalty,
+      Some(difference) if difference > STOP_ANGLE => self.stop_penalty,
+      _ => Default::default(),
+    }
+  }
+}
+
+/// Finds the cheapest route between any pair of origin and destination candidates.
+///
+/// A node is paid for when the route leaves it, so the cost of each destination node is added on top of the
+/// cost to reach it. The search continues until no other candidate pair can be cheaper.
 pub fn route<G: Copy + IntoNeighbors<Forward> + Weighted>(
   graph: G,
   from: &MatchedWaypoint<G::NodeId>,
   to: &MatchedWaypoint<G::NodeId>,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  let start_costs = vec![Default::default(); from.snapped.len()];
+  route_with_candidate_costs(graph, from, to, &CandidateCosts::whole_nodes(graph, start_costs, to))
+    .ok_or(Error::NoRoute)
+}
+
+/// Same as [`route`], but the route only pays for the parts of the origin and destination nodes it travels along,
+/// from and to the snapped positions, and each candidate costs getting to it from the waypoint, `snap_cost` of the
+/// snapping distance in meters. The best candidate pair is picked by the total cost, e.g. a candidate a bit further
+/// away than the closest one can be the better start if it's near the end of its node.
+///
+/// The candidates are expected to be nodes of the graph, not overlay nodes which already start or end at the snapped
+/// positions, their parts would be left out twice. A destination candidate behind an origin candidate on the same
+/// node can't be reached, the search doesn't return to the nodes it starts from.
+pub fn route_from_candidates<G, S>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  snap_cost: S,
+) -> Result<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: ScalableWeight + Sub<Output = G::Weight>,
+  S: Fn(f32) -> G::Weight,
+{
+  let (costs, shift) = CandidateCosts::snapped(graph, from, to, snap_cost);
+  route_with_candidate_costs(graph, from, to, &costs)
+    .map(|route| Route {
+      cost: route.cost - shift,
+      ..route
+    })
+    .ok_or(Error::NoRoute)
+}
+
+/// Same as [`route`], but gives up on the routes costing more than `max_cost`. The search stops once every node left
+/// costs more, so a query without a route, e.g. from an island, settles only the nodes within the limit instead of
+/// every node reachable from the origin.
+pub fn route_with_limit<G: Copy + IntoNeighbors<Forward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  max_cost: G::Weight,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  let start_costs = vec![Default::default(); from.snapped.len()];
+  let costs = CandidateCosts::whole_nodes(graph, start_costs, to).with_limit(max_cost);
+  route_with_candidate_costs(graph, from, to, &costs).ok_or(Error::NoRoute)
+}
+
+/// Same as [`route`], but only relaxes the transitions flagged for the cells of the destination candidates, so the
+/// search settles far fewer nodes. The flags must be computed in the weights of the graph, otherwise the route may be
+/// more expensive than the one of [`route`].
+pub fn route_with_arc_flags<G: Copy + IntoNeighbors<Forward, NodeId = Idx> + Weighted>(
+  graph: G,
+  flags: &ArcFlags,
+  from: &MatchedWaypoint<Idx>,
+  to: &MatchedWaypoint<Idx>,
+) -> Result<Route<G::Weight, Idx>> {
+  let mask = flags.mask(to.snapped.iter().map(|candidate| candidate.1));
+  route(FlaggedGraph::new(graph, flags, &mask), from, to)
+}
+
+/// Same as [`route`], but the origin candidates which don't suit the motion of the vehicle at the origin start with
+/// a penalty, so a moving vehicle isn't sent on a route starting with a U-turn. The penalty is a part of the route
+/// cost.
+///
+/// Start costs are computed from the geometry of the origin candidates, so they can be either nodes of the graph
+/// or overlay nodes starting at the snapped positions.
+pub fn route_with_options<G: Copy + IntoNeighbors<Forward> + IntoGeometry + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  options: &RouteOptions<G::Weight>,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  let start_costs: Vec<_> = from
+    .snapped
+    .iter()
+    .map(|candidate| options.start_cost(graph, candidate))
+    .collect();
+  route_with_candidate_costs(graph, from, to, &CandidateCosts::whole_nodes(graph, start_costs, to))
+    .ok_or(Error::NoRoute)
+}
+
+/// Finds every route between the origin and destination candidates which isn't dominated by another one, i.e. no
+/// other route is at least as cheap by every criterion of the weight. E.g. with [`BicriteriaGraph`] of the travel time
+/// and the toll money it's the fastest route, the cheapest one and the trade-offs in between.
+///
+/// The routes are sorted by their costs, so the first one is the route of [`route`] with the weights ordered by the
+/// first criterion. The search keeps a set of costs per node instead of one cost, so it's much slower than [`route`]
+/// and the number of routes can grow with the distance.
+///
+/// [`BicriteriaGraph`]: crate::graph_impl::BicriteriaGraph
+pub fn route_pareto<G>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+) -> Vec<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: ParetoWeight,
+{
+  let mut search: ParetoSearchSpace<G::Weight, G::NodeId> = ParetoSearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    search.init(*id);
+  }
+  let targets: HashMap<G::NodeId, usize> = to
+    .snapped
+    .iter()
+    .enumerate()
+    .rev()
+    .map(|(k, SnappedOnEdge(_, id))| (*id, k))
+    .collect();
+
+  // Cost, destination node and label of each route found so far
+  let mut found: Vec<(G::Weight, G::NodeId, usize)> = Vec::new();
+  while let Some((id, label, cost)) = search.pop() {
+    // The weights don't go below zero, so every route through a label dominated by a found route is dominated as well
+    if found.iter().any(|(total, _, _)| total.dominates(&cost)) {
+      continue;
+    }
+    if let Some(k) = targets.get(&id) {
+      let total = cost + graph.transition_weight(to.snapped[*k].1, to.snapped[*k].1);
+      if !found.iter().any(|(other, _, _)| other.dominates(&total)) {
+        found.retain(|(other, _, _)| !total.dominates(other));
+        found.push((total, id, label));
+      }
+    }
+    search.expand(graph, id, label);
+  }
+
+  found.sort_by_key(|(cost, _, _)| *cost);
+  found
+    .into_iter()
+    .map(|(cost, id, label)| {
+      let ids: Vec<_> = search.unwind(id, label).iter().rev().cloned().collect();
+      let origin_candidate = candidate_index(from, ids[0]).unwrap();
+      let destination_candidate = candidate_index(to, id).unwrap();
+      Route {
+        cost,
+        origin_candidate,
+        destination_candidate,
+        num_resolved: search.num_settled(),
+        quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+        ids,
+      }
+    })
+    .collect()
+}
+
+// Costs the searches from the origin and the destination candidates start with
+struct CandidateCosts<W> {
+  origin: Vec<W>,
+  // Cost of the part of each destination node up to the candidate
+  destination: Vec<W>,
+  // Positions of the candidates along their nodes, a route can only start and end on the same node if the origin is
+  // before the destination
+  origin_factors: Vec<f32>,
+  destination_factors: Vec<f32>,
+  // Routes costing more aren't searched for
+  max_cost: Option<W>,
+}
+
+impl<W: Weight> CandidateCosts<W> {
+  // The whole origin and destination nodes are paid for, the origin candidates start with the start costs
+  fn whole_nodes<G: Weighted<Weight = W>>(graph: G, start_costs: Vec<W>, to: &MatchedWaypoint<G::NodeId>) -> Self {
+    Self {
+      origin_factors: vec![0.0; start_costs.len()],
+      origin: start_costs,
+      destination: to.snapped.iter().map(|SnappedOnEdge(_, id)| graph.transition_weight(*id, *id)).collect(),
+      destination_factors: vec![0.0; to.snapped.len()],
+      max_cost: None,
+    }
+  }
+
+  fn with_limit(self, max_cost: W) -> Self {
+    Self {
+      max_cost: Some(max_cost),
+      ..self
+    }
+  }
+
+  fn allows_same_node(&self, origin: usize, destination: usize) -> bool {
+    self.origin_factors[origin] <= self.destination_factors[destination]
+  }
+
+  fn exceeds_limit(&self, cost: W) -> bool {
+    matches!(self.max_cost, Some(max_cost) if cost > max_cost)
+  }
+}
+
+impl<W: ScalableWeight + Sub<Output = W>> CandidateCosts<W> {
+  // Only the parts of the nodes from and to the snapped positions are paid for, plus the snapping costs. A search
+  // can't start below zero, so instead of subtracting the part of each origin node before its candidate, the parts of
+  // all the other origin nodes are added. The returned shift is the sum of the parts, every route costs it on top.
+  fn snapped<G, S>(
+    graph: G,
+    from: &MatchedWaypoint<G::NodeId>,
+    to: &MatchedWaypoint<G::NodeId>,
+    snap_cost: S,
+  ) -> (Self, W)
+  where
+    G: Weighted<Weight = W>,
+    S: Fn(f32) -> W,
+  {
+    let part = |SnappedOnEdge(snapped, id): &SnappedOnEdge<G::NodeId>| {
+      graph.transition_weight(*id, *id).scale(snapped.factor)
+    };
+    let skipped: Vec<_> = from.snapped.iter().map(part).collect();
+    let shift = skipped.iter().fold(W::default(), |sum, part| sum + *part);
+    let origin = from
+      .snapped
+      .iter()
+      .zip(&skipped)
+      .map(|(candidate, skipped)| snap_cost(candidate.0.distance) + (shift - *skipped))
+      .collect();
+    let destination = to.snapped.iter().map(|candidate| snap_cost(candidate.0.distance) + part(candidate)).collect();
+    let costs = Self {
+      origin,
+      destination,
+      origin_factors: from.snapped.iter().map(|candidate| candidate.0.factor).collect(),
+      destination_factors: to.snapped.iter().map(|candidate| candidate.0.factor).collect(),
+      max_cost: None,
+    };
+    (costs, shift)
+  }
+}
+
+// Index of the candidate on the node
+fn candidate_index<N: Identifier>(waypoint: &MatchedWaypoint<N>, id: N) -> Option<usize> {
+  waypoint.snapped.iter().position(|s| s.1 == id)
+}
+
+fn route_with_candidate_costs<G: Copy + IntoNeighbors<Forward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  costs: &CandidateCosts<G::Weight>,
 ) -> Option<Route<G::Weight, G::NodeId>> {
   let mut forward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
 
-  for SnappedOnEdge(_, id) in &from.snapped {
-    //todo: partial cost and augmented graph are needed to properly initialize the start and end edges
-    forward_search.init(*id);
+  for (SnappedOnEdge(_, id), start_cost) in from.snapped.iter().zip(&costs.origin) {
+    forward_search.init_with_cost(*id, *start_cost);
   }
 
-  let target_ids: HashSet<G::NodeId> = to.snapped.iter().map(|s| s.1).collect();
+  let targets: HashMap<G::NodeId, usize> = to
+    .snapped
+    .iter()
+    .enumerate()
+    .rev()
+    .map(|(k, SnappedOnEdge(_, id))| (*id, k))
+    .collect();
 
-  loop {
+  let mut best: Option<(G::Weight, G::NodeId)> = None;
+  while let Some((id, cost)) = forward_search.min() {
+    if let Some((best_cost, _)) = best {
+      if cost >= best_cost {
+        break;
+      }
+    }
+    // Every node left costs more, e.g. the origin is on an island the destination can't be reached from
+    if costs.exceeds_limit(cost) {
+      break;
+    }
+    if let Some(k) = targets.get(&id) {
+      // A route starting and ending on the same node behind the origin would have to go around
+      let same_node = forward_search.parent(id) == Some(id);
+      if !same_node || costs.allows_same_node(candidate_index(from, id).unwrap(), *k) {
+        let total = cost + costs.destination[*k];
+        match best {
+          Some((best_cost, _)) if best_cost <= total => {}
+          _ if costs.exceeds_limit(total) => {}
+          _ => best = Some((total, id)),
+        }
+      }
+    }
     forward_search.update(graph);
-    match forward_search.min() {
-      Some((id, value)) => {
-        if target_ids.contains(&id) {
-          return Some(Route {
-            cost: value,
-            // Need to reverse the list to get elements in the routing order
-            ids: forward_search.unwind(id).iter().rev().cloned().collect(),
-          });
+  }
+
+  best.map(|(cost, id)| {
+    // Need to reverse the list to get elements in the routing order
+    let ids: Vec<_> = forward_search.unwind(id).iter().rev().cloned().collect();
+    let origin_candidate = candidate_index(from, ids[0]).unwrap();
+    let destination_candidate = candidate_index(to, id).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: forward_search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+// Keeps the cheapest meeting of the forward and backward searches: the last node of the forward part of the route
+// and the first node of the backward part. Both are the same node if the searches meet at a node. Ties are broken by
+// the node ids, so the meeting doesn't depend on the order the searches find them in.
+fn update_meeting<W: Weight, N: Identifier>(best: &mut Option<(W, N, N)>, cost: W, forward_id: N, backward_id: N) {
+  let meeting = (cost, forward_id, backward_id);
+  match best {
+    Some(best_meeting) if *best_meeting <= meeting => {}
+    _ => *best = Some(meeting),
+  }
+}
+
+/// Same as [`route`], but runs a forward search from the origin and a backward search from the destination,
+/// which explores fewer nodes.
+///
+/// In the edge-based graph the searches don't necessarily meet at a node reached by both of them. They can also
+/// meet on a transition from a node reached only by the forward search to a node reached only by the backward
+/// search, so both kinds of meetings are checked whenever a node is settled.
+///
+/// Among the routes of the same cost, the searches return the same one on every run, but not necessarily the one
+/// [`route`] returns: the route goes through the meeting with the lowest node ids, not the nodes settled first from
+/// the origin.
+pub fn route_bidir<G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  let start_costs = vec![Default::default(); from.snapped.len()];
+  route_bidir_with_candidate_costs(graph, from, to, &CandidateCosts::whole_nodes(graph, start_costs, to))
+    .ok_or(Error::NoRoute)
+}
+
+/// Same as [`route_with_limit`], but runs the searches of [`route_bidir`]. Both searches stop once the routes left
+/// cost more than `max_cost`, including the search which goes on alone after the other one is exhausted.
+pub fn route_bidir_with_limit<G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  max_cost: G::Weight,
+) -> Result<Route<G::Weight, G::NodeId>> {
+  let start_costs = vec![Default::default(); from.snapped.len()];
+  let costs = CandidateCosts::whole_nodes(graph, start_costs, to).with_limit(max_cost);
+  route_bidir_with_candidate_costs(graph, from, to, &costs).ok_or(Error::NoRoute)
+}
+
+/// Same as [`route_from_candidates`], but runs the searches of [`route_bidir`]
+pub fn route_bidir_from_candidates<G, S>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  snap_cost: S,
+) -> Result<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted,
+  G::Weight: ScalableWeight + Sub<Output = G::Weight>,
+  S: Fn(f32) -> G::Weight,
+{
+  let (costs, shift) = CandidateCosts::snapped(graph, from, to, snap_cost);
+  route_bidir_with_candidate_costs(graph, from, to, &costs)
+    .map(|route| Route {
+      cost: route.cost - shift,
+      ..route
+    })
+    .ok_or(Error::NoRoute)
+}
+
+fn route_bidir_with_candidate_costs<G: Copy + IntoNeighbors<Forward> + IntoNeighbors<Backward> + Weighted>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  costs: &CandidateCosts<G::Weight>,
+) -> Option<Route<G::Weight, G::NodeId>> {
+  let reversed = ReversedGraph::new(graph);
+
+  let mut forward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+  for (SnappedOnEdge(_, id), start_cost) in from.snapped.iter().zip(&costs.origin) {
+    forward_search.init_with_cost(*id, *start_cost);
+  }
+  // Cost of the backward search includes the cost of the destination node
+  let mut backward_search: SearchSpace<G::Weight, G::NodeId> = SearchSpace::new();
+  for (SnappedOnEdge(_, id), target_cost) in to.snapped.iter().zip(&costs.destination) {
+    backward_search.init_with_cost(*id, *target_cost);
+  }
+  // A route starting and ending on the same node behind the origin would have to go around
+  let meets = |id: G::NodeId, forward_start: bool, backward_start: bool| {
+    !(forward_start && backward_start)
+      || costs.allows_same_node(candidate_index(from, id).unwrap(), candidate_index(to, id).unwrap())
+  };
+
+  let mut best: Option<(G::Weight, G::NodeId, G::NodeId)> = None;
+  loop {
+    let forward_min = forward_search.min();
+    let backward_min = backward_search.min();
+    // Every route which is not found yet costs at least the sum of both minimums, or the minimum of the only
+    // search which isn't exhausted. The search with the smaller queue goes on, so both settle about as many nodes
+    // even if one waypoint is in a dense city and the other one on a country road.
+    let (bound, forward_turn) = match (forward_min, backward_min) {
+      (Some((_, f)), Some((_, b))) => (f + b, forward_search.queue_len() <= backward_search.queue_len()),
+      (Some((_, f)), None) => (f, true),
+      (None, Some((_, b))) => (b, false),
+      (None, None) => break,
+    };
+    if let Some((best_cost, _, _)) = best {
+      if bound >= best_cost {
+        break;
+      }
+    }
+    if costs.exceeds_limit(bound) {
+      break;
+    }
+
+    if forward_turn {
+      let (id, _) = forward_min.unwrap();
+      let cost = forward_search.cost(id).unwrap();
+      if let Some(backward_cost) = backward_search.cost(id) {
+        if meets(id, forward_search.parent(id) == Some(id), backward_search.parent(id) == Some(id)) {
+          update_meeting(&mut best, cost + backward_cost, id, id);
+        }
+      }
+      for next in neighbors_forward(graph, id) {
+        if let Some(backward_cost) = backward_search.cost(next) {
+          update_meeting(&mut best, cost + graph.transition_weight(id, next) + backward_cost, id, next);
+        }
+      }
+      forward_search.update(graph);
+    } else {
+      let (id, _) = backward_min.unwrap();
+      let cost = backward_search.cost(id).unwrap();
+      if let Some(forward_cost) = forward_search.cost(id) {
+        if meets(id, forward_search.parent(id) == Some(id), backward_search.parent(id) == Some(id)) {
+          update_meeting(&mut best, forward_cost + cost, id, id);
         }
       }
-      None => return None,
+      for previous in neighbors_forward(reversed, id) {
+        if let Some(forward_cost) = forward_search.cost(previous) {
+          update_meeting(&mut best, forward_cost + graph.transition_weight(previous, id) + cost, previous, id);
+        }
+      }
+      backward_search.update(reversed);
     }
   }
+
+  // The searches can meet on a route above the limit before their minimums exceed it
+  let best = best.filter(|(cost, _, _)| !costs.exceeds_limit(*cost));
+  best.map(|(cost, forward_id, backward_id)| {
+    // Forward part is unwound from the meeting towards the origin, backward part is already in the routing order
+    let mut ids: Vec<_> = forward_search.unwind(forward_id).iter().rev().cloned().collect();
+    let backward_ids = backward_search.unwind(backward_id);
+    // The node where the searches meet is a part of both
+    let shared = if forward_id == backward_id { 1 } else { 0 };
+    ids.extend(backward_ids.iter().skip(shared));
+
+    let origin_candidate = candidate_index(from, ids[0]).unwrap();
+    let destination_candidate = candidate_index(to, ids[ids.len() - 1]).unwrap();
+    Route {
+      cost,
+      origin_candidate,
+      destination_candidate,
+      num_resolved: forward_search.num_resolved() + backward_search.num_resolved(),
+      quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+      ids,
+    }
+  })
+}
+
+/// Thresholds of the alternative routes, see [`route_alternatives_with_options`]
+#[derive(Clone, Copy, Debug)]
+pub struct AlternativeOptions {
+  /// Largest share of the cost of an alternative spent on the nodes of the routes found before it
+  pub max_sharing: f32,
+  /// Largest cost of an alternative relative to the cost of the cheapest route
+  pub max_stretch: f32,
+  /// Factor the weights of the nodes of every route found are multiplied by for the next search
+  pub penalty: f32,
+  /// Searches run for each alternative asked for, the search gives up after that many rejected candidates
+  pub attempts_per_route: usize,
+}
+
+impl Default for AlternativeOptions {
+  fn default() -> Self {
+    Self {
+      max_sharing: 0.75,
+      max_stretch: 1.4,
+      penalty: 1.4,
+      attempts_per_route: 3,
+    }
+  }
+}
+
+/// Same as [`route_alternatives_with_options`] with the default thresholds
+pub fn route_alternatives<G>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  k: usize,
+) -> Vec<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: ScalableWeight,
+{
+  route_alternatives_with_options(graph, from, to, k, &AlternativeOptions::default())
+}
+
+/// Finds up to `k` meaningfully different routes, the cheapest one first.
+///
+/// Each search runs with the weights of the nodes of the routes found before multiplied by the penalty, so it's
+/// pushed away from them. A candidate is kept if at most `max_sharing` of its cost is spent on the nodes of the
+/// routes kept before and it costs at most `max_stretch` times the cheapest route. The costs of the routes are in the
+/// weights of the graph, without the penalties.
+pub fn route_alternatives_with_options<G>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+  k: usize,
+  options: &AlternativeOptions,
+) -> Vec<Route<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: ScalableWeight,
+{
+  let mut routes = Vec::new();
+  let cheapest = match route(graph, from, to) {
+    Ok(cheapest) if k > 0 => cheapest,
+    _ => return routes,
+  };
+  let max_cost = cheapest.cost.scale(options.max_stretch);
+  let mut penalties: HashMap<G::NodeId, f32> = HashMap::new();
+  penalize(&mut penalties, &cheapest.ids, options.penalty);
+  let mut used: HashSet<G::NodeId> = cheapest.ids.iter().cloned().collect();
+  routes.push(cheapest);
+
+  let mut attempts = (k - 1) * options.attempts_per_route;
+  while routes.len() < k && attempts > 0 {
+    attempts -= 1;
+    let factor = |id: G::NodeId| penalties.get(&id).cloned().unwrap_or(1.0);
+    let candidate = match route(PreferenceWeighted::new(graph, factor), from, to) {
+      Ok(candidate) => candidate,
+      Err(_) => break,
+    };
+    penalize(&mut penalties, &candidate.ids, options.penalty);
+
+    let costs: Vec<G::Weight> = (0..candidate.ids.len())
+      .map(|i| {
+        let next = candidate.ids.get(i + 1).unwrap_or(&candidate.ids[i]);
+        graph.transition_weight(candidate.ids[i], *next)
+      })
+      .collect();
+    let cost = costs.iter().fold(G::Weight::default(), |total, cost| total + *cost);
+    let shared = candidate
+      .ids
+      .iter()
+      .zip(&costs)
+      .filter(|(id, _)| used.contains(id))
+      .fold(G::Weight::default(), |total, (_, cost)| total + *cost);
+    if cost > max_cost || shared > cost.scale(options.max_sharing) {
+      continue;
+    }
+    used.extend(candidate.ids.iter().cloned());
+    routes.push(Route { cost, ..candidate });
+  }
+  routes
+}
+
+// Multiplies the penalties of the route nodes by the factor, a node of several routes is penalized for each of them
+fn penalize<N: Identifier>(penalties: &mut HashMap<N, f32>, ids: &[N], factor: f32) {
+  for id in ids {
+    *penalties.entry(*id).or_insert(1.0) *= factor;
+  }
+}
+
+/// Part of a route between two consecutive waypoints, see [`route_via`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteLeg<W> {
+  /// Positions of the leg nodes in the route. The node of a via waypoint ends one leg and starts the next one.
+  pub nodes: Range<usize>,
+  /// Cost of the leg, the node of a via waypoint is paid for by the leg leaving it
+  pub cost: W,
+}
+
+/// Route through a sequence of waypoints, see [`route_via`]
+pub struct ViaRoute<W: Weight, N: Identifier> {
+  /// Legs joined into one route, its cost is the sum of the leg costs
+  pub route: Route<W, N>,
+  pub legs: Vec<RouteLeg<W>>,
+}
+
+/// Finds the route from the first waypoint through the others in the given order, e.g. through the stops the user
+/// added to a route.
+///
+/// Each leg is routed by [`route`] and starts on the candidate the previous leg arrived at, so the route passes each
+/// via waypoint without turning or jumping to a parallel road. The node of a via waypoint is paid for once, by the leg
+/// leaving it. A single waypoint makes a route of one leg to itself. Fails with [`Error::NoRoute`] if there are no
+/// waypoints or one of the legs has no route.
+pub fn route_via<G>(graph: G, waypoints: &[MatchedWaypoint<G::NodeId>]) -> Result<ViaRoute<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: Sub<Output = G::Weight>,
+{
+  let mut via: Option<ViaRoute<G::Weight, G::NodeId>> = None;
+  for leg in waypoints.windows(2) {
+    let from = match &via {
+      Some(previous) => MatchedWaypoint {
+        waypoint: leg[0].waypoint,
+        snapped: vec![leg[0].snapped[previous.route.destination_candidate]],
+      },
+      None => leg[0].clone(),
+    };
+    let next = route(graph, &from, &leg[1])?;
+    via = Some(match via {
+      Some(previous) => join_legs(graph, previous, next),
+      None => ViaRoute {
+        legs: vec![RouteLeg {
+          nodes: 0..next.ids.len(),
+          cost: next.cost,
+        }],
+        route: next,
+      },
+    });
+  }
+  match via {
+    Some(via) => Ok(via),
+    None => {
+      let waypoint = waypoints.first().ok_or(Error::NoRoute)?;
+      let route = route(graph, waypoint, waypoint)?;
+      let legs = vec![RouteLeg {
+        nodes: 0..route.ids.len(),
+        cost: route.cost,
+      }];
+      Ok(ViaRoute { route, legs })
+    }
+  }
+}
+
+/// Route visiting all the waypoints of a trip, see [`trip`]
+pub struct TripRoute<W: Weight, N: Identifier> {
+  /// Indices of the waypoints in the order they are visited. A round trip returns to the first one at the end, it
+  /// isn't repeated here.
+  pub order: Vec<usize>,
+  /// Legs between the waypoints joined into one route, its cost is the sum of the leg costs
+  pub route: Route<W, N>,
+}
+
+/// Finds the cheapest order of visiting the waypoints and the route through them in that order, e.g. for the stops
+/// of a delivery tour.
+///
+/// The order is optimized on the matrix of the costs between all the waypoints by [`optimize_order`], the legs are
+/// routed one after the other then. Each leg starts on the candidate the previous one arrived at, so the route
+/// doesn't jump between the candidates of a waypoint. Fails with [`Error::Trip`] if no order is found and with
+/// [`Error::NoRoute`] if there are no waypoints.
+pub fn trip<G>(
+  graph: G,
+  waypoints: &[MatchedWaypoint<G::NodeId>],
+  options: &TripOptions,
+) -> Result<TripRoute<G::Weight, G::NodeId>>
+where
+  G: Copy + IntoNeighbors<Forward> + Weighted,
+  G::Weight: Sub<Output = G::Weight>,
+{
+  let costs = many_to_many(graph, waypoints, waypoints, &MatrixLimits::square(waypoints.len()))?;
+  let order = optimize_order(&costs, options)?;
+  let mut stops = order.clone();
+  if options.roundtrip {
+    stops.extend(order.first());
+  }
+
+  let stops: Vec<_> = stops.iter().map(|stop| waypoints[*stop].clone()).collect();
+  let route = route_via(graph, &stops)?.route;
+  Ok(TripRoute { order, route })
+}
+
+// Appends the leg starting at the node the route ends at. Both routes pay for the node, only the leg leaving it keeps
+// the cost.
+fn join_legs<G>(
+  graph: G,
+  via: ViaRoute<G::Weight, G::NodeId>,
+  leg: Route<G::Weight, G::NodeId>,
+) -> ViaRoute<G::Weight, G::NodeId>
+where
+  G: Copy + Weighted,
+  G::Weight: Sub<Output = G::Weight>,
+{
+  let ViaRoute { route, mut legs } = via;
+  let shared = graph.transition_weight(leg.ids[0], leg.ids[0]);
+  if let Some(last) = legs.last_mut() {
+    last.cost = last.cost - shared;
+  }
+  let start = route.ids.len() - 1;
+  legs.push(RouteLeg {
+    nodes: start..start + leg.ids.len(),
+    cost: leg.cost,
+  });
+  let mut ids = route.ids;
+  ids.extend(leg.ids.into_iter().skip(1));
+  let route = Route {
+    cost: route.cost + leg.cost - shared,
+    ids,
+    origin_candidate: route.origin_candidate,
+    destination_candidate: leg.destination_candidate,
+    num_resolved: route.num_resolved + leg.num_resolved,
+    quality: RouteQuality {
+      snap_distance: route.quality.snap_distance.max(leg.quality.snap_distance),
+      ..route.quality
+    },
+  };
+  ViaRoute { route, legs }
+}
+
+/// Drops the via candidate on the reverse counterpart of the node the previous leg arrived at, so the next leg
+/// can't start with an instant U-turn. `reverse` maps a node to the node of the same road in opposite direction.
+///
+/// The arrival node is a candidate of the via waypoint as well, so the next leg can always continue from it.
+pub fn forbid_u_turn<N: Identifier, R: Fn(N) -> Option<N>>(via: &mut MatchedWaypoint<N>, arrival: N, reverse: R) {
+  if let Some(reverse_id) = reverse(arrival) {
+    via.snapped.retain(|SnappedOnEdge(_, id)| *id != reverse_id);
+  }
+}
+
+/// What to do with the vertex shared by the geometries of consecutive route nodes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinVertices {
+  /// Concatenate the node geometries as they are, each join vertex appears twice
+  Keep,
+  /// Keep a single copy of each join vertex. The geometries must be continuous, which is checked in debug builds
+  Drop,
 }
 
 pub fn collect_route_geometry<G: Copy + IntoGeometry, Ids: Iterator<Item = G::NodeId>>(
   graph: G,
   ids: Ids,
+  joins: JoinVertices,
 ) -> Vec<Position> {
-  ids
-    .flat_map(|id| graph.geometry(id))
-    .map(|p| p.into())
-    .collect()
+  let mut result: Vec<Position> = Vec::new();
+  for id in ids {
+    let mut geometry = graph.geometry(id).map(|p| p.into()).peekable();
+    if joins == JoinVertices::Drop {
+      if let (Some(last), Some(first)) = (result.last(), geometry.peek()) {
+        debug_assert_eq!(last, first, "route geometry is not continuous at node {:?}", id);
+        if last == first {
+          geometry.next();
+        }
+      }
+    }
+    result.extend(geometry);
+  }
+  result
 }
 
 pub fn calculate_weight<G: Copy + Weighted, Ids: Iterator<Item = G::NodeId>>(
@@ -137,3 +1124,682 @@ where
 {
   ids.map(|id| graph.transition_weight(id, id)).sum()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::super::graph_impl::{BicriteriaGraph, CompactGraph};
+  use super::super::partition::Partition;
+  use super::super::test_utils::{graph_from_data_and_edges, graph_from_intersections, next_random, Segment};
+  use super::*;
+
+  fn matched(ids: &[u32]) -> MatchedWaypoint<u32> {
+    let position = Position { x: 0.0, y: 0.0 };
+    MatchedWaypoint {
+      waypoint: position,
+      snapped: ids
+        .iter()
+        .map(|id| {
+          let snapped = SnappedPosition {
+            snapped: position,
+            distance: 0.0,
+            factor: 0.0,
+          };
+          SnappedOnEdge(snapped, *id)
+        })
+        .collect(),
+    }
+  }
+
+  // Cost of leaving a node is the node data
+  fn node_cost(from: &u32, _to: &u32) -> u32 {
+    *from
+  }
+
+  #[test]
+  fn test_same_node_route() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2), (2, 0)]);
+
+    let route = route((&graph, node_cost), &matched(&[1]), &matched(&[1])).unwrap();
+
+    assert_eq!(route.ids, vec![1]);
+    assert_eq!(route.cost, 2);
+  }
+
+  #[test]
+  fn test_adjacent_nodes_route() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2), (2, 0)]);
+
+    let route = route((&graph, node_cost), &matched(&[0]), &matched(&[1])).unwrap();
+
+    assert_eq!(route.ids, vec![0, 1]);
+    assert_eq!(route.cost, 3);
+  }
+
+  #[test]
+  fn test_best_candidate_pair() {
+    // Node 3 is reached first, but it's expensive to travel along
+    let graph = graph_from_data_and_edges(vec![1, 5, 100, 50, 1], vec![(0, 3), (1, 4), (3, 2)]);
+
+    let route = route((&graph, node_cost), &matched(&[0, 1]), &matched(&[3, 4])).unwrap();
+
+    assert_eq!(route.ids, vec![1, 4]);
+    assert_eq!(route.cost, 6);
+    assert_eq!(route.origin_candidate, 1);
+    assert_eq!(route.destination_candidate, 1);
+  }
+
+  #[test]
+  fn test_pareto_routes() {
+    // Nodes are (time, toll): a fast toll road through 1, a free back road through 2, a cheaper toll road through 5
+    // in between, and a toll road through 3 which is slower than the back road
+    let data = vec![(1, 0), (10, 5), (30, 0), (40, 5), (1, 0), (20, 2)];
+    let graph = graph_from_data_and_edges(data, vec![(0, 1), (0, 2), (0, 3), (0, 5), (1, 4), (2, 4), (3, 4), (5, 4)]);
+    let time = (&graph, |from: &(u32, u32), _to: &(u32, u32)| from.0);
+    let toll = (&graph, |from: &(u32, u32), _to: &(u32, u32)| from.1);
+
+    let routes = route_pareto(BicriteriaGraph::new(time, toll), &matched(&[0]), &matched(&[4]));
+
+    let ids: Vec<_> = routes.iter().map(|route| route.ids.clone()).collect();
+    assert_eq!(ids, vec![vec![0, 1, 4], vec![0, 5, 4], vec![0, 2, 4]]);
+    let costs: Vec<_> = routes.iter().map(|route| route.cost).collect();
+    assert_eq!(costs, vec![Bicriteria(12, 5), Bicriteria(22, 2), Bicriteria(32, 0)]);
+    assert_eq!(route(time, &matched(&[0]), &matched(&[4])).unwrap().ids, routes[0].ids);
+  }
+
+  #[test]
+  fn test_forbid_u_turn() {
+    // Nodes 2 and 3 are the opposite directions of the same road, continuing from 3 is cheaper
+    let graph = graph_from_data_and_edges(vec![1, 1, 1, 1, 5], vec![(0, 2), (3, 1), (2, 4), (4, 1)]);
+    let reverse = |id: u32| match id {
+      2 => Some(3),
+      3 => Some(2),
+      _ => None,
+    };
+
+    let first_leg = route((&graph, node_cost), &matched(&[0]), &matched(&[2, 3])).unwrap();
+    let arrival = *first_leg.ids.last().unwrap();
+    assert_eq!(arrival, 2);
+
+    let mut via = matched(&[2, 3]);
+    forbid_u_turn(&mut via, arrival, reverse);
+    let second_leg = route((&graph, node_cost), &via, &matched(&[1])).unwrap();
+
+    assert_eq!(second_leg.ids, vec![2, 4, 1]);
+  }
+
+  #[test]
+  fn test_route_with_moving_start() {
+    // Two-way road going north with a side road at its end, the vehicle is snapped to both directions of it
+    let positions = vec![
+      Position { x: 13.40, y: 52.50 },
+      Position { x: 13.40, y: 52.51 },
+      Position { x: 13.41, y: 52.51 },
+    ];
+    let graph = graph_from_intersections(positions, vec![(0, 1), (1, 0), (1, 2)]);
+    let cost = |_: &Segment, _: &Segment| 1;
+    let snapped = SnappedPosition {
+      snapped: Position { x: 13.40, y: 52.505 },
+      distance: 0.0,
+      factor: 0.5,
+    };
+    let from = MatchedWaypoint {
+      waypoint: snapped.snapped,
+      snapped: vec![SnappedOnEdge(snapped, 0), SnappedOnEdge(snapped, 1)],
+    };
+    let to = matched(&[2]);
+    let options = |heading: f32, speed_mps: f32| RouteOptions {
+      start: Some(StartMotion { heading, speed_mps }),
+      u_turn_penalty: 100,
+      stop_penalty: 20,
+    };
+
+    // A stopped vehicle can turn around and go north right away
+    let stopped = route_with_options((&graph, cost), &from, &to, &options(180.0, 0.0)).unwrap();
+    assert_eq!(stopped.ids, vec![0, 2]);
+    assert_eq!(stopped.cost, 2);
+
+    // A vehicle moving south continues and turns around at the end of the road
+    let moving_south = route_with_options((&graph, cost), &from, &to, &options(180.0, 10.0)).unwrap();
+    assert_eq!(moving_south.ids, vec![1, 0, 2]);
+    assert_eq!(moving_south.cost, 3);
+
+    // The road is perpendicular to the heading, both candidates need a stop
+    let moving_east = route_with_options((&graph, cost), &from, &to, &options(90.0, 10.0)).unwrap();
+    assert_eq!(moving_east.ids, vec![0, 2]);
+    assert_eq!(moving_east.cost, 22);
+  }
+
+  #[test]
+  fn test_no_route() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1)]);
+
+    assert!(matches!(route((&graph, node_cost), &matched(&[1]), &matched(&[2])), Err(Error::NoRoute)));
+    assert!(matches!(route_bidir((&graph, node_cost), &matched(&[1]), &matched(&[2])), Err(Error::NoRoute)));
+  }
+
+  #[test]
+  fn test_route_with_limit() {
+    // A street of 10 nodes and a node which can't be reached
+    let mut edges: Vec<_> = (0..9).map(|i| (i, i + 1)).collect();
+    edges.push((10, 0));
+    let graph = graph_from_data_and_edges(vec![1; 11], edges);
+    let (from, to, island) = (matched(&[0]), matched(&[9]), matched(&[10]));
+
+    assert_eq!(route_with_limit((&graph, node_cost), &from, &to, 10).unwrap().cost, 10);
+    assert!(route_with_limit((&graph, node_cost), &from, &to, 9).is_err());
+    assert!(route_with_limit((&graph, node_cost), &from, &island, 5).is_err());
+
+    assert_eq!(route_bidir_with_limit((&graph, node_cost), &from, &to, 10).unwrap().ids, (0..10).collect::<Vec<_>>());
+    assert!(route_bidir_with_limit((&graph, node_cost), &from, &to, 9).is_err());
+    assert!(route_bidir_with_limit((&graph, node_cost), &from, &island, 5).is_err());
+  }
+
+  #[test]
+  fn test_bidir_same_node() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2), (2, 0)]);
+
+    let route = route_bidir((&graph, node_cost), &matched(&[1]), &matched(&[1])).unwrap();
+
+    assert_eq!(route.ids, vec![1]);
+    assert_eq!(route.cost, 2);
+  }
+
+  #[test]
+  fn test_bidir_adjacent_nodes() {
+    // Searches meet on the transition 0 -> 1 without a node reached by both of them
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2), (2, 0)]);
+
+    let route = route_bidir((&graph, node_cost), &matched(&[0]), &matched(&[1])).unwrap();
+
+    assert_eq!(route.ids, vec![0, 1]);
+    assert_eq!(route.cost, 3);
+  }
+
+  #[test]
+  fn test_bidir_meeting_on_transition() {
+    // Nodes 1 and 2 are settled by the forward and the backward search respectively before they meet
+    let graph = graph_from_data_and_edges(vec![1, 1, 1, 1], vec![(0, 1), (1, 2), (2, 3)]);
+
+    let route = route_bidir((&graph, node_cost), &matched(&[0]), &matched(&[3])).unwrap();
+
+    assert_eq!(route.ids, vec![0, 1, 2, 3]);
+    assert_eq!(route.cost, 4);
+  }
+
+  #[test]
+  fn test_bidir_best_candidate_pair() {
+    let graph = graph_from_data_and_edges(vec![1, 5, 100, 50, 1], vec![(0, 3), (1, 4), (3, 2)]);
+
+    let route = route_bidir((&graph, node_cost), &matched(&[0, 1]), &matched(&[3, 4])).unwrap();
+
+    assert_eq!(route.ids, vec![1, 4]);
+    assert_eq!(route.cost, 6);
+    assert_eq!(route.origin_candidate, 1);
+    assert_eq!(route.destination_candidate, 1);
+  }
+
+  // Candidates at the snapping distance and the position along the node
+  fn snapped(candidates: &[(u32, f32, f32)]) -> MatchedWaypoint<u32> {
+    let position = Position { x: 0.0, y: 0.0 };
+    let snapped = candidates.iter().map(|(id, distance, factor)| {
+      let snapped = SnappedPosition {
+        snapped: position,
+        distance: *distance,
+        factor: *factor,
+      };
+      SnappedOnEdge(snapped, *id)
+    });
+    MatchedWaypoint {
+      waypoint: position,
+      snapped: snapped.collect(),
+    }
+  }
+
+  #[test]
+  fn test_candidate_costs() {
+    // Both origin nodes cost 100 and lead to node 2, 0 is snapped closer but 1 is snapped near its end
+    let graph = graph_from_data_and_edges(vec![100, 100, 1, 50], vec![(0, 2), (1, 2), (2, 3), (3, 2)]);
+    let snap_cost = |distance: f32| distance.round() as u32;
+    let from = snapped(&[(0, 5.0, 0.0), (1, 20.0, 0.9)]);
+    let to = snapped(&[(2, 0.0, 1.0)]);
+
+    for route in [
+      route_from_candidates((&graph, node_cost), &from, &to, snap_cost).unwrap(),
+      route_bidir_from_candidates((&graph, node_cost), &from, &to, snap_cost).unwrap(),
+    ] {
+      assert_eq!(route.ids, vec![1, 2]);
+      assert_eq!(route.origin_candidate, 1);
+      assert_eq!(route.cost, 20 + 10 + 1);
+    }
+
+    // On the same node the route only pays for the part between the candidates
+    let (from, to) = (snapped(&[(3, 0.0, 0.2)]), snapped(&[(3, 0.0, 0.8)]));
+    assert_eq!(route_from_candidates((&graph, node_cost), &from, &to, snap_cost).unwrap().cost, 30);
+    // The destination candidate on the same node is behind the origin, the route continues to the other one
+    let from = snapped(&[(3, 0.0, 0.8)]);
+    let to = snapped(&[(3, 0.0, 0.2), (2, 0.0, 1.0)]);
+    for route in [
+      route_from_candidates((&graph, node_cost), &from, &to, snap_cost).unwrap(),
+      route_bidir_from_candidates((&graph, node_cost), &from, &to, snap_cost).unwrap(),
+    ] {
+      assert_eq!(route.ids, vec![3, 2]);
+      assert_eq!(route.destination_candidate, 1);
+      assert_eq!(route.cost, 10 + 1);
+    }
+  }
+
+  #[test]
+  fn test_bidir_same_cost_as_route() {
+    let graph = graph_from_data_and_edges(
+      vec![1, 5, 2, 2, 1, 3, 1, 4],
+      vec![(0, 1), (0, 2), (1, 6), (2, 3), (3, 4), (4, 6), (2, 5), (5, 6), (6, 0), (6, 7), (7, 3), (4, 1)],
+    );
+    let weighted_graph = (&graph, node_cost);
+
+    for origin in 0..8 {
+      for destination in 0..8 {
+        let (from, to) = (matched(&[origin]), matched(&[destination]));
+        let expected = route(weighted_graph, &from, &to).unwrap();
+        let actual = route_bidir(weighted_graph, &from, &to).unwrap();
+
+        assert_eq!(actual.cost, expected.cost);
+        assert_eq!(calculate_weight(weighted_graph, actual.ids.iter().cloned()), actual.cost);
+        assert_eq!(actual.ids[0], origin);
+        assert_eq!(*actual.ids.last().unwrap(), destination);
+        for pair in actual.ids.windows(2) {
+          assert!(neighbors_forward(weighted_graph, pair[0]).any(|next| next == pair[1]));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_bidir_same_cost_as_route_on_random_graphs() {
+    let mut state = 11;
+    for _ in 0..20 {
+      let data: Vec<u32> = (0..30).map(|_| 1 + (next_random(&mut state) % 10) as u32).collect();
+      let edges: Vec<_> = (0..80)
+        .map(|_| ((next_random(&mut state) % 30) as usize, (next_random(&mut state) % 30) as usize))
+        .filter(|(from, to)| from != to)
+        .collect();
+      let graph = graph_from_data_and_edges(data, edges);
+      let weighted_graph = (&graph, node_cost);
+
+      for origin in 0..30 {
+        for destination in 0..30 {
+          let (from, to) = (matched(&[origin]), matched(&[destination]));
+          let expected = route(weighted_graph, &from, &to).ok().map(|route| route.cost);
+          let actual = route_bidir(weighted_graph, &from, &to);
+          assert_eq!(actual.as_ref().ok().map(|route| route.cost), expected);
+          if let Ok(actual) = actual {
+            assert_eq!(calculate_weight(weighted_graph, actual.ids.iter().cloned()), actual.cost);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_equal_cost_routes_are_reproducible() {
+    // Grid of 5 x 5 nodes where all 70 shortest routes across cost the same, once with the transitions added in the
+    // opposite order
+    let mut edges = Vec::new();
+    for i in 0..25 {
+      if i % 5 < 4 {
+        edges.extend(vec![(i, i + 1), (i + 1, i)]);
+      }
+      if i < 20 {
+        edges.extend(vec![(i, i + 5), (i + 5, i)]);
+      }
+    }
+    let graph = graph_from_data_and_edges(vec![1; 25], edges.clone());
+    let reversed = graph_from_data_and_edges(vec![1; 25], edges.into_iter().rev().collect());
+    let (from, to) = (matched(&[0]), matched(&[24]));
+
+    // Nodes of the same cost are settled by their ids, so the route takes the neighbor with the lowest id first
+    let expected = route((&graph, node_cost), &from, &to).unwrap();
+    assert_eq!(expected.ids, vec![0, 1, 2, 3, 4, 9, 14, 19, 24]);
+    assert_eq!(route((&graph, node_cost), &from, &to).unwrap().ids, expected.ids);
+    assert_eq!(route((&reversed, node_cost), &from, &to).unwrap().ids, expected.ids);
+
+    // The bidirectional searches pick one of the equal cost routes as well, again the same one on every run
+    let bidir = route_bidir((&graph, node_cost), &from, &to).unwrap();
+    assert_eq!((bidir.cost, &bidir.ids), (expected.cost, &expected.ids));
+    assert_eq!(route_bidir((&graph, node_cost), &from, &to).unwrap().ids, bidir.ids);
+    assert_eq!(route_bidir((&reversed, node_cost), &from, &to).unwrap().ids, bidir.ids);
+
+    // From 0 to 3 through 4 or through 1 and 2 at the same cost. The route goes through 4, which is settled before 2,
+    // the bidirectional searches meet on the other route, with the lower ids.
+    let graph = graph_from_data_and_edges(vec![1, 1, 1, 1, 2], vec![(0, 4), (4, 3), (0, 1), (1, 2), (2, 3)]);
+    let (from, to) = (matched(&[0]), matched(&[3]));
+    let expected = route((&graph, node_cost), &from, &to).unwrap();
+    assert_eq!(expected.ids, vec![0, 4, 3]);
+    let bidir = route_bidir((&graph, node_cost), &from, &to).unwrap();
+    assert_eq!((bidir.cost, &bidir.ids), (expected.cost, &vec![0, 1, 2, 3]));
+    assert_eq!(route_bidir((&graph, node_cost), &from, &to).unwrap().ids, bidir.ids);
+  }
+
+  #[test]
+  fn test_arc_flags_same_cost_as_route() {
+    // Grid of 8 x 8 nodes with transitions to the right, left, up and down neighbors
+    let side = 8;
+    let (mut offsets, mut references) = (vec![0], vec![]);
+    for id in 0..side * side {
+      let (x, y) = (id % side, id / side);
+      if x + 1 < side {
+        references.push(id + 1);
+      }
+      if x > 0 {
+        references.push(id - 1);
+      }
+      if y + 1 < side {
+        references.push(id + side);
+      }
+      if y > 0 {
+        references.push(id - side);
+      }
+      offsets.push(references.len());
+    }
+    let data: Vec<u32> = (0..side * side).map(|id| 1 + id * 7 % 5).collect();
+    let graph = CompactGraph::from_row_data(data, offsets, references);
+    let weighted_graph = (&graph, node_cost);
+    let partition = Partition::grow(&graph, (side * side) as usize, 8);
+    let flags = ArcFlags::compute(weighted_graph, partition);
+
+    let (mut resolved, mut resolved_with_flags) = (0, 0);
+    for origin in 0..side * side {
+      for destination in 0..side * side {
+        let (from, to) = (matched(&[origin]), matched(&[destination]));
+        let expected = route(weighted_graph, &from, &to).unwrap();
+        let actual = route_with_arc_flags(weighted_graph, &flags, &from, &to).unwrap();
+
+        assert_eq!(actual.cost, expected.cost);
+        assert_eq!(calculate_weight(weighted_graph, actual.ids.iter().cloned()), actual.cost);
+        resolved += expected.num_resolved;
+        resolved_with_flags += actual.num_resolved;
+      }
+    }
+    assert!(resolved_with_flags < resolved);
+  }
+
+  #[test]
+  fn test_route_geometry_joins() {
+    let positions = vec![
+      Position { x: 13.40, y: 52.50 },
+      Position { x: 13.40, y: 52.51 },
+      Position { x: 13.41, y: 52.51 },
+      Position { x: 13.41, y: 52.52 },
+    ];
+    let graph = graph_from_intersections(positions.clone(), vec![(0, 1), (1, 2), (2, 3)]);
+
+    let kept = collect_route_geometry(&graph, 0..3, JoinVertices::Keep);
+    assert_eq!(kept.len(), 6);
+
+    let dropped = collect_route_geometry(&graph, 0..3, JoinVertices::Drop);
+    assert_eq!(dropped, positions);
+
+    // The overlay node starts at the snapped position and ends at the join with the next node
+    let mut overlay = OverlayGraph::new(&graph);
+    let snapped = Position { x: 13.40, y: 52.505 };
+    let origin = overlay
+      .add_origin(
+        0,
+        SnappedPosition {
+          snapped,
+          distance: 0.0,
+          factor: 0.5,
+        },
+      )
+      .unwrap();
+    let geometry = collect_route_geometry(&overlay, vec![origin, 1].into_iter(), JoinVertices::Drop);
+    assert_eq!(geometry, vec![snapped, positions[1], positions[2]]);
+  }
+
+  #[test]
+  fn test_same_segment_route() {
+    // One-way ring of four roads, the waypoints are on the first one
+    let positions = vec![
+      Position { x: 13.40, y: 52.50 },
+      Position { x: 13.40, y: 52.51 },
+      Position { x: 13.41, y: 52.51 },
+      Position { x: 13.41, y: 52.50 },
+    ];
+    let graph = graph_from_intersections(positions.clone(), vec![(0, 1), (1, 2), (2, 3), (3, 0)]);
+    // Segments are 100 long, a partially traveled one costs its traveled share
+    let cost = |_: &Segment, _: &Segment, snapped: Option<SnappedPosition>| match snapped {
+      Some(snapped) => (100.0 * snapped.factor).round() as i32,
+      None => 100,
+    };
+    let on_first_road = |factor: f32| {
+      let snapped = SnappedPosition {
+        snapped: Position {
+          x: 13.40,
+          y: 52.50 + 0.01 * factor,
+        },
+        distance: 0.0,
+        factor,
+      };
+      MatchedWaypoint {
+        waypoint: snapped.snapped,
+        snapped: vec![SnappedOnEdge(snapped, 0)],
+      }
+    };
+
+    // The destination is ahead, the route is the part of the road between the waypoints
+    let (mut from, mut to) = (on_first_road(0.25), on_first_road(0.75));
+    let overlay = connect_waypoints_to_graph(&graph, &mut from, &mut to).unwrap();
+    let ahead = route((&overlay, cost), &from, &to).unwrap();
+    assert_eq!(ahead.ids.len(), 1);
+    assert_eq!(overlay.base_id(ahead.ids[0]), 0);
+    assert_eq!(ahead.cost, 50);
+    assert_eq!((ahead.origin_candidate, ahead.destination_candidate), (1, 1));
+    assert_eq!(
+      collect_route_geometry(&overlay, ahead.ids.iter().cloned(), JoinVertices::Drop),
+      vec![from.snapped[0].0.snapped, to.snapped[0].0.snapped]
+    );
+    assert_eq!(route_bidir((&overlay, cost), &from, &to).unwrap().cost, 50);
+
+    // The destination is behind, the route loops around the ring
+    let (mut from, mut to) = (on_first_road(0.75), on_first_road(0.25));
+    let overlay = connect_waypoints_to_graph(&graph, &mut from, &mut to).unwrap();
+    assert_eq!((from.snapped.len(), to.snapped.len()), (1, 1));
+    let behind = route((&overlay, cost), &from, &to).unwrap();
+    let base_ids: Vec<_> = behind.ids.iter().map(|id| overlay.base_id(*id)).collect();
+    assert_eq!(base_ids, vec![0, 1, 2, 3, 0]);
+    // The route ends at the destination, only the share of the first road before it is paid for
+    assert_eq!(behind.cost, 25 + 3 * 100 + 25);
+    assert_eq!(route_bidir((&overlay, cost), &from, &to).unwrap().cost, 25 + 3 * 100 + 25);
+    assert_eq!(
+      collect_route_geometry(&overlay, behind.ids.iter().cloned(), JoinVertices::Drop).last(),
+      Some(&to.snapped[0].0.snapped)
+    );
+
+    // A waypoint without candidates can't be connected
+    let mut off_graph = MatchedWaypoint {
+      waypoint: Position { x: 13.50, y: 52.50 },
+      snapped: Vec::new(),
+    };
+    assert!(matches!(connect_waypoints_to_graph(&graph, &mut from, &mut off_graph), Err(Error::NotSnapped)));
+    assert!(matches!(connect_origin_to_graph(&graph, &mut off_graph), Err(Error::NotSnapped)));
+  }
+
+  #[test]
+  fn test_u_turn_at_waypoints() {
+    // One-way ring of four roads, the first road is a two-way one with the segment 4 going back from 1 to 0
+    let positions = vec![
+      Position { x: 13.40, y: 52.50 },
+      Position { x: 13.40, y: 52.51 },
+      Position { x: 13.41, y: 52.51 },
+      Position { x: 13.41, y: 52.50 },
+    ];
+    let graph = graph_from_intersections(positions, vec![(0, 1), (1, 2), (2, 3), (3, 0), (1, 0)]);
+    let reverse = |id: u32| match id {
+      0 => Some(4),
+      4 => Some(0),
+      _ => None,
+    };
+    let cost = |_: &Segment, _: &Segment, snapped: Option<SnappedPosition>| match snapped {
+      Some(snapped) => (100.0 * snapped.factor).round() as i32,
+      None => 100,
+    };
+    // Waypoint at the share of the first road from 0 to 1, snapped to one of its segments
+    let on_first_road = |id: u32, share: f32| {
+      let snapped = SnappedPosition {
+        snapped: Position {
+          x: 13.40,
+          y: 52.50 + 0.01 * share,
+        },
+        distance: 0.0,
+        factor: if id == 0 { share } else { 1.0 - share },
+      };
+      MatchedWaypoint {
+        waypoint: snapped.snapped,
+        snapped: vec![SnappedOnEdge(snapped, id)],
+      }
+    };
+
+    // The destination is behind the origin on the other side of the road, by default the route turns at the
+    // junction 1
+    let (mut from, mut to) = (on_first_road(0, 0.75), on_first_road(4, 0.5));
+    let overlay = connect_waypoints_to_graph(&graph, &mut from, &mut to).unwrap();
+    assert_eq!(route((&overlay, cost), &from, &to).unwrap().cost, 25 + 50);
+
+    let (mut from, mut to) = (on_first_road(0, 0.75), on_first_road(4, 0.5));
+    let overlay = connect_waypoints_with_u_turns(&graph, &mut from, &mut to, reverse).unwrap();
+    let turned = route(overlay.with_u_turn_penalty(cost, 10), &from, &to).unwrap();
+    assert_eq!(turned.ids.len(), 1);
+    assert!(overlay.is_u_turn(turned.ids[0]));
+    assert_eq!(overlay.base_id(turned.ids[0]), 4);
+    assert_eq!(turned.cost, 25 + 10);
+    assert_eq!(route_bidir(overlay.with_u_turn_penalty(cost, 10), &from, &to).unwrap().cost, 25 + 10);
+    // Going around is cheaper than a costly U-turn
+    let around = route(overlay.with_u_turn_penalty(cost, 200), &from, &to).unwrap();
+    assert_eq!(around.cost, 25 + 50);
+    assert!(!around.ids.iter().any(|id| overlay.is_u_turn(*id)));
+
+    // The destination is ahead of the origin on the other side of the road, the route turns around at it
+    let (mut from, mut to) = (on_first_road(0, 0.25), on_first_road(4, 0.75));
+    let overlay = connect_waypoints_with_u_turns(&graph, &mut from, &mut to, reverse).unwrap();
+    let turned = route(overlay.with_u_turn_penalty(cost, 10), &from, &to).unwrap();
+    assert_eq!(overlay.base_id(turned.ids[0]), 0);
+    assert_eq!(turned.cost, 50 + 10);
+    assert_eq!(to.snapped[turned.destination_candidate].0, to.snapped[0].0);
+  }
+
+  #[test]
+  fn test_trip() {
+    // Nodes 0, 1, 2 and 3 in a row, connected in both directions
+    let graph = graph_from_data_and_edges(vec![1, 1, 1, 1], vec![(0, 1), (1, 0), (1, 2), (2, 1), (2, 3), (3, 2)]);
+    let open = TripOptions {
+      roundtrip: false,
+      fixed_source: true,
+      fixed_destination: true,
+    };
+    let waypoints = vec![matched(&[0]), matched(&[2]), matched(&[1]), matched(&[3])];
+    let open_trip = trip((&graph, node_cost), &waypoints, &open).unwrap();
+    assert_eq!(open_trip.order, vec![0, 2, 1, 3]);
+    assert_eq!(open_trip.route.ids, vec![0, 1, 2, 3]);
+    assert_eq!(open_trip.route.cost, 4);
+
+    let round_trip = trip((&graph, node_cost), &[matched(&[0]), matched(&[3])], &TripOptions::default()).unwrap();
+    assert_eq!(round_trip.order, vec![0, 1]);
+    assert_eq!(round_trip.route.ids, vec![0, 1, 2, 3, 2, 1, 0]);
+    assert_eq!(round_trip.route.cost, 7);
+
+    let unreachable = trip((&graph, node_cost), &[matched(&[0]), matched(&[])], &TripOptions::default());
+    assert!(matches!(unreachable, Err(Error::Trip(_))));
+  }
+
+  #[test]
+  fn test_route_via() {
+    // Nodes 0, 1, 2 and 3 in a row, connected in both directions, 3 is expensive to leave
+    let graph = graph_from_data_and_edges(vec![1, 2, 1, 5], vec![(0, 1), (1, 0), (1, 2), (2, 1), (2, 3), (3, 2)]);
+    let via = route_via((&graph, node_cost), &[matched(&[0]), matched(&[3]), matched(&[1])]).unwrap();
+    assert_eq!(via.route.ids, vec![0, 1, 2, 3, 2, 1]);
+    assert_eq!(via.route.cost, 1 + 2 + 1 + 5 + 1 + 2);
+    let legs: Vec<_> = via.legs.iter().map(|leg| (leg.nodes.clone(), leg.cost)).collect();
+    assert_eq!(legs, vec![(0..4, 4), (3..6, 8)]);
+    assert_eq!(via.legs.iter().map(|leg| leg.cost).sum::<u32>(), via.route.cost);
+
+    // The second leg starts on the candidate the first one arrived at, not on the cheaper one to leave
+    let via = route_via((&graph, node_cost), &[matched(&[0]), matched(&[1, 2]), matched(&[3])]).unwrap();
+    assert_eq!(via.route.ids, vec![0, 1, 2, 3]);
+    assert_eq!(via.route.destination_candidate, 0);
+    assert_eq!(via.legs[1].nodes, 1..4);
+
+    let single = route_via((&graph, node_cost), &[matched(&[2])]).unwrap();
+    assert_eq!(single.legs, vec![RouteLeg { nodes: 0..1, cost: 1 }]);
+    assert!(matches!(route_via((&graph, node_cost), &[matched(&[0]), matched(&[])]), Err(Error::NoRoute)));
+    let no_waypoints: &[MatchedWaypoint<u32>] = &[];
+    assert!(matches!(route_via((&graph, node_cost), no_waypoints), Err(Error::NoRoute)));
+  }
+
+  #[test]
+  fn test_route_alternatives() {
+    // From 0 to 4 through 1, 2 or 3, the route through 3 is much longer
+    let graph = graph_from_data_and_edges(
+      vec![1, 10, 11, 25, 1],
+      vec![(0, 1), (0, 2), (0, 3), (1, 4), (2, 4), (3, 4)],
+    );
+    let routes = route_alternatives((&graph, node_cost), &matched(&[0]), &matched(&[4]), 3);
+    let found: Vec<_> = routes.iter().map(|route| (route.ids.clone(), route.cost)).collect();
+    assert_eq!(found, vec![(vec![0, 1, 4], 12), (vec![0, 2, 4], 13)]);
+
+    let long_detours = AlternativeOptions {
+      max_stretch: 3.0,
+      ..Default::default()
+    };
+    let routes = route_alternatives_with_options((&graph, node_cost), &matched(&[0]), &matched(&[4]), 3, &long_detours);
+    assert_eq!(routes.len(), 3);
+    assert_eq!((routes[2].ids.clone(), routes[2].cost), (vec![0, 3, 4], 27));
+
+    // The alternatives have to share less than a tenth of their cost with the cheapest route
+    let distinct = AlternativeOptions {
+      max_sharing: 0.1,
+      ..Default::default()
+    };
+    let routes = route_alternatives_with_options((&graph, node_cost), &matched(&[0]), &matched(&[4]), 3, &distinct);
+    assert_eq!(routes.len(), 1);
+    assert!(route_alternatives((&graph, node_cost), &matched(&[0]), &matched(&[4]), 0).is_empty());
+  }
+
+  #[test]
+  fn test_route_quality() {
+    let graph = graph_from_data_and_edges(vec![1, 2, 3], vec![(0, 1), (1, 2)]);
+    let mut to = matched(&[2]);
+    to.snapped[0].0.distance = 50.0;
+
+    let route = route((&graph, node_cost), &matched(&[0]), &to).unwrap();
+    assert_eq!(route.quality.snap_distance, 50.0);
+    assert_eq!(route_bidir((&graph, node_cost), &matched(&[0]), &to).unwrap().quality, route.quality);
+
+    let mut quality = route.quality;
+    // Node 1 is a low class road
+    quality.low_class_share = low_class_share(&route.ids, |id| (*graph.data(id) as f32, id == 1));
+    assert_eq!(quality.low_class_share, 2.0 / 6.0);
+    assert!(quality.score() < route.quality.score());
+
+    let mut fallback = quality;
+    fallback.fallback = true;
+    assert!(fallback.score() < quality.score());
+    assert_eq!(RouteQuality::default().score(), 1.0);
+  }
+
+  #[test]
+  fn test_split_by_region() {
+    let regions = |id: u32| match id {
+      0..=2 => Some("DE"),
+      3 => None,
+      _ => Some("PL"),
+    };
+
+    let sections = split_by_region(&[1, 2, 3, 4, 5, 0], regions);
+
+    let summary: Vec<_> = sections.iter().map(|s| (s.region, s.nodes.clone())).collect();
+    assert_eq!(
+      summary,
+      vec![(Some("DE"), 0..2), (None, 2..3), (Some("PL"), 3..5), (Some("DE"), 5..6)]
+    );
+    assert!(split_by_region(&[], regions).is_empty());
+  }
+}
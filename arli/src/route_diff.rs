@@ -0,0 +1,228 @@
+//! Differences of two routes between the same waypoints, e.g. before and after a graph update, with the changes of the
+//! segments explaining why the route changed.
+//!
+//! Node ids aren't kept between the graph builds, so the routes are compared by the keys of their segments, e.g. the
+//! OSM way and the nodes the segment goes between, and the weights are looked up by the key in each graph.
+
+use crate::graph::Weight;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Change of a segment between the graphs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentChange<W> {
+  /// Segment has the same weight in both graphs
+  Unchanged,
+  /// Segment is only in the graph before the update, e.g. a closed road
+  Removed,
+  /// Segment is only in the graph after the update, e.g. a new road
+  Added,
+  /// Weight of the segment changed, e.g. by a new speed limit
+  WeightChanged { before: W, after: W },
+}
+
+impl<W: Weight> SegmentChange<W> {
+  /// Weight after the update compared to the one before, `None` unless the weight changed
+  pub fn weight_change(&self) -> Option<Ordering> {
+    match self {
+      SegmentChange::WeightChanged { before, after } => Some(after.cmp(before)),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChangedSegment<K, W> {
+  pub key: K,
+  pub change: SegmentChange<W>,
+}
+
+/// Most likely reason of a divergence, the first one found in the order of the variants
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DivergenceReason {
+  /// A segment of the route before the update is removed
+  SegmentRemoved,
+  /// A segment of the route after the update is new
+  SegmentAdded,
+  /// A segment of the route before the update got more expensive
+  WeightIncreased,
+  /// A segment of the route after the update got cheaper
+  WeightDecreased,
+  /// No segment of the divergence changed, e.g. a turn cost changed or the searches broke a tie differently
+  Unexplained,
+}
+
+/// Segments of the routes between two parts they share, one of the sides is empty if a route only makes a detour
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence<K, W> {
+  pub before: Vec<ChangedSegment<K, W>>,
+  pub after: Vec<ChangedSegment<K, W>>,
+}
+
+impl<K: Copy, W: Weight> Divergence<K, W> {
+  pub fn reason(&self) -> DivergenceReason {
+    let changes = |segments: &[ChangedSegment<K, W>]| segments.iter().map(|s| s.change).collect::<Vec<_>>();
+    let (before, after) = (changes(&self.before), changes(&self.after));
+    if before.contains(&SegmentChange::Removed) {
+      DivergenceReason::SegmentRemoved
+    } else if after.contains(&SegmentChange::Added) {
+      DivergenceReason::SegmentAdded
+    } else if before.iter().any(|change| change.weight_change() == Some(Ordering::Greater)) {
+      DivergenceReason::WeightIncreased
+    } else if after.iter().any(|change| change.weight_change() == Some(Ordering::Less)) {
+      DivergenceReason::WeightDecreased
+    } else {
+      DivergenceReason::Unexplained
+    }
+  }
+}
+
+/// Part of the routes, the parts are in the order of the routes
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteDiffPart<K, W> {
+  /// Segments of both routes
+  Shared(Vec<K>),
+  Diverging(Divergence<K, W>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteDiff<K, W> {
+  pub parts: Vec<RouteDiffPart<K, W>>,
+}
+
+impl<K, W> RouteDiff<K, W> {
+  /// The routes take the same segments
+  pub fn is_same(&self) -> bool {
+    self.divergences().next().is_none()
+  }
+
+  /// Segments both routes start with, all of them if the routes are the same
+  pub fn shared_prefix(&self) -> &[K] {
+    match self.parts.first() {
+      Some(RouteDiffPart::Shared(keys)) => keys,
+      _ => &[],
+    }
+  }
+
+  /// Segments both routes end with after the last divergence, empty if the routes are the same
+  pub fn shared_suffix(&self) -> &[K] {
+    match self.parts.last() {
+      Some(RouteDiffPart::Shared(keys)) if self.parts.len() > 1 => keys,
+      _ => &[],
+    }
+  }
+
+  pub fn divergences(&self) -> impl Iterator<Item = &Divergence<K, W>> {
+    self.parts.iter().filter_map(|part| match part {
+      RouteDiffPart::Diverging(divergence) => Some(divergence),
+      RouteDiffPart::Shared(_) => None,
+    })
+  }
+}
+
+fn segment_change<K: Copy, W: Weight>(
+  key: K,
+  before_weight: &impl Fn(K) -> Option<W>,
+  after_weight: &impl Fn(K) -> Option<W>,
+) -> ChangedSegment<K, W> {
+  let change = match (before_weight(key), after_weight(key)) {
+    (Some(before), Some(after)) if before != after => SegmentChange::WeightChanged { before, after },
+    (Some(_), Some(_)) => SegmentChange::Unchanged,
+    (Some(_), None) => SegmentChange::Removed,
+    (None, _) => SegmentChange::Added,
+  };
+  ChangedSegment { key, change }
+}
+
+/// Compares the segment keys of the routes before and after an update, the weights return `None` for the segments
+/// missing from their graph.
+///
+/// After a divergence the routes rejoin at the first segment of the route after the update which the route before
+/// takes later on, so a route crossing the other one without sharing a part isn't split there.
+pub fn diff_routes<K, W, B, A>(before: &[K], after: &[K], before_weight: B, after_weight: A) -> RouteDiff<K, W>
+where
+  K: Copy + Eq + Hash,
+  W: Weight,
+  B: Fn(K) -> Option<W>,
+  A: Fn(K) -> Option<W>,
+{
+  let mut positions = HashMap::new();
+  for (i, key) in before.iter().enumerate() {
+    positions.entry(*key).or_insert(i);
+  }
+  let changes = |keys: &[K]| -> Vec<_> {
+    keys.iter().map(|key| segment_change(*key, &before_weight, &after_weight)).collect()
+  };
+
+  let mut parts = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  loop {
+    let shared = before[i..].iter().zip(&after[j..]).take_while(|(b, a)| b == a).count();
+    if shared > 0 {
+      parts.push(RouteDiffPart::Shared(before[i..i + shared].to_vec()));
+      i += shared;
+      j += shared;
+    }
+    if i == before.len() && j == after.len() {
+      break;
+    }
+    let rejoin = (j..after.len()).find_map(|k| match positions.get(&after[k]) {
+      Some(&position) if position >= i => Some((position, k)),
+      _ => None,
+    });
+    let (next_i, next_j) = rejoin.unwrap_or((before.len(), after.len()));
+    parts.push(RouteDiffPart::Diverging(Divergence {
+      before: changes(&before[i..next_i]),
+      after: changes(&after[j..next_j]),
+    }));
+    i = next_i;
+    j = next_j;
+  }
+  RouteDiff { parts }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_diff_routes() {
+    // Segment 2 is closed and the route takes 8 instead, later 6 gets slower than 9
+    let before_weights: HashMap<u32, i32> = (1..=9).filter(|key| *key != 8).map(|key| (key, 10)).collect();
+    let mut after_weights: HashMap<u32, i32> = (1..=9).filter(|key| *key != 2).map(|key| (key, 10)).collect();
+    after_weights.insert(6, 30);
+    let before = [1, 2, 3, 4, 5, 6, 7];
+    let after = [1, 8, 3, 4, 5, 9, 7];
+    let diff = diff_routes(&before, &after, |key| before_weights.get(&key).cloned(), |key| {
+      after_weights.get(&key).cloned()
+    });
+    assert!(!diff.is_same());
+    assert_eq!(diff.shared_prefix(), &[1]);
+    assert_eq!(diff.shared_suffix(), &[7]);
+    assert_eq!(diff.parts[2], RouteDiffPart::Shared(vec![3, 4, 5]));
+    let divergences: Vec<_> = diff.divergences().collect();
+    assert_eq!(divergences.len(), 2);
+    assert_eq!(divergences[0].before, vec![ChangedSegment { key: 2, change: SegmentChange::Removed }]);
+    assert_eq!(divergences[0].after, vec![ChangedSegment { key: 8, change: SegmentChange::Added }]);
+    assert_eq!(divergences[0].reason(), DivergenceReason::SegmentRemoved);
+    let slower = SegmentChange::WeightChanged { before: 10, after: 30 };
+    assert_eq!(divergences[1].before, vec![ChangedSegment { key: 6, change: slower }]);
+    assert_eq!(divergences[1].reason(), DivergenceReason::WeightIncreased);
+
+    // A detour through unchanged segments has no explanation, e.g. it was a tie
+    let weight = |key: u32| Some(key as i32);
+    let detour = diff_routes(&[1, 2, 5], &[1, 3, 4, 2, 5], weight, weight);
+    assert_eq!(detour.shared_suffix(), &[2, 5]);
+    let divergence = detour.divergences().next().unwrap();
+    assert!(divergence.before.is_empty());
+    assert_eq!(divergence.after.len(), 2);
+    assert_eq!(divergence.reason(), DivergenceReason::Unexplained);
+
+    let same = diff_routes(&[1, 2], &[1, 2], weight, weight);
+    assert!(same.is_same());
+    assert_eq!(same.shared_prefix(), &[1, 2]);
+    assert!(same.shared_suffix().is_empty());
+  }
+}
@@ -1,6 +1,11 @@
+//! Search spaces of the label-setting searches, the nodes reached so far with their costs and parents.
+
 use crate::graph::*;
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use hashbrown::HashMap;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 struct State<W: Weight, N: Identifier> {
@@ -8,23 +13,36 @@ struct State<W: Weight, N: Identifier> {
   id: N,
 }
 
-impl<W: Weight, N: Identifier> Ord for State<W, N> {
+// Node in the queue, the key is the cost for Dijkstra and the cost plus the potential for A*
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Queued<W: Weight, N: Identifier> {
+  key: W,
+  cost: W,
+  id: N,
+}
+
+impl<W: Weight, N: Identifier> Ord for Queued<W, N> {
   fn cmp(&self, other: &Self) -> Ordering {
-    // Sorted desc
-    other.cost.cmp(&self.cost)
-    //todo: resolve ties with Node ID; .then_with(|| self.id.cmp(&other.id))
+    // Sorted desc, the ties are resolved by the cost and then by the node id, so the searches settle the nodes in the
+    // same order. A* settles the nodes of the same key by their cost, like Dijkstra does, and finds the same routes
+    // among the equal cost ones as long as the potentials are exact.
+    other
+      .key
+      .cmp(&self.key)
+      .then_with(|| other.cost.cmp(&self.cost))
+      .then_with(|| other.id.cmp(&self.id))
   }
 }
 
 // `PartialOrd` needs to be implemented as well.
-impl<W: Weight, N: Identifier> PartialOrd for State<W, N> {
+impl<W: Weight, N: Identifier> PartialOrd for Queued<W, N> {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
     Some(self.cmp(other))
   }
 }
 
 pub struct SearchSpace<W: Weight, N: Identifier> {
-  pq: BinaryHeap<State<W, N>>,
+  pq: BinaryHeap<Queued<W, N>>,
   resolved: HashMap<N, State<W, N>>,
 }
 
@@ -37,7 +55,27 @@ impl<W: Weight, N: Identifier> SearchSpace<W, N> {
   }
 
   pub fn min(&self) -> Option<(N, W)> {
-    self.pq.peek().map(|state| (state.id, state.cost))
+    self.pq.peek().map(|queued| (queued.id, queued.key))
+  }
+
+  /// Cost of the cheapest path found so far to the node
+  pub fn cost(&self, node: N) -> Option<W> {
+    self.resolved.get(&node).map(|state| state.cost)
+  }
+
+  /// Number of entries in the queue, a node queued more than once is counted each time
+  pub fn queue_len(&self) -> usize {
+    self.pq.len()
+  }
+
+  /// Number of nodes reached by the search
+  pub fn num_resolved(&self) -> usize {
+    self.resolved.len()
+  }
+
+  /// Predecessor of the node on the cheapest path found so far, the start nodes are their own predecessors
+  pub fn parent(&self, node: N) -> Option<N> {
+    self.resolved.get(&node).map(|state| state.id)
   }
 
   pub fn init(&mut self, node: N) {
@@ -48,6 +86,29 @@ impl<W: Weight, N: Identifier> SearchSpace<W, N> {
     self.resolve(node, node, cost);
   }
 
+  /// Starts an A* search from the node, `potential` is the estimated cost from the node to the target
+  pub fn init_with_potential(&mut self, node: N, cost: W, potential: W) {
+    self.resolve_with_key(node, node, cost, cost + potential);
+  }
+
+  /// Removes the node with the lowest key from the queue, together with its key. The same node can be returned
+  /// again with a higher key if it was queued more than once.
+  pub fn pop(&mut self) -> Option<(N, W)> {
+    self.pq.pop().map(|queued| (queued.id, queued.key))
+  }
+
+  /// Queues the node with the key if the path through the parent is cheaper than the one found so far, returns
+  /// whether it was
+  pub fn relax_with_key(&mut self, node: N, parent_node: N, path_cost: W, key: W) -> bool {
+    if let Some(state) = self.resolved.get(&node) {
+      if path_cost >= state.cost {
+        return false;
+      }
+    }
+    self.resolve_with_key(node, parent_node, path_cost, key);
+    true
+  }
+
   pub fn unwind(&self, node: N) -> Vec<N> {
     let mut result: Vec<N> = Vec::new();
 
@@ -70,7 +131,7 @@ impl<W: Weight, N: Identifier> SearchSpace<W, N> {
   where
     G: Copy + Weighted<Weight = W> + IntoNeighbors<Forward, NodeId = N>,
   {
-    if let Some(State { cost, id }) = self.pq.pop() {
+    if let Some(Queued { cost, id, .. }) = self.pq.pop() {
       //println!("PQ: {} @ {}", id, cost);
 
       if let Some(resolved) = self.resolved.get(&id) {
@@ -98,7 +159,72 @@ impl<W: Weight, N: Identifier> SearchSpace<W, N> {
     false
   }
 
+  /// Same as [`update`](Self::update), but the queue is ordered by the path cost plus the potential of the node,
+  /// like in A*. The potential must not overestimate the remaining cost. Nodes without a potential can't reach the
+  /// target and are never queued.
+  pub fn update_with_potential<G, P>(&mut self, graph: G, potential: P) -> bool
+  where
+    G: Copy + Weighted<Weight = W> + IntoNeighbors<Forward, NodeId = N>,
+    P: Fn(N) -> Option<W>,
+  {
+    if let Some(Queued { key, id, .. }) = self.pq.pop() {
+      let cost = self.resolved[&id].cost;
+      if let Some(node_potential) = potential(id) {
+        if key > cost + node_potential {
+          return true;
+        }
+      }
+
+      for target_id in neighbors_forward(graph, id) {
+        let path_cost: W = cost + graph.transition_weight(id, target_id);
+        if let Some(target_state) = self.resolved.get(&target_id) {
+          if path_cost >= target_state.cost {
+            continue;
+          }
+        }
+        if let Some(target_potential) = potential(target_id) {
+          self.resolve_with_key(target_id, id, path_cost, path_cost + target_potential);
+        }
+      }
+
+      return true;
+    }
+    false
+  }
+
+  /// Same as [`update`](Self::update) for a time dependent graph, the cost of a node is the time it's reached at.
+  /// Transitions are weighted at that time, so the weights must not let a later start arrive earlier.
+  pub fn update_time_dependent<G>(&mut self, graph: G) -> bool
+  where
+    G: Copy + TimeDependent<Weight = W> + IntoNeighbors<Forward, NodeId = N>,
+  {
+    if let Some(Queued { cost, id, .. }) = self.pq.pop() {
+      if let Some(resolved) = self.resolved.get(&id) {
+        if cost > resolved.cost {
+          return true;
+        }
+      }
+
+      for target_id in neighbors_forward(graph, id) {
+        let path_cost: W = cost + graph.transition_weight_at(id, target_id, cost);
+        if let Some(target_state) = self.resolved.get(&target_id) {
+          if path_cost >= target_state.cost {
+            continue;
+          }
+        }
+        self.resolve(target_id, id, path_cost);
+      }
+
+      return true;
+    }
+    false
+  }
+
   fn resolve(&mut self, node: N, parent_node: N, path_cost: W) {
+    self.resolve_with_key(node, parent_node, path_cost, path_cost);
+  }
+
+  fn resolve_with_key(&mut self, node: N, parent_node: N, path_cost: W, key: W) {
     self.resolved.insert(
       node,
       State {
@@ -106,13 +232,153 @@ impl<W: Weight, N: Identifier> SearchSpace<W, N> {
         id: parent_node,
       },
     );
-    self.pq.push(State {
-      cost: path_cost,
+    self.pq.push(Queued { key, cost: path_cost, id: node });
+  }
+}
+
+impl<W: Weight, N: Identifier> Default for SearchSpace<W, N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Path to a node kept in its Pareto set
+struct Label<W, N> {
+  cost: W,
+  // Node and label the path comes from, `None` for the start nodes
+  parent: Option<(N, usize)>,
+  // A label dominated by a later one stays in the set, so the indices of the other labels don't change
+  dominated: bool,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct LabelState<W: Weight, N: Identifier> {
+  cost: W,
+  id: N,
+  label: usize,
+}
+
+impl<W: Weight, N: Identifier> Ord for LabelState<W, N> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    // Sorted desc
+    other.cost.cmp(&self.cost)
+  }
+}
+
+impl<W: Weight, N: Identifier> PartialOrd for LabelState<W, N> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// Search space of a multi-criteria search, which keeps every path to a node not dominated by another one.
+///
+/// A node has a Pareto set of labels instead of a single cost. The labels are settled in the order of their weights,
+/// and a label is never dominated by one settled after it, so a settled label which isn't dominated yet is final.
+pub struct ParetoSearchSpace<W: ParetoWeight, N: Identifier> {
+  pq: BinaryHeap<LabelState<W, N>>,
+  labels: HashMap<N, Vec<Label<W, N>>>,
+  settled: usize,
+}
+
+impl<W: ParetoWeight, N: Identifier> ParetoSearchSpace<W, N> {
+  pub fn new() -> Self {
+    Self {
+      pq: BinaryHeap::new(),
+      labels: HashMap::new(),
+      settled: 0,
+    }
+  }
+
+  pub fn init(&mut self, node: N) {
+    self.add_label(node, None, Default::default());
+  }
+
+  pub fn init_with_cost(&mut self, node: N, cost: W) {
+    self.add_label(node, None, cost);
+  }
+
+  pub fn cost(&self, node: N, label: usize) -> W {
+    self.labels[&node][label].cost
+  }
+
+  /// Node and label the path of the label comes from, `None` for the labels the search starts with
+  pub fn parent(&self, node: N, label: usize) -> Option<(N, usize)> {
+    self.labels[&node][label].parent
+  }
+
+  /// Number of labels settled by the search, a measure of the search effort
+  pub fn num_settled(&self) -> usize {
+    self.settled
+  }
+
+  /// Removes the cheapest label from the queue which isn't dominated, returns its node, index and cost
+  pub fn pop(&mut self) -> Option<(N, usize, W)> {
+    while let Some(LabelState { cost, id, label }) = self.pq.pop() {
+      if !self.labels[&id][label].dominated {
+        self.settled += 1;
+        return Some((id, label, cost));
+      }
+    }
+    None
+  }
+
+  /// Extends the path of a popped label by the transitions leaving its node
+  pub fn expand<G>(&mut self, graph: G, node: N, label: usize)
+  where
+    G: Copy + Weighted<Weight = W> + IntoNeighbors<Forward, NodeId = N>,
+  {
+    let cost = self.labels[&node][label].cost;
+    for target_id in neighbors_forward(graph, node) {
+      self.relax(target_id, node, label, cost + graph.transition_weight(node, target_id));
+    }
+  }
+
+  /// Adds a label extending the path of the parent label, for the searches computing the costs themselves, e.g. with
+  /// a criterion which isn't a sum along the path
+  pub fn relax(&mut self, node: N, parent_node: N, parent_label: usize, cost: W) {
+    self.add_label(node, Some((parent_node, parent_label)), cost);
+  }
+
+  /// Nodes of the path of the label, from the label node back to the start
+  pub fn unwind(&self, node: N, label: usize) -> Vec<N> {
+    let mut result = vec![node];
+    let mut current = self.labels[&node][label].parent;
+    while let Some((id, label)) = current {
+      result.push(id);
+      current = self.labels[&id][label].parent;
+    }
+    result
+  }
+
+  // Adds the label unless the node has a label at least as good, the labels it dominates are dropped
+  fn add_label(&mut self, node: N, parent: Option<(N, usize)>, cost: W) {
+    let labels = self.labels.entry(node).or_default();
+    if labels.iter().any(|other| !other.dominated && other.cost.dominates(&cost)) {
+      return;
+    }
+    for other in labels.iter_mut() {
+      other.dominated |= cost.dominates(&other.cost);
+    }
+    labels.push(Label {
+      cost,
+      parent,
+      dominated: false,
+    });
+    self.pq.push(LabelState {
+      cost,
       id: node,
+      label: labels.len() - 1,
     });
   }
 }
 
+impl<W: ParetoWeight, N: Identifier> Default for ParetoSearchSpace<W, N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::super::test_utils::graph_from_data_and_edges;
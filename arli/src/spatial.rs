@@ -1,8 +1,8 @@
 //! Geographic types.
 
 use geo::{
-  haversine_destination::HaversineDestination, haversine_distance::*,
-  line_locate_point::LineLocatePoint,
+  closest_point::ClosestPoint, haversine_destination::HaversineDestination, haversine_distance::*,
+  line_locate_point::LineLocatePoint, Closest,
 };
 
 use geo::{LineString, Point, Rect};
@@ -17,11 +17,15 @@ pub type Polyline = LineString<f32>;
 
 pub type BoundingBox = Rect<f32>;
 
+/// Area bounded by a ring of positions, with the rings of its holes
+pub type Polygon = geo::Polygon<f32>;
+
 
 pub fn envelope(center: &Position, distance_m: f32) -> BoundingBox {
   let center_point = Point::from(*center);
-  let right = center_point.haversine_destination(0., distance_m).0;
-  let top = center_point.haversine_destination(90., distance_m).0;
+  // Bearings are clockwise from north
+  let right = center_point.haversine_destination(90., distance_m).0;
+  let top = center_point.haversine_destination(0., distance_m).0;
 
   BoundingBox::new(
     Coordinate {
@@ -40,6 +44,25 @@ pub fn haversine_distance(from: &Position, to: &Position) -> f32 {
   Point::from(*from).haversine_distance(&Point::from(*to))
 }
 
+/// Shortest distance in meters from the position to the polyline, `None` if the polyline has no points
+pub fn distance_to_polyline(polyline: &Polyline, position: &Position) -> Option<f32> {
+  let point = Point::from(*position);
+  match polyline.closest_point(&point) {
+    Closest::SinglePoint(closest) => Some(point.haversine_distance(&closest)),
+    Closest::Intersection(_) => Some(0.0),
+    Closest::Indeterminate => None,
+  }
+}
+
+/// Initial bearing of the great circle from one position to another, in degrees clockwise from north in `[0, 360)`
+pub fn bearing(from: &Position, to: &Position) -> f32 {
+  let (lat1, lat2) = (from.y.to_radians(), to.y.to_radians());
+  let delta_lon = (to.x - from.x).to_radians();
+  let y = delta_lon.sin() * lat2.cos();
+  let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+  (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
 pub fn bounding_box<P: Iterator<Item = Position>>(points: P) -> Option<BoundingBox> {
   let mut extremes: Option<(Position, Position)> = None;
   for p in points {
@@ -53,6 +76,204 @@ pub fn bounding_box<P: Iterator<Item = Position>>(points: P) -> Option<BoundingB
   extremes.map(|e| BoundingBox::new(e.0, e.1))
 }
 
+/// Parts of the polyline within the bounding box, e.g. the part of a route on the screen. A polyline leaving the box
+/// and coming back has a part for each time it's in the box, the parts start and end where the polyline crosses the
+/// sides of the box.
+pub fn clip_polyline(points: &[Position], bbox: &BoundingBox) -> Vec<Vec<Position>> {
+  let mut parts = Vec::new();
+  if let [point] = points {
+    if clip_segment(*point, *point, bbox).is_some() {
+      parts.push(vec![*point]);
+    }
+    return parts;
+  }
+  let mut part: Vec<Position> = Vec::new();
+  for segment in points.windows(2) {
+    match clip_segment(segment[0], segment[1], bbox) {
+      Some((start, end)) => {
+        if part.last() != Some(&start) {
+          if !part.is_empty() {
+            parts.push(std::mem::take(&mut part));
+          }
+          part.push(start);
+        }
+        if part.last() != Some(&end) {
+          part.push(end);
+        }
+      }
+      None if !part.is_empty() => parts.push(std::mem::take(&mut part)),
+      None => {}
+    }
+  }
+  if !part.is_empty() {
+    parts.push(part);
+  }
+  parts
+}
+
+// Part of the segment within the bounding box by the Liang-Barsky algorithm, the ends of the segment within the box
+// are returned as they are
+fn clip_segment(from: Position, to: Position, bbox: &BoundingBox) -> Option<(Position, Position)> {
+  let (dx, dy) = (to.x - from.x, to.y - from.y);
+  let (min, max) = (bbox.min(), bbox.max());
+  let (mut enter, mut exit) = (0.0, 1.0);
+  // The segment is inside of each side where `direction * t <= distance`
+  let sides = [(-dx, from.x - min.x), (dx, max.x - from.x), (-dy, from.y - min.y), (dy, max.y - from.y)];
+  for (direction, distance) in sides.iter() {
+    if *direction == 0.0 {
+      if *distance < 0.0 {
+        return None;
+      }
+    } else if *direction < 0.0 {
+      enter = f32::max(enter, distance / direction);
+    } else {
+      exit = f32::min(exit, distance / direction);
+    }
+  }
+  if enter > exit {
+    return None;
+  }
+  let at = |t: f32| {
+    if t <= 0.0 {
+      from
+    } else if t >= 1.0 {
+      to
+    } else {
+      Position {
+        x: from.x + t * dx,
+        y: from.y + t * dy,
+      }
+    }
+  };
+  Some((at(enter), at(exit)))
+}
+
+/// Whether the position is inside the polygon and outside of its holes. A position on the boundary can be either.
+pub fn polygon_contains(polygon: &Polygon, position: &Position) -> bool {
+  ring_contains(&polygon.exterior().0, position)
+    && !polygon.interiors().iter().any(|hole| ring_contains(&hole.0, position))
+}
+
+/// Whether any part of the polyline is inside the polygon, e.g. a road through an area a route must avoid
+pub fn polyline_intersects_polygon(points: &[Position], polygon: &Polygon) -> bool {
+  if points.iter().any(|point| polygon_contains(polygon, point)) {
+    return true;
+  }
+  // Both ends of a segment can be outside of the polygon while the segment crosses it
+  let mut rings = std::iter::once(polygon.exterior()).chain(polygon.interiors());
+  rings.any(|ring| {
+    ring.0.windows(2).any(|edge| {
+      points.windows(2).any(|segment| segments_intersect(segment[0], segment[1], edge[0], edge[1]))
+    })
+  })
+}
+
+// Crossing number of a ray going east from the position, the rings of geo polygons are closed
+fn ring_contains(ring: &[Position], position: &Position) -> bool {
+  let mut inside = false;
+  for edge in ring.windows(2) {
+    let (a, b) = (edge[0], edge[1]);
+    if (a.y > position.y) != (b.y > position.y) && position.x < a.x + (position.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+      inside = !inside;
+    }
+  }
+  inside
+}
+
+// Whether the segments `a-b` and `c-d` cross or touch
+fn segments_intersect(a: Position, b: Position, c: Position, d: Position) -> bool {
+  // Positive if `q` is to the left of `o-p`, zero if it's on the line
+  let side = |o: Position, p: Position, q: Position| (p.x - o.x) * (q.y - o.y) - (p.y - o.y) * (q.x - o.x);
+  let within = |o: Position, p: Position, q: Position| {
+    q.x >= o.x.min(p.x) && q.x <= o.x.max(p.x) && q.y >= o.y.min(p.y) && q.y <= o.y.max(p.y)
+  };
+  let (a_side, b_side) = (side(c, d, a), side(c, d, b));
+  let (c_side, d_side) = (side(a, b, c), side(a, b, d));
+  if a_side * b_side < 0.0 && c_side * d_side < 0.0 {
+    return true;
+  }
+  (a_side == 0.0 && within(c, d, a))
+    || (b_side == 0.0 && within(c, d, b))
+    || (c_side == 0.0 && within(a, b, c))
+    || (d_side == 0.0 && within(a, b, d))
+}
+
+/// Point of a polyline at a known distance from its start
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DistanceMarker {
+  /// Distance from the start of the polyline in meters
+  pub distance: f32,
+  pub position: Position,
+  /// Index of the polyline point the marker follows
+  pub index: usize,
+  /// Relative position between the points `index` and `index + 1`
+  pub factor: f32,
+}
+
+impl DistanceMarker {
+  /// Interpolates a value given for every point of the polyline, e.g. elevation or speed, at the marker
+  pub fn interpolate(&self, values: &[f32]) -> f32 {
+    match values.get(self.index + 1) {
+      Some(next) => values[self.index] + (next - values[self.index]) * self.factor,
+      None => values[self.index],
+    }
+  }
+}
+
+/// Places markers along the polyline every `interval_m` meters, starting at its first point. The last point of the
+/// polyline is always included, so the last interval may be shorter. An interval which isn't positive places no
+/// markers in between.
+pub fn resample(points: &[Position], interval_m: f32) -> Vec<DistanceMarker> {
+  let mut result = Vec::new();
+  let first = match points.first() {
+    Some(first) => *first,
+    None => return result,
+  };
+  result.push(DistanceMarker {
+    distance: 0.0,
+    position: first,
+    index: 0,
+    factor: 0.0,
+  });
+
+  // NaN isn't positive either
+  let interval_m = if interval_m > 0.0 { interval_m } else { f32::INFINITY };
+  // Markers are counted instead of adding up the intervals, a short interval added to a long distance gets lost in
+  // the rounding and the distance stops growing
+  let mut count = 1;
+  let mut start_distance = 0.0;
+  let mut next_distance = interval_m;
+  for (index, line) in points.windows(2).enumerate() {
+    let length = haversine_distance(&line[0], &line[1]);
+    let end_distance = start_distance + length;
+    while next_distance < end_distance {
+      let factor = (next_distance - start_distance) / length;
+      result.push(DistanceMarker {
+        distance: next_distance,
+        position: Position {
+          x: line[0].x + (line[1].x - line[0].x) * factor,
+          y: line[0].y + (line[1].y - line[0].y) * factor,
+        },
+        index,
+        factor,
+      });
+      count += 1;
+      next_distance = count as f32 * interval_m;
+    }
+    start_distance = end_distance;
+  }
+
+  if points.len() > 1 {
+    result.push(DistanceMarker {
+      distance: start_distance,
+      position: points[points.len() - 1],
+      index: points.len() - 1,
+      factor: 0.0,
+    });
+  }
+  result
+}
+
 fn to_s2_latlng(p: &Position) -> s2::latlng::LatLng {
   LatLng::new(Angle::from(Deg(p.y as f64)), Angle::from(Deg(p.x as f64)))
 }
@@ -105,12 +326,13 @@ pub fn cut_geometry_after<T: Into<Position>, Geometry: Iterator<Item = T>>(
 ) -> Vec<Position> {
   let line_string: Polyline = geometry.collect();
 
+  // The start of the line the point is on is kept, unless the point is at the start
   let mut result: Vec<Position> = line_string
     .lines()
     .take_while(|line| {
       line
         .line_locate_point(&geo::Point::from(point))
-        .map_or(false, |factor| factor >= 1.0)
+        .map_or(false, |factor| factor > 0.0)
     })
     .map(|line| line.start_point().0)
     .collect();
@@ -123,6 +345,116 @@ pub fn cut_geometry_after<T: Into<Position>, Geometry: Iterator<Item = T>>(
 mod tests {
   use super::*;
 
+  #[test]
+  fn test_bearing() {
+    let origin = Position { x: 13.4, y: 52.5 };
+    let bearing_to = |x, y| bearing(&origin, &Position { x, y }).round();
+
+    assert_eq!(bearing_to(13.4, 52.6), 0.0);
+    assert_eq!(bearing_to(13.5, 52.5), 90.0);
+    assert_eq!(bearing_to(13.4, 52.4), 180.0);
+    assert_eq!(bearing_to(13.3, 52.5), 270.0);
+  }
+
+  #[test]
+  fn test_distance_to_polyline() {
+    let polyline = Polyline::from(vec![(13.4, 52.5), (13.41, 52.5)]);
+    let distance = |x, y| distance_to_polyline(&polyline, &Position { x, y }).unwrap();
+
+    assert_eq!(distance(13.405, 52.5), 0.0);
+    // 0.0009 degrees of latitude are about 100 meters
+    assert!((distance(13.405, 52.5009) - 100.0).abs() < 1.0);
+    assert!((distance(13.39, 52.5) - haversine_distance(&Position { x: 13.39, y: 52.5 }, &polyline.0[0])).abs() < 0.1);
+    assert!(distance_to_polyline(&Polyline::from(Vec::<Position>::new()), &polyline.0[0]).is_none());
+  }
+
+  #[test]
+  fn test_clip_polyline() {
+    let bbox = BoundingBox::new(Position { x: 0.0, y: 0.0 }, Position { x: 10.0, y: 10.0 });
+    let points = |coordinates: &[(f32, f32)]| coordinates.iter().map(|c| Position::from(*c)).collect::<Vec<_>>();
+
+    // Enters the box, leaves it and comes back
+    let parts = clip_polyline(&points(&[(-5.0, 5.0), (5.0, 5.0), (5.0, 15.0), (8.0, 15.0), (8.0, 5.0)]), &bbox);
+    assert_eq!(parts, vec![points(&[(0.0, 5.0), (5.0, 5.0), (5.0, 10.0)]), points(&[(8.0, 10.0), (8.0, 5.0)])]);
+
+    // Crosses the box without a point in it
+    let parts = clip_polyline(&points(&[(-5.0, 5.0), (15.0, 5.0)]), &bbox);
+    assert_eq!(parts, vec![points(&[(0.0, 5.0), (10.0, 5.0)])]);
+
+    let inside = points(&[(1.0, 1.0), (2.0, 2.0), (3.0, 1.0)]);
+    assert_eq!(clip_polyline(&inside, &bbox), vec![inside.clone()]);
+    assert!(clip_polyline(&points(&[(-5.0, 5.0), (5.0, 20.0)]), &bbox).is_empty());
+    assert_eq!(clip_polyline(&inside[..1], &bbox), vec![inside[..1].to_vec()]);
+  }
+
+  #[test]
+  fn test_polyline_intersects_polygon() {
+    // Square with a square hole in the middle
+    let square = LineString::from(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]);
+    let hole = LineString::from(vec![(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0)]);
+    let polygon = Polygon::new(square, vec![hole]);
+    let points = |coordinates: &[(f32, f32)]| coordinates.iter().map(|c| Position::from(*c)).collect::<Vec<_>>();
+
+    assert!(polygon_contains(&polygon, &Position { x: 2.0, y: 8.0 }));
+    assert!(!polygon_contains(&polygon, &Position { x: 5.0, y: 5.0 }));
+    assert!(!polygon_contains(&polygon, &Position { x: 12.0, y: 5.0 }));
+
+    assert!(polyline_intersects_polygon(&points(&[(-5.0, 5.0), (2.0, 5.0)]), &polygon));
+    // Crosses the polygon without a point in it
+    assert!(polyline_intersects_polygon(&points(&[(-5.0, 2.0), (15.0, 2.0)]), &polygon));
+    assert!(!polyline_intersects_polygon(&points(&[(-5.0, -2.0), (15.0, -2.0)]), &polygon));
+    // Within the hole
+    assert!(!polyline_intersects_polygon(&points(&[(4.5, 5.0), (5.5, 5.0)]), &polygon));
+  }
+
+  #[test]
+  fn test_envelope() {
+    let center = Position { x: 13.4, y: 52.5 };
+    let bbox = envelope(&center, 100.0);
+
+    // 100 meters are about 0.0009 degrees of latitude and 0.0015 degrees of longitude
+    assert!((bbox.height() - 0.0018).abs() < 0.0001);
+    assert!((bbox.width() - 0.003).abs() < 0.0001);
+    assert_eq!(bbox.center(), center);
+  }
+
+  #[test]
+  fn test_resample() {
+    // Roughly 1112 m north, then the same distance east along the equator
+    let points = vec![
+      Position { x: 0.0, y: 0.0 },
+      Position { x: 0.0, y: 0.01 },
+      Position { x: 0.01, y: 0.01 },
+    ];
+
+    let markers = resample(&points, 500.0);
+
+    let distances: Vec<_> = markers.iter().map(|m| m.distance.round()).collect();
+    assert_eq!(distances, vec![0.0, 500.0, 1000.0, 1500.0, 2000.0, 2224.0]);
+    assert_eq!(markers[1].index, 0);
+    assert_eq!(markers[3].index, 1);
+    assert!((markers[1].position.y - 0.0045).abs() < 1e-4);
+
+    let elevation = [100.0, 200.0, 200.0];
+    assert!((markers[1].interpolate(&elevation) - 145.0).abs() < 1.0);
+    assert_eq!(markers[5].interpolate(&elevation), 200.0);
+  }
+
+  #[test]
+  fn test_resample_empty() {
+    assert!(resample(&[], 100.0).is_empty());
+    assert_eq!(resample(&[Position { x: 1.0, y: 1.0 }], 100.0).len(), 1);
+  }
+
+  #[test]
+  fn test_resample_without_interval() {
+    let points = [Position { x: 0.0, y: 0.0 }, Position { x: 0.0, y: 0.01 }];
+    for interval_m in &[0.0, -100.0, f32::NAN] {
+      let distances: Vec<_> = resample(&points, *interval_m).iter().map(|m| m.distance.round()).collect();
+      assert_eq!(distances, vec![0.0, 1112.0], "interval: {}", interval_m);
+    }
+  }
+
   #[test]
   fn test_cut_geometry_before() {
     let coordinates: Vec<Position> = vec![
@@ -171,10 +503,19 @@ mod tests {
     };
     let result = cut_geometry_after(coordinates.iter().cloned(), cut_at);
 
-    assert_eq!(result.len(), 3);
+    assert_eq!(result.len(), 4);
 
     assert_eq!(result[0], coordinates[0]);
     assert_eq!(result[1], coordinates[1]);
-    assert_eq!(result[2], cut_at);
+    assert_eq!(result[2], coordinates[2]);
+    assert_eq!(result[3], cut_at);
+
+    // A single line is cut at the point, not replaced by it
+    let line = vec![coordinates[0], coordinates[1]];
+    let middle = Position {
+      x: (coordinates[0].x + coordinates[1].x) / 2.0,
+      y: (coordinates[0].y + coordinates[1].y) / 2.0,
+    };
+    assert_eq!(cut_geometry_after(line.into_iter(), middle), vec![coordinates[0], middle]);
   }
 }
@@ -1,5 +1,7 @@
 use crate::spatial::*;
 use crate::graph_impl::*;
+#[cfg(test)]
+use crate::waypoint::MatchedWaypoint;
 
 use std::collections::HashMap;
 
@@ -25,6 +27,19 @@ impl HasGeometry for Segment {
   }
 }
 
+// Linear congruential generator, enough for random test data without a dependency
+#[cfg(test)]
+pub fn next_random(state: &mut u64) -> u64 {
+  *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+  *state >> 33
+}
+
+// Waypoint matched exactly on a single node, for tests that don't care about snapping
+#[cfg(test)]
+pub fn matched(id: u32) -> MatchedWaypoint<u32> {
+  MatchedWaypoint::from_node(id, Position { x: 0.0, y: 0.0 })
+}
+
 pub fn graph_from_data_and_edges<T>(data: Vec<T>, edges: Vec<(usize, usize)>) -> DynamicGraph<T> {
   let mut graph = DynamicGraph::new_with_data(data);
   for (from, to) in edges {
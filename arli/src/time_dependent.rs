@@ -0,0 +1,198 @@
+//! Routes on graphs with time dependent weights, e.g. with the travel times of the rush hour.
+//!
+//! The searches run from a reference time: the departure from the origin or the arrival at the destination. The
+//! route cost is the travel time, so the other end of the route is the reference time plus or minus the cost.
+
+use crate::graph::*;
+use crate::graph_impl::ReversedGraph;
+use crate::route::{Route, RouteQuality};
+use crate::search_space::SearchSpace;
+use crate::waypoint::*;
+
+use std::collections::HashSet;
+
+/// Finds the fastest route leaving the origin at the reference time.
+///
+/// The time passed to the graph is the time since the departure when the transition starts.
+pub fn route_departing<G: Copy + IntoNeighbors<Forward> + TimeDependent>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+) -> Option<Route<G::Weight, G::NodeId>> {
+  let mut search = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &from.snapped {
+    search.init(*id);
+  }
+  let targets: HashSet<G::NodeId> = to.snapped.iter().map(|SnappedOnEdge(_, id)| *id).collect();
+
+  let mut best: Option<(G::Weight, G::NodeId)> = None;
+  while let Some((id, time)) = search.min() {
+    if let Some((best_time, _)) = best {
+      if time >= best_time {
+        break;
+      }
+    }
+    if targets.contains(&id) {
+      let arrival = time + graph.transition_weight_at(id, id, time);
+      match best {
+        Some((best_time, _)) if best_time <= arrival => {}
+        _ => best = Some((arrival, id)),
+      }
+    }
+    search.update_time_dependent(graph);
+  }
+
+  best.map(|(cost, id)| {
+    let ids: Vec<_> = search.unwind(id).iter().rev().cloned().collect();
+    make_route(cost, ids, from, to, search.num_resolved())
+  })
+}
+
+/// Finds the fastest route reaching the destination at the reference time, with a search running backward from the
+/// destination.
+///
+/// The time passed to the graph is the time left until the arrival when the transition ends. A transition is weighted
+/// as if it ended at that time, which is exact only if its weight doesn't change while it's traveled.
+pub fn route_arriving<G: Copy + IntoNeighbors<Backward> + TimeDependent>(
+  graph: G,
+  from: &MatchedWaypoint<G::NodeId>,
+  to: &MatchedWaypoint<G::NodeId>,
+) -> Option<Route<G::Weight, G::NodeId>> {
+  let reversed = ReversedGraph::new(graph);
+  let mut search = SearchSpace::new();
+  for SnappedOnEdge(_, id) in &to.snapped {
+    // The destination node is the last one traveled before the arrival
+    search.init_with_cost(*id, graph.transition_weight_at(*id, *id, Default::default()));
+  }
+  let targets: HashSet<G::NodeId> = from.snapped.iter().map(|SnappedOnEdge(_, id)| *id).collect();
+
+  // Costs of the origin nodes include traveling them, so the first settled origin is the fastest one
+  while let Some((id, time)) = search.min() {
+    if targets.contains(&id) && search.cost(id) == Some(time) {
+      let ids = search.unwind(id);
+      return Some(make_route(time, ids, from, to, search.num_resolved()));
+    }
+    search.update_time_dependent(reversed);
+  }
+  None
+}
+
+/// Travel time of the route on a time dependent graph, the first node is left at the reference time. Use a
+/// [`ReversedGraph`] and the nodes in the reversed order for the travel time of a route arriving at the reference time.
+pub fn calculate_time_dependent_weight<G: Copy + TimeDependent, Ids: Iterator<Item = G::NodeId>>(
+  graph: G,
+  ids: Ids,
+) -> G::Weight {
+  ids.fold(Default::default(), |time, id| time + graph.transition_weight_at(id, id, time))
+}
+
+fn make_route<W: Weight, N: Identifier>(
+  cost: W,
+  ids: Vec<N>,
+  from: &MatchedWaypoint<N>,
+  to: &MatchedWaypoint<N>,
+  num_resolved: usize,
+) -> Route<W, N> {
+  let origin_candidate = from.snapped.iter().position(|s| s.1 == ids[0]).unwrap();
+  let destination_candidate = to.snapped.iter().position(|s| s.1 == ids[ids.len() - 1]).unwrap();
+  Route {
+    cost,
+    origin_candidate,
+    destination_candidate,
+    num_resolved,
+    quality: RouteQuality::snapped(&from.snapped[origin_candidate], &to.snapped[destination_candidate]),
+    ids,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::route::route;
+  use super::super::test_utils::{graph_from_data_and_edges, matched};
+  use super::*;
+  use crate::graph_impl::DynamicGraph;
+
+  // Node 1 is a road jammed until the time 10, it takes 10 instead of 1 to travel it before then
+  #[derive(Clone, Copy)]
+  struct RushHour<'a> {
+    graph: &'a DynamicGraph<u32>,
+    // Time of the search reference
+    start: i32,
+    // Whether the search runs forward from the reference time or backward from it
+    forward: bool,
+  }
+
+  impl<'a> GraphBase for RushHour<'a> {
+    type NodeId = u32;
+  }
+
+  impl<'a> IntoNeighbors<Forward> for RushHour<'a> {
+    type Neighbors = <&'a DynamicGraph<u32> as IntoNeighbors<Forward>>::Neighbors;
+
+    fn neighbors(self, node: u32) -> Self::Neighbors {
+      neighbors_forward(self.graph, node)
+    }
+  }
+
+  impl<'a> IntoNeighbors<Backward> for RushHour<'a> {
+    type Neighbors = <&'a DynamicGraph<u32> as IntoNeighbors<Backward>>::Neighbors;
+
+    fn neighbors(self, node: u32) -> Self::Neighbors {
+      neighbors_backward(self.graph, node)
+    }
+  }
+
+  impl<'a> TimeDependent for RushHour<'a> {
+    type Weight = i32;
+
+    fn transition_weight_at(&self, from: u32, _to: u32, time: i32) -> i32 {
+      let clock = if self.forward { self.start + time } else { self.start - time };
+      match from {
+        1 if clock < 10 => 10,
+        _ => *self.graph.data(from) as i32,
+      }
+    }
+  }
+
+  #[test]
+  fn test_route_departing() {
+    // 0 -> 1 -> 3 is faster than 0 -> 2 -> 3 after the rush hour
+    let graph = graph_from_data_and_edges(vec![1, 1, 4, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let rush_hour = |start: i32| RushHour {
+      graph: &graph,
+      start,
+      forward: true,
+    };
+
+    let early = route_departing(rush_hour(0), &matched(0), &matched(3)).unwrap();
+    assert_eq!(early.ids, vec![0, 2, 3]);
+    assert_eq!(early.cost, 6);
+
+    let late = route_departing(rush_hour(10), &matched(0), &matched(3)).unwrap();
+    assert_eq!(late.ids, vec![0, 1, 3]);
+    assert_eq!(late.cost, route((&graph, |from: &u32, _to: &u32| *from as i32), &matched(0), &matched(3)).unwrap().cost);
+  }
+
+  #[test]
+  fn test_route_arriving() {
+    let graph = graph_from_data_and_edges(vec![1, 1, 4, 1], vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let rush_hour = |start: i32| RushHour {
+      graph: &graph,
+      start,
+      forward: false,
+    };
+
+    // Node 1 would be left at the time 9, in the rush hour
+    let early = route_arriving(rush_hour(10), &matched(0), &matched(3)).unwrap();
+    assert_eq!(early.ids, vec![0, 2, 3]);
+    assert_eq!(early.cost, 6);
+
+    let late = route_arriving(rush_hour(20), &matched(0), &matched(3)).unwrap();
+    assert_eq!(late.ids, vec![0, 1, 3]);
+    assert_eq!(late.cost, 3);
+    assert_eq!((late.origin_candidate, late.destination_candidate), (0, 0));
+
+    let reversed = ReversedGraph::new(rush_hour(10));
+    assert_eq!(calculate_time_dependent_weight(reversed, [0, 1, 3].iter().rev().cloned()), 12);
+  }
+}
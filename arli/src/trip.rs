@@ -0,0 +1,358 @@
+//! Order of visiting waypoints (trip) computations, e.g. the stops of a delivery tour.
+//!
+//! The order is optimized on a cost matrix of the waypoints, see [`crate::matrix`], with the constraints of the OSRM
+//! trip service: a round trip may start at the first waypoint and end at the last one, an open trip has to do both.
+
+use crate::graph::Weight;
+
+use thiserror::Error;
+use std::ops::Sub;
+
+// Orders of at most this many waypoints are found by trying all of them, larger ones by farthest insertion and Or-opt
+const MAX_EXACT_WAYPOINTS: usize = 10;
+// Longest chain of consecutive waypoints moved at once when improving a heuristic order
+const MAX_MOVED_WAYPOINTS: usize = 3;
+
+/// Constraints of the waypoint order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TripOptions {
+  /// The trip returns from the last waypoint to the first one
+  pub roundtrip: bool,
+  /// The trip starts at the first waypoint of the matrix
+  pub fixed_source: bool,
+  /// The trip ends at the last waypoint of the matrix
+  pub fixed_destination: bool,
+}
+
+impl Default for TripOptions {
+  fn default() -> Self {
+    Self {
+      roundtrip: true,
+      fixed_source: false,
+      fixed_destination: false,
+    }
+  }
+}
+
+/// Reason no trip is found
+#[derive(Clone, Copy, Debug, PartialEq, Error)]
+pub enum TripError {
+  /// An open trip is only supported from the first to the last waypoint
+  #[error("A trip which isn't a round trip must start at the first waypoint and end at the last one")]
+  Unsupported,
+  /// Some waypoints can't be reached from the others
+  #[error("Not all waypoints can be reached from each other")]
+  Unreachable,
+}
+
+struct Trip<'a, W> {
+  costs: &'a [Vec<Option<W>>],
+  // The trip returns to the first waypoint
+  closed: bool,
+  // Waypoint the trip has to end at, it's only left to return to the first one
+  end: Option<usize>,
+}
+
+impl<'a, W: Weight + Sub<Output = W>> Trip<'a, W> {
+  fn cost(&self, from: usize, to: usize) -> Option<W> {
+    self.costs[from][to]
+  }
+
+  // Cost of the whole trip, `None` if one of the legs can't be traveled
+  fn total(&self, order: &[usize]) -> Option<W> {
+    let closing = if self.closed {
+      Some(self.cost(order[order.len() - 1], order[0]))
+    } else {
+      None
+    };
+    order
+      .windows(2)
+      .map(|leg| self.cost(leg[0], leg[1]))
+      .chain(closing)
+      .try_fold(W::default(), |total, cost| Some(total + cost?))
+  }
+
+  // Waypoints the order can be chosen for, the trip always starts at the first one
+  fn free_waypoints(&self) -> Vec<usize> {
+    (1..self.costs.len()).filter(|id| Some(*id) != self.end).collect()
+  }
+
+  fn exact(&self) -> Option<Vec<usize>> {
+    let mut best = None;
+    self.extend(&mut vec![0], &mut self.free_waypoints(), W::default(), &mut best);
+    best.map(|(_, order)| order)
+  }
+
+  // Tries all the orders of the remaining waypoints after the partial order, skipping the ones costing more than the
+  // best trip so far
+  fn extend(&self, order: &mut Vec<usize>, remaining: &mut Vec<usize>, cost: W, best: &mut Option<(W, Vec<usize>)>) {
+    if let Some((best_cost, _)) = best {
+      if cost >= *best_cost {
+        return;
+      }
+    }
+    if remaining.is_empty() {
+      let mut complete = order.clone();
+      complete.extend(self.end);
+      if let Some(total) = self.total(&complete) {
+        match best {
+          Some((best_cost, _)) if *best_cost <= total => {}
+          _ => *best = Some((total, complete)),
+        }
+      }
+      return;
+    }
+
+    let last = order[order.len() - 1];
+    for index in 0..remaining.len() {
+      let next = remaining[index];
+      if let Some(leg) = self.cost(last, next) {
+        remaining.swap_remove(index);
+        order.push(next);
+        self.extend(order, remaining, cost + leg, best);
+        order.pop();
+        remaining.push(next);
+        let moved = remaining.len() - 1;
+        remaining.swap(index, moved);
+      }
+    }
+  }
+
+  // Builds the trip up by inserting the waypoint farthest from it at the cheapest position, so the outline of the trip
+  // is settled first and the waypoints close to it are picked up along the way
+  fn farthest_insertion(&self) -> Option<Vec<usize>> {
+    let mut order = vec![0];
+    order.extend(self.end);
+    let mut remaining = self.free_waypoints();
+    while !remaining.is_empty() {
+      let (index, _) = remaining
+        .iter()
+        .enumerate()
+        .filter_map(|(index, id)| Some((index, self.distance_to(&order, *id)?)))
+        .max_by_key(|(_, distance)| *distance)?;
+      let id = remaining.swap_remove(index);
+      let position = self.cheapest_insertion(&order, id)?;
+      order.insert(position, id);
+    }
+    self.total(&order).map(|_| order)
+  }
+
+  // Moves chains of consecutive waypoints to the position where the trip costs the least, until no move makes it
+  // cheaper (Or-opt). A chain keeps its direction, unlike the reversals of 2-opt, so the one-way costs stay valid.
+  fn improve(&self, order: &mut Vec<usize>) {
+    let mut best = match self.total(order) {
+      Some(total) => total,
+      None => return,
+    };
+    while let Some((total, moved)) = self.cheaper_move(order, best) {
+      best = total;
+      *order = moved;
+    }
+  }
+
+  // First move of a chain of waypoints making the trip cheaper than the cost
+  fn cheaper_move(&self, order: &[usize], cost: W) -> Option<(W, Vec<usize>)> {
+    // The first waypoint stays first and the fixed end stays last
+    let movable = order.len() - self.end.map_or(0, |_| 1);
+    for length in 1..=MAX_MOVED_WAYPOINTS {
+      for start in 1..movable.saturating_sub(length - 1) {
+        let mut rest = order.to_vec();
+        let chain: Vec<_> = rest.drain(start..start + length).collect();
+        for position in (1..=movable - length).filter(|position| *position != start) {
+          let mut moved = rest.clone();
+          moved.splice(position..position, chain.iter().cloned());
+          match self.total(&moved) {
+            Some(total) if total < cost => return Some((total, moved)),
+            _ => {}
+          }
+        }
+      }
+    }
+    None
+  }
+
+  // Cost between the waypoint and the closest one of the trip, in either direction
+  fn distance_to(&self, order: &[usize], id: usize) -> Option<W> {
+    order
+      .iter()
+      .flat_map(|other| vec![self.cost(*other, id), self.cost(id, *other)])
+      .flatten()
+      .min()
+  }
+
+  // Position in the order where inserting the waypoint adds the least cost
+  fn cheapest_insertion(&self, order: &[usize], id: usize) -> Option<usize> {
+    // Nothing is inserted after the fixed end, an open trip always has one
+    let positions = match self.end {
+      Some(_) => 1..order.len(),
+      None => 1..order.len() + 1,
+    };
+    positions
+      .filter_map(|position| {
+        let previous = order[position - 1];
+        let next = *order.get(position).unwrap_or(&order[0]);
+        let added = self.cost(previous, id)? + self.cost(id, next)?;
+        let cost = match self.cost(previous, next) {
+          Some(removed) => added - removed,
+          None => added,
+        };
+        Some((position, cost))
+      })
+      .min_by_key(|(_, cost)| *cost)
+      .map(|(position, _)| position)
+  }
+}
+
+/// Finds the cheapest order of visiting all the waypoints of the cost matrix, `costs[from][to]` is `None` if `to`
+/// can't be reached from `from`.
+///
+/// The result has the indices of the waypoints in the order they are visited. A round trip returns from the last one
+/// to the first one, it starts at the first waypoint of the matrix unless only the destination is fixed.
+pub fn optimize_order<W: Weight + Sub<Output = W>>(
+  costs: &[Vec<Option<W>>],
+  options: &TripOptions,
+) -> Result<Vec<usize>, TripError> {
+  let fixed_ends = options.fixed_source && options.fixed_destination;
+  if !options.roundtrip && !fixed_ends {
+    return Err(TripError::Unsupported);
+  }
+  let waypoints = costs.len();
+  if waypoints <= 1 {
+    return Ok((0..waypoints).collect());
+  }
+
+  let trip = Trip {
+    costs,
+    closed: options.roundtrip,
+    end: if fixed_ends { Some(waypoints - 1) } else { None },
+  };
+  let mut order = if waypoints <= MAX_EXACT_WAYPOINTS {
+    trip.exact()
+  } else {
+    trip.farthest_insertion().map(|mut order| {
+      trip.improve(&mut order);
+      order
+    })
+  }
+  .ok_or(TripError::Unreachable)?;
+
+  // A round trip is the same from any of its waypoints
+  if options.roundtrip && options.fixed_destination && !options.fixed_source {
+    let last = order.iter().position(|id| *id == waypoints - 1).unwrap();
+    order.rotate_left(last + 1);
+  }
+  Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Waypoints on a line, the cost is the distance between them
+  fn line(positions: &[i32]) -> Vec<Vec<Option<i32>>> {
+    positions
+      .iter()
+      .map(|from| positions.iter().map(|to| Some((from - to).abs())).collect())
+      .collect()
+  }
+
+  fn total(costs: &[Vec<Option<i32>>], order: &[usize], roundtrip: bool) -> i32 {
+    let trip = Trip {
+      costs,
+      closed: roundtrip,
+      end: None,
+    };
+    trip.total(order).unwrap()
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let costs = line(&[0, 30, 10, 20]);
+
+    let order = optimize_order(&costs, &TripOptions::default()).unwrap();
+    assert_eq!(order[0], 0);
+    assert_eq!(total(&costs, &order, true), 60);
+
+    let to_last = TripOptions {
+      fixed_destination: true,
+      ..TripOptions::default()
+    };
+    let order = optimize_order(&costs, &to_last).unwrap();
+    assert_eq!(order.last(), Some(&3));
+    assert_eq!(total(&costs, &order, true), 60);
+  }
+
+  #[test]
+  fn test_open_trip() {
+    let costs = line(&[0, 30, 10, 20]);
+    let open = TripOptions {
+      roundtrip: false,
+      fixed_source: true,
+      fixed_destination: true,
+    };
+    assert_eq!(optimize_order(&costs, &open), Ok(vec![0, 2, 1, 3]));
+
+    let from_first = TripOptions {
+      roundtrip: false,
+      fixed_source: true,
+      fixed_destination: false,
+    };
+    assert_eq!(optimize_order(&costs, &from_first), Err(TripError::Unsupported));
+  }
+
+  #[test]
+  fn test_one_way_costs() {
+    // Going around 0 -> 1 -> 2 is cheap, the other way around is expensive
+    let costs = vec![
+      vec![Some(0), Some(1), Some(10)],
+      vec![Some(10), Some(0), Some(1)],
+      vec![Some(1), Some(10), Some(0)],
+    ];
+    assert_eq!(optimize_order(&costs, &TripOptions::default()), Ok(vec![0, 1, 2]));
+  }
+
+  #[test]
+  fn test_unreachable_waypoint() {
+    let mut costs = line(&[0, 10, 20]);
+    costs[0][2] = None;
+    costs[1][2] = None;
+    assert_eq!(optimize_order(&costs, &TripOptions::default()), Err(TripError::Unreachable));
+  }
+
+  #[test]
+  fn test_improve() {
+    let costs = line(&[0, 10, 20, 30, 40]);
+    let trip = Trip {
+      costs: &costs,
+      closed: true,
+      end: None,
+    };
+    let mut order = vec![0, 3, 1, 4, 2];
+    trip.improve(&mut order);
+    assert_eq!(total(&costs, &order, true), 80);
+    assert_eq!(order[0], 0);
+
+    // The fixed end isn't moved
+    let open = Trip {
+      costs: &costs,
+      closed: false,
+      end: Some(4),
+    };
+    let mut order = vec![0, 3, 1, 2, 4];
+    open.improve(&mut order);
+    assert_eq!(order, vec![0, 1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn test_farthest_insertion() {
+    let positions = [50, 0, 70, 10, 90, 30, 100, 20, 60, 40, 80, 5];
+    let costs = line(&positions);
+
+    let order = optimize_order(&costs, &TripOptions::default()).unwrap();
+    let mut visited = order.clone();
+    visited.sort_unstable();
+    assert_eq!(visited, (0..positions.len()).collect::<Vec<_>>());
+    // Out to one end of the line and back
+    assert_eq!(total(&costs, &order, true), 200);
+  }
+}
@@ -1,24 +1,42 @@
 //! Waypoint matching.
 
-use crate::graph::{Identifier, IntoGeometry, Spatial};
+use crate::error::{Error, Result};
+use crate::graph::{GraphData, Identifier, IntoGeometry, Spatial};
 use crate::spatial::*;
 use geo::{Closest, closest_point::*, haversine_distance::*, line_locate_point::*};
 use std::fmt;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct SnappedPosition {
   pub snapped: Position,
   pub distance: f32,
   pub factor: f32,
 }
 
+#[derive(Clone, Copy)]
 pub struct SnappedOnEdge<N: Identifier>(pub SnappedPosition, pub N);
 
+#[derive(Clone)]
 pub struct MatchedWaypoint<N: Identifier> {
   pub waypoint: Position,
   pub snapped: Vec<SnappedOnEdge<N>>,
 }
 
+impl<N: Identifier> MatchedWaypoint<N> {
+  /// Waypoint matched to the beginning of a known node, e.g. when routing between graph nodes directly.
+  pub fn from_node(id: N, position: Position) -> Self {
+    let snapped = SnappedPosition {
+      snapped: position,
+      distance: 0.0,
+      factor: 0.0,
+    };
+    MatchedWaypoint {
+      waypoint: position,
+      snapped: vec![SnappedOnEdge(snapped, id)],
+    }
+  }
+}
+
 fn snap_to_geometry(
   geometry: &Polyline,
   position: &Position,
@@ -49,24 +67,143 @@ fn snap_to_geometry(
   }
 }
 
-pub fn match_waypoint<G: Copy + IntoGeometry + Spatial>(
+// Bearing of the polyline piece which contains the point at `factor` of the polyline length
+fn bearing_at(geometry: &Polyline, factor: f32) -> Option<f32> {
+  let lengths: Vec<f32> = geometry
+    .lines()
+    .map(|line| line.dx().hypot(line.dy()))
+    .collect();
+  let mut remaining = factor * lengths.iter().sum::<f32>();
+  for (line, length) in geometry.lines().zip(lengths) {
+    if length > 0.0 && remaining <= length {
+      return Some(bearing(&line.start, &line.end));
+    }
+    remaining -= length;
+  }
+  geometry
+    .lines()
+    .filter(|line| line.start != line.end)
+    .last()
+    .map(|line| bearing(&line.start, &line.end))
+}
+
+// Smallest angle in degrees between two bearings
+fn angle_difference(a: f32, b: f32) -> f32 {
+  let difference = (a - b).abs() % 360.0;
+  difference.min(360.0 - difference)
+}
+
+/// Difference in degrees between the heading and the direction of the geometry at the position on it, `None` if the
+/// geometry has no direction
+pub fn heading_difference(geometry: &Polyline, position: &Position, heading: f32) -> Option<f32> {
+  let factor = geometry.line_locate_point(&geo::Point::from(*position))?;
+  bearing_at(geometry, factor).map(|bearing| angle_difference(bearing, heading))
+}
+
+/// Snapping candidate as seen by a scoring function
+pub struct SnapCandidate<'a, Data> {
+  pub snapped: &'a SnappedPosition,
+  pub data: &'a Data,
+  /// Difference in degrees between the requested heading and the direction of the node at the snapped position,
+  /// `None` if no heading is requested
+  pub heading_difference: Option<f32>,
+}
+
+/// Decides whether a waypoint can be snapped to a node, e.g. by the distance acceptable for the road class
+pub trait SnapFilter<Data> {
+  fn accepts(&self, data: &Data, snapped: &SnappedPosition) -> bool;
+}
+
+impl<Data, F: Fn(&Data, &SnappedPosition) -> bool> SnapFilter<Data> for F {
+  fn accepts(&self, data: &Data, snapped: &SnappedPosition) -> bool {
+    self(data, snapped)
+  }
+}
+
+/// Filter accepting all nodes within the search radius
+#[derive(Clone, Copy, Debug)]
+pub struct AnyCandidate;
+
+impl<Data> SnapFilter<Data> for AnyCandidate {
+  fn accepts(&self, _: &Data, _: &SnappedPosition) -> bool {
+    true
+  }
+}
+
+/// Options for [`match_waypoint_with`]
+pub struct SnapOptions<S, A = AnyCandidate> {
+  /// Nodes further away from the waypoint are ignored, the filter can reject closer ones
+  pub radius_m: f32,
+  /// Number of the best scored candidates to keep
+  pub max_candidates: usize,
+  /// Direction of travel at the waypoint in degrees clockwise from north, e.g. the heading of a vehicle
+  pub heading: Option<f32>,
+  /// Scores a candidate, lower scores are better
+  pub score: S,
+  /// Rejects candidates before they are scored
+  pub filter: A,
+  /// Radius of the search for the nearest node when no candidate is accepted, see [`match_waypoint_or_nearest`]
+  pub fallback_radius_m: Option<f32>,
+}
+
+impl<S> SnapOptions<S> {
+  pub fn new(score: S) -> Self {
+    SnapOptions {
+      radius_m: 100.0,
+      max_candidates: 4,
+      heading: None,
+      score,
+      filter: AnyCandidate,
+      fallback_radius_m: None,
+    }
+  }
+}
+
+impl<S, A> SnapOptions<S, A> {
+  /// Replaces the filter, e.g. to accept a larger snapping distance to a motorway than to a footway. The radius must
+  /// cover the largest accepted distance.
+  pub fn with_filter<B>(self, filter: B) -> SnapOptions<S, B> {
+    SnapOptions {
+      radius_m: self.radius_m,
+      max_candidates: self.max_candidates,
+      heading: self.heading,
+      score: self.score,
+      filter,
+      fallback_radius_m: self.fallback_radius_m,
+    }
+  }
+}
+
+/// Default score which only prefers closer candidates
+pub fn distance_score<Data>(candidate: &SnapCandidate<Data>) -> f32 {
+  candidate.snapped.distance
+}
+
+fn snap_nearby<G: Copy + IntoGeometry + Spatial>(
   graph: G,
   waypoint: &Position,
-) -> MatchedWaypoint<G::NodeId> {
-  let elements_nearby = graph.find_nodes(&envelope(waypoint, 100.));
+  radius_m: f32,
+) -> Vec<(SnappedOnEdge<G::NodeId>, Polyline)> {
+  let elements_nearby = graph.find_nodes(&envelope(waypoint, radius_m));
 
-  let mut snapped_positions: Vec<_> = elements_nearby
+  elements_nearby
     .into_iter()
     // TODO: Rtree does not seem to work, returns too many elements
     //.inspect(|x| println!(" > found nearby: {}", x))
     .filter_map(|id| {
-      snap_to_geometry(
-        &Polyline::from(graph.geometry(id).collect::<Vec<_>>()),
-        waypoint,
-        100.0,
-      )
-      .map(|snapped| SnappedOnEdge(snapped, id))
+      let geometry = Polyline::from(graph.geometry(id).collect::<Vec<_>>());
+      snap_to_geometry(&geometry, waypoint, radius_m).map(|snapped| (SnappedOnEdge(snapped, id), geometry))
     })
+    .collect()
+}
+
+pub fn match_waypoint<G: Copy + IntoGeometry + Spatial>(
+  graph: G,
+  waypoint: &Position,
+) -> MatchedWaypoint<G::NodeId> {
+  let mut snapped_positions: Vec<_> = snap_nearby(graph, waypoint, 100.0)
+    .into_iter()
+    .map(|(snapped, _)| snapped)
     .collect();
 
   snapped_positions.sort_by(|a, b| a.0.distance.partial_cmp(&b.0.distance).unwrap());
@@ -78,6 +215,118 @@ pub fn match_waypoint<G: Copy + IntoGeometry + Spatial>(
   }
 }
 
+/// Matches the waypoint to the nearby nodes and keeps the candidates with the best score.
+///
+/// Pure distance sorting can prefer a wrong road, e.g. a frontage road a meter closer than the parallel motorway.
+/// The scoring function can take the node data, like the road class, and the heading into account.
+pub fn match_waypoint_with<G, S, A>(
+  graph: G,
+  waypoint: &Position,
+  options: &SnapOptions<S, A>,
+) -> MatchedWaypoint<G::NodeId>
+where
+  G: Copy + GraphData + IntoGeometry + Spatial,
+  S: Fn(&SnapCandidate<G::Data>) -> f32,
+  A: SnapFilter<G::Data>,
+{
+  let mut scored: Vec<_> = snap_nearby(graph, waypoint, options.radius_m)
+    .into_iter()
+    .filter(|(snapped, _)| options.filter.accepts(graph.data(snapped.1), &snapped.0))
+    .map(|(snapped, geometry)| {
+      let heading_difference = options
+        .heading
+        .map(|heading| angle_difference(bearing_at(&geometry, snapped.0.factor).unwrap_or(heading), heading));
+      let score = (options.score)(&SnapCandidate {
+        snapped: &snapped.0,
+        data: graph.data(snapped.1),
+        heading_difference,
+      });
+      (score, snapped)
+    })
+    .collect();
+
+  scored.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+  scored.truncate(options.max_candidates);
+
+  MatchedWaypoint {
+    waypoint: *waypoint,
+    snapped: scored.into_iter().map(|(_, snapped)| snapped).collect(),
+  }
+}
+
+/// Result of [`match_waypoint_or_nearest`]
+pub enum WaypointMatch<N: Identifier> {
+  /// Candidates accepted within the snapping radius
+  Matched(MatchedWaypoint<N>),
+  /// No candidate is accepted, the waypoint is matched to the nearest nodes within the fallback radius instead
+  Nearest(MatchedWaypoint<N>),
+  /// No node within the fallback radius either, or no fallback radius is set
+  Unmatched,
+}
+
+impl<N: Identifier> WaypointMatch<N> {
+  /// Candidates of the waypoint, fails with [`Error::NotSnapped`] if it's unmatched
+  pub fn matched(&self) -> Result<&MatchedWaypoint<N>> {
+    match self {
+      WaypointMatch::Matched(matched) | WaypointMatch::Nearest(matched) => Ok(matched),
+      WaypointMatch::Unmatched => Err(Error::NotSnapped),
+    }
+  }
+
+  pub fn into_matched(self) -> Result<MatchedWaypoint<N>> {
+    match self {
+      WaypointMatch::Matched(matched) | WaypointMatch::Nearest(matched) => Ok(matched),
+      WaypointMatch::Unmatched => Err(Error::NotSnapped),
+    }
+  }
+
+  /// The waypoint is matched to the nearest nodes, e.g. the client can tell the user the route starts from the
+  /// nearest road
+  pub fn is_fallback(&self) -> bool {
+    matches!(self, WaypointMatch::Nearest(_))
+  }
+}
+
+/// Same as [`match_waypoint_with`], but when no candidate is accepted and `fallback_radius_m` is set, matches the
+/// waypoint to the nearest nodes within the fallback radius instead of failing.
+///
+/// The fallback candidates are sorted by distance only, the filter and the score of the options aren't applied to
+/// them: the filter would reject them for being too far away.
+pub fn match_waypoint_or_nearest<G, S, A>(
+  graph: G,
+  waypoint: &Position,
+  options: &SnapOptions<S, A>,
+) -> WaypointMatch<G::NodeId>
+where
+  G: Copy + GraphData + IntoGeometry + Spatial,
+  S: Fn(&SnapCandidate<G::Data>) -> f32,
+  A: SnapFilter<G::Data>,
+{
+  let matched = match_waypoint_with(graph, waypoint, options);
+  if !matched.snapped.is_empty() {
+    return WaypointMatch::Matched(matched);
+  }
+  let fallback_radius_m = match options.fallback_radius_m {
+    Some(radius_m) => radius_m,
+    None => return WaypointMatch::Unmatched,
+  };
+
+  let mut nearest: Vec<_> = snap_nearby(graph, waypoint, fallback_radius_m)
+    .into_iter()
+    .map(|(snapped, _)| snapped)
+    .collect();
+  if nearest.is_empty() {
+    return WaypointMatch::Unmatched;
+  }
+  nearest.sort_by(|a, b| a.0.distance.partial_cmp(&b.0.distance).unwrap());
+  nearest.truncate(options.max_candidates);
+
+  WaypointMatch::Nearest(MatchedWaypoint {
+    waypoint: *waypoint,
+    snapped: nearest,
+  })
+}
+
 impl fmt::Debug for SnappedPosition {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     write!(
@@ -96,6 +345,8 @@ impl<N: Identifier> fmt::Debug for SnappedOnEdge<N> {
 
 #[cfg(test)]
 mod tests {
+  use super::super::graph_impl::HasGeometry;
+  use super::super::test_utils::{graph_from_intersections, Segment};
   use super::*;
   use geo::{map_coords::MapCoords};
   use geo::point;
@@ -124,4 +375,103 @@ mod tests {
     );
     println!("result2 = {:?}", result2.unwrap());
   }
+
+  #[test]
+  fn test_match_waypoint_with_score() {
+    // Two parallel roads going north, the second one is a few meters further from the waypoint
+    let graph = graph_from_intersections(
+      vec![
+        Position { x: 13.4, y: 52.5 },
+        Position { x: 13.4, y: 52.501 },
+        Position { x: 13.4001, y: 52.5 },
+        Position { x: 13.4001, y: 52.501 },
+      ],
+      vec![(0, 1), (3, 2)],
+    );
+    let waypoint = Position { x: 13.40004, y: 52.5005 };
+
+    let by_distance = match_waypoint_with(&graph, &waypoint, &SnapOptions::new(distance_score));
+    assert_eq!(by_distance.snapped[0].1, 0);
+
+    // Prefer the second road, as if it was of a higher class
+    let prefer_second = |candidate: &SnapCandidate<Segment>| {
+      candidate.snapped.distance + if candidate.data.geometry().next().unwrap().x > 13.40005 { 0.0 } else { 10.0 }
+    };
+    let by_class = match_waypoint_with(&graph, &waypoint, &SnapOptions::new(prefer_second));
+    assert_eq!(by_class.snapped[0].1, 1);
+
+    // The second road goes south, it's a wrong direction for the heading north
+    let mut options = SnapOptions::new(|candidate: &SnapCandidate<Segment>| {
+      candidate.snapped.distance + candidate.heading_difference.unwrap()
+    });
+    options.heading = Some(10.0);
+    let by_heading = match_waypoint_with(&graph, &waypoint, &options);
+    assert_eq!(by_heading.snapped[0].1, 0);
+    assert_eq!(by_heading.snapped.len(), 2);
+  }
+
+  #[test]
+  fn test_bearing_at() {
+    let geometry: Polyline = vec![[13.4, 52.5], [13.4, 52.501], [13.401, 52.501]].into();
+
+    assert_eq!(bearing_at(&geometry, 0.25).map(f32::round), Some(0.0));
+    assert_eq!(bearing_at(&geometry, 0.75).map(f32::round), Some(90.0));
+    assert_eq!(bearing_at(&geometry, 1.0).map(f32::round), Some(90.0));
+  }
+
+  #[test]
+  fn test_match_waypoint_with_filter() {
+    // A road 7 meters away from the waypoint and another one about 14 meters away
+    let graph = graph_from_intersections(
+      vec![
+        Position { x: 13.4, y: 52.5 },
+        Position { x: 13.4, y: 52.501 },
+        Position { x: 13.4003, y: 52.5 },
+        Position { x: 13.4003, y: 52.501 },
+      ],
+      vec![(0, 1), (2, 3)],
+    );
+    let waypoint = Position { x: 13.4001, y: 52.5005 };
+
+    let any = match_waypoint_with(&graph, &waypoint, &SnapOptions::new(distance_score));
+    assert_eq!(any.snapped.len(), 2);
+
+    // Only the first road accepts waypoints from further than 10 meters
+    let max_distance = |segment: &Segment, snapped: &SnappedPosition| {
+      let max_distance = if segment.geometry().next().unwrap().x < 13.4001 { 50.0 } else { 10.0 };
+      snapped.distance <= max_distance
+    };
+    let filtered = match_waypoint_with(&graph, &waypoint, &SnapOptions::new(distance_score).with_filter(max_distance));
+    assert_eq!(filtered.snapped.len(), 1);
+    assert_eq!(filtered.snapped[0].1, 0);
+  }
+
+  #[test]
+  fn test_match_waypoint_or_nearest() {
+    // A road about 70 meters away from the waypoint
+    let graph = graph_from_intersections(
+      vec![Position { x: 13.4, y: 52.5 }, Position { x: 13.4, y: 52.501 }],
+      vec![(0, 1)],
+    );
+    let waypoint = Position { x: 13.401, y: 52.5005 };
+    let mut options = SnapOptions::new(distance_score);
+    options.radius_m = 50.0;
+
+    assert!(matches!(
+      match_waypoint_or_nearest(&graph, &waypoint, &options),
+      WaypointMatch::Unmatched
+    ));
+
+    options.fallback_radius_m = Some(500.0);
+    let nearest = match_waypoint_or_nearest(&graph, &waypoint, &options);
+    assert!(nearest.is_fallback());
+    let matched = nearest.into_matched().unwrap();
+    assert_eq!(matched.snapped[0].1, 0);
+    assert!(matched.snapped[0].0.distance > 50.0);
+
+    options.radius_m = 100.0;
+    let within_radius = match_waypoint_or_nearest(&graph, &waypoint, &options);
+    assert!(!within_radius.is_fallback());
+    assert_eq!(within_radius.matched().unwrap().snapped.len(), 1);
+  }
 }